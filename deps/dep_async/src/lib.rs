@@ -1,3 +1,11 @@
 #![allow(unused, dead_code, unreachable_code, path_statements, unexpected_cfgs)]
 
 mod tokio;
+
+pub use self::tokio::_10_file_tail as file_tail;
+pub use self::tokio::_11_worker_pool as worker_pool;
+pub use self::tokio::_12_rate_limit as rate_limit;
+pub use self::tokio::_13_resilience as resilience;
+pub use self::tokio::_14_pubsub as pubsub;
+pub use self::tokio::_15_resp_server as resp_server;
+pub use self::tokio::_16_resp_client as resp_client;
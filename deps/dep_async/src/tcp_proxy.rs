@@ -0,0 +1,509 @@
+// TCP 四层反向代理
+// 在 _05_io.rs 的 echo_server / io_copy 基础上：
+// 1. 上游池 + 轮询(round-robin) / 最少连接(least-conn) 两种负载均衡
+// 2. 主动健康检查：定时 connect 上游，失败的节点摘除，恢复后重新加入
+// 3. 连接上游带超时，单个上游失败会换下一个
+// 4. 用 io::copy_bidirectional 双向转发，并统计每条连接/每个上游的字节数；
+//    字节数在读的时候就记下来，连接中途出错已经转发的部分也算
+//
+// 用法(配置规则见 dep_serialization::ConfigLoader，也可以写进 --config 指定的 YAML)：
+// cargo run -p dep_async --bin tcp_proxy -- \
+//     --listen 127.0.0.1:6142 \
+//     --upstreams '[127.0.0.1:7001, 127.0.0.1:7002]' \
+//     --strategy least-conn --connect-timeout-ms 1000 --health-interval-ms 2000
+// 或者 TCP_PROXY__UPSTREAMS='[127.0.0.1:7001]' TCP_PROXY__STRATEGY=rr cargo run -p dep_async --bin tcp_proxy
+
+use dep_serialization::{ConfigLoader, Settings};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{self, AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{self, timeout};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Strategy {
+    #[serde(alias = "rr")]
+    RoundRobin,
+    #[serde(alias = "lc")]
+    LeastConn,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct ProxyConfig {
+    listen: SocketAddr,
+    upstreams: Vec<SocketAddr>,
+    strategy: Strategy,
+    connect_timeout_ms: u64,
+    health_interval_ms: u64,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        ProxyConfig {
+            listen: "127.0.0.1:6142".parse().unwrap(),
+            upstreams: Vec::new(),
+            strategy: Strategy::RoundRobin,
+            connect_timeout_ms: 1000,
+            health_interval_ms: 2000,
+        }
+    }
+}
+
+impl Settings for ProxyConfig {
+    fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.upstreams.is_empty() {
+            problems.push("upstreams: at least one upstream is required".to_string());
+        }
+        if self.connect_timeout_ms == 0 {
+            problems.push("connect_timeout_ms: must be at least 1".to_string());
+        }
+        if self.health_interval_ms == 0 {
+            problems.push("health_interval_ms: must be at least 1".to_string());
+        }
+        problems
+    }
+}
+
+impl ProxyConfig {
+    fn connect_timeout(&self) -> Duration {
+        Duration::from_millis(self.connect_timeout_ms)
+    }
+
+    fn health_interval(&self) -> Duration {
+        Duration::from_millis(self.health_interval_ms)
+    }
+}
+
+/// 一个上游节点以及它的运行时统计
+#[derive(Debug)]
+struct Upstream {
+    addr: SocketAddr,
+    healthy: AtomicBool,
+    active: AtomicUsize,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+}
+
+impl Upstream {
+    fn new(addr: SocketAddr) -> Self {
+        Upstream {
+            addr,
+            healthy: AtomicBool::new(true),
+            active: AtomicUsize::new(0),
+            bytes_sent: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+        }
+    }
+
+    fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+}
+
+// 连接结束(包括 panic / 提前 return)时自动把活跃连接数减回去
+struct ActiveGuard(Arc<Upstream>);
+
+impl ActiveGuard {
+    fn new(upstream: Arc<Upstream>) -> Self {
+        upstream.active.fetch_add(1, Ordering::Relaxed);
+        ActiveGuard(upstream)
+    }
+}
+
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// 包一层，记下从里面读出来的字节数，写直接透传
+struct Counted<S> {
+    inner: S,
+    read: u64,
+}
+
+impl<S> Counted<S> {
+    fn new(inner: S) -> Self {
+        Counted { inner, read: 0 }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Counted<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        self.read += (buf.filled().len() - before) as u64;
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Counted<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[derive(Debug)]
+struct UpstreamPool {
+    upstreams: Vec<Arc<Upstream>>,
+    strategy: Strategy,
+    next: AtomicUsize,
+}
+
+impl UpstreamPool {
+    fn new(addrs: &[SocketAddr], strategy: Strategy) -> Self {
+        UpstreamPool {
+            upstreams: addrs.iter().map(|a| Arc::new(Upstream::new(*a))).collect(),
+            strategy,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// 按策略选出一个健康的上游，`skip` 里是本次已经连接失败过的
+    fn pick(&self, skip: &[SocketAddr]) -> Option<Arc<Upstream>> {
+        let candidates: Vec<&Arc<Upstream>> = self
+            .upstreams
+            .iter()
+            .filter(|u| u.is_healthy() && !skip.contains(&u.addr))
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let picked = match self.strategy {
+            Strategy::RoundRobin => {
+                let i = self.next.fetch_add(1, Ordering::Relaxed);
+                candidates[i % candidates.len()]
+            }
+            // 活跃连接数相同的时候取靠前的那个
+            Strategy::LeastConn => candidates
+                .iter()
+                .min_by_key(|u| u.active.load(Ordering::Relaxed))
+                .unwrap(),
+        };
+        Some(picked.clone())
+    }
+
+    async fn check_health(&self, connect_timeout: Duration) {
+        for upstream in &self.upstreams {
+            let ok = matches!(
+                timeout(connect_timeout, TcpStream::connect(upstream.addr)).await,
+                Ok(Ok(_))
+            );
+            let was = upstream.healthy.swap(ok, Ordering::Relaxed);
+            if was != ok {
+                println!(
+                    "upstream {} is now {}",
+                    upstream.addr,
+                    if ok { "healthy" } else { "unhealthy" }
+                );
+            }
+        }
+    }
+}
+
+struct Proxy {
+    pool: UpstreamPool,
+    connect_timeout: Duration,
+}
+
+impl Proxy {
+    fn new(config: &ProxyConfig) -> Self {
+        Proxy {
+            pool: UpstreamPool::new(&config.upstreams, config.strategy),
+            connect_timeout: config.connect_timeout(),
+        }
+    }
+
+    // 依次尝试健康的上游，连接失败/超时的直接标记为不健康，等健康检查再拉回来
+    async fn connect_upstream(&self) -> io::Result<(TcpStream, Arc<Upstream>)> {
+        let mut tried = Vec::new();
+        while let Some(upstream) = self.pool.pick(&tried) {
+            match timeout(self.connect_timeout, TcpStream::connect(upstream.addr)).await {
+                Ok(Ok(stream)) => return Ok((stream, upstream)),
+                Ok(Err(e)) => println!("connect {} failed: {}", upstream.addr, e),
+                Err(_) => println!("connect {} timed out", upstream.addr),
+            }
+            upstream.healthy.store(false, Ordering::Relaxed);
+            tried.push(upstream.addr);
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotConnected,
+            "no healthy upstream available",
+        ))
+    }
+
+    async fn handle(&self, inbound: TcpStream, peer: SocketAddr) -> io::Result<()> {
+        let (outbound, upstream) = self.connect_upstream().await?;
+        let _guard = ActiveGuard::new(upstream.clone());
+
+        // 从客户端读到的就是发给上游的，从上游读到的就是回给客户端的
+        let mut inbound = Counted::new(inbound);
+        let mut outbound = Counted::new(outbound);
+        let result = io::copy_bidirectional(&mut inbound, &mut outbound).await;
+        let (sent, received) = (inbound.read, outbound.read);
+
+        upstream.bytes_sent.fetch_add(sent, Ordering::Relaxed);
+        upstream
+            .bytes_received
+            .fetch_add(received, Ordering::Relaxed);
+        match result {
+            Ok(_) => {
+                println!(
+                    "{} <-> {} closed, sent={} received={}",
+                    peer, upstream.addr, sent, received
+                );
+                Ok(())
+            }
+            Err(e) => {
+                println!(
+                    "{} <-> {} closed with error: {}, sent={} received={}",
+                    peer, upstream.addr, e, sent, received
+                );
+                Err(e)
+            }
+        }
+    }
+
+    async fn serve(self: Arc<Self>, listener: TcpListener) -> io::Result<()> {
+        loop {
+            let (socket, peer) = listener.accept().await?;
+            let proxy = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = proxy.handle(socket, peer).await {
+                    println!("proxy {} failed: {}", peer, e);
+                }
+            });
+        }
+    }
+
+    fn spawn_health_check(self: &Arc<Self>, interval: Duration) {
+        let proxy = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = time::interval(interval);
+            loop {
+                ticker.tick().await;
+                proxy.pool.check_health(proxy.connect_timeout).await;
+            }
+        });
+    }
+}
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    let config: ProxyConfig = match ConfigLoader::new("TCP_PROXY")
+        .args(env::args().skip(1))
+        .load()
+    {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            eprintln!(
+                "usage: tcp_proxy --upstreams '[<addr>, ...]' [--listen <addr>] \
+                 [--strategy round-robin|least-conn] [--connect-timeout-ms <ms>] \
+                 [--health-interval-ms <ms>]"
+            );
+            std::process::exit(2);
+        }
+    };
+
+    let listener = TcpListener::bind(config.listen).await?;
+    println!(
+        "tcp proxy listening on {}, upstreams={:?}, strategy={:?}",
+        listener.local_addr()?,
+        config.upstreams,
+        config.strategy
+    );
+
+    let proxy = Arc::new(Proxy::new(&config));
+    proxy.spawn_health_check(config.health_interval());
+    proxy.serve(listener).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // 和 _05_io.rs 里的 echo_server 一样，不过会在回显前面加上自己的标记
+    async fn spawn_echo(tag: &'static str) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut buf = vec![0; 1024];
+                    loop {
+                        match socket.read(&mut buf).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => {
+                                let mut out = tag.as_bytes().to_vec();
+                                out.extend_from_slice(&buf[..n]);
+                                if socket.write_all(&out).await.is_err() {
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    async fn spawn_proxy(
+        upstreams: Vec<SocketAddr>,
+        strategy: Strategy,
+    ) -> (SocketAddr, Arc<Proxy>) {
+        let config = ProxyConfig {
+            listen: "127.0.0.1:0".parse().unwrap(),
+            upstreams,
+            strategy,
+            connect_timeout_ms: 200,
+            health_interval_ms: 50,
+        };
+        let listener = TcpListener::bind(config.listen).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let proxy = Arc::new(Proxy::new(&config));
+        tokio::spawn(proxy.clone().serve(listener));
+        (addr, proxy)
+    }
+
+    async fn round_trip(proxy: SocketAddr, msg: &[u8]) -> String {
+        let mut socket = TcpStream::connect(proxy).await.unwrap();
+        socket.write_all(msg).await.unwrap();
+        let mut buf = vec![0; 128];
+        let n = socket.read(&mut buf).await.unwrap();
+        String::from_utf8_lossy(&buf[..n]).to_string()
+    }
+
+    #[test]
+    fn parse_args() {
+        let load = |args: &[&str]| {
+            ConfigLoader::new("TCP_PROXY")
+                .vars(Vec::new())
+                .args(args.iter().map(|s| s.to_string()))
+                .load::<ProxyConfig>()
+        };
+        let config = load(&[
+            "--listen",
+            "0.0.0.0:9000",
+            "--upstreams",
+            "[127.0.0.1:7001, 127.0.0.1:7002]",
+            "--strategy",
+            "least-conn",
+        ])
+        .unwrap();
+        assert_eq!(config.listen, "0.0.0.0:9000".parse().unwrap());
+        assert_eq!(config.upstreams.len(), 2);
+        assert_eq!(config.strategy, Strategy::LeastConn);
+        assert_eq!(config.connect_timeout(), Duration::from_millis(1000));
+        let config = load(&["--upstreams", "[127.0.0.1:7001]", "--strategy", "rr"]).unwrap();
+        assert_eq!(config.strategy, Strategy::RoundRobin);
+
+        assert!(load(&[]).is_err());
+        assert!(load(&["--upstreams", "[127.0.0.1:7001]", "--strategy", "random"]).is_err());
+        assert!(load(&["--upstream", "127.0.0.1:7001"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn round_robin_forwarding() {
+        let a = spawn_echo("a:").await;
+        let b = spawn_echo("b:").await;
+        let (proxy_addr, proxy) = spawn_proxy(vec![a, b], Strategy::RoundRobin).await;
+
+        assert_eq!(round_trip(proxy_addr, b"hello").await, "a:hello");
+        assert_eq!(round_trip(proxy_addr, b"hello").await, "b:hello");
+        assert_eq!(round_trip(proxy_addr, b"hello").await, "a:hello");
+
+        // 连接关闭后字节数才会累加上去
+        time::sleep(Duration::from_millis(50)).await;
+        let first = &proxy.pool.upstreams[0];
+        assert_eq!(first.bytes_sent.load(Ordering::Relaxed), 10);
+        assert_eq!(first.bytes_received.load(Ordering::Relaxed), 14);
+    }
+
+    #[tokio::test]
+    async fn counts_partial_transfer() {
+        let (client, mut server) = io::duplex(64);
+        let mut counted = Counted::new(client);
+        server.write_all(b"partial").await.unwrap();
+        drop(server);
+
+        let mut buf = Vec::new();
+        counted.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(counted.read, 7);
+        // 对端已经关了，写会失败，但前面读到的字节数还在
+        assert!(counted.write_all(b"x").await.is_err());
+        assert_eq!(counted.read, 7);
+    }
+
+    #[test]
+    fn least_conn_pick() {
+        let addrs: Vec<SocketAddr> = vec![
+            "127.0.0.1:7001".parse().unwrap(),
+            "127.0.0.1:7002".parse().unwrap(),
+        ];
+        let pool = UpstreamPool::new(&addrs, Strategy::LeastConn);
+
+        let first = pool.pick(&[]).unwrap();
+        assert_eq!(first.addr, addrs[0]);
+        let _guard = ActiveGuard::new(first);
+
+        // 第一个上游有一条活跃连接，应该选第二个
+        assert_eq!(pool.pick(&[]).unwrap().addr, addrs[1]);
+        // 排除掉第二个以后只剩第一个
+        assert_eq!(pool.pick(&addrs[1..]).unwrap().addr, addrs[0]);
+    }
+
+    #[tokio::test]
+    async fn failover_and_health_check() {
+        let a = spawn_echo("a:").await;
+        // 先占一个端口再释放，拿到一个大概率没人监听的地址
+        let dead = {
+            let l = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            l.local_addr().unwrap()
+        };
+        let (proxy_addr, proxy) = spawn_proxy(vec![dead, a], Strategy::RoundRobin).await;
+
+        // 第一次轮到 dead，连接失败后自动换到 a
+        assert_eq!(round_trip(proxy_addr, b"x").await, "a:x");
+        assert!(!proxy.pool.upstreams[0].is_healthy());
+
+        proxy.pool.check_health(Duration::from_millis(200)).await;
+        assert!(!proxy.pool.upstreams[0].is_healthy());
+        assert!(proxy.pool.upstreams[1].is_healthy());
+
+        // 所有上游都挂掉时直接关闭客户端连接
+        proxy.pool.upstreams[1]
+            .healthy
+            .store(false, Ordering::Relaxed);
+        assert!(proxy.connect_upstream().await.is_err());
+    }
+}
@@ -35,6 +35,22 @@ impl Connection {
         }
     }
 
+    /// Returns `true` if the peer already closed the connection, or sent data
+    /// nobody asked for. Never waits; a pooled connection is checked with this
+    /// before a request is written to it.
+    pub fn is_stale(&self) -> bool {
+        use futures::FutureExt;
+
+        let mut byte = [0u8; 1];
+        !self.buffer.is_empty()
+            || self
+                .stream
+                .get_ref()
+                .peek(&mut byte)
+                .now_or_never()
+                .is_some()
+    }
+
     /// Read a single `Frame` value from the underlying stream.
     ///
     /// The function waits until it has retrieved enough data to parse a frame.
@@ -0,0 +1,411 @@
+// tail -F 风格的异步文件跟踪
+// _05_io.rs 里都是 read_to_end 一次性读完，这里改成：
+// 1. 按行产出一个 Stream，文件增长时继续产出新行
+// 2. 最后一行没写完(没有 \n)时先缓存着，等写完整了再产出
+// 3. 日志轮转：rename + 重新创建(文件 inode 变了) 以及 truncate 都能接上；
+//    rename 之前先把旧文件读到末尾，轮转前刚写进去的行不会丢；
+//    truncate 看文件有没有变短，还要看开头的内容有没有变，截断以后又写得比原来还长也能发现
+// 4. 可以从头、从尾或者从指定偏移开始读
+
+use async_stream::try_stream;
+use futures::Stream;
+use std::collections::VecDeque;
+use std::fs::Metadata;
+use std::io::SeekFrom;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::fs::{self, File};
+use tokio::io::{self, AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, BufReader};
+use tokio::time::sleep;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartFrom {
+    Beginning,
+    End,
+    Offset(u64),
+}
+
+#[derive(Debug, Clone)]
+pub struct TailOptions {
+    pub start: StartFrom,
+    // 读到文件末尾以后隔多久再看一次
+    pub poll_interval: Duration,
+}
+
+impl Default for TailOptions {
+    fn default() -> Self {
+        TailOptions {
+            start: StartFrom::End,
+            poll_interval: Duration::from_millis(250),
+        }
+    }
+}
+
+// 记住文件开头这么多字节，用来发现截断以后又重新写了的情况
+const HEAD_LEN: usize = 64;
+
+// 用 (dev, inode) 判断路径指向的还是不是同一个文件
+#[cfg(unix)]
+fn file_id(meta: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+// 非 unix 平台拿不到 inode，只能靠文件变短来发现轮转
+#[cfg(not(unix))]
+fn file_id(_meta: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+struct Tailer {
+    path: PathBuf,
+    options: TailOptions,
+    reader: Option<BufReader<File>>,
+    id: Option<(u64, u64)>,
+    // 当前文件已经消费到的位置
+    pos: u64,
+    // 还没遇到 \n 的半行
+    partial: Vec<u8>,
+    // 文件开头的内容，最多 HEAD_LEN 字节
+    head: Vec<u8>,
+    // 轮转时从旧文件里读出来、还没产出的行
+    pending: VecDeque<String>,
+}
+
+impl Tailer {
+    fn new(path: PathBuf, options: TailOptions) -> Self {
+        Tailer {
+            path,
+            options,
+            reader: None,
+            id: None,
+            pos: 0,
+            partial: Vec::new(),
+            head: Vec::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    // 打开文件并定位，文件不存在时返回 Ok(false)，调用方稍后重试
+    async fn open(&mut self, start: StartFrom) -> io::Result<bool> {
+        let mut file = match File::open(&self.path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        let meta = file.metadata().await?;
+        let pos = match start {
+            StartFrom::Beginning => 0,
+            StartFrom::End => meta.len(),
+            StartFrom::Offset(offset) => offset.min(meta.len()),
+        };
+        file.seek(SeekFrom::Start(pos)).await?;
+
+        self.reader = Some(BufReader::new(file));
+        self.id = file_id(&meta);
+        self.pos = pos;
+        self.partial.clear();
+        self.head = self.read_head().await?;
+        Ok(true)
+    }
+
+    // 单独打开一次读文件开头，不动 reader 的位置
+    async fn read_head(&self) -> io::Result<Vec<u8>> {
+        let mut head = Vec::with_capacity(HEAD_LEN);
+        match File::open(&self.path).await {
+            Ok(file) => {
+                file.take(HEAD_LEN as u64).read_to_end(&mut head).await?;
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(head)
+    }
+
+    // 从当前文件读出一整行，读到末尾还没有完整的行时返回 None
+    async fn read_line(&mut self) -> io::Result<Option<String>> {
+        let Some(reader) = self.reader.as_mut() else {
+            return Ok(None);
+        };
+        let n = reader.read_until(b'\n', &mut self.partial).await?;
+        self.pos += n as u64;
+        if self.partial.last() == Some(&b'\n') {
+            Ok(Some(self.take_partial()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn take_partial(&mut self) -> String {
+        let mut line = std::mem::take(&mut self.partial);
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        }
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        String::from_utf8_lossy(&line).into_owned()
+    }
+
+    // 已经读到当前文件末尾时检查是否发生了轮转，旧文件里剩下的行放进 pending
+    async fn check_rotation(&mut self) -> io::Result<()> {
+        if self.reader.is_none() {
+            // 启动时文件还不存在，等它出现以后从头开始读，和 tail -F 一样
+            self.open(StartFrom::Beginning).await?;
+            return Ok(());
+        }
+
+        let meta = match fs::metadata(&self.path).await {
+            Ok(meta) => meta,
+            // 已经被 rename 走了但是新文件还没创建，继续等
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        if self.id.is_some() && file_id(&meta) != self.id {
+            // rename + 重新创建：上次读到末尾以后旧文件可能又写了几行，先读完，
+            // 最后的半行轮转后就不会再写完了，也一起产出
+            while let Some(line) = self.read_line().await? {
+                self.pending.push_back(line);
+            }
+            if !self.partial.is_empty() {
+                let rest = self.take_partial();
+                self.pending.push_back(rest);
+            }
+            if self.open(StartFrom::Beginning).await? {
+                println!("{} rotated, reopened", self.path.display());
+            }
+            return Ok(());
+        }
+
+        // copytruncate：同一个文件被截断了，从头开始读；
+        // 截断以后写回去的比原来还长时文件没变短，但开头的内容变了
+        let head = self.read_head().await?;
+        let rewritten = !head.starts_with(&self.head) && !self.head.starts_with(&head);
+        if meta.len() < self.pos || rewritten {
+            println!("{} truncated, seek to start", self.path.display());
+            if let Some(reader) = self.reader.as_mut() {
+                reader.seek(SeekFrom::Start(0)).await?;
+            }
+            self.pos = 0;
+            self.partial.clear();
+        }
+        // 文件还不到 HEAD_LEN 的时候开头会越写越长
+        if head.len() >= self.head.len() {
+            self.head = head;
+        }
+        Ok(())
+    }
+
+    // 缓冲区里已经有完整的一行时直接读，不用碰文件
+    fn has_buffered_line(&self) -> bool {
+        self.reader
+            .as_ref()
+            .is_some_and(|r| r.buffer().contains(&b'\n'))
+    }
+
+    async fn next_line(&mut self) -> io::Result<String> {
+        loop {
+            if let Some(line) = self.pending.pop_front() {
+                return Ok(line);
+            }
+            // 要从文件里读新数据之前先看一眼有没有轮转，不然截断后重写的内容会从旧位置接着读
+            if !self.has_buffered_line() {
+                self.check_rotation().await?;
+                if let Some(line) = self.pending.pop_front() {
+                    return Ok(line);
+                }
+            }
+            if let Some(line) = self.read_line().await? {
+                return Ok(line);
+            }
+            sleep(self.options.poll_interval).await;
+        }
+    }
+}
+
+/// 跟踪 `path`，文件每多出一行就产出一行(不含换行符)，永远不会主动结束
+///
+/// 起始位置在这里就已经定好了，而不是等第一次 poll 的时候
+pub async fn tail_lines(
+    path: impl Into<PathBuf>,
+    options: TailOptions,
+) -> io::Result<impl Stream<Item = io::Result<String>>> {
+    let mut tailer = Tailer::new(path.into(), options);
+    let start = tailer.options.start;
+    tailer.open(start).await?;
+    Ok(try_stream! {
+        loop {
+            let line = tailer.next_line().await?;
+            yield line;
+        }
+    })
+}
+
+fn temp_log(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("dep_async_tail_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn append(path: &PathBuf, data: &str) {
+    use std::io::Write;
+    let mut f = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .unwrap();
+    f.write_all(data.as_bytes()).unwrap();
+}
+
+fn fast() -> TailOptions {
+    TailOptions {
+        start: StartFrom::Beginning,
+        poll_interval: Duration::from_millis(10),
+    }
+}
+
+async fn next<S: Stream<Item = io::Result<String>> + Unpin>(stream: &mut S) -> String {
+    use tokio_stream::StreamExt;
+    tokio::time::timeout(Duration::from_secs(2), stream.next())
+        .await
+        .expect("no line within 2s")
+        .unwrap()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn tail_growing_file_with_partial_line() {
+    let path = temp_log("grow.log");
+    append(&path, "first\r\nsecond\nthi");
+
+    let stream = tail_lines(&path, fast()).await.unwrap();
+    tokio::pin!(stream);
+    assert_eq!(next(&mut stream).await, "first");
+    assert_eq!(next(&mut stream).await, "second");
+
+    // 半行 "thi" 要等 \n 写进来才会产出
+    append(&path, "rd\nfourth\n");
+    assert_eq!(next(&mut stream).await, "third");
+    assert_eq!(next(&mut stream).await, "fourth");
+}
+
+#[tokio::test]
+async fn tail_start_from_end_and_offset() {
+    let path = temp_log("start.log");
+    append(&path, "old-1\nold-2\n");
+
+    let options = TailOptions {
+        start: StartFrom::End,
+        ..fast()
+    };
+    let from_end = tail_lines(&path, options).await.unwrap();
+    tokio::pin!(from_end);
+
+    let options = TailOptions {
+        start: StartFrom::Offset(6),
+        ..fast()
+    };
+    let from_offset = tail_lines(&path, options).await.unwrap();
+    tokio::pin!(from_offset);
+    assert_eq!(next(&mut from_offset).await, "old-2");
+
+    append(&path, "new\n");
+    assert_eq!(next(&mut from_end).await, "new");
+    assert_eq!(next(&mut from_offset).await, "new");
+}
+
+#[tokio::test]
+async fn tail_survives_truncate() {
+    let path = temp_log("truncate.log");
+    append(&path, "before-1\nbefore-2\n");
+
+    let stream = tail_lines(&path, fast()).await.unwrap();
+    tokio::pin!(stream);
+    assert_eq!(next(&mut stream).await, "before-1");
+    assert_eq!(next(&mut stream).await, "before-2");
+
+    std::fs::write(&path, "").unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    append(&path, "after\n");
+    assert_eq!(next(&mut stream).await, "after");
+}
+
+#[tokio::test]
+async fn tail_detects_truncate_then_longer_rewrite() {
+    let path = temp_log("rewrite.log");
+    append(&path, "one\n");
+
+    let stream = tail_lines(&path, fast()).await.unwrap();
+    tokio::pin!(stream);
+    assert_eq!(next(&mut stream).await, "one");
+
+    // 截断后一次写得比原来还长，光看长度发现不了
+    std::fs::write(&path, "rewritten-1\nrewritten-2\n").unwrap();
+    assert_eq!(next(&mut stream).await, "rewritten-1");
+    assert_eq!(next(&mut stream).await, "rewritten-2");
+}
+
+#[tokio::test]
+async fn tail_survives_rename_and_recreate() {
+    let path = temp_log("rotate.log");
+    let rotated = path.with_extension("log.1");
+    append(&path, "a\n");
+
+    let stream = tail_lines(&path, fast()).await.unwrap();
+    tokio::pin!(stream);
+    assert_eq!(next(&mut stream).await, "a");
+
+    // 旧文件最后的半行在轮转后也要吐出来
+    append(&path, "tail-of-old");
+    std::fs::rename(&path, &rotated).unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    append(&path, "b\n");
+
+    assert_eq!(next(&mut stream).await, "tail-of-old");
+    assert_eq!(next(&mut stream).await, "b");
+    let _ = std::fs::remove_file(rotated);
+}
+
+#[tokio::test]
+async fn rotation_drains_old_file_first() {
+    let path = temp_log("drain.log");
+    let rotated = path.with_extension("log.1");
+    append(&path, "a\n");
+
+    let mut tailer = Tailer::new(path.clone(), fast());
+    tailer.open(StartFrom::Beginning).await.unwrap();
+    assert_eq!(tailer.read_line().await.unwrap().as_deref(), Some("a"));
+    assert_eq!(tailer.read_line().await.unwrap(), None);
+
+    // 上次读到末尾以后、发现轮转之前，旧文件又写了几行
+    append(&path, "late-1\nlate-2\nhalf");
+    std::fs::rename(&path, &rotated).unwrap();
+    append(&path, "new\n");
+
+    tailer.check_rotation().await.unwrap();
+    assert_eq!(tailer.pending, ["late-1", "late-2", "half"]);
+    assert_eq!(tailer.next_line().await.unwrap(), "late-1");
+    tailer.pending.clear();
+    assert_eq!(tailer.next_line().await.unwrap(), "new");
+    let _ = std::fs::remove_file(rotated);
+}
+
+#[tokio::test]
+async fn tail_waits_for_missing_file() {
+    let path = temp_log("missing.log");
+
+    let stream = tail_lines(&path, fast()).await.unwrap();
+    tokio::pin!(stream);
+
+    tokio::spawn({
+        let path = path.clone();
+        async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            append(&path, "created\n");
+        }
+    });
+    assert_eq!(next(&mut stream).await, "created");
+}
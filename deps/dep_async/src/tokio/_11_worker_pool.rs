@@ -0,0 +1,278 @@
+// 带背压的有界 worker 池
+// _04_channel.rs 的 channel_learn 只有一个 manager 在消费 Command，这里推广成 N 个 worker：
+// 1. 每个 worker 一个有界 mpsc，队列满了 submit 会等待(背压)，try_submit 直接返回 Full
+// 2. 同一个 key 按 hash 固定落到同一个 worker，和 _03 里分片 db 的 hash() 一样，保证同 key 有序
+// 3. shutdown 时关掉所有发送端，worker 把队列里剩下的处理完才退出
+// 4. 可以随时拿到每个 worker 的队列深度、已处理数、失败数
+// 5. worker 个数和队列容量都不能是 0，配置不对 new 直接返回错误
+
+use futures::FutureExt;
+use std::collections::hash_map::DefaultHasher;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::{SendError, TrySendError};
+use tokio::task::JoinHandle;
+
+fn hash<T: Hash + ?Sized>(t: &T) -> u64 {
+    let mut s = DefaultHasher::new();
+    t.hash(&mut s);
+    s.finish()
+}
+
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    // worker 个数，也就是最大并发
+    pub workers: usize,
+    // 每个 worker 的队列容量
+    pub queue_capacity: usize,
+}
+
+impl PoolConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.workers == 0 {
+            return Err("worker pool needs at least one worker".to_string());
+        }
+        // mpsc::channel(0) 会 panic
+        if self.queue_capacity == 0 {
+            return Err("worker pool queue_capacity must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            workers: 4,
+            queue_capacity: 32,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    processed: AtomicU64,
+    panicked: AtomicU64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolMetrics {
+    // 下标就是 worker 编号
+    pub queue_depth: Vec<usize>,
+    pub processed: u64,
+    pub panicked: u64,
+}
+
+impl PoolMetrics {
+    pub fn total_queued(&self) -> usize {
+        self.queue_depth.iter().sum()
+    }
+}
+
+pub struct WorkerPool<T> {
+    senders: Vec<mpsc::Sender<T>>,
+    workers: Vec<JoinHandle<()>>,
+    counters: Arc<Counters>,
+}
+
+impl<T: Send + 'static> WorkerPool<T> {
+    pub fn new<F, Fut>(config: PoolConfig, handler: F) -> Result<Self, String>
+    where
+        F: Fn(T) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        config.validate()?;
+        let handler = Arc::new(handler);
+        let counters = Arc::new(Counters::default());
+
+        let mut senders = Vec::with_capacity(config.workers);
+        let mut workers = Vec::with_capacity(config.workers);
+        for id in 0..config.workers {
+            let (tx, mut rx) = mpsc::channel::<T>(config.queue_capacity);
+            let handler = handler.clone();
+            let counters = counters.clone();
+            workers.push(tokio::spawn(async move {
+                // 发送端全部 drop 以后 recv 会先把缓冲区里的取完再返回 None
+                while let Some(item) = rx.recv().await {
+                    // handler panic 不能把整个 worker 带走，否则这个分片上的 key 全都卡住
+                    match AssertUnwindSafe(handler(item)).catch_unwind().await {
+                        Ok(()) => counters.processed.fetch_add(1, Ordering::Relaxed),
+                        Err(_) => {
+                            println!("worker {} handler panicked", id);
+                            counters.panicked.fetch_add(1, Ordering::Relaxed)
+                        }
+                    };
+                }
+            }));
+            senders.push(tx);
+        }
+
+        Ok(WorkerPool {
+            senders,
+            workers,
+            counters,
+        })
+    }
+
+    fn sender_for<K: Hash + ?Sized>(&self, key: &K) -> &mpsc::Sender<T> {
+        let index = (hash(key) % self.senders.len() as u64) as usize;
+        &self.senders[index]
+    }
+
+    /// 队列满了会一直等到有空位，这就是背压
+    pub async fn submit<K: Hash + ?Sized>(&self, key: &K, item: T) -> Result<(), SendError<T>> {
+        self.sender_for(key).send(item).await
+    }
+
+    /// 队列满了立刻返回 `TrySendError::Full`，由调用方决定丢弃还是重试
+    pub fn try_submit<K: Hash + ?Sized>(&self, key: &K, item: T) -> Result<(), TrySendError<T>> {
+        self.sender_for(key).try_send(item)
+    }
+
+    pub fn metrics(&self) -> PoolMetrics {
+        PoolMetrics {
+            queue_depth: self
+                .senders
+                .iter()
+                .map(|tx| tx.max_capacity() - tx.capacity())
+                .collect(),
+            processed: self.counters.processed.load(Ordering::Relaxed),
+            panicked: self.counters.panicked.load(Ordering::Relaxed),
+        }
+    }
+
+    /// 不再接收新任务，等所有已经入队的任务处理完
+    pub async fn shutdown(self) -> PoolMetrics {
+        let WorkerPool {
+            senders,
+            workers,
+            counters,
+        } = self;
+        drop(senders);
+        for worker in workers {
+            let _ = worker.await;
+        }
+        PoolMetrics {
+            queue_depth: Vec::new(),
+            processed: counters.processed.load(Ordering::Relaxed),
+            panicked: counters.panicked.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[tokio::test]
+async fn worker_pool_keeps_order_per_key() {
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    let seen: Arc<Mutex<Vec<(String, u64)>>> = Arc::new(Mutex::new(Vec::new()));
+    let pool = WorkerPool::new(
+        PoolConfig {
+            workers: 3,
+            queue_capacity: 8,
+        },
+        {
+            let seen = seen.clone();
+            move |(key, seq): (String, u64)| {
+                let seen = seen.clone();
+                async move {
+                    // 前面的任务睡得更久，如果不是按 key 串行，后面的就会先完成
+                    tokio::time::sleep(Duration::from_millis(5 - seq)).await;
+                    seen.lock().unwrap().push((key, seq));
+                }
+            }
+        },
+    )
+    .unwrap();
+
+    for seq in 0..5 {
+        for key in ["a", "b", "c", "d"] {
+            pool.submit(key, (key.to_string(), seq)).await.unwrap();
+        }
+    }
+    let metrics = pool.shutdown().await;
+    assert_eq!(metrics.processed, 20);
+
+    let seen = seen.lock().unwrap();
+    for key in ["a", "b", "c", "d"] {
+        let seqs: Vec<u64> = seen
+            .iter()
+            .filter(|(k, _)| k == key)
+            .map(|(_, s)| *s)
+            .collect();
+        assert_eq!(seqs, vec![0, 1, 2, 3, 4], "key {}", key);
+    }
+}
+
+#[tokio::test]
+async fn worker_pool_backpressure_and_metrics() {
+    use tokio::sync::Semaphore;
+
+    // 用信号量卡住 worker，让队列堆起来
+    let gate = Arc::new(Semaphore::new(0));
+    let pool = WorkerPool::new(
+        PoolConfig {
+            workers: 1,
+            queue_capacity: 2,
+        },
+        {
+            let gate = gate.clone();
+            move |_: u32| {
+                let gate = gate.clone();
+                async move {
+                    gate.acquire().await.unwrap().forget();
+                }
+            }
+        },
+    )
+    .unwrap();
+
+    // 第一个被 worker 取走卡在 handler 里，后两个占满队列
+    pool.try_submit("k", 1).unwrap();
+    tokio::task::yield_now().await;
+    pool.try_submit("k", 2).unwrap();
+    pool.try_submit("k", 3).unwrap();
+    assert!(matches!(
+        pool.try_submit("k", 4),
+        Err(TrySendError::Full(4))
+    ));
+    assert_eq!(pool.metrics().queue_depth, vec![2]);
+
+    gate.add_permits(3);
+    let metrics = pool.shutdown().await;
+    assert_eq!(metrics.processed, 3);
+}
+
+#[tokio::test]
+async fn worker_pool_survives_panicking_handler() {
+    let pool = WorkerPool::new(PoolConfig::default(), |n: u32| async move {
+        if n.is_multiple_of(2) {
+            panic!("bad item {}", n);
+        }
+    })
+    .unwrap();
+    for n in 0..10 {
+        pool.submit(&n, n).await.unwrap();
+    }
+    let metrics = pool.shutdown().await;
+    assert_eq!(metrics.processed, 5);
+    assert_eq!(metrics.panicked, 5);
+}
+
+#[tokio::test]
+async fn worker_pool_rejects_invalid_config() {
+    let config = PoolConfig {
+        workers: 2,
+        queue_capacity: 0,
+    };
+    assert!(WorkerPool::new(config, |_: u32| async {}).is_err());
+    let config = PoolConfig {
+        workers: 0,
+        ..PoolConfig::default()
+    };
+    assert!(WorkerPool::new(config, |_: u32| async {}).is_err());
+}
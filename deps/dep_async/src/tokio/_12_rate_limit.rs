@@ -0,0 +1,649 @@
+// 限流：令牌桶 / 漏桶 / 滑动窗口日志
+// 1. 三种算法都只是一个纯状态机 decide(state, now_ms, permits)，不关心时间从哪来、状态存在哪
+// 2. LocalRateLimiter：状态放在进程内的 HashMap，acquire 会一直等到拿到许可
+// 3. RespRateLimiter：状态放在 _15_resp_server 的 key-value 服务端上，服务端多出一个 RL.ACQUIRE 命令，
+//    在同一把锁里完成 "读状态 -> 计算 -> 写状态"，多个进程共享同一份限额
+//    (思路和 redis-cell 的 CL.THROTTLE 一样，算法参数每次由客户端带上)；服务端重启以后自动重连，见 _16_resp_client
+// 4. 状态按 (算法, key) 存，同一个 key 换了算法互不影响；已经恢复到初始额度的状态定期清掉，map 不会一直涨
+// 5. 算法参数在创建限流器时校验，速率、容量、窗口都必须是正数
+
+use crate::tokio::_16_resp_client::RespConnection;
+use async_trait::async_trait;
+use bytes::Bytes;
+use mini_redis::Frame;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::{Instant, sleep};
+
+// 多久清一次空闲状态
+const SWEEP_INTERVAL_MS: u64 = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    // 桶里最多 capacity 个令牌，每秒补 refill_per_sec 个，允许突发
+    TokenBucket { capacity: u32, refill_per_sec: f64 },
+    // 每个请求往桶里加水，每秒漏掉 leak_per_sec，水满了就拒绝
+    LeakyBucket { capacity: u32, leak_per_sec: f64 },
+    // 记录 window 内每一次许可的时间戳，精确但是占内存
+    SlidingWindowLog { limit: u32, window: Duration },
+}
+
+impl Algorithm {
+    pub fn limit(&self) -> u32 {
+        match *self {
+            Algorithm::TokenBucket { capacity, .. } => capacity,
+            Algorithm::LeakyBucket { capacity, .. } => capacity,
+            Algorithm::SlidingWindowLog { limit, .. } => limit,
+        }
+    }
+
+    /// 容量 / 次数至少 1，速率和窗口必须是有限的正数
+    pub fn validate(&self) -> Result<(), String> {
+        if self.limit() == 0 {
+            return Err(format!("{} limit must be at least 1", self.name()));
+        }
+        let rate = self.rate_arg();
+        if !rate.is_finite() || rate <= 0.0 {
+            return Err(format!(
+                "{} rate must be positive, got {}",
+                self.name(),
+                rate
+            ));
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Algorithm::TokenBucket { .. } => "token_bucket",
+            Algorithm::LeakyBucket { .. } => "leaky_bucket",
+            Algorithm::SlidingWindowLog { .. } => "sliding_window_log",
+        }
+    }
+
+    // 第二个参数：两种桶是每秒速率，滑动窗口是窗口毫秒数
+    fn rate_arg(&self) -> f64 {
+        match *self {
+            Algorithm::TokenBucket { refill_per_sec, .. } => refill_per_sec,
+            Algorithm::LeakyBucket { leak_per_sec, .. } => leak_per_sec,
+            Algorithm::SlidingWindowLog { window, .. } => window.as_millis() as f64,
+        }
+    }
+
+    fn from_parts(name: &str, limit: u32, rate: f64) -> Option<Algorithm> {
+        match name {
+            "token_bucket" => Some(Algorithm::TokenBucket {
+                capacity: limit,
+                refill_per_sec: rate,
+            }),
+            "leaky_bucket" => Some(Algorithm::LeakyBucket {
+                capacity: limit,
+                leak_per_sec: rate,
+            }),
+            "sliding_window_log" => Some(Algorithm::SlidingWindowLog {
+                limit,
+                window: Duration::from_millis(rate as u64),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// 一次限流判断的结果，字段和 RateLimit-* 响应头一一对应
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    // 被拒绝时多久以后再试有可能成功，允许时为 0
+    pub retry_after: Duration,
+    // 多久以后额度完全恢复
+    pub reset_after: Duration,
+}
+
+#[derive(Debug)]
+enum State {
+    // 令牌桶里是剩余令牌数，漏桶里是当前水位
+    Bucket { level: f64, last_ms: u64 },
+    Log(VecDeque<u64>),
+}
+
+impl State {
+    fn new(algorithm: &Algorithm, now_ms: u64) -> State {
+        match *algorithm {
+            Algorithm::TokenBucket { capacity, .. } => State::Bucket {
+                level: capacity as f64,
+                last_ms: now_ms,
+            },
+            Algorithm::LeakyBucket { .. } => State::Bucket {
+                level: 0.0,
+                last_ms: now_ms,
+            },
+            Algorithm::SlidingWindowLog { .. } => State::Log(VecDeque::new()),
+        }
+    }
+
+    // 已经恢复到和新建的一样了，删掉也不影响结果
+    fn is_idle(&self, algorithm: &Algorithm, now_ms: u64) -> bool {
+        match (algorithm, self) {
+            (
+                Algorithm::TokenBucket {
+                    capacity,
+                    refill_per_sec,
+                },
+                State::Bucket { level, last_ms },
+            ) => {
+                let elapsed = now_ms.saturating_sub(*last_ms) as f64 / 1000.0;
+                level + elapsed * refill_per_sec >= *capacity as f64
+            }
+            (Algorithm::LeakyBucket { leak_per_sec, .. }, State::Bucket { level, last_ms }) => {
+                let elapsed = now_ms.saturating_sub(*last_ms) as f64 / 1000.0;
+                level - elapsed * leak_per_sec <= 0.0
+            }
+            (Algorithm::SlidingWindowLog { window, .. }, State::Log(log)) => log
+                .back()
+                .is_none_or(|t| t + window.as_millis() as u64 <= now_ms),
+            _ => true,
+        }
+    }
+}
+
+/// 所有 key 的限流状态，LocalRateLimiter 和服务端的 RL.ACQUIRE 共用
+#[derive(Debug)]
+pub(crate) struct States {
+    start: Instant,
+    // 同一个 key 在不同算法下的状态含义不一样(令牌桶的 level 是剩余令牌，漏桶的是水位)，分开存
+    map: HashMap<(&'static str, String), (Algorithm, State)>,
+    last_sweep_ms: u64,
+}
+
+impl States {
+    pub(crate) fn new() -> Self {
+        States {
+            start: Instant::now(),
+            map: HashMap::new(),
+            last_sweep_ms: 0,
+        }
+    }
+
+    pub(crate) fn acquire(
+        &mut self,
+        algorithm: &Algorithm,
+        key: &str,
+        permits: u32,
+    ) -> mini_redis::Result<Decision> {
+        let now_ms = self.start.elapsed().as_millis() as u64;
+        if now_ms >= self.last_sweep_ms + SWEEP_INTERVAL_MS {
+            self.sweep(now_ms);
+        }
+        let (current, state) = self
+            .map
+            .entry((algorithm.name(), key.to_string()))
+            .or_insert_with(|| (*algorithm, State::new(algorithm, now_ms)));
+        // 参数(容量、速率)可能变了，清理时按最新的算
+        *current = *algorithm;
+        decide(algorithm, state, now_ms, permits)
+    }
+
+    fn sweep(&mut self, now_ms: u64) {
+        self.map
+            .retain(|_, (algorithm, state)| !state.is_idle(algorithm, now_ms));
+        self.last_sweep_ms = now_ms;
+    }
+}
+
+fn ms(v: f64) -> Duration {
+    Duration::from_millis(v.max(0.0).ceil() as u64)
+}
+
+fn decide(
+    algorithm: &Algorithm,
+    state: &mut State,
+    now_ms: u64,
+    permits: u32,
+) -> mini_redis::Result<Decision> {
+    let limit = algorithm.limit();
+    if permits > limit {
+        return Err(format!("permits {} exceed limit {}", permits, limit).into());
+    }
+    let want = permits as f64;
+
+    let decision = match (algorithm, state) {
+        (
+            Algorithm::TokenBucket {
+                capacity,
+                refill_per_sec,
+            },
+            State::Bucket { level, last_ms },
+        ) => {
+            let capacity = *capacity as f64;
+            let elapsed = now_ms.saturating_sub(*last_ms) as f64 / 1000.0;
+            let mut tokens = (*level + elapsed * refill_per_sec).min(capacity);
+            let allowed = tokens >= want;
+            let retry_after = if allowed {
+                tokens -= want;
+                Duration::ZERO
+            } else {
+                ms((want - tokens) / refill_per_sec * 1000.0)
+            };
+            *level = tokens;
+            *last_ms = now_ms;
+            Decision {
+                allowed,
+                limit,
+                remaining: tokens.floor() as u32,
+                retry_after,
+                reset_after: ms((capacity - tokens) / refill_per_sec * 1000.0),
+            }
+        }
+        (
+            Algorithm::LeakyBucket {
+                capacity,
+                leak_per_sec,
+            },
+            State::Bucket { level, last_ms },
+        ) => {
+            let capacity = *capacity as f64;
+            let elapsed = now_ms.saturating_sub(*last_ms) as f64 / 1000.0;
+            let mut water = (*level - elapsed * leak_per_sec).max(0.0);
+            let allowed = water + want <= capacity;
+            let retry_after = if allowed {
+                water += want;
+                Duration::ZERO
+            } else {
+                ms((water + want - capacity) / leak_per_sec * 1000.0)
+            };
+            *level = water;
+            *last_ms = now_ms;
+            Decision {
+                allowed,
+                limit,
+                remaining: (capacity - water).floor() as u32,
+                retry_after,
+                reset_after: ms(water / leak_per_sec * 1000.0),
+            }
+        }
+        (Algorithm::SlidingWindowLog { window, .. }, State::Log(log)) => {
+            let window = window.as_millis() as u64;
+            while log.front().is_some_and(|t| t + window <= now_ms) {
+                log.pop_front();
+            }
+            let used = log.len() as u32;
+            let allowed = used + permits <= limit;
+            let retry_after = if allowed {
+                log.extend(std::iter::repeat_n(now_ms, permits as usize));
+                Duration::ZERO
+            } else {
+                // 要等到最老的 (used + permits - limit) 条记录都滑出窗口
+                let t = log[(used + permits - limit - 1) as usize];
+                Duration::from_millis(t + window - now_ms)
+            };
+            Decision {
+                allowed,
+                limit,
+                remaining: limit - log.len() as u32,
+                retry_after,
+                reset_after: log
+                    .back()
+                    .map(|t| Duration::from_millis(t + window - now_ms))
+                    .unwrap_or_default(),
+            }
+        }
+        // 状态按算法分开存，正常走不到这里，保险起见按新算法重新开始
+        (algorithm, state) => {
+            *state = State::new(algorithm, now_ms);
+            return decide(algorithm, state, now_ms, permits);
+        }
+    };
+    Ok(decision)
+}
+
+#[async_trait]
+pub trait RateLimiter: Send + Sync {
+    /// 立刻给出结果，不等待
+    async fn try_acquire(&self, key: &str, permits: u32) -> mini_redis::Result<Decision>;
+
+    /// 拿不到许可就按 retry_after 睡一会儿再试，直到成功
+    async fn acquire(&self, key: &str, permits: u32) -> mini_redis::Result<()> {
+        loop {
+            let decision = self.try_acquire(key, permits).await?;
+            if decision.allowed {
+                return Ok(());
+            }
+            sleep(decision.retry_after).await;
+        }
+    }
+}
+
+/// 进程内限流，时间用 tokio::time::Instant，测试里可以暂停/快进
+pub struct LocalRateLimiter {
+    algorithm: Algorithm,
+    states: Mutex<States>,
+}
+
+impl LocalRateLimiter {
+    pub fn new(algorithm: Algorithm) -> Result<Self, String> {
+        algorithm.validate()?;
+        Ok(LocalRateLimiter {
+            algorithm,
+            states: Mutex::new(States::new()),
+        })
+    }
+}
+
+#[async_trait]
+impl RateLimiter for LocalRateLimiter {
+    async fn try_acquire(&self, key: &str, permits: u32) -> mini_redis::Result<Decision> {
+        // 同步锁，不跨 await
+        self.states
+            .lock()
+            .unwrap()
+            .acquire(&self.algorithm, key, permits)
+    }
+}
+
+/// 服务端的 `RL.ACQUIRE <key> <algorithm> <limit> <rate> <permits>`，
+/// 返回 `[allowed, limit, remaining, retry_after_ms, reset_after_ms]`
+pub(crate) fn acquire_command(states: &Mutex<States>, args: &[String]) -> Frame {
+    let (key, algorithm, permits) = match parse_rl_acquire(args) {
+        Ok(parsed) => parsed,
+        Err(msg) => return Frame::Error(format!("ERR {}", msg)),
+    };
+    // 读状态、计算、写回都在这一把锁里，多个客户端并发也不会超发
+    match states.lock().unwrap().acquire(&algorithm, &key, permits) {
+        Ok(decision) => decision_to_frame(&decision),
+        Err(e) => Frame::Error(format!("ERR {}", e)),
+    }
+}
+
+fn parse_rl_acquire(args: &[String]) -> Result<(String, Algorithm, u32), String> {
+    let [_, key, name, limit, rate, permits] = args else {
+        return Err("wrong number of arguments for 'rl.acquire'".to_string());
+    };
+    let limit: u32 = limit.parse().map_err(|_| "limit is not an integer")?;
+    let rate: f64 = rate.parse().map_err(|_| "rate is not a number")?;
+    let permits: u32 = permits.parse().map_err(|_| "permits is not an integer")?;
+    let algorithm = Algorithm::from_parts(name, limit, rate)
+        .ok_or_else(|| format!("unknown algorithm {}", name))?;
+    algorithm.validate()?;
+    Ok((key.clone(), algorithm, permits))
+}
+
+fn decision_to_frame(d: &Decision) -> Frame {
+    Frame::Array(vec![
+        Frame::Integer(d.allowed as u64),
+        Frame::Integer(d.limit as u64),
+        Frame::Integer(d.remaining as u64),
+        Frame::Integer(d.retry_after.as_millis() as u64),
+        Frame::Integer(d.reset_after.as_millis() as u64),
+    ])
+}
+
+fn frame_to_decision(frame: Frame) -> mini_redis::Result<Decision> {
+    let ints = match frame {
+        Frame::Array(parts) => parts
+            .into_iter()
+            .map(|p| match p {
+                Frame::Integer(v) => Ok(v),
+                other => Err(format!("unexpected frame {:?}", other)),
+            })
+            .collect::<Result<Vec<u64>, String>>()?,
+        Frame::Error(msg) => return Err(msg.into()),
+        other => return Err(format!("unexpected frame {:?}", other).into()),
+    };
+    let [allowed, limit, remaining, retry_after, reset_after] = ints[..] else {
+        return Err(format!("unexpected response {:?}", ints).into());
+    };
+    Ok(Decision {
+        allowed: allowed == 1,
+        limit: limit as u32,
+        remaining: remaining as u32,
+        retry_after: Duration::from_millis(retry_after),
+        reset_after: Duration::from_millis(reset_after),
+    })
+}
+
+/// 状态放在 key-value 服务端，多个进程用同一个 key 就共享同一份额度
+pub struct RespRateLimiter {
+    algorithm: Algorithm,
+    // 服务端重启以后自己重连
+    connection: RespConnection,
+}
+
+impl RespRateLimiter {
+    pub async fn connect(addr: impl ToString, algorithm: Algorithm) -> mini_redis::Result<Self> {
+        algorithm.validate()?;
+        Ok(RespRateLimiter {
+            algorithm,
+            connection: RespConnection::connect(addr).await?,
+        })
+    }
+}
+
+#[async_trait]
+impl RateLimiter for RespRateLimiter {
+    async fn try_acquire(&self, key: &str, permits: u32) -> mini_redis::Result<Decision> {
+        let bulk = |s: String| Frame::Bulk(Bytes::from(s));
+        let request = Frame::Array(vec![
+            bulk("RL.ACQUIRE".to_string()),
+            bulk(key.to_string()),
+            bulk(self.algorithm.name().to_string()),
+            bulk(self.algorithm.limit().to_string()),
+            bulk(self.algorithm.rate_arg().to_string()),
+            bulk(permits.to_string()),
+        ]);
+
+        frame_to_decision(self.connection.call(&request).await?)
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn token_bucket_refills_over_time() {
+    let limiter = LocalRateLimiter::new(Algorithm::TokenBucket {
+        capacity: 2,
+        refill_per_sec: 1.0,
+    })
+    .unwrap();
+
+    assert!(limiter.try_acquire("k", 1).await.unwrap().allowed);
+    assert!(limiter.try_acquire("k", 1).await.unwrap().allowed);
+    let denied = limiter.try_acquire("k", 1).await.unwrap();
+    assert!(!denied.allowed);
+    assert_eq!(denied.remaining, 0);
+    assert_eq!(denied.retry_after, Duration::from_secs(1));
+    assert_eq!(denied.reset_after, Duration::from_secs(2));
+
+    // 其他 key 不受影响
+    assert!(limiter.try_acquire("other", 2).await.unwrap().allowed);
+
+    tokio::time::advance(Duration::from_secs(1)).await;
+    assert!(limiter.try_acquire("k", 1).await.unwrap().allowed);
+    assert!(!limiter.try_acquire("k", 1).await.unwrap().allowed);
+}
+
+#[tokio::test(start_paused = true)]
+async fn leaky_bucket_leaks_at_fixed_rate() {
+    let limiter = LocalRateLimiter::new(Algorithm::LeakyBucket {
+        capacity: 3,
+        leak_per_sec: 2.0,
+    })
+    .unwrap();
+
+    let first = limiter.try_acquire("k", 3).await.unwrap();
+    assert!(first.allowed);
+    assert_eq!(first.remaining, 0);
+    assert_eq!(first.reset_after, Duration::from_millis(1500));
+
+    let denied = limiter.try_acquire("k", 1).await.unwrap();
+    assert!(!denied.allowed);
+    assert_eq!(denied.retry_after, Duration::from_millis(500));
+
+    tokio::time::advance(Duration::from_millis(500)).await;
+    assert!(limiter.try_acquire("k", 1).await.unwrap().allowed);
+}
+
+#[tokio::test(start_paused = true)]
+async fn sliding_window_log_is_exact() {
+    let limiter = LocalRateLimiter::new(Algorithm::SlidingWindowLog {
+        limit: 3,
+        window: Duration::from_secs(1),
+    })
+    .unwrap();
+
+    assert!(limiter.try_acquire("k", 2).await.unwrap().allowed);
+    tokio::time::advance(Duration::from_millis(600)).await;
+    assert!(limiter.try_acquire("k", 1).await.unwrap().allowed);
+
+    // 前两次要到 t=1000ms 才滑出窗口
+    let denied = limiter.try_acquire("k", 1).await.unwrap();
+    assert!(!denied.allowed);
+    assert_eq!(denied.retry_after, Duration::from_millis(400));
+
+    tokio::time::advance(Duration::from_millis(400)).await;
+    let allowed = limiter.try_acquire("k", 2).await.unwrap();
+    assert!(allowed.allowed);
+    assert_eq!(allowed.remaining, 0);
+}
+
+#[tokio::test(start_paused = true)]
+async fn acquire_waits_for_permit() {
+    let limiter = LocalRateLimiter::new(Algorithm::TokenBucket {
+        capacity: 1,
+        refill_per_sec: 10.0,
+    })
+    .unwrap();
+
+    let start = Instant::now();
+    for _ in 0..5 {
+        limiter.acquire("k", 1).await.unwrap();
+    }
+    // 第一个立即拿到，后面四个每个等 100ms
+    assert_eq!(start.elapsed(), Duration::from_millis(400));
+
+    // 永远不可能满足的请求直接报错，而不是死等
+    assert!(limiter.acquire("k", 2).await.is_err());
+}
+
+#[test]
+fn invalid_algorithms_are_rejected() {
+    for algorithm in [
+        Algorithm::TokenBucket {
+            capacity: 1,
+            refill_per_sec: 0.0,
+        },
+        Algorithm::LeakyBucket {
+            capacity: 1,
+            leak_per_sec: f64::NAN,
+        },
+        Algorithm::TokenBucket {
+            capacity: 0,
+            refill_per_sec: 1.0,
+        },
+        Algorithm::SlidingWindowLog {
+            limit: 1,
+            window: Duration::ZERO,
+        },
+    ] {
+        assert!(LocalRateLimiter::new(algorithm).is_err(), "{:?}", algorithm);
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn states_are_per_algorithm_and_evicted_when_idle() {
+    let token = Algorithm::TokenBucket {
+        capacity: 2,
+        refill_per_sec: 1.0,
+    };
+    let leaky = Algorithm::LeakyBucket {
+        capacity: 2,
+        leak_per_sec: 1.0,
+    };
+    let mut states = States::new();
+    assert!(states.acquire(&token, "k", 1).unwrap().allowed);
+    // 令牌桶剩 1 个令牌，要是漏桶拿它当水位，再来 2 个就满了
+    assert!(states.acquire(&leaky, "k", 2).unwrap().allowed);
+    for i in 0..100 {
+        states.acquire(&token, &format!("client-{}", i), 1).unwrap();
+    }
+    assert_eq!(states.map.len(), 102);
+
+    // 过了清理间隔，都已经恢复满额了，下一次调用时清掉
+    tokio::time::advance(Duration::from_millis(SWEEP_INTERVAL_MS)).await;
+    states.acquire(&token, "new", 1).unwrap();
+    assert_eq!(states.map.len(), 1);
+}
+
+#[test]
+fn parse_rl_acquire_command() {
+    let args: Vec<String> = ["RL.ACQUIRE", "user:1", "token_bucket", "10", "2.5", "1"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let (key, algorithm, permits) = parse_rl_acquire(&args).unwrap();
+    assert_eq!(key, "user:1");
+    assert_eq!(
+        algorithm,
+        Algorithm::TokenBucket {
+            capacity: 10,
+            refill_per_sec: 2.5
+        }
+    );
+    assert_eq!(permits, 1);
+
+    assert!(parse_rl_acquire(&args[..5]).is_err());
+    let mut bad = args.clone();
+    bad[2] = "fixed_window".to_string();
+    assert!(parse_rl_acquire(&bad).is_err());
+
+    let decision = Decision {
+        allowed: true,
+        limit: 10,
+        remaining: 9,
+        retry_after: Duration::ZERO,
+        reset_after: Duration::from_millis(400),
+    };
+    assert_eq!(
+        frame_to_decision(decision_to_frame(&decision)).unwrap(),
+        decision
+    );
+}
+
+#[tokio::test]
+async fn resp_rate_limiter_shared_between_clients() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(crate::tokio::_15_resp_server::serve(listener));
+
+    let algorithm = Algorithm::TokenBucket {
+        capacity: 3,
+        refill_per_sec: 0.001,
+    };
+    // 模拟两个进程各自连到同一个服务端
+    let a = RespRateLimiter::connect(addr, algorithm).await.unwrap();
+    let b = RespRateLimiter::connect(addr, algorithm).await.unwrap();
+
+    let mut allowed = 0;
+    for i in 0..6 {
+        let limiter = if i % 2 == 0 { &a } else { &b };
+        if limiter.try_acquire("shared", 1).await.unwrap().allowed {
+            allowed += 1;
+        }
+    }
+    assert_eq!(allowed, 3);
+
+    let other = RespRateLimiter::connect(
+        addr,
+        Algorithm::SlidingWindowLog {
+            limit: 1,
+            window: Duration::from_secs(60),
+        },
+    )
+    .await
+    .unwrap();
+    assert!(other.try_acquire("another", 1).await.unwrap().allowed);
+    let denied = other.try_acquire("another", 1).await.unwrap();
+    assert!(!denied.allowed);
+    assert!(denied.retry_after > Duration::from_secs(59));
+    assert!(other.try_acquire("another", 2).await.is_err());
+}
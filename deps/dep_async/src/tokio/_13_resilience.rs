@@ -0,0 +1,640 @@
+// 异步调用的容错组合子
+// 前面的例子里网络错误全都是 unwrap()，这里提供：
+// 1. RetryPolicy：指数退避 + 抖动重试，每次尝试可以单独设超时
+// 2. RetryBudget：限制重试占正常请求的比例，下游挂掉时不会被重试流量再打一遍
+// 3. CircuitBreaker：closed -> open -> half-open 三态熔断，状态变化时回调 hook；
+//    调用的 Future 没跑完就被 drop(比如外面套了超时)也算一次失败，half-open 的试探名额不会一直占着
+// 它们都包装一个 "返回 Future 的闭包"，每次尝试都重新调用闭包拿一个新的 Future
+
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::{Instant, sleep, timeout};
+
+/// 被包装的调用失败的原因
+#[derive(Debug, PartialEq, Eq)]
+pub enum CallError<E> {
+    // 调用本身返回的错误
+    Inner(E),
+    // 单次尝试超时
+    Timeout(Duration),
+    // 熔断器打开，调用根本没有发出去
+    CircuitOpen,
+}
+
+impl<E> CallError<E> {
+    pub fn is_circuit_open(&self) -> bool {
+        matches!(self, CallError::CircuitOpen)
+    }
+
+    pub fn into_inner(self) -> Option<E> {
+        match self {
+            CallError::Inner(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl<E> CallError<CallError<E>> {
+    /// 熔断器里面套重试(或者反过来)时把两层错误拍平
+    pub fn flatten(self) -> CallError<E> {
+        match self {
+            CallError::Inner(inner) => inner,
+            CallError::Timeout(d) => CallError::Timeout(d),
+            CallError::CircuitOpen => CallError::CircuitOpen,
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for CallError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallError::Inner(e) => write!(f, "{}", e),
+            CallError::Timeout(d) => write!(f, "call timed out after {:?}", d),
+            CallError::CircuitOpen => write!(f, "circuit breaker is open"),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for CallError<E> {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Jitter {
+    None,
+    // 在 [0, delay] 里随机
+    Full,
+    // 在 [delay/2, delay] 里随机
+    Equal,
+}
+
+/// 重试预算：每个首次请求存入 `ratio` 个令牌，每次重试取走 1 个
+/// 比如 ratio = 0.2 就是重试最多占正常请求的 20%，`min_reserve` 保证低流量时也能重试几次
+#[derive(Debug)]
+pub struct RetryBudget {
+    ratio: f64,
+    max_balance: f64,
+    balance: Mutex<f64>,
+}
+
+impl RetryBudget {
+    pub fn new(ratio: f64, min_reserve: u32) -> Self {
+        let min_reserve = min_reserve as f64;
+        RetryBudget {
+            ratio,
+            max_balance: min_reserve + ratio * 100.0,
+            balance: Mutex::new(min_reserve),
+        }
+    }
+
+    fn deposit(&self) {
+        let mut balance = self.balance.lock().unwrap();
+        *balance = (*balance + self.ratio).min(self.max_balance);
+    }
+
+    fn try_withdraw(&self) -> bool {
+        let mut balance = self.balance.lock().unwrap();
+        if *balance >= 1.0 {
+            *balance -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    // 包括第一次在内最多尝试几次
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f64,
+    pub jitter: Jitter,
+    // 单次尝试的超时，None 表示不限
+    pub attempt_timeout: Option<Duration>,
+    pub budget: Option<Arc<RetryBudget>>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(5),
+            multiplier: 2.0,
+            jitter: Jitter::Full,
+            attempt_timeout: None,
+            budget: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// 第 `retry` 次重试之前要等多久(从 1 开始)
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exp = self.multiplier.powf(f64::from(retry.saturating_sub(1)));
+        // 重试次数多了乘出来会溢出，mul_f64 会 panic，先按秒算再截到 max_delay
+        let delay = Duration::try_from_secs_f64(self.base_delay.as_secs_f64() * exp)
+            .map_or(self.max_delay, |d| d.min(self.max_delay));
+        match self.jitter {
+            Jitter::None => delay,
+            Jitter::Full => delay.mul_f64(rand::random::<f64>()),
+            Jitter::Equal => delay / 2 + (delay / 2).mul_f64(rand::random::<f64>()),
+        }
+    }
+
+    /// 所有错误都重试
+    pub async fn run<T, E, F, Fut>(&self, op: F) -> Result<T, CallError<E>>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        self.run_if(op, |_| true).await
+    }
+
+    /// 只有 `retryable` 返回 true 的错误才重试，超时总是重试
+    pub async fn run_if<T, E, F, Fut, P>(&self, mut op: F, retryable: P) -> Result<T, CallError<E>>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        P: Fn(&E) -> bool,
+    {
+        if let Some(budget) = &self.budget {
+            budget.deposit();
+        }
+
+        let mut attempt = 1;
+        loop {
+            let result = match self.attempt_timeout {
+                Some(limit) => match timeout(limit, op()).await {
+                    Ok(result) => result.map_err(CallError::Inner),
+                    Err(_) => Err(CallError::Timeout(limit)),
+                },
+                None => op().await.map_err(CallError::Inner),
+            };
+
+            let err = match result {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+            let should_retry = match &err {
+                CallError::Inner(e) => retryable(e),
+                CallError::Timeout(_) => true,
+                CallError::CircuitOpen => false,
+            };
+            if !should_retry || attempt >= self.max_attempts {
+                return Err(err);
+            }
+            if let Some(budget) = &self.budget
+                && !budget.try_withdraw()
+            {
+                println!("retry budget exhausted after attempt {}", attempt);
+                return Err(err);
+            }
+
+            sleep(self.backoff(attempt)).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug, Clone)]
+pub struct BreakerConfig {
+    // 连续失败多少次后打开
+    pub failure_threshold: u32,
+    // 打开后多久进入 half-open 放少量请求试探
+    pub open_duration: Duration,
+    // half-open 时最多同时放行几个试探请求，这些全部成功才关闭
+    pub half_open_max_calls: u32,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        BreakerConfig {
+            failure_threshold: 5,
+            open_duration: Duration::from_secs(10),
+            half_open_max_calls: 1,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct BreakerInner {
+    state: CircuitState,
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    // half-open 时已经放出去的试探请求数 / 其中成功的数量
+    trial_calls: u32,
+    trial_successes: u32,
+}
+
+type StateHook = Box<dyn Fn(CircuitState, CircuitState) + Send + Sync>;
+
+pub struct CircuitBreaker {
+    config: BreakerConfig,
+    inner: Mutex<BreakerInner>,
+    hooks: Vec<StateHook>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: BreakerConfig) -> Self {
+        CircuitBreaker {
+            config,
+            inner: Mutex::new(BreakerInner {
+                state: CircuitState::Closed,
+                consecutive_failures: 0,
+                opened_at: None,
+                trial_calls: 0,
+                trial_successes: 0,
+            }),
+            hooks: Vec::new(),
+        }
+    }
+
+    /// 状态变化时回调 `hook(from, to)`，比如打日志、上报指标
+    pub fn on_state_change<H>(mut self, hook: H) -> Self
+    where
+        H: Fn(CircuitState, CircuitState) + Send + Sync + 'static,
+    {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    pub fn state(&self) -> CircuitState {
+        let mut inner = self.inner.lock().unwrap();
+        self.refresh(&mut inner);
+        inner.state
+    }
+
+    fn transition(&self, inner: &mut BreakerInner, to: CircuitState) {
+        let from = inner.state;
+        if from == to {
+            return;
+        }
+        inner.state = to;
+        inner.trial_calls = 0;
+        inner.trial_successes = 0;
+        match to {
+            CircuitState::Open => inner.opened_at = Some(Instant::now()),
+            CircuitState::Closed => {
+                inner.opened_at = None;
+                inner.consecutive_failures = 0;
+            }
+            CircuitState::HalfOpen => {}
+        }
+        for hook in &self.hooks {
+            hook(from, to);
+        }
+    }
+
+    // open 状态到时间了就转成 half-open
+    fn refresh(&self, inner: &mut BreakerInner) {
+        if inner.state == CircuitState::Open
+            && inner
+                .opened_at
+                .is_some_and(|t| t.elapsed() >= self.config.open_duration)
+        {
+            self.transition(inner, CircuitState::HalfOpen);
+        }
+    }
+
+    fn try_enter(&self) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        self.refresh(&mut inner);
+        match inner.state {
+            CircuitState::Closed => true,
+            CircuitState::Open => false,
+            CircuitState::HalfOpen => {
+                if inner.trial_calls < self.config.half_open_max_calls {
+                    inner.trial_calls += 1;
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    fn record(&self, success: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        match (inner.state, success) {
+            (CircuitState::Closed, true) => inner.consecutive_failures = 0,
+            (CircuitState::Closed, false) => {
+                inner.consecutive_failures += 1;
+                if inner.consecutive_failures >= self.config.failure_threshold {
+                    self.transition(&mut inner, CircuitState::Open);
+                }
+            }
+            (CircuitState::HalfOpen, true) => {
+                inner.trial_successes += 1;
+                if inner.trial_successes >= self.config.half_open_max_calls {
+                    self.transition(&mut inner, CircuitState::Closed);
+                }
+            }
+            // 试探失败，重新打开并重新计时
+            (CircuitState::HalfOpen, false) => self.transition(&mut inner, CircuitState::Open),
+            // 打开之前就发出去的请求，结果不影响状态
+            (CircuitState::Open, _) => {}
+        }
+    }
+
+    pub async fn call<T, E, F, Fut>(&self, op: F) -> Result<T, CallError<E>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        if !self.try_enter() {
+            return Err(CallError::CircuitOpen);
+        }
+        let attempt = Attempt {
+            breaker: self,
+            recorded: false,
+        };
+        let result = op().await;
+        attempt.finish(result.is_ok());
+        result.map_err(CallError::Inner)
+    }
+}
+
+// 放进去的一次调用，没等到结果就被 drop 时记一次失败
+struct Attempt<'a> {
+    breaker: &'a CircuitBreaker,
+    recorded: bool,
+}
+
+impl Attempt<'_> {
+    fn finish(mut self, success: bool) {
+        self.recorded = true;
+        self.breaker.record(success);
+    }
+}
+
+impl Drop for Attempt<'_> {
+    fn drop(&mut self) {
+        if !self.recorded {
+            self.breaker.record(false);
+        }
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn retry_with_exponential_backoff() {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    let policy = RetryPolicy {
+        max_attempts: 4,
+        base_delay: Duration::from_millis(100),
+        jitter: Jitter::None,
+        ..RetryPolicy::default()
+    };
+    assert_eq!(policy.backoff(1), Duration::from_millis(100));
+    assert_eq!(policy.backoff(3), Duration::from_millis(400));
+    assert_eq!(policy.backoff(30), Duration::from_secs(5));
+    // 乘到溢出也不会 panic
+    assert_eq!(policy.backoff(5000), Duration::from_secs(5));
+    assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(5));
+
+    let calls = AtomicU32::new(0);
+    let start = Instant::now();
+    let result: Result<u32, CallError<&str>> = policy
+        .run(|| async {
+            match calls.fetch_add(1, Ordering::SeqCst) {
+                0..=2 => Err("connection refused"),
+                n => Ok(n),
+            }
+        })
+        .await;
+    assert_eq!(result, Ok(3));
+    // 100 + 200 + 400
+    assert_eq!(start.elapsed(), Duration::from_millis(700));
+
+    // 不可重试的错误直接返回
+    let calls = AtomicU32::new(0);
+    let result: Result<(), CallError<&str>> = policy
+        .run_if(
+            || async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err("bad request")
+            },
+            |e| *e != "bad request",
+        )
+        .await;
+    assert_eq!(result, Err(CallError::Inner("bad request")));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn retry_attempt_timeout_and_jitter() {
+    let policy = RetryPolicy {
+        max_attempts: 2,
+        attempt_timeout: Some(Duration::from_millis(50)),
+        jitter: Jitter::Equal,
+        ..RetryPolicy::default()
+    };
+    for retry in 1..5 {
+        let d = policy.backoff(retry);
+        let full = Duration::from_millis(100 * 2u64.pow(retry - 1));
+        assert!(d >= full / 2 && d <= full, "{:?}", d);
+    }
+
+    let result: Result<(), CallError<()>> = policy
+        .run(|| async {
+            sleep(Duration::from_secs(1)).await;
+            Ok(())
+        })
+        .await;
+    assert_eq!(result, Err(CallError::Timeout(Duration::from_millis(50))));
+}
+
+#[tokio::test(start_paused = true)]
+async fn retry_budget_limits_retries() {
+    let budget = Arc::new(RetryBudget::new(0.5, 1));
+    let policy = RetryPolicy {
+        max_attempts: 10,
+        jitter: Jitter::None,
+        budget: Some(budget.clone()),
+        ..RetryPolicy::default()
+    };
+
+    let attempts = Mutex::new(0);
+    let always_fail = || async {
+        *attempts.lock().unwrap() += 1;
+        Err::<(), _>("down")
+    };
+
+    // 起始 1 个 + 这次存入 0.5 个 -> 只够重试一次
+    assert!(policy.run(always_fail).await.is_err());
+    assert_eq!(*attempts.lock().unwrap(), 2);
+
+    // 余额 0.5 + 0.5 = 1 -> 又能重试一次
+    *attempts.lock().unwrap() = 0;
+    assert!(policy.run(always_fail).await.is_err());
+    assert_eq!(*attempts.lock().unwrap(), 2);
+}
+
+#[tokio::test(start_paused = true)]
+async fn circuit_breaker_state_machine() {
+    let changes = Arc::new(Mutex::new(Vec::new()));
+    let breaker = CircuitBreaker::new(BreakerConfig {
+        failure_threshold: 2,
+        open_duration: Duration::from_secs(1),
+        half_open_max_calls: 1,
+    })
+    .on_state_change({
+        let changes = changes.clone();
+        move |from, to| changes.lock().unwrap().push((from, to))
+    });
+
+    let fail = || async { Err::<(), _>("boom") };
+    let ok = || async { Ok::<_, &str>(()) };
+
+    assert_eq!(breaker.call(fail).await, Err(CallError::Inner("boom")));
+    assert_eq!(breaker.call(fail).await, Err(CallError::Inner("boom")));
+    assert_eq!(breaker.state(), CircuitState::Open);
+    assert_eq!(breaker.call(ok).await, Err(CallError::CircuitOpen));
+
+    // 到时间进入 half-open，试探失败又打开
+    tokio::time::advance(Duration::from_secs(1)).await;
+    assert_eq!(breaker.state(), CircuitState::HalfOpen);
+    assert_eq!(breaker.call(fail).await, Err(CallError::Inner("boom")));
+    assert_eq!(breaker.state(), CircuitState::Open);
+
+    // 再等一轮，试探成功后关闭
+    tokio::time::advance(Duration::from_secs(1)).await;
+    assert_eq!(breaker.call(ok).await, Ok(()));
+    assert_eq!(breaker.state(), CircuitState::Closed);
+
+    use CircuitState::*;
+    assert_eq!(
+        *changes.lock().unwrap(),
+        vec![
+            (Closed, Open),
+            (Open, HalfOpen),
+            (HalfOpen, Open),
+            (Open, HalfOpen),
+            (HalfOpen, Closed)
+        ]
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn circuit_breaker_half_open_limits_trial_calls() {
+    let breaker = Arc::new(CircuitBreaker::new(BreakerConfig {
+        failure_threshold: 1,
+        open_duration: Duration::from_secs(1),
+        half_open_max_calls: 1,
+    }));
+    let _ = breaker.call(|| async { Err::<(), _>("boom") }).await;
+    tokio::time::advance(Duration::from_secs(1)).await;
+
+    // 第一个试探请求还没返回时，第二个直接被拒绝
+    let slow = tokio::spawn({
+        let breaker = breaker.clone();
+        async move {
+            breaker
+                .call(|| async {
+                    sleep(Duration::from_millis(100)).await;
+                    Ok::<_, ()>(())
+                })
+                .await
+        }
+    });
+    tokio::task::yield_now().await;
+    assert_eq!(
+        breaker.call(|| async { Ok::<_, ()>(()) }).await,
+        Err(CallError::CircuitOpen)
+    );
+    assert_eq!(slow.await.unwrap(), Ok(()));
+    assert_eq!(breaker.state(), CircuitState::Closed);
+}
+
+// 外面套超时把试探调用取消掉，算一次失败，熔断器不会卡在 half-open
+#[tokio::test(start_paused = true)]
+async fn circuit_breaker_counts_dropped_calls_as_failures() {
+    let breaker = CircuitBreaker::new(BreakerConfig {
+        failure_threshold: 1,
+        open_duration: Duration::from_secs(1),
+        half_open_max_calls: 1,
+    });
+    let _ = breaker.call(|| async { Err::<(), _>("boom") }).await;
+    tokio::time::advance(Duration::from_secs(1)).await;
+    assert_eq!(breaker.state(), CircuitState::HalfOpen);
+
+    let hang = breaker.call(|| async {
+        sleep(Duration::from_secs(60)).await;
+        Ok::<_, ()>(())
+    });
+    assert!(timeout(Duration::from_millis(10), hang).await.is_err());
+    assert_eq!(breaker.state(), CircuitState::Open);
+
+    // 下一轮 half-open 又能放试探请求进去
+    tokio::time::advance(Duration::from_secs(1)).await;
+    assert_eq!(breaker.call(|| async { Ok::<_, ()>(()) }).await, Ok(()));
+    assert_eq!(breaker.state(), CircuitState::Closed);
+}
+
+// 重试包在熔断器外面：熔断打开时不再重试
+#[tokio::test(start_paused = true)]
+async fn retry_around_circuit_breaker() {
+    let breaker = CircuitBreaker::new(BreakerConfig {
+        failure_threshold: 2,
+        ..BreakerConfig::default()
+    });
+    let policy = RetryPolicy {
+        max_attempts: 5,
+        jitter: Jitter::None,
+        ..RetryPolicy::default()
+    };
+
+    let attempts = Mutex::new(0);
+    let result = policy
+        .run_if(
+            || {
+                breaker.call(|| async {
+                    *attempts.lock().unwrap() += 1;
+                    Err::<(), _>("down")
+                })
+            },
+            |e| !e.is_circuit_open(),
+        )
+        .await
+        .map_err(CallError::flatten);
+    // 第二次失败后熔断打开，第三次尝试拿到 CircuitOpen 就不再重试
+    assert_eq!(result, Err(CallError::CircuitOpen));
+    assert_eq!(*attempts.lock().unwrap(), 2);
+}
+
+// 包在 mini-redis client 外面，需要先启动 mini-redis-server
+#[tokio::test]
+async fn resilient_mini_redis_get() -> mini_redis::Result<()> {
+    let policy = RetryPolicy {
+        attempt_timeout: Some(Duration::from_secs(1)),
+        ..RetryPolicy::default()
+    };
+    let breaker = CircuitBreaker::new(BreakerConfig::default()).on_state_change(|from, to| {
+        println!("mini-redis breaker {:?} -> {:?}", from, to);
+    });
+
+    let value = policy
+        .run_if(
+            || {
+                breaker.call(|| async {
+                    let mut client = mini_redis::client::connect("127.0.0.1:6379").await?;
+                    client.get("hello").await
+                })
+            },
+            |e| !e.is_circuit_open(),
+        )
+        .await
+        .map_err(CallError::flatten)?;
+    println!("got value from the server; value={:?}", value);
+    Ok(())
+}
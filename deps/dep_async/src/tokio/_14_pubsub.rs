@@ -0,0 +1,239 @@
+// 发布订阅的客户端：和 redis 一样的 PUBLISH / SUBSCRIBE / UNSUBSCRIBE
+// 1. 服务端就是 _15_resp_server(和 mini-redis 一样 PUBLISH / SUBSCRIBE 都支持)，这里不再单独起一个
+// 2. 推给订阅者的消息格式是 ["message", channel, payload]，跟 redis 协议一致，
+//    所以 Subscriber / Publisher 也能直接连真正的 redis
+// 3. Publisher 走 _16_resp_client 的连接，服务端重启以后自动重连；
+//    Subscriber 是一条长连接，订阅状态在连接上，断了由调用方重连后重新订阅
+// 4. 一条 Subscriber 连接可以随时追加 / 退订频道(send_subscribe / send_unsubscribe)，
+//    确认和消息都从 next_event 里出来，多个频道共用一条连接
+
+use crate::tokio::_06_framing::Connection;
+use crate::tokio::_16_resp_client::RespConnection;
+use bytes::Bytes;
+use mini_redis::Frame;
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub channel: String,
+    pub content: Bytes,
+}
+
+/// 订阅连接上收到的东西
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Message(Message),
+    Subscribed(String),
+    Unsubscribed(String),
+}
+
+fn bulk(s: &str) -> Frame {
+    Frame::Bulk(Bytes::copy_from_slice(s.as_bytes()))
+}
+
+pub struct Publisher {
+    connection: RespConnection,
+}
+
+impl Publisher {
+    pub async fn connect(addr: impl ToString) -> mini_redis::Result<Self> {
+        Ok(Publisher {
+            connection: RespConnection::connect(addr).await?,
+        })
+    }
+
+    /// 返回收到这条消息的订阅连接数
+    pub async fn publish(&self, channel: &str, message: Bytes) -> mini_redis::Result<u64> {
+        let frame = Frame::Array(vec![bulk("PUBLISH"), bulk(channel), Frame::Bulk(message)]);
+        match self.connection.call(&frame).await? {
+            Frame::Integer(n) => Ok(n),
+            Frame::Error(msg) => Err(msg.into()),
+            other => Err(format!("unexpected frame {:?}", other).into()),
+        }
+    }
+}
+
+/// 订阅以后这条连接就只能收消息了，要发布得另开一个 Publisher
+pub struct Subscriber {
+    connection: Connection,
+    channels: Vec<String>,
+}
+
+impl Subscriber {
+    /// 只连上，不订阅任何频道
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> mini_redis::Result<Self> {
+        let socket = TcpStream::connect(addr).await?;
+        Ok(Subscriber {
+            connection: Connection::new(socket),
+            channels: Vec::new(),
+        })
+    }
+
+    /// 连上并订阅，每个频道都确认了才返回，之后发布的消息一定能收到；
+    /// 服务端对每个频道名回一次确认，重复的名字也一样，所以按确认数等而不是按频道数
+    pub async fn subscribe<A: ToSocketAddrs>(
+        addr: A,
+        channels: &[String],
+    ) -> mini_redis::Result<Self> {
+        let mut subscriber = Self::connect(addr).await?;
+        subscriber.send_subscribe(channels).await?;
+        let mut acked = 0;
+        while acked < channels.len() {
+            match subscriber.next_event().await? {
+                Some(Event::Subscribed(_)) => acked += 1,
+                Some(other) => {
+                    return Err(format!("unexpected subscribe response {:?}", other).into());
+                }
+                None => return Err("connection closed by server".into()),
+            }
+        }
+        Ok(subscriber)
+    }
+
+    /// 已经确认订阅了的频道
+    pub fn channels(&self) -> &[String] {
+        &self.channels
+    }
+
+    /// 只发命令不等确认，确认从 next_event 里以 Event::Subscribed 出来
+    pub async fn send_subscribe(&mut self, channels: &[String]) -> mini_redis::Result<()> {
+        self.send("SUBSCRIBE", channels).await
+    }
+
+    pub async fn send_unsubscribe(&mut self, channels: &[String]) -> mini_redis::Result<()> {
+        self.send("UNSUBSCRIBE", channels).await
+    }
+
+    async fn send(&mut self, command: &str, channels: &[String]) -> mini_redis::Result<()> {
+        if channels.is_empty() {
+            return Ok(());
+        }
+        let mut frame = vec![bulk(command)];
+        frame.extend(channels.iter().map(|c| bulk(c)));
+        self.connection.write_frame(&Frame::Array(frame)).await?;
+        Ok(())
+    }
+
+    /// 服务端断开时返回 None；只等读，可以放进 select! 里和别的事情一起等
+    pub async fn next_event(&mut self) -> mini_redis::Result<Option<Event>> {
+        loop {
+            let Some(frame) = self.connection.read_frame().await? else {
+                return Ok(None);
+            };
+            let parts = match frame {
+                Frame::Array(parts) => parts,
+                Frame::Error(msg) => return Err(msg.into()),
+                other => return Err(format!("unexpected frame {:?}", other).into()),
+            };
+            let text = |b: &Bytes| String::from_utf8_lossy(b).into_owned();
+            match &parts[..] {
+                [
+                    Frame::Bulk(kind),
+                    Frame::Bulk(channel),
+                    Frame::Bulk(content),
+                ] if kind.as_ref() == b"message" => {
+                    return Ok(Some(Event::Message(Message {
+                        channel: text(channel),
+                        content: content.clone(),
+                    })));
+                }
+                [Frame::Bulk(kind), Frame::Bulk(channel), _] if kind.as_ref() == b"subscribe" => {
+                    let channel = text(channel);
+                    if !self.channels.contains(&channel) {
+                        self.channels.push(channel.clone());
+                    }
+                    return Ok(Some(Event::Subscribed(channel)));
+                }
+                [Frame::Bulk(kind), Frame::Bulk(channel), _] if kind.as_ref() == b"unsubscribe" => {
+                    let channel = text(channel);
+                    self.channels.retain(|c| *c != channel);
+                    return Ok(Some(Event::Unsubscribed(channel)));
+                }
+                // pong 之类，跳过
+                _ => continue,
+            }
+        }
+    }
+
+    /// 服务端断开时返回 None，订阅 / 退订确认跳过
+    pub async fn next_message(&mut self) -> mini_redis::Result<Option<Message>> {
+        loop {
+            match self.next_event().await? {
+                Some(Event::Message(message)) => return Ok(Some(message)),
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn publish_subscribe_roundtrip() {
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(crate::tokio::_15_resp_server::serve(listener));
+
+    let publisher = Publisher::connect(addr).await.unwrap();
+    // 还没人订阅
+    assert_eq!(publisher.publish("news", "nobody".into()).await.unwrap(), 0);
+
+    let channels = ["news".to_string(), "sport".to_string()];
+    let mut a = Subscriber::subscribe(addr, &channels).await.unwrap();
+    let mut b = Subscriber::subscribe(addr, &channels[..1]).await.unwrap();
+    assert_eq!(a.channels(), &channels);
+
+    // 重复的频道名不会让 subscribe 一直等下去
+    let twice = ["news".to_string(), "news".to_string()];
+    let c = tokio::time::timeout(Duration::from_secs(1), Subscriber::subscribe(addr, &twice))
+        .await
+        .expect("subscribe with a duplicate channel hung")
+        .unwrap();
+    assert_eq!(c.channels(), &twice[..1]);
+    drop(c);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    assert_eq!(publisher.publish("news", "hello".into()).await.unwrap(), 2);
+    assert_eq!(publisher.publish("sport", "goal".into()).await.unwrap(), 1);
+
+    let news = Message {
+        channel: "news".to_string(),
+        content: Bytes::from("hello"),
+    };
+    assert_eq!(a.next_message().await.unwrap(), Some(news.clone()));
+    assert_eq!(b.next_message().await.unwrap(), Some(news));
+    assert_eq!(
+        a.next_message().await.unwrap().unwrap().content,
+        Bytes::from("goal")
+    );
+
+    // 订阅者断开以后就不再算进接收者里
+    drop(b);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(publisher.publish("news", "again".into()).await.unwrap(), 1);
+    assert_eq!(a.next_message().await.unwrap().unwrap().content, "again");
+
+    // 同一条连接上追加、退订频道
+    a.send_subscribe(&["weather".to_string()]).await.unwrap();
+    assert_eq!(
+        a.next_event().await.unwrap(),
+        Some(Event::Subscribed("weather".to_string()))
+    );
+    a.send_unsubscribe(&["news".to_string()]).await.unwrap();
+    assert_eq!(
+        a.next_event().await.unwrap(),
+        Some(Event::Unsubscribed("news".to_string()))
+    );
+    assert_eq!(a.channels(), &["sport".to_string(), "weather".to_string()]);
+    assert_eq!(publisher.publish("news", "gone".into()).await.unwrap(), 0);
+    assert_eq!(
+        publisher.publish("weather", "sunny".into()).await.unwrap(),
+        1
+    );
+    assert_eq!(
+        a.next_message().await.unwrap().unwrap().content,
+        Bytes::from("sunny")
+    );
+}
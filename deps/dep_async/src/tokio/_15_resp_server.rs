@@ -0,0 +1,318 @@
+// RESP key-value 服务端：_03_shared_state_mutex 里那个 GET/SET 服务端的可复用版本，
+// 限流(RL.ACQUIRE)和 dep_web 的会话、缓存都连这一个，不再各自复制一份
+// 1. 连接用 _06_framing 的 Connection，每条连接一个任务，db 是一把锁保护的 HashMap，和 _03 一样
+// 2. GET / SET 交给 mini_redis::Command 解析，SET 支持 EX / PX 过期，过期的在 GET 时删掉；
+//    只写不读的 key(比如没人再用的会话)靠后台任务每秒扫一遍删掉，服务端停了任务跟着退出
+// 3. 另外加了 DEL / PING，以及 _12_rate_limit 的 RL.ACQUIRE，这几个按字符串参数自己解析
+// 4. PUBLISH / SUBSCRIBE / UNSUBSCRIBE 和 redis 一样(客户端见 _14_pubsub)：每个频道一个 broadcast::Sender，
+//    订阅了的连接同时等两件事：客户端发来的新命令、已订阅频道里的新消息，用 StreamMap 合成一个流再和 read_frame 一起 select；
+//    推给订阅者的消息格式是 ["message", channel, payload]；频道最后一个订阅者走了就从 map 里删掉
+
+use crate::tokio::_06_framing::Connection;
+use crate::tokio::_12_rate_limit::{self, States};
+use bytes::Bytes;
+use mini_redis::Frame;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+use tokio_stream::{Stream, StreamExt, StreamMap};
+
+// 每个频道里最多积压多少条，订阅者跟不上就丢掉它最老的消息
+const CHANNEL_CAPACITY: usize = 1024;
+// 多久主动清一次过期的 key
+const EXPIRE_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+type Messages = Pin<Box<dyn Stream<Item = Bytes> + Send>>;
+
+#[derive(Debug)]
+struct Shared {
+    // 值和过期时间
+    db: Mutex<HashMap<String, (Bytes, Option<Instant>)>>,
+    limits: Mutex<States>,
+    channels: Mutex<HashMap<String, broadcast::Sender<Bytes>>>,
+}
+
+impl Shared {
+    fn new() -> Self {
+        Shared {
+            db: Mutex::new(HashMap::new()),
+            limits: Mutex::new(States::new()),
+            channels: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // 返回删掉了几个
+    fn purge_expired(&self, now: Instant) -> usize {
+        let mut db = self.db.lock().unwrap();
+        let before = db.len();
+        db.retain(|_, (_, expires_at)| expires_at.is_none_or(|at| at > now));
+        before - db.len()
+    }
+
+    fn subscribe(&self, channel: &str) -> Messages {
+        let mut rx = self
+            .channels
+            .lock()
+            .unwrap()
+            .entry(channel.to_string())
+            .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+            .subscribe();
+        Box::pin(async_stream::stream! {
+            loop {
+                match rx.recv().await {
+                    Ok(msg) => yield msg,
+                    // 跟不上就跳过丢掉的那些，继续收
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+
+    // 订阅者的 Receiver 已经 drop 掉了，频道没人听就删掉
+    fn prune(&self, channel: &str) {
+        let mut channels = self.channels.lock().unwrap();
+        if channels
+            .get(channel)
+            .is_some_and(|tx| tx.receiver_count() == 0)
+        {
+            channels.remove(channel);
+        }
+    }
+
+    // 没人订阅时 send 会失败，返回 0 个接收者
+    fn publish(&self, channel: &str, message: Bytes) -> u64 {
+        let receivers = self
+            .channels
+            .lock()
+            .unwrap()
+            .get(channel)
+            .and_then(|tx| tx.send(message).ok())
+            .unwrap_or(0);
+        if receivers == 0 {
+            self.prune(channel);
+        }
+        receivers as u64
+    }
+}
+
+pub async fn serve(listener: TcpListener) -> mini_redis::Result<()> {
+    let shared = Arc::new(Shared::new());
+    tokio::spawn(purge_expired_keys(Arc::downgrade(&shared)));
+
+    loop {
+        let (socket, _) = listener.accept().await?;
+        let shared = shared.clone();
+        tokio::spawn(async move {
+            if let Err(e) = process(socket, shared).await {
+                println!("connection error: {}", e);
+            }
+        });
+    }
+}
+
+// 只拿弱引用，serve 退出、连接都断了以后 Shared 释放，任务也就结束了
+async fn purge_expired_keys(shared: Weak<Shared>) {
+    let mut interval = tokio::time::interval(EXPIRE_SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let Some(shared) = shared.upgrade() else {
+            return;
+        };
+        shared.purge_expired(Instant::now());
+    }
+}
+
+async fn process(socket: TcpStream, shared: Arc<Shared>) -> mini_redis::Result<()> {
+    let mut connection = Connection::new(socket);
+    let mut subscriptions: StreamMap<String, Messages> = StreamMap::new();
+    let result = serve_connection(&mut connection, &shared, &mut subscriptions).await;
+    // 连接断了，它订阅的频道可能就没人听了
+    let channels: Vec<String> = subscriptions.keys().cloned().collect();
+    drop(subscriptions);
+    for channel in channels {
+        shared.prune(&channel);
+    }
+    result
+}
+
+async fn serve_connection(
+    connection: &mut Connection,
+    shared: &Shared,
+    subscriptions: &mut StreamMap<String, Messages>,
+) -> mini_redis::Result<()> {
+    use mini_redis::Command::{self, Get, Set};
+
+    loop {
+        let frame = tokio::select! {
+            Some((channel, content)) = subscriptions.next(), if !subscriptions.is_empty() => {
+                let frame = Frame::Array(vec![bulk("message"), bulk(&channel), Frame::Bulk(content)]);
+                connection.write_frame(&frame).await?;
+                continue;
+            }
+            frame = connection.read_frame() => match frame? {
+                Some(frame) => frame,
+                None => return Ok(()),
+            },
+        };
+        let args = string_args(&frame);
+        let name = args
+            .as_ref()
+            .and_then(|args| args.first())
+            .map(|s| s.to_lowercase());
+        let response = match (name.as_deref(), args) {
+            (Some("rl.acquire"), Some(args)) => {
+                _12_rate_limit::acquire_command(&shared.limits, &args)
+            }
+            (Some("del"), Some(args)) => {
+                let mut db = shared.db.lock().unwrap();
+                let removed = args[1..]
+                    .iter()
+                    .filter(|key| db.remove(*key).is_some())
+                    .count();
+                Frame::Integer(removed as u64)
+            }
+            (Some("ping"), _) => Frame::Simple("PONG".to_string()),
+            // 消息体按原始字节转发，不能走 string_args
+            (Some("publish"), Some(args)) => match &frame {
+                Frame::Array(parts) if parts.len() == 3 => {
+                    let message = match &parts[2] {
+                        Frame::Bulk(message) => message.clone(),
+                        _ => Bytes::from(args[2].clone()),
+                    };
+                    Frame::Integer(shared.publish(&args[1], message))
+                }
+                _ => Frame::Error("ERR wrong number of arguments for 'publish'".to_string()),
+            },
+            (Some("subscribe"), Some(args)) => {
+                for channel in &args[1..] {
+                    if !subscriptions.contains_key(channel) {
+                        subscriptions.insert(channel.clone(), shared.subscribe(channel));
+                    }
+                    let frame = Frame::Array(vec![
+                        bulk("subscribe"),
+                        bulk(channel),
+                        Frame::Integer(subscriptions.len() as u64),
+                    ]);
+                    connection.write_frame(&frame).await?;
+                }
+                continue;
+            }
+            (Some("unsubscribe"), Some(args)) => {
+                // 不带参数就是全部退订
+                let targets: Vec<String> = if args.len() == 1 {
+                    subscriptions.keys().cloned().collect()
+                } else {
+                    args[1..].to_vec()
+                };
+                for channel in targets {
+                    if subscriptions.remove(&channel).is_some() {
+                        shared.prune(&channel);
+                    }
+                    let frame = Frame::Array(vec![
+                        bulk("unsubscribe"),
+                        bulk(&channel),
+                        Frame::Integer(subscriptions.len() as u64),
+                    ]);
+                    connection.write_frame(&frame).await?;
+                }
+                continue;
+            }
+            _ => match Command::from_frame(frame)? {
+                Set(cmd) => {
+                    let mut db = shared.db.lock().unwrap();
+                    let expires_at = cmd.expire().map(|ttl| Instant::now() + ttl);
+                    db.insert(cmd.key().to_string(), (cmd.value().clone(), expires_at));
+                    Frame::Simple("OK".to_string())
+                }
+                Get(cmd) => {
+                    let mut db = shared.db.lock().unwrap();
+                    match db.get(cmd.key()) {
+                        Some((_, Some(expires_at))) if *expires_at <= Instant::now() => {
+                            db.remove(cmd.key());
+                            Frame::Null
+                        }
+                        Some((value, _)) => Frame::Bulk(value.clone()),
+                        None => Frame::Null,
+                    }
+                }
+                cmd => Frame::Error(format!("ERR unimplemented {:?}", cmd)),
+            },
+        };
+
+        connection.write_frame(&response).await?;
+    }
+}
+
+fn bulk(s: &str) -> Frame {
+    Frame::Bulk(Bytes::copy_from_slice(s.as_bytes()))
+}
+
+// 自己处理的命令(RL.ACQUIRE、DEL、PING、发布订阅)把参数按字符串取出来，其它的交给 mini_redis::Command 去解析
+fn string_args(frame: &Frame) -> Option<Vec<String>> {
+    let Frame::Array(parts) = frame else {
+        return None;
+    };
+    let mut args = Vec::with_capacity(parts.len());
+    for part in parts {
+        match part {
+            Frame::Bulk(b) => args.push(String::from_utf8_lossy(b).into_owned()),
+            Frame::Simple(s) => args.push(s.clone()),
+            _ => return None,
+        }
+    }
+    Some(args)
+}
+
+#[tokio::test]
+async fn channels_are_pruned_when_last_subscriber_leaves() {
+    let shared = Shared::new();
+    let a = shared.subscribe("news");
+    let mut b = shared.subscribe("news");
+    assert_eq!(shared.publish("news", Bytes::from("hi")), 2);
+    assert_eq!(b.next().await.unwrap(), "hi");
+
+    drop(a);
+    shared.prune("news");
+    assert_eq!(shared.channels.lock().unwrap().len(), 1);
+    drop(b);
+    shared.prune("news");
+    assert!(shared.channels.lock().unwrap().is_empty());
+
+    // 没人订阅的频道发布一次也不会留下空的 Sender
+    assert_eq!(shared.publish("nobody", Bytes::from("hi")), 0);
+    assert!(shared.channels.lock().unwrap().is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn expired_keys_are_purged_without_get() {
+    let shared = Arc::new(Shared::new());
+    let now = Instant::now();
+    {
+        let mut db = shared.db.lock().unwrap();
+        db.insert(
+            "short".to_string(),
+            (Bytes::from("v"), Some(now + Duration::from_millis(500))),
+        );
+        db.insert(
+            "long".to_string(),
+            (Bytes::from("v"), Some(now + Duration::from_secs(60))),
+        );
+        db.insert("forever".to_string(), (Bytes::from("v"), None));
+    }
+    let sweeper = tokio::spawn(purge_expired_keys(Arc::downgrade(&shared)));
+
+    tokio::time::sleep(EXPIRE_SWEEP_INTERVAL * 2).await;
+    let mut keys: Vec<String> = shared.db.lock().unwrap().keys().cloned().collect();
+    keys.sort();
+    assert_eq!(keys, ["forever", "long"]);
+
+    // Shared 没了任务自己退出
+    drop(shared);
+    tokio::time::sleep(EXPIRE_SWEEP_INTERVAL * 2).await;
+    assert!(sweeper.is_finished());
+}
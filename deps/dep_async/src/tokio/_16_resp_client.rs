@@ -0,0 +1,282 @@
+// 连 _15_resp_server 的客户端连接，断了会自己重连
+// 1. 一条连接同一时间只能有一个请求在等响应，用 tokio 的 Mutex 排队
+// 2. 请求期间把连接从槽里拿出来，成功了才放回去：读写出错、超时、或者调用方的 future 半路被丢掉，
+//    这条连接就不要了(可能还有没读的响应)，下一次调用重新连
+// 3. 复用之前连好的连接前先看一眼对端是不是已经关了(服务端重启过)，关了就直接换新连接；
+//    写请求时出错也换新连接重发一次；请求写出去以后才出错的不重发，
+//    RL.ACQUIRE 这种命令服务端可能已经执行了，重发会多扣一次额度
+// 4. 连接和一次请求来回都有超时(默认 CALL_TIMEOUT)，服务端卡住时排队的请求不会跟着一直等
+// 5. 服务端回的 Error 帧是正常响应，不算连接出错，交给调用方处理
+// 6. KvClient 是在它上面包的 GET / SET(带过期) / DEL，dep_web 的会话存储和响应缓存用它
+
+use crate::tokio::_06_framing::Connection;
+use bytes::Bytes;
+use mini_redis::Frame;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio::time::timeout;
+
+pub const CALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct RespConnection {
+    addr: String,
+    connection: Mutex<Option<Connection>>,
+    timeout: Duration,
+}
+
+// 请求有没有可能已经到了服务端
+enum Failure {
+    Unsent(mini_redis::Error),
+    Sent(mini_redis::Error),
+}
+
+impl From<Failure> for mini_redis::Error {
+    fn from(failure: Failure) -> Self {
+        match failure {
+            Failure::Unsent(e) | Failure::Sent(e) => e,
+        }
+    }
+}
+
+impl RespConnection {
+    /// 创建时先连一次，地址不对能马上发现
+    pub async fn connect(addr: impl ToString) -> mini_redis::Result<Self> {
+        let addr = addr.to_string();
+        let socket = connect_within(&addr, CALL_TIMEOUT).await?;
+        Ok(RespConnection {
+            addr,
+            connection: Mutex::new(Some(Connection::new(socket))),
+            timeout: CALL_TIMEOUT,
+        })
+    }
+
+    /// 连接和每次请求来回的超时
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub async fn call(&self, request: &Frame) -> mini_redis::Result<Frame> {
+        let mut slot = self.connection.lock().await;
+        let reused = match slot.take() {
+            Some(connection) if !connection.is_stale() => connection,
+            Some(_) => {
+                println!("resp connection to {} closed, reconnecting", self.addr);
+                return self.fresh_call(&mut slot, request).await;
+            }
+            None => return self.fresh_call(&mut slot, request).await,
+        };
+        match self.round_trip(reused, request).await {
+            Ok((connection, frame)) => {
+                *slot = Some(connection);
+                Ok(frame)
+            }
+            Err(Failure::Unsent(e)) => {
+                println!(
+                    "resp connection to {} lost ({}), reconnecting",
+                    self.addr, e
+                );
+                self.fresh_call(&mut slot, request).await
+            }
+            Err(Failure::Sent(e)) => Err(e),
+        }
+    }
+
+    async fn fresh_call(
+        &self,
+        slot: &mut Option<Connection>,
+        request: &Frame,
+    ) -> mini_redis::Result<Frame> {
+        let socket = connect_within(&self.addr, self.timeout).await?;
+        let (connection, frame) = self.round_trip(Connection::new(socket), request).await?;
+        *slot = Some(connection);
+        Ok(frame)
+    }
+
+    async fn round_trip(
+        &self,
+        mut connection: Connection,
+        request: &Frame,
+    ) -> Result<(Connection, Frame), Failure> {
+        let exchange = async {
+            connection
+                .write_frame(request)
+                .await
+                .map_err(|e| Failure::Unsent(e.into()))?;
+            match connection.read_frame().await {
+                Ok(Some(frame)) => Ok(frame),
+                Ok(None) => Err(Failure::Sent("connection closed by server".into())),
+                Err(e) => Err(Failure::Sent(e)),
+            }
+        };
+        // 超时的时候不知道写没写出去，按写出去了算
+        match timeout(self.timeout, exchange).await {
+            Ok(Ok(frame)) => Ok((connection, frame)),
+            Ok(Err(failure)) => Err(failure),
+            Err(_) => Err(Failure::Sent(
+                format!("resp request to {} timed out", self.addr).into(),
+            )),
+        }
+    }
+}
+
+async fn connect_within(addr: &str, limit: Duration) -> mini_redis::Result<TcpStream> {
+    match timeout(limit, TcpStream::connect(addr)).await {
+        Ok(socket) => Ok(socket?),
+        Err(_) => Err(format!("connect to {} timed out", addr).into()),
+    }
+}
+
+/// _15_resp_server 的 key-value 服务端的客户端，只有 GET / SET(带过期) / DEL
+pub struct KvClient {
+    // 服务端重启以后自己重连
+    connection: RespConnection,
+}
+
+impl KvClient {
+    pub async fn connect(addr: impl ToString) -> mini_redis::Result<Self> {
+        Ok(KvClient {
+            connection: RespConnection::connect(addr).await?,
+        })
+    }
+
+    async fn call(&self, parts: Vec<Bytes>) -> mini_redis::Result<Frame> {
+        let request = Frame::Array(parts.into_iter().map(Frame::Bulk).collect());
+        match self.connection.call(&request).await? {
+            Frame::Error(msg) => Err(msg.into()),
+            frame => Ok(frame),
+        }
+    }
+
+    pub async fn get(&self, key: &str) -> mini_redis::Result<Option<Bytes>> {
+        match self
+            .call(vec!["GET".into(), Bytes::copy_from_slice(key.as_bytes())])
+            .await?
+        {
+            Frame::Bulk(value) => Ok(Some(value)),
+            Frame::Null => Ok(None),
+            other => Err(format!("unexpected frame {:?}", other).into()),
+        }
+    }
+
+    /// ttl 按毫秒发过去，None 就是不过期
+    pub async fn set(
+        &self,
+        key: &str,
+        value: Bytes,
+        ttl: Option<Duration>,
+    ) -> mini_redis::Result<()> {
+        let mut parts = vec!["SET".into(), Bytes::copy_from_slice(key.as_bytes()), value];
+        if let Some(ttl) = ttl {
+            // 不到 1 毫秒的按 1 毫秒算
+            let ms = ttl.as_millis().max(1);
+            parts.extend(["PX".into(), Bytes::from(ms.to_string())]);
+        }
+        self.call(parts).await?;
+        Ok(())
+    }
+
+    /// 返回 key 之前是否存在
+    pub async fn del(&self, key: &str) -> mini_redis::Result<bool> {
+        match self
+            .call(vec!["DEL".into(), Bytes::copy_from_slice(key.as_bytes())])
+            .await?
+        {
+            Frame::Integer(n) => Ok(n > 0),
+            other => Err(format!("unexpected frame {:?}", other).into()),
+        }
+    }
+}
+
+#[tokio::test]
+async fn reconnects_after_connection_loss() {
+    use bytes::Bytes;
+    use tokio::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = RespConnection::connect(addr).await.unwrap();
+    // 第一条连接接下来就关掉，模拟服务端重启，之后的连接正常服务
+    let (first, _) = listener.accept().await.unwrap();
+    drop(first);
+    // 等 reactor 看到对端关闭，跟真实场景一样：重启发生在下一次调用之前
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    tokio::spawn(crate::tokio::_15_resp_server::serve(listener));
+
+    let ping = Frame::Array(vec![Frame::Bulk(Bytes::from("PING"))]);
+    for _ in 0..2 {
+        let pong = client.call(&ping).await.unwrap();
+        assert!(
+            matches!(&pong, Frame::Simple(s) if s == "PONG"),
+            "{:?}",
+            pong
+        );
+    }
+
+    // 服务端根本连不上时返回错误，而不是卡住
+    let nobody = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let gone = RespConnection::connect(nobody.local_addr().unwrap())
+        .await
+        .unwrap();
+    drop(nobody);
+    assert!(gone.call(&ping).await.is_err());
+}
+
+#[tokio::test]
+async fn does_not_resend_after_write_and_times_out() {
+    use bytes::Bytes;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    let ping = Frame::Array(vec![Frame::Bulk(Bytes::from("PING"))]);
+
+    // 请求读到了但是没回就断开：可能已经执行过了，不能换连接重发
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let client = RespConnection::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (mut first, _) = listener.accept().await.unwrap();
+    let server = tokio::spawn(async move {
+        let mut buf = [0u8; 64];
+        let n = first.read(&mut buf).await.unwrap();
+        assert!(n > 0);
+    });
+    assert!(client.call(&ping).await.is_err());
+    server.await.unwrap();
+    let resent = timeout(Duration::from_millis(100), listener.accept()).await;
+    assert!(resent.is_err(), "request was sent again");
+
+    // 服务端卡住不回，到时间就报错，后面的请求也不会一直排队
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let client = RespConnection::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap()
+        .timeout(Duration::from_millis(100));
+    let (_stalled, _) = listener.accept().await.unwrap();
+    let err = client.call(&ping).await.unwrap_err();
+    assert!(err.to_string().contains("timed out"), "{}", err);
+}
+
+#[tokio::test]
+async fn kv_client_get_set_del_with_expiry() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(crate::tokio::_15_resp_server::serve(listener));
+
+    let kv = KvClient::connect(addr).await.unwrap();
+    assert_eq!(kv.get("k").await.unwrap(), None);
+    kv.set("k", "v".into(), None).await.unwrap();
+    assert_eq!(kv.get("k").await.unwrap(), Some(Bytes::from("v")));
+    assert!(kv.del("k").await.unwrap());
+    assert!(!kv.del("k").await.unwrap());
+    assert_eq!(kv.get("k").await.unwrap(), None);
+
+    // 过期之后就读不到了
+    kv.set("short", "v".into(), Some(Duration::from_millis(50)))
+        .await
+        .unwrap();
+    assert!(kv.get("short").await.unwrap().is_some());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(kv.get("short").await.unwrap(), None);
+}
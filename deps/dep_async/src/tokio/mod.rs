@@ -8,3 +8,10 @@ mod _06_framing;
 mod _07_async_in_depth;
 mod _08_select;
 mod _09_streams;
+pub mod _10_file_tail;
+pub mod _11_worker_pool;
+pub mod _12_rate_limit;
+pub mod _13_resilience;
+pub mod _14_pubsub;
+pub mod _15_resp_server;
+pub mod _16_resp_client;
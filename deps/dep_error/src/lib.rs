@@ -1,3 +1,4 @@
 mod _01_thiserror;
 mod _02_anyhow;
-mod _03_anyhow_with_thiserror;
\ No newline at end of file
+mod _03_anyhow_with_thiserror;
+mod _04_err_code;
\ No newline at end of file
@@ -1,10 +1,14 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     // .build_server(true)
     // .build_client(true)
+    // 描述符集合给 REST 转码用，里面带着 google.api.http 注解
+    let descriptor =
+        std::path::PathBuf::from(std::env::var("OUT_DIR")?).join("demo_descriptor.bin");
     tonic_prost_build::configure()
+        .file_descriptor_set_path(descriptor)
         .compile_protos(
             &["proto/demo.proto"],
-            &["proto", "proto/third_party/google/protobuf"], // 搜索 proto 依赖的路径
+            &["proto", "proto/third_party"], // 搜索 proto 依赖的路径
         )?;
     Ok(())
 }
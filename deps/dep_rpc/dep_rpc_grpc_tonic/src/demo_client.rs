@@ -1,20 +1,76 @@
-use demo::UserRequest;
-use demo::user_service_client::UserServiceClient;
+use dep_async::resilience::{BreakerConfig, CallError, CircuitBreaker, RetryPolicy};
+use dep_rpc_grpc_tonic::demo::UserRequest;
+use dep_rpc_grpc_tonic::demo::user_service_client::UserServiceClient;
+use dep_serialization::{ConfigLoader, Settings};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tonic::Code;
 
-pub mod demo {
-    tonic::include_proto!("demo");
+// 只有暂时性的错误才值得重试，参数错误之类的重试也没用
+fn retryable(status: &tonic::Status) -> bool {
+    matches!(
+        status.code(),
+        Code::Unavailable | Code::DeadlineExceeded | Code::ResourceExhausted
+    )
 }
 
+// --endpoint http://127.0.0.1:50052 或 GRPC_CLIENT__ENDPOINT=...
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+struct ClientSettings {
+    endpoint: String,
+}
+
+impl Default for ClientSettings {
+    fn default() -> Self {
+        ClientSettings {
+            endpoint: "http://localhost:50051".to_string(),
+        }
+    }
+}
+
+impl Settings for ClientSettings {}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = UserServiceClient::connect("http://localhost:50051").await?;
-
-    let req = tonic::Request::new(UserRequest {
-        user_id: 42,
-        filter: None,
+    let settings: ClientSettings = ConfigLoader::new("GRPC_CLIENT")
+        .args(std::env::args().skip(1))
+        .load()?;
+    let policy = RetryPolicy {
+        max_attempts: 4,
+        attempt_timeout: Some(Duration::from_secs(2)),
+        ..RetryPolicy::default()
+    };
+    let breaker = CircuitBreaker::new(BreakerConfig::default()).on_state_change(|from, to| {
+        println!("UserService breaker {:?} -> {:?}", from, to);
     });
 
-    let resp = client.get_user(req).await?.into_inner();
+    // 服务端可能比客户端晚启动，连接也重试几次
+    let client = policy
+        .run(|| UserServiceClient::connect(settings.endpoint.clone()))
+        .await?;
+
+    let resp = policy
+        .run_if(
+            || {
+                let mut client = client.clone();
+                breaker.call(move || async move {
+                    // Request 不能 clone，每次尝试都重新构造
+                    let req = tonic::Request::new(UserRequest {
+                        user_id: 42,
+                        filter: None,
+                    });
+                    client.get_user(req).await
+                })
+            },
+            |e| match e {
+                CallError::Inner(status) => retryable(status),
+                _ => false,
+            },
+        )
+        .await
+        .map_err(CallError::flatten)?
+        .into_inner();
 
     println!("Status: {:?}", resp.status);
     let info = resp.info.unwrap();
@@ -1,12 +1,12 @@
-use crate::demo::user_service_server::{UserService, UserServiceServer};
-use crate::demo::{UserInfo, UserRequest, UserResponse, UserStatus};
+use dep_rpc_grpc_tonic::demo::user_service_server::{UserService, UserServiceServer};
+use dep_rpc_grpc_tonic::demo::{self, UserInfo, UserRequest, UserResponse, UserStatus};
+use dep_rpc_grpc_tonic::transcode::Transcoder;
+use dep_serialization::{ConfigLoader, Settings};
 use prost_types::{Duration, Timestamp};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
 use tonic::{Request, Response, Status, transport::Server};
 
-pub mod demo {
-    tonic::include_proto!("demo");
-}
-
 #[derive(Default)]
 pub struct MyUserService;
 
@@ -39,15 +39,40 @@ impl UserService for MyUserService {
     }
 }
 
+// --listen 127.0.0.1:50052 或 GRPC_SERVER__LISTEN=...，规则见 dep_serialization::ConfigLoader
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+struct ServerSettings {
+    listen: SocketAddr,
+    /// REST/JSON 转码的端口，curl http://127.0.0.1:8081/v1/users/42
+    rest_listen: SocketAddr,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        ServerSettings {
+            listen: "0.0.0.0:50051".parse().unwrap(),
+            rest_listen: "0.0.0.0:8081".parse().unwrap(),
+        }
+    }
+}
+
+impl Settings for ServerSettings {}
+
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let addr = "0.0.0.0:50051".parse()?;
-    let svc = MyUserService::default();
-
-    Server::builder()
-        .add_service(UserServiceServer::new(svc))
-        .serve(addr)
-        .await?;
+async fn main() -> Result<(), dep_rpc_grpc_tonic::transcode::Error> {
+    let settings: ServerSettings = ConfigLoader::new("GRPC_SERVER")
+        .args(std::env::args().skip(1))
+        .load()?;
+    let addr = settings.listen;
+    let svc = UserServiceServer::new(MyUserService);
+
+    // 转码层在进程内直接调 svc，不再走一次网络
+    let rest = Transcoder::new(demo::FILE_DESCRIPTOR_SET, svc.clone())?.router();
+    let listener = tokio::net::TcpListener::bind(settings.rest_listen).await?;
+    tokio::spawn(async move { axum::serve(listener, rest).await });
+
+    Server::builder().add_service(svc).serve(addr).await?;
 
     Ok(())
 }
@@ -0,0 +1,8 @@
+pub mod transcode;
+
+pub mod demo {
+    tonic::include_proto!("demo");
+
+    /// demo.proto 连同它 import 的文件编译出来的描述符，transcode 按里面的 google.api.http 注解建路由
+    pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("demo_descriptor");
+}
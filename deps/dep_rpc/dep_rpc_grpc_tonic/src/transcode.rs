@@ -0,0 +1,667 @@
+// REST/JSON 转码：HTTP 客户端直接调 gRPC 服务，不用再单独写一套 REST 接口
+// 路由来自 .proto 里方法上的 google.api.http 注解，比如
+//   rpc GetUser(UserRequest) returns (UserResponse) { option (google.api.http) = { get: "/v1/users/{user_id}" }; }
+// GET /v1/users/42?filter=x 就变成 GetUser(UserRequest { user_id: 42, filter: Some("x") })
+// 1. 请求消息的字段依次来自：body("*" 是整个消息，写字段名就只填那个字段)、路径模板里的 {field}、其余的查询参数，
+//    查询参数写 proto 字段名或 JSON 名都行，嵌套字段用 a.b，重复字段写多次；
+//    和 google.api.http 一样，路径和 body 已经占用的字段不能再用查询参数覆盖，否则返回 INVALID_ARGUMENT
+// 2. JSON 按 protobuf 的规则转：Timestamp 是 RFC 3339 字符串、Duration 是 "3600s"、枚举是名字(ACTIVE)、int64 是字符串
+// 3. 失败时按 google.rpc.Code 的对照表转 HTTP 状态码，响应体是 {"code": 5, "message": "..."}
+// 4. Authorization 和 x- 开头的请求头带到 gRPC metadata 里
+// 5. 不支持流式方法、response_body、axum 路由不了的 custom 方法(比如 LIST)和 {name=shelves/*} 这种带子模板的变量，
+//    建路由时直接报错
+
+use axum::Router;
+use axum::body::Bytes;
+use axum::extract::{RawPathParams, RawQuery};
+use axum::http::{HeaderMap, Method, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{MethodFilter, on};
+use prost::Message;
+use prost_reflect::{
+    DescriptorPool, DynamicMessage, ExtensionDescriptor, Kind, MessageDescriptor, MethodDescriptor,
+    Value,
+};
+use serde_json::{Map, Value as Json, json};
+use std::sync::Arc;
+use tonic::codec::{Codec, DecodeBuf, Decoder, EncodeBuf, Encoder};
+use tonic::codegen::{Body, StdError, http};
+use tonic::metadata::{MetadataKey, MetadataValue};
+use tonic::{Code, Status};
+
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+pub const HTTP_EXTENSION: &str = "google.api.http";
+
+struct Binding {
+    method: MethodFilter,
+    template: String,
+    /// 路径变量按出现顺序对应的字段，axum 路由里的名字是 p0、p1 ...
+    vars: Vec<String>,
+    body: Option<String>,
+    grpc_path: String,
+    input: MessageDescriptor,
+    output: MessageDescriptor,
+}
+
+pub struct Transcoder<S> {
+    bindings: Vec<Arc<Binding>>,
+    service: S,
+}
+
+impl<S> Transcoder<S>
+where
+    S: tonic::client::GrpcService<tonic::body::Body> + Clone + Send + Sync + 'static,
+    S::Error: Into<StdError>,
+    S::Future: Send,
+    S::ResponseBody: Body<Data = Bytes> + Send + 'static,
+    <S::ResponseBody as Body>::Error: Into<StdError> + Send,
+{
+    /// descriptors 是 tonic::include_file_descriptor_set! 拿到的描述符，service 可以是 Channel，
+    /// 也可以直接是 XxxServer::new(..)，在同一个进程里调不用走网络
+    pub fn new(descriptors: &[u8], service: S) -> Result<Self, Error> {
+        let pool = DescriptorPool::decode(descriptors)?;
+        let extension = pool
+            .get_extension_by_name(HTTP_EXTENSION)
+            .ok_or("descriptor set does not include google/api/annotations.proto")?;
+        let mut bindings = Vec::new();
+        for grpc_service in pool.services() {
+            for method in grpc_service.methods() {
+                for rule in http_rules(&method, &extension) {
+                    bindings.push(Arc::new(Binding::new(&method, &rule)?));
+                }
+            }
+        }
+        Ok(Transcoder { bindings, service })
+    }
+
+    /// 注解里的每个 HTTP 映射一条路由
+    pub fn router(self) -> Router {
+        let mut router = Router::new();
+        for binding in self.bindings {
+            let path = axum_path(&binding.template);
+            let service = self.service.clone();
+            router = router.route(
+                &path,
+                on(
+                    binding.method,
+                    move |params: RawPathParams,
+                          RawQuery(query): RawQuery,
+                          headers: HeaderMap,
+                          body: Bytes| {
+                        let (binding, service) = (binding.clone(), service.clone());
+                        async move {
+                            match transcode(&binding, service, &params, query, &headers, body).await
+                            {
+                                Ok(response) => json_response(StatusCode::OK, &response),
+                                Err(status) => status_response(&status),
+                            }
+                        }
+                    },
+                ),
+            );
+        }
+        router
+    }
+}
+
+// 注解本身加上 additional_bindings
+fn http_rules(method: &MethodDescriptor, extension: &ExtensionDescriptor) -> Vec<DynamicMessage> {
+    let options = method.options();
+    if !options.has_extension(extension) {
+        return Vec::new();
+    }
+    let Value::Message(rule) = options.get_extension(extension).into_owned() else {
+        return Vec::new();
+    };
+    let mut rules = vec![rule.clone()];
+    if let Some(Value::List(more)) = rule.get_field_by_name("additional_bindings").as_deref() {
+        rules.extend(more.iter().filter_map(|v| v.as_message().cloned()));
+    }
+    rules
+}
+
+fn string_field(message: &DynamicMessage, name: &str) -> Option<String> {
+    if !message.has_field_by_name(name) {
+        return None;
+    }
+    let value = message.get_field_by_name(name)?;
+    value.as_str().map(str::to_string)
+}
+
+impl Binding {
+    fn new(method: &MethodDescriptor, rule: &DynamicMessage) -> Result<Self, Error> {
+        let name = method.full_name();
+        if method.is_client_streaming() || method.is_server_streaming() {
+            return Err(format!("{}: streaming methods can not be transcoded", name).into());
+        }
+        if string_field(rule, "response_body").is_some_and(|s| !s.is_empty()) {
+            return Err(format!("{}: response_body is not supported", name).into());
+        }
+        let verbs = [
+            ("get", Method::GET),
+            ("put", Method::PUT),
+            ("post", Method::POST),
+            ("delete", Method::DELETE),
+            ("patch", Method::PATCH),
+        ];
+        let (method_name, template) = match verbs
+            .into_iter()
+            .find_map(|(field, verb)| Some((verb, string_field(rule, field)?)))
+        {
+            Some(found) => found,
+            None => {
+                let Some(Value::Message(custom)) =
+                    rule.get_field_by_name("custom").as_deref().cloned()
+                else {
+                    return Err(format!("{}: google.api.http has no pattern", name).into());
+                };
+                let kind = string_field(&custom, "kind").unwrap_or_default();
+                let verb = Method::from_bytes(kind.as_bytes())
+                    .map_err(|_| format!("{}: invalid custom method {}", name, kind))?;
+                (verb, string_field(&custom, "path").unwrap_or_default())
+            }
+        };
+        let method_name = MethodFilter::try_from(method_name.clone())
+            .map_err(|_| format!("{}: unsupported HTTP method {}", name, method_name))?;
+        let vars = parse_template(&template).map_err(|e| format!("{}: {}", name, e))?;
+        let input = method.input();
+        for var in &vars {
+            find_field(&input, var).map_err(|e| format!("{}: {}", name, e.message()))?;
+        }
+        let body = string_field(rule, "body").filter(|b| !b.is_empty());
+        if let Some(field) = body.as_deref().filter(|b| *b != "*") {
+            input
+                .get_field_by_name(field)
+                .ok_or_else(|| format!("{}: unknown body field {}", name, field))?;
+        }
+        Ok(Binding {
+            method: method_name,
+            template,
+            vars,
+            body,
+            grpc_path: format!("/{}/{}", method.parent_service().full_name(), method.name()),
+            input,
+            output: method.output(),
+        })
+    }
+}
+
+impl Binding {
+    // 查询参数指向的字段是不是已经由路径变量或者 body 字段填了，a.b 和 a 互相覆盖也算
+    fn bound(&self, key: &str) -> Result<bool, Status> {
+        let names = |path: &str| -> Result<Vec<String>, Status> {
+            let fields = find_field(&self.input, path)?;
+            Ok(fields.iter().map(|f| f.name().to_string()).collect())
+        };
+        let query = names(key)?;
+        for var in &self.vars {
+            let var = names(var)?;
+            if query.starts_with(&var) || var.starts_with(&query) {
+                return Ok(true);
+            }
+        }
+        Ok(self.body.as_deref().is_some_and(|body| query[0] == body))
+    }
+}
+
+// "/v1/users/{user_id}" -> ["user_id"]，{name} {name=*} 匹配一段，{name=**} 匹配剩下的所有段
+fn parse_template(template: &str) -> Result<Vec<String>, String> {
+    if !template.starts_with('/') {
+        return Err(format!("path template {} must start with /", template));
+    }
+    let mut vars = Vec::new();
+    for segment in template[1..].split('/') {
+        let Some(inner) = segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) else {
+            if segment.contains(['{', '}', '*', ':']) {
+                return Err(format!("unsupported path segment {}", segment));
+            }
+            continue;
+        };
+        let (field, pattern) = inner.split_once('=').unwrap_or((inner, "*"));
+        if pattern != "*" && pattern != "**" {
+            return Err(format!("unsupported variable pattern {}", segment));
+        }
+        vars.push(field.to_string());
+    }
+    Ok(vars)
+}
+
+fn axum_path(template: &str) -> String {
+    let mut index = 0;
+    let segments: Vec<String> = template[1..]
+        .split('/')
+        .map(|segment| match segment.strip_prefix('{') {
+            Some(inner) => {
+                let name = format!("p{}", index);
+                index += 1;
+                if inner.ends_with("=**}") {
+                    format!("{{*{}}}", name)
+                } else {
+                    format!("{{{}}}", name)
+                }
+            }
+            None => segment.to_string(),
+        })
+        .collect();
+    format!("/{}", segments.join("/"))
+}
+
+// 按 a.b.c 找字段，proto 名和 JSON 名都认
+fn find_field(
+    message: &MessageDescriptor,
+    path: &str,
+) -> Result<Vec<prost_reflect::FieldDescriptor>, Status> {
+    let mut fields = Vec::new();
+    let mut current = message.clone();
+    for (i, segment) in path.split('.').enumerate() {
+        if i > 0 {
+            let Kind::Message(inner) = fields
+                .last()
+                .map(prost_reflect::FieldDescriptor::kind)
+                .unwrap()
+            else {
+                return Err(Status::invalid_argument(format!(
+                    "{} is not a message field",
+                    path
+                )));
+            };
+            current = inner;
+        }
+        let field = current
+            .get_field_by_name(segment)
+            .or_else(|| current.get_field_by_json_name(segment))
+            .ok_or_else(|| {
+                Status::invalid_argument(format!(
+                    "unknown field {} in {}",
+                    path,
+                    message.full_name()
+                ))
+            })?;
+        fields.push(field);
+    }
+    Ok(fields)
+}
+
+// 路径和查询参数都是字符串，按字段类型填进 JSON，交给 prost-reflect 按 protobuf JSON 规则解析
+fn set_field(
+    json: &mut Map<String, Json>,
+    message: &MessageDescriptor,
+    path: &str,
+    raw: &str,
+) -> Result<(), Status> {
+    let fields = find_field(message, path)?;
+    let (leaf, parents) = fields.split_last().unwrap();
+    let mut object = json;
+    for field in parents {
+        let entry = object
+            .entry(field.name().to_string())
+            .or_insert_with(|| Json::Object(Map::new()));
+        object = entry
+            .as_object_mut()
+            .ok_or_else(|| Status::invalid_argument(format!("{} set twice", path)))?;
+    }
+    let invalid = || Status::invalid_argument(format!("{}: invalid value {:?}", path, raw));
+    let valid = match leaf.kind() {
+        Kind::Int32 | Kind::Sint32 | Kind::Sfixed32 => raw.parse::<i32>().is_ok(),
+        Kind::Int64 | Kind::Sint64 | Kind::Sfixed64 => raw.parse::<i64>().is_ok(),
+        Kind::Uint32 | Kind::Fixed32 => raw.parse::<u32>().is_ok(),
+        Kind::Uint64 | Kind::Fixed64 => raw.parse::<u64>().is_ok(),
+        Kind::Float | Kind::Double => raw.parse::<f64>().is_ok(),
+        _ => true,
+    };
+    if !valid {
+        return Err(invalid());
+    }
+    // 数字按 protobuf JSON 的规则可以是字符串，只有 bool 必须是 true / false
+    let value = match leaf.kind() {
+        Kind::Bool => Json::Bool(raw.parse().map_err(|_| invalid())?),
+        _ => Json::String(raw.to_string()),
+    };
+    if leaf.is_list() {
+        let list = object
+            .entry(leaf.name().to_string())
+            .or_insert_with(|| Json::Array(Vec::new()));
+        if let Json::Array(list) = list {
+            list.push(value);
+        }
+    } else {
+        object.insert(leaf.name().to_string(), value);
+    }
+    Ok(())
+}
+
+fn build_request(
+    binding: &Binding,
+    params: &RawPathParams,
+    query: Option<String>,
+    body: &[u8],
+) -> Result<DynamicMessage, Status> {
+    let parse_body = || -> Result<Json, Status> {
+        if body.is_empty() {
+            return Ok(Json::Object(Map::new()));
+        }
+        serde_json::from_slice(body)
+            .map_err(|e| Status::invalid_argument(format!("invalid JSON body: {}", e)))
+    };
+    let mut json = Map::new();
+    match binding.body.as_deref() {
+        Some("*") => match parse_body()? {
+            Json::Object(object) => json = object,
+            _ => {
+                return Err(Status::invalid_argument(
+                    "request body must be a JSON object",
+                ));
+            }
+        },
+        Some(field) => {
+            json.insert(field.to_string(), parse_body()?);
+        }
+        None => {}
+    }
+    for (name, value) in params.iter() {
+        let index: usize = name[1..].parse().unwrap();
+        set_field(&mut json, &binding.input, &binding.vars[index], value)?;
+    }
+    // body 是 "*" 的时候所有字段都从 body 来，查询参数不用管
+    if binding.body.as_deref() != Some("*") {
+        let pairs: Vec<(String, String)> =
+            serde_urlencoded::from_str(query.as_deref().unwrap_or(""))
+                .map_err(|e| Status::invalid_argument(format!("invalid query string: {}", e)))?;
+        for (key, value) in pairs {
+            if binding.bound(&key)? {
+                return Err(Status::invalid_argument(format!(
+                    "{} is bound by the path or body and can not be set in the query",
+                    key
+                )));
+            }
+            set_field(&mut json, &binding.input, &key, &value)?;
+        }
+    }
+    DynamicMessage::deserialize(binding.input.clone(), Json::Object(json))
+        .map_err(|e| Status::invalid_argument(e.to_string()))
+}
+
+async fn transcode<S>(
+    binding: &Binding,
+    service: S,
+    params: &RawPathParams,
+    query: Option<String>,
+    headers: &HeaderMap,
+    body: Bytes,
+) -> Result<DynamicMessage, Status>
+where
+    S: tonic::client::GrpcService<tonic::body::Body>,
+    S::Error: Into<StdError>,
+    S::ResponseBody: Body<Data = Bytes> + Send + 'static,
+    <S::ResponseBody as Body>::Error: Into<StdError> + Send,
+{
+    let message = build_request(binding, params, query, &body)?;
+    let mut request = tonic::Request::new(message);
+    for (name, value) in headers {
+        let name = name.as_str();
+        let forwarded = name == header::AUTHORIZATION.as_str() || name.starts_with("x-");
+        if !forwarded || name.ends_with("-bin") {
+            continue;
+        }
+        if let (Ok(key), Ok(value)) = (
+            MetadataKey::from_bytes(name.as_bytes()),
+            MetadataValue::try_from(value.as_bytes()),
+        ) {
+            request.metadata_mut().insert(key, value);
+        }
+    }
+
+    let mut grpc = tonic::client::Grpc::new(service);
+    grpc.ready()
+        .await
+        .map_err(|e| Status::unavailable(format!("service not ready: {}", e.into())))?;
+    let path = http::uri::PathAndQuery::try_from(binding.grpc_path.as_str())
+        .map_err(|e| Status::internal(e.to_string()))?;
+    let codec = DynamicCodec(binding.output.clone());
+    let response = grpc.unary(request, path, codec).await?;
+    Ok(response.into_inner())
+}
+
+/// google.rpc.Code 到 HTTP 状态码的标准对照
+pub fn http_status(code: Code) -> StatusCode {
+    match code {
+        Code::Ok => StatusCode::OK,
+        Code::Cancelled => StatusCode::from_u16(499).unwrap(),
+        Code::InvalidArgument | Code::FailedPrecondition | Code::OutOfRange => {
+            StatusCode::BAD_REQUEST
+        }
+        Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::Unknown | Code::Internal | Code::DataLoss => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn json_response(status: StatusCode, body: &impl serde::Serialize) -> Response {
+    let body = serde_json::to_vec(body).unwrap();
+    (status, [(header::CONTENT_TYPE, "application/json")], body).into_response()
+}
+
+fn status_response(status: &Status) -> Response {
+    let body = json!({ "code": status.code() as i32, "message": status.message() });
+    json_response(http_status(status.code()), &body)
+}
+
+// 请求和响应都是运行时才知道类型的 DynamicMessage，生成的代码里用的 ProstCodec 要求 Default，这里自己实现
+#[derive(Clone)]
+struct DynamicCodec(MessageDescriptor);
+
+struct DynamicEncoder;
+
+struct DynamicDecoder(MessageDescriptor);
+
+impl Codec for DynamicCodec {
+    type Encode = DynamicMessage;
+    type Decode = DynamicMessage;
+    type Encoder = DynamicEncoder;
+    type Decoder = DynamicDecoder;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        DynamicEncoder
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        DynamicDecoder(self.0.clone())
+    }
+}
+
+impl Encoder for DynamicEncoder {
+    type Item = DynamicMessage;
+    type Error = Status;
+
+    fn encode(&mut self, item: Self::Item, dst: &mut EncodeBuf<'_>) -> Result<(), Self::Error> {
+        item.encode(dst)
+            .map_err(|e| Status::internal(format!("encode request: {}", e)))
+    }
+}
+
+impl Decoder for DynamicDecoder {
+    type Item = DynamicMessage;
+    type Error = Status;
+
+    fn decode(&mut self, src: &mut DecodeBuf<'_>) -> Result<Option<Self::Item>, Self::Error> {
+        DynamicMessage::decode(self.0.clone(), src)
+            .map(Some)
+            .map_err(|e| Status::internal(format!("decode response: {}", e)))
+    }
+}
+
+#[cfg(test)]
+struct TestUserService;
+
+#[cfg(test)]
+#[tonic::async_trait]
+impl crate::demo::user_service_server::UserService for TestUserService {
+    async fn get_user(
+        &self,
+        request: tonic::Request<crate::demo::UserRequest>,
+    ) -> Result<tonic::Response<crate::demo::UserResponse>, Status> {
+        use crate::demo::{UserInfo, UserResponse, UserStatus};
+        use prost_types::{Duration, Timestamp};
+
+        let token = request.metadata().get("authorization").cloned();
+        let req = request.into_inner();
+        if req.user_id <= 0 {
+            return Err(Status::invalid_argument("user_id must be positive"));
+        }
+        if token.is_none() {
+            return Err(Status::unauthenticated("missing token"));
+        }
+        let name = format!("User {}", req.user_id);
+        if req
+            .filter
+            .as_ref()
+            .is_some_and(|f| !name.contains(f.as_str()))
+        {
+            return Err(Status::not_found("no user matches filter"));
+        }
+        Ok(tonic::Response::new(UserResponse {
+            status: UserStatus::Active.into(),
+            info: Some(UserInfo {
+                name,
+                created_at: Some(Timestamp {
+                    seconds: 1_700_000_000,
+                    nanos: 0,
+                }),
+                active_duration: Some(Duration {
+                    seconds: 3600,
+                    nanos: 0,
+                }),
+            }),
+        }))
+    }
+}
+
+#[tokio::test]
+async fn transcodes_get_user() {
+    use crate::demo::user_service_server::UserServiceServer;
+    use axum::body::Body as AxumBody;
+    use axum::http::Request;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    let service = UserServiceServer::new(TestUserService);
+    let app = Transcoder::new(crate::demo::FILE_DESCRIPTOR_SET, service)
+        .unwrap()
+        .router();
+    let call = |method: &str, uri: &str| {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("authorization", "Bearer t")
+            .body(AxumBody::empty())
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (
+                status,
+                serde_json::from_slice::<Json>(&body).unwrap_or(Json::Null),
+            )
+        }
+    };
+
+    // 字段名是 JSON 名，枚举是名字，Timestamp / Duration 是字符串
+    let (status, body) = call("GET", "/v1/users/42").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body,
+        json!({
+            "status": "ACTIVE",
+            "info": {
+                "name": "User 42",
+                "createdAt": "2023-11-14T22:13:20Z",
+                "activeDuration": "3600s",
+            }
+        })
+    );
+
+    // 查询参数填到没在路径里的字段
+    let (status, _) = call("GET", "/v1/users/42?filter=User%204").await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = call("GET", "/v1/users/42?filter=nobody").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(
+        body,
+        json!({"code": 5, "message": "no user matches filter"})
+    );
+
+    // 服务端返回的错误和请求本身的错误都按 Code 转
+    let (status, body) = call("GET", "/v1/users/0").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], 3);
+    let (status, body) = call("GET", "/v1/users/abc").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(
+        body["message"].as_str().unwrap().contains("user_id"),
+        "{}",
+        body
+    );
+    let (status, body) = call("GET", "/v1/users/42?bogus=1").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["message"], "unknown field bogus in demo.UserRequest");
+    let (status, _) = call("POST", "/v1/users/42").await;
+    assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+    // 路径里绑定了的字段不能再用查询参数改，proto 名和 JSON 名都不行
+    for uri in ["/v1/users/42?user_id=1", "/v1/users/42?userId=1"] {
+        let (status, body) = call("GET", uri).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], 3);
+        assert!(
+            body["message"]
+                .as_str()
+                .unwrap()
+                .contains("bound by the path"),
+            "{}",
+            body
+        );
+    }
+
+    // axum 路由不了的 custom 方法建路由时就报错，不会等到 router() 才 panic
+    let pool = DescriptorPool::decode(crate::demo::FILE_DESCRIPTOR_SET).unwrap();
+    let method = pool
+        .get_service_by_name("demo.UserService")
+        .unwrap()
+        .methods()
+        .next()
+        .unwrap();
+    let rule_type = pool.get_message_by_name("google.api.HttpRule").unwrap();
+    let custom_type = pool
+        .get_message_by_name("google.api.CustomHttpPattern")
+        .unwrap();
+    let mut custom = DynamicMessage::new(custom_type);
+    custom.set_field_by_name("kind", Value::String("LIST".to_string()));
+    custom.set_field_by_name("path", Value::String("/v1/users".to_string()));
+    let mut rule = DynamicMessage::new(rule_type);
+    rule.set_field_by_name("custom", Value::Message(custom));
+    let err = Binding::new(&method, &rule).err().unwrap();
+    assert!(
+        err.to_string().contains("unsupported HTTP method LIST"),
+        "{}",
+        err
+    );
+
+    // 请求头带到 metadata
+    let request = Request::get("/v1/users/42")
+        .body(AxumBody::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    assert_eq!(parse_template("/v1/{name=**}").unwrap(), ["name"]);
+    assert_eq!(axum_path("/v1/a/{x}/b/{y=**}"), "/v1/a/{p0}/b/{*p1}");
+    assert!(parse_template("/v1/{name=shelves/*}").is_err());
+}
@@ -1,3 +1,7 @@
 #![allow(unused, dead_code, unreachable_code, path_statements, unexpected_cfgs)]
 
 mod serde;
+
+pub use self::serde::_01_json::{ApiResponse, PageMeta};
+pub use self::serde::_02_yaml::{Gateway, GatewayConfig, Route};
+pub use self::serde::_03_config::{ConfigError, ConfigLoader, Secret, Settings};
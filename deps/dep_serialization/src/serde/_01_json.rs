@@ -76,10 +76,45 @@ fn read_json_from_file() {
 }
 
 // 定义一个泛型结构体
+// 同时也是 dep_web 等服务共用的响应信封，列表接口会额外带上 meta 分页信息
 #[derive(Serialize, Deserialize, Debug)]
-struct ApiResponse<T> {
-    status: String,
-    data: T,
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ApiResponse<T> {
+    pub status: String,
+    pub data: T,
+    // 老的 JSON 里没有 meta 字段，反序列化时给 None；为 None 时也不输出
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<PageMeta>,
+}
+
+impl<T> ApiResponse<T> {
+    pub fn success(data: T) -> Self {
+        ApiResponse {
+            status: "success".to_string(),
+            data,
+            meta: None,
+        }
+    }
+
+    pub fn with_meta(mut self, meta: PageMeta) -> Self {
+        self.meta = Some(meta);
+        self
+    }
+}
+
+// 分页信息：offset 分页时有 offset/total，游标分页时只有 next_cursor
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PageMeta {
+    pub limit: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+    // 不透明的游标，原样放到下一次请求的 cursor 参数里
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    pub has_more: bool,
 }
 
 // 第一个结构体：产品信息
@@ -108,6 +143,7 @@ fn generic_struct_to_json() {
             name: "Laptop".to_string(),
             price: 1299.99,
         },
+        meta: None,
     };
 
     let product_json = serde_json::to_string_pretty(&product_response).unwrap();
@@ -121,6 +157,7 @@ fn generic_struct_to_json() {
             product_ids: vec![101, 102, 103],
             total_amount: 2999.50,
         },
+        meta: None,
     };
 
     let order_json = serde_json::to_string_pretty(&order_response).unwrap();
@@ -158,6 +195,32 @@ fn generic_json_to_struct() {
     println!("Parsed Order Struct: {:?}", parsed_order);
 }
 
+#[test]
+fn generic_response_with_page_meta() {
+    let page = ApiResponse::success(vec![Product {
+        id: 1,
+        name: "Mouse".to_string(),
+        price: 9.9,
+    }])
+    .with_meta(PageMeta {
+        limit: 1,
+        next_cursor: Some("abc".to_string()),
+        has_more: true,
+        ..PageMeta::default()
+    });
+
+    let value = serde_json::to_value(&page).unwrap();
+    println!("Page JSON:\n{}", serde_json::to_string_pretty(&value).unwrap());
+    // 没有值的 offset/total 不会输出
+    assert_eq!(
+        value["meta"],
+        serde_json::json!({"limit": 1, "next_cursor": "abc", "has_more": true})
+    );
+
+    let parsed: ApiResponse<Vec<Product>> = serde_json::from_value(value).unwrap();
+    assert_eq!(parsed.meta.unwrap().next_cursor.as_deref(), Some("abc"));
+}
+
 #[test]
 fn generic_json_to_dynamic() {
     let json_input = r#"
@@ -1,22 +1,25 @@
 use serde::{Deserialize, Serialize};
 use std::fs;
 
-#[derive(Debug, Serialize, Deserialize)]
-struct GatewayConfig {
-    gateway: Gateway,
+// dep_web 的 gateway 直接读这份配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GatewayConfig {
+    pub gateway: Gateway,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Gateway {
-    routes: Vec<Route>,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Gateway {
+    pub routes: Vec<Route>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Route {
-    id: String,
-    uri: String,
-    predicates: Vec<String>,
-    filters: Vec<String>,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Route {
+    pub id: String,
+    pub uri: String,
+    pub predicates: Vec<String>,
+    // 不需要过滤器的路由可以不写 filters
+    #[serde(default)]
+    pub filters: Vec<String>,
 }
 
 #[test]
@@ -0,0 +1,579 @@
+// 分层配置：各个二进制(dep_web、gateway、gRPC demo)共用同一套加载逻辑，配置本身是各自的强类型结构体
+// 优先级从低到高：
+// 1. 结构体的 Default
+// 2. YAML 文件，--config <path> 或者 <PREFIX>_CONFIG 指定，格式同 _02_yaml.rs
+// 3. .env(dotenvy 读进进程环境变量，不覆盖已有的)
+// 4. 环境变量 <PREFIX>__SECTION__KEY，比如 APP__SERVER__LISTEN
+// 5. 命令行 --section.key value 或 --section.key=value，横线等同下划线：--server.drain-secs 5
+// 环境变量和命令行的值按默认值的类型解析：默认值是数字、布尔、列表、对象时按 YAML 解析("[a, b]" 是列表)，
+// 是字符串时原样当字符串；默认为空的(Option、Map 里新加的键)先当字符串，类型对不上再按 YAML 试一次，
+// 所以 Option<String> 里的 "0123" 还是 "0123"，Option<u16> 里的 "30" 是 30
+// 配置文件和环境变量、命令行一样，有字段定义的对象里出现没有的键就报错；空的 Map 不管
+// 出错时报出是哪个键、来自哪一层，但不带值本身；密码之类用 Secret 包一下，Debug 打印出来是 ***
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+pub trait Settings: Serialize + DeserializeOwned + Default {
+    /// 反序列化之后的业务校验，返回所有问题，每条形如 "server.listen: ..."
+    fn validate(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("read config file {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("parse config file {path}: {source}")]
+    Yaml {
+        path: PathBuf,
+        source: serde_yaml::Error,
+    },
+    #[error("{0}")]
+    Flag(String),
+    #[error("unknown config key `{key}` (from {origin})")]
+    UnknownKey { key: String, origin: String },
+    #[error("invalid config `{key}`: {message} (from {origin})")]
+    Invalid {
+        key: String,
+        message: String,
+        origin: String,
+    },
+    #[error("invalid config:\n  {}", .0.join("\n  "))]
+    Validation(Vec<String>),
+}
+
+pub struct ConfigLoader {
+    prefix: String,
+    file: Option<PathBuf>,
+    args: Vec<String>,
+    vars: Option<Vec<(String, String)>>,
+}
+
+impl ConfigLoader {
+    /// prefix 是环境变量前缀，比如 "APP"
+    pub fn new(prefix: impl Into<String>) -> Self {
+        ConfigLoader {
+            prefix: prefix.into(),
+            file: None,
+            args: Vec::new(),
+            vars: None,
+        }
+    }
+
+    /// 默认的配置文件，不存在就跳过；--config / <PREFIX>_CONFIG 指定的文件不存在会报错
+    pub fn file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.file = Some(path.into());
+        self
+    }
+
+    /// 命令行参数，不含程序名
+    pub fn args(mut self, args: impl IntoIterator<Item = String>) -> Self {
+        self.args = args.into_iter().collect();
+        self
+    }
+
+    /// 用给定的环境变量代替进程的(测试用)，这时也不会去读 .env
+    pub fn vars(mut self, vars: impl IntoIterator<Item = (String, String)>) -> Self {
+        self.vars = Some(vars.into_iter().collect());
+        self
+    }
+
+    pub fn load<T: Settings>(self) -> Result<T, ConfigError> {
+        let mut tree = serde_json::to_value(T::default()).expect("defaults serialize to JSON");
+        let mut origins = HashMap::new();
+        // 默认为空、先当字符串放进去的键 -> 原始值
+        let mut untyped = HashMap::new();
+
+        let flags = parse_flags(&self.args)?;
+        let vars = match self.vars {
+            Some(vars) => vars,
+            None => {
+                dotenvy::dotenv().ok();
+                std::env::vars().collect()
+            }
+        };
+
+        // 配置文件：命令行 > 环境变量 > 默认路径
+        let file_var = format!("{}_CONFIG", self.prefix);
+        let explicit = flags
+            .iter()
+            .find(|(key, _)| key == "config")
+            .map(|(_, value)| PathBuf::from(value))
+            .or_else(|| {
+                vars.iter()
+                    .find(|(key, _)| *key == file_var)
+                    .map(|(_, value)| PathBuf::from(value))
+            });
+        let file = match (explicit, self.file) {
+            (Some(path), _) => Some(path),
+            (None, Some(path)) if path.exists() => Some(path),
+            _ => None,
+        };
+        if let Some(path) = file {
+            let yaml = read_yaml(&path)?;
+            let origin = format!("file {}", path.display());
+            if !yaml.is_null() {
+                merge(&mut tree, yaml, "", &origin, &mut origins)?;
+            }
+        }
+
+        let env_prefix = format!("{}__", self.prefix);
+        let mut env: Vec<_> = vars
+            .iter()
+            .filter_map(|(key, value)| Some((key.strip_prefix(&env_prefix)?, key, value)))
+            .collect();
+        // 环境变量顺序不固定，排一下让报错稳定
+        env.sort();
+        for (path, key, value) in env {
+            let path: Vec<String> = path.split("__").map(|s| s.to_lowercase()).collect();
+            set(
+                &mut tree,
+                &path,
+                value,
+                &format!("env {}", key),
+                &mut origins,
+                &mut untyped,
+            )?;
+        }
+
+        for (key, value) in flags.iter().filter(|(key, _)| key != "config") {
+            let path: Vec<String> = key.split('.').map(|s| s.replace('-', "_")).collect();
+            set(
+                &mut tree,
+                &path,
+                value,
+                &format!("flag --{}", key),
+                &mut origins,
+                &mut untyped,
+            )?;
+        }
+
+        let config: T = loop {
+            let e = match serde_path_to_error::deserialize(tree.clone()) {
+                Ok(config) => break config,
+                Err(e) => e,
+            };
+            let key = e.path().to_string();
+            if let Some(raw) = untyped.remove(&key)
+                && let Some(node) = lookup(&mut tree, &key)
+            {
+                *node = serde_yaml::from_str(&raw).unwrap_or(Value::String(raw));
+                continue;
+            }
+            return Err(ConfigError::Invalid {
+                origin: origin_of(&origins, &key),
+                message: redact(&e.into_inner().to_string()),
+                key,
+            });
+        };
+        let problems = config.validate();
+        if !problems.is_empty() {
+            return Err(ConfigError::Validation(problems));
+        }
+        Ok(config)
+    }
+}
+
+fn read_yaml(path: &Path) -> Result<Value, ConfigError> {
+    let text = std::fs::read_to_string(path).map_err(|source| ConfigError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    // 空文件当成没有配置
+    let value: Option<Value> = serde_yaml::from_str(&text).map_err(|source| ConfigError::Yaml {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    Ok(value.unwrap_or(Value::Null))
+}
+
+fn parse_flags(args: &[String]) -> Result<Vec<(String, String)>, ConfigError> {
+    let mut flags = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        // 不回显参数本身，可能是放错了位置的密码
+        let Some(flag) = arg.strip_prefix("--") else {
+            return Err(ConfigError::Flag(
+                "unexpected argument, expected --section.key value".to_string(),
+            ));
+        };
+        let (key, value) = match flag.split_once('=') {
+            Some((key, value)) => (key.to_string(), value.to_string()),
+            None => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| ConfigError::Flag(format!("missing value for --{}", flag)))?;
+                (flag.to_string(), value.clone())
+            }
+        };
+        flags.push((key, value));
+    }
+    Ok(flags)
+}
+
+fn join(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", prefix, key)
+    }
+}
+
+// 对象逐个键合并，其它类型(包括列表)整个替换；规则和 set 一样，有字段定义的对象里不认识的键报错
+fn merge(
+    base: &mut Value,
+    overlay: Value,
+    prefix: &str,
+    origin: &str,
+    origins: &mut HashMap<String, String>,
+) -> Result<(), ConfigError> {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            let known = base.is_empty();
+            for (key, value) in overlay {
+                let path = join(prefix, &key);
+                if !known && !base.contains_key(&key) {
+                    return Err(ConfigError::UnknownKey {
+                        key: path,
+                        origin: origin.to_string(),
+                    });
+                }
+                let slot = base.entry(key).or_insert(Value::Null);
+                merge(slot, value, &path, origin, origins)?;
+            }
+        }
+        (base, overlay) => {
+            *base = overlay;
+            origins.insert(prefix.to_string(), origin.to_string());
+        }
+    }
+    Ok(())
+}
+
+// 沿着默认值的结构往下找；有字段定义的对象里出现没有的键就是拼错了，空对象(比如 HashMap)和空值不管
+fn set(
+    tree: &mut Value,
+    path: &[String],
+    raw: &str,
+    origin: &str,
+    origins: &mut HashMap<String, String>,
+    untyped: &mut HashMap<String, String>,
+) -> Result<(), ConfigError> {
+    let key = path.join(".");
+    let mut node = tree;
+    for segment in path {
+        if node.is_null() {
+            *node = Value::Object(Map::new());
+        }
+        let Value::Object(map) = node else {
+            return Err(ConfigError::UnknownKey {
+                key,
+                origin: origin.to_string(),
+            });
+        };
+        if !map.is_empty() && !map.contains_key(segment) {
+            return Err(ConfigError::UnknownKey {
+                key,
+                origin: origin.to_string(),
+            });
+        }
+        node = map.entry(segment.clone()).or_insert(Value::Null);
+    }
+    *node = match node {
+        // 环境变量先设过、命令行又设一次时，node 已经是字符串了，记下的原始值也要跟着换
+        Value::String(_) => {
+            if let Some(previous) = untyped.get_mut(&key) {
+                *previous = raw.to_string();
+            }
+            Value::String(raw.to_string())
+        }
+        Value::Null => {
+            untyped.insert(key.clone(), raw.to_string());
+            Value::String(raw.to_string())
+        }
+        _ => serde_yaml::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string())),
+    };
+    origins.insert(key, origin.to_string());
+    Ok(())
+}
+
+// 按 serde_path_to_error 给的 a.b.c 找回 set 放进去的那个值
+fn lookup<'a>(tree: &'a mut Value, key: &str) -> Option<&'a mut Value> {
+    key.split('.')
+        .try_fold(tree, |node, segment| node.as_object_mut()?.get_mut(segment))
+}
+
+// serde 的报错会带上值本身(invalid type: string "hunter2", expected u16)，只留类型
+fn redact(message: &str) -> String {
+    for prefix in ["invalid type: ", "invalid value: "] {
+        if let Some(rest) = message.strip_prefix(prefix)
+            && let Some((unexpected, expected)) = rest.split_once(", expected ")
+        {
+            let kind = unexpected.split_whitespace().next().unwrap_or_default();
+            return format!("{}{}, expected {}", prefix, kind, expected);
+        }
+    }
+    if let Some(rest) = message.strip_prefix("unknown variant `")
+        && let Some((_, expected)) = rest.split_once("`, expected ")
+    {
+        return format!("unknown variant, expected {}", expected);
+    }
+    message.to_string()
+}
+
+// 出错的键可能是某一层整个替换进来的对象里面的，往上找最近一个有来源的
+fn origin_of(origins: &HashMap<String, String>, key: &str) -> String {
+    let mut key = key;
+    loop {
+        if let Some(origin) = origins.get(key) {
+            return origin.clone();
+        }
+        match key.rfind(['.', '[']) {
+            Some(i) => key = &key[..i],
+            None => return "defaults".to_string(),
+        }
+    }
+}
+
+/// 密码、密钥之类，Debug / Display 都只打印 ***，用的时候 expose()
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Secret(value)
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("\"***\"")
+    }
+}
+
+impl<T> fmt::Display for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("***")
+    }
+}
+
+// 序列化保持原值，分层合并要靠它；不要拿 Serialize 的结果去打日志
+impl<T: Serialize> Serialize for Secret<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Secret<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Secret)
+    }
+}
+
+#[cfg(test)]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+struct DemoConfig {
+    server: DemoServer,
+    database: DemoDatabase,
+    tags: Vec<String>,
+    labels: HashMap<String, String>,
+}
+
+#[cfg(test)]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+struct DemoServer {
+    listen: std::net::SocketAddr,
+    workers: u16,
+    debug: bool,
+    timeout_secs: Option<u16>,
+}
+
+#[cfg(test)]
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct DemoDatabase {
+    url: Secret<String>,
+    user: Option<String>,
+}
+
+#[cfg(test)]
+impl Default for DemoConfig {
+    fn default() -> Self {
+        DemoConfig {
+            server: DemoServer::default(),
+            database: DemoDatabase {
+                url: Secret::new("postgres://localhost/demo".to_string()),
+                user: None,
+            },
+            tags: vec!["default".to_string()],
+            labels: HashMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Default for DemoServer {
+    fn default() -> Self {
+        DemoServer {
+            listen: "127.0.0.1:3000".parse().unwrap(),
+            workers: 4,
+            debug: false,
+            timeout_secs: None,
+        }
+    }
+}
+
+#[cfg(test)]
+impl Settings for DemoConfig {
+    fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.server.workers == 0 {
+            problems.push("server.workers: must be at least 1".to_string());
+        }
+        if !self.database.url.expose().starts_with("postgres://") {
+            problems.push("database.url: must start with postgres://".to_string());
+        }
+        problems
+    }
+}
+
+#[test]
+fn layered_config() {
+    let dir = std::env::temp_dir().join(format!("layered-config-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("app.yaml");
+    std::fs::write(
+        &file,
+        "server:\n  workers: 8\n  debug: true\ntags: [a, b]\ndatabase:\n  url: postgres://file/db\n",
+    )
+    .unwrap();
+    let load = |vars: &[(&str, &str)], args: &[&str]| {
+        ConfigLoader::new("APP")
+            .file(&file)
+            .vars(vars.iter().map(|(k, v)| (k.to_string(), v.to_string())))
+            .args(args.iter().map(|s| s.to_string()))
+            .load::<DemoConfig>()
+    };
+
+    // 默认值 < 文件 < 环境变量 < 命令行
+    let config = load(
+        &[
+            ("APP__SERVER__WORKERS", "16"),
+            ("APP__DATABASE__USER", "admin"),
+            ("APP__LABELS__REGION", "cn"),
+            ("OTHER__SERVER__WORKERS", "1"),
+        ],
+        &["--server.workers", "32", "--server.listen=0.0.0.0:8080"],
+    )
+    .unwrap();
+    assert_eq!(config.server.workers, 32);
+    assert_eq!(config.server.listen.port(), 8080);
+    assert!(config.server.debug);
+    assert_eq!(config.tags, ["a", "b"]);
+    assert_eq!(config.database.url.expose(), "postgres://file/db");
+    assert_eq!(config.database.user.as_deref(), Some("admin"));
+    assert_eq!(config.labels["region"], "cn");
+
+    // 默认为空的字段：要字符串的原样保留，要数字的再按 YAML 解析
+    let config = load(
+        &[
+            ("APP__DATABASE__USER", "0123"),
+            ("APP__LABELS__ZONE", "007"),
+            ("APP__SERVER__TIMEOUT_SECS", "10"),
+        ],
+        &["--server.timeout-secs", "30"],
+    )
+    .unwrap();
+    assert_eq!(config.database.user.as_deref(), Some("0123"));
+    assert_eq!(config.labels["zone"], "007");
+    assert_eq!(config.server.timeout_secs, Some(30));
+
+    let config = load(&[], &[]).unwrap();
+    assert_eq!(config.server.workers, 8);
+    assert_eq!(config.database.user, None);
+
+    // 打印时密码不出来
+    let printed = format!("{:?}", config);
+    assert!(printed.contains("url: \"***\""), "{}", printed);
+    assert!(!printed.contains("file/db"));
+
+    // --config 指定别的文件，不存在就报错
+    let other = dir.join("other.yaml");
+    std::fs::write(&other, "server:\n  workers: 2\n").unwrap();
+    let config = load(&[], &["--config", other.to_str().unwrap()]).unwrap();
+    assert_eq!(config.server.workers, 2);
+    let err = load(&[("APP_CONFIG", "/nonexistent.yaml")], &[]).unwrap_err();
+    assert!(matches!(err, ConfigError::Io { .. }), "{}", err);
+
+    // 报错带上键和来源
+    let err = load(&[("APP__SERVER__WORKERS", "many")], &[]).unwrap_err();
+    let message = err.to_string();
+    assert!(
+        message.starts_with("invalid config `server.workers`:"),
+        "{}",
+        message
+    );
+    assert!(
+        message.ends_with("(from env APP__SERVER__WORKERS)"),
+        "{}",
+        message
+    );
+    // 值本身不出现在报错里
+    assert!(!message.contains("many"), "{}", message);
+    let err = load(&[("APP__SERVER__TIMEOUT_SECS", "hunter2")], &[]).unwrap_err();
+    assert!(err.to_string().contains("`server.timeout_secs`"), "{}", err);
+    assert!(!err.to_string().contains("hunter2"), "{}", err);
+    let err = load(&[], &["--server.workers", "1", "hunter2"]).unwrap_err();
+    assert!(!err.to_string().contains("hunter2"), "{}", err);
+    let err = load(&[], &["--server.port", "80"]).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "unknown config key `server.port` (from flag --server.port)"
+    );
+    let err = load(&[], &["--server.workers"]).unwrap_err();
+    assert_eq!(err.to_string(), "missing value for --server.workers");
+    // 配置文件里拼错的键也报错，Map 里随便加
+    std::fs::write(&other, "server:\n  port: 80\nlabels:\n  any: thing\n").unwrap();
+    let err = load(&[], &["--config", other.to_str().unwrap()]).unwrap_err();
+    assert!(
+        err.to_string()
+            .starts_with("unknown config key `server.port` (from file "),
+        "{}",
+        err
+    );
+    std::fs::write(&other, "labels:\n  any: thing\n").unwrap();
+    let config = load(&[], &["--config", other.to_str().unwrap()]).unwrap();
+    assert_eq!(config.labels["any"], "thing");
+    std::fs::write(&other, "tags: [a, {x: 2}]\n").unwrap();
+    let err = load(&[], &["--config", other.to_str().unwrap()]).unwrap_err();
+    assert!(err.to_string().contains("`tags[1]`"), "{}", err);
+    assert!(err.to_string().contains("(from file "), "{}", err);
+
+    // 校验一次报出所有问题
+    let err = load(
+        &[("APP__DATABASE__URL", "mysql://x")],
+        &["--server.workers", "0"],
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid config:\n  server.workers: must be at least 1\n  database.url: must start with postgres://"
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -1,2 +1,3 @@
-mod _01_json;
-mod _02_yaml;
+pub mod _01_json;
+pub mod _02_yaml;
+pub mod _03_config;
@@ -0,0 +1,223 @@
+// 统一的模板引擎接口：Handlebars / Tera / MiniJinja 每个项目挑一个，调用方只认 TemplateEngine
+// 1. 模板从一个目录递归加载，模板名是相对路径(用 / 分隔，带扩展名)，比如 layouts/base.html
+// 2. 布局和片段用各引擎自己的写法：
+//    Tera / MiniJinja：{% extends "layouts/base.html" %} 加 {% block %}，片段用 {% include "partials/nav.html" %}
+//    Handlebars：页面用 {{#> layouts/base.hbs}}...{{/layouts/base.hbs}} 包起来，
+//    布局里 {{> @partial-block}} 放正文，{{#> title}}默认{{/title}} 留给页面用 {{#*inline "title"}} 覆盖，
+//    片段用 {{> partials/nav.hbs}}
+// 3. 自动转义：Handlebars 的 {{ }} 全部转义；Tera / MiniJinja 按扩展名，.html / .htm / .xml 的转义
+// 4. 数据统一传 serde_json::Value，出错返回 anyhow::Error，错误里带模板名
+
+use anyhow::Context as _;
+use handlebars::Handlebars;
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+pub trait TemplateEngine: Send + Sync {
+    fn render(&self, name: &str, data: &Value) -> anyhow::Result<String>;
+
+    fn has_template(&self, name: &str) -> bool;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EngineKind {
+    Handlebars,
+    Tera,
+    MiniJinja,
+}
+
+impl FromStr for EngineKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "handlebars" | "hbs" => Ok(EngineKind::Handlebars),
+            "tera" => Ok(EngineKind::Tera),
+            "minijinja" | "jinja" => Ok(EngineKind::MiniJinja),
+            other => anyhow::bail!("unknown template engine {}", other),
+        }
+    }
+}
+
+impl EngineKind {
+    pub fn load(self, dir: impl AsRef<Path>) -> anyhow::Result<Arc<dyn TemplateEngine>> {
+        let dir = dir.as_ref();
+        Ok(match self {
+            EngineKind::Handlebars => Arc::new(HandlebarsEngine::from_dir(dir)?),
+            EngineKind::Tera => Arc::new(TeraEngine::from_dir(dir)?),
+            EngineKind::MiniJinja => Arc::new(MiniJinjaEngine::from_dir(dir)?),
+        })
+    }
+}
+
+// 递归读出目录下所有文件，返回 (模板名, 内容)，按名字排序保证每次加载顺序一样
+fn read_templates(dir: &Path) -> anyhow::Result<Vec<(String, String)>> {
+    fn walk(root: &Path, dir: &Path, out: &mut Vec<(String, String)>) -> anyhow::Result<()> {
+        let entries =
+            std::fs::read_dir(dir).with_context(|| format!("read dir {}", dir.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                walk(root, &path, out)?;
+                continue;
+            }
+            let name = path
+                .strip_prefix(root)?
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            let content = std::fs::read_to_string(&path)
+                .with_context(|| format!("read template {}", path.display()))?;
+            out.push((name, content));
+        }
+        Ok(())
+    }
+
+    let mut templates = Vec::new();
+    walk(dir, dir, &mut templates)?;
+    templates.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(templates)
+}
+
+pub struct HandlebarsEngine {
+    registry: Handlebars<'static>,
+}
+
+impl HandlebarsEngine {
+    pub fn from_dir(dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let mut registry = Handlebars::new();
+        for (name, content) in read_templates(dir.as_ref())? {
+            // 注册成模板的同时也能当 partial 用
+            registry
+                .register_template_string(&name, content)
+                .with_context(|| format!("parse template {}", name))?;
+        }
+        Ok(HandlebarsEngine { registry })
+    }
+}
+
+impl TemplateEngine for HandlebarsEngine {
+    fn render(&self, name: &str, data: &Value) -> anyhow::Result<String> {
+        self.registry
+            .render(name, data)
+            .with_context(|| format!("render template {}", name))
+    }
+
+    fn has_template(&self, name: &str) -> bool {
+        self.registry.has_template(name)
+    }
+}
+
+pub struct TeraEngine {
+    tera: tera::Tera,
+}
+
+impl TeraEngine {
+    pub fn from_dir(dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let mut tera = tera::Tera::default();
+        // 一次加进去，继承关系才能解析，父模板在不在前面都行
+        tera.add_raw_templates(read_templates(dir.as_ref())?)
+            .context("parse tera templates")?;
+        Ok(TeraEngine { tera })
+    }
+}
+
+impl TemplateEngine for TeraEngine {
+    fn render(&self, name: &str, data: &Value) -> anyhow::Result<String> {
+        let context = tera::Context::from_value(data.clone())?;
+        self.tera
+            .render(name, &context)
+            .with_context(|| format!("render template {}", name))
+    }
+
+    fn has_template(&self, name: &str) -> bool {
+        self.tera.get_template_names().any(|n| n == name)
+    }
+}
+
+pub struct MiniJinjaEngine {
+    env: minijinja::Environment<'static>,
+}
+
+impl MiniJinjaEngine {
+    pub fn from_dir(dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let mut env = minijinja::Environment::new();
+        for (name, content) in read_templates(dir.as_ref())? {
+            env.add_template_owned(name.clone(), content)
+                .with_context(|| format!("parse template {}", name))?;
+        }
+        Ok(MiniJinjaEngine { env })
+    }
+}
+
+impl TemplateEngine for MiniJinjaEngine {
+    fn render(&self, name: &str, data: &Value) -> anyhow::Result<String> {
+        self.env
+            .get_template(name)
+            .and_then(|tpl| tpl.render(data))
+            .with_context(|| format!("render template {}", name))
+    }
+
+    fn has_template(&self, name: &str) -> bool {
+        self.env.get_template(name).is_ok()
+    }
+}
+
+#[cfg(test)]
+fn testdata(dir: &str) -> std::path::PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("testdata/templates")
+        .join(dir)
+}
+
+#[test]
+fn engines_render_layouts_partials_and_escape() {
+    let data = serde_json::json!({
+        "current_user": "admin",
+        "users": [
+            {"name": "<b>bob</b>", "age": 30},
+            {"name": "alice", "age": 25},
+        ],
+    });
+    // 同一份数据，三个引擎各自的模板渲染出来的内容一样
+    for (kind, dir, page) in [
+        (EngineKind::Handlebars, "handlebars", "users.hbs"),
+        (EngineKind::Tera, "jinja", "users.html"),
+        (EngineKind::MiniJinja, "jinja", "users.html"),
+    ] {
+        let engine = kind.load(testdata(dir)).unwrap();
+        assert!(engine.has_template(page));
+        assert!(!engine.has_template("missing.html"));
+
+        let html = engine.render(page, &data).unwrap();
+        assert!(
+            html.contains("<title>Users</title>"),
+            "{:?}: {}",
+            kind,
+            html
+        );
+        assert!(
+            html.contains("<nav>signed in as admin</nav>"),
+            "{:?}: {}",
+            kind,
+            html
+        );
+        assert!(html.contains("<li>alice (25)</li>"), "{:?}: {}", kind, html);
+        assert!(html.contains("&lt;b&gt;bob"), "{:?}: {}", kind, html);
+        assert!(!html.contains("<b>"), "{:?}: {}", kind, html);
+
+        let err = engine.render("missing.html", &data).unwrap_err();
+        assert!(format!("{:#}", err).contains("missing.html"));
+    }
+
+    assert_eq!(
+        "Jinja".parse::<EngineKind>().unwrap(),
+        EngineKind::MiniJinja
+    );
+    assert!("mustache".parse::<EngineKind>().is_err());
+}
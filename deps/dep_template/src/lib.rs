@@ -1,5 +1,9 @@
-mod _00_common;
 mod _01_native;
 mod _02_handlebars;
 mod _03_tera;
 mod _04_minijinja;
+pub mod _05_engine;
+
+pub use self::_05_engine::{
+    EngineKind, HandlebarsEngine, MiniJinjaEngine, TemplateEngine, TeraEngine,
+};
@@ -0,0 +1,422 @@
+// 前端静态文件，挂在所有路由后面兜底
+// 1. 来源可以是构建目录(Assets::dir)，也可以是用 rust-embed 编进二进制的(Assets::embedded)，单文件部署用后者
+// 2. 预压缩：构建时在原文件旁边生成 app.js.br / app.js.gz，按 Accept-Encoding 优先 br 再 gzip，
+//    都没有或者客户端不支持就给原文件；响应一律带 Vary: Accept-Encoding；
+//    .br / .gz 本身不能直接请求(404)，不然拿到的是没有 Content-Encoding 的压缩字节
+// 3. 强 ETag：嵌入的用内容的 SHA-256，目录里的用 长度-修改时间(和 nginx 一样，不用每次读整个文件)，
+//    压缩版本内容不同，ETag 加上 -br / -gz 区分
+// 4. Cache-Control 按路径模式配置，先匹配上的生效，都没匹配上是 no-cache(每次带 ETag 回来验证)
+// 5. SPA 回退：找不到文件、路径最后一段又没有扩展名时返回 index.html，交给前端路由；
+//    带扩展名的找不到就是 404，不会把 index.html 当成 js 返回
+
+use crate::error::ApiError;
+use crate::files::etag_matches;
+use crate::gateway::PathPattern;
+use axum::Router;
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::header::{
+    ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG,
+    IF_NONE_MATCH, VARY,
+};
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode};
+use axum::response::{IntoResponse, Response};
+use rust_embed::{EmbeddedFile, RustEmbed};
+use std::borrow::Cow;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use tokio_util::io::ReaderStream;
+
+const INDEX: &str = "index.html";
+// 按这个顺序尝试，q 值一样时 br 优先
+const ENCODINGS: [(&str, &str); 2] = [("br", ".br"), ("gzip", ".gz")];
+
+enum Source {
+    Dir(PathBuf),
+    Embedded(fn(&str) -> Option<EmbeddedFile>),
+}
+
+struct Found {
+    body: Body,
+    len: u64,
+    etag: String,
+}
+
+impl Source {
+    async fn open(&self, path: &str) -> std::io::Result<Option<Found>> {
+        match self {
+            Source::Dir(root) => {
+                let path = root.join(path);
+                let file = match tokio::fs::File::open(&path).await {
+                    Ok(file) => file,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                    Err(e) => return Err(e),
+                };
+                let meta = file.metadata().await?;
+                if !meta.is_file() {
+                    return Ok(None);
+                }
+                let modified = meta
+                    .modified()?
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos();
+                Ok(Some(Found {
+                    body: Body::from_stream(ReaderStream::new(file)),
+                    len: meta.len(),
+                    etag: format!("{:x}-{:x}", meta.len(), modified),
+                }))
+            }
+            Source::Embedded(get) => Ok(get(path).map(|file| {
+                let etag = hex::encode(file.metadata.sha256_hash());
+                let data = match file.data {
+                    Cow::Borrowed(data) => bytes::Bytes::from_static(data),
+                    Cow::Owned(data) => bytes::Bytes::from(data),
+                };
+                Found {
+                    len: data.len() as u64,
+                    body: Body::from(data),
+                    etag,
+                }
+            })),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Assets {
+    source: Arc<Source>,
+    cache_rules: Vec<(PathPattern, HeaderValue)>,
+    spa_fallback: bool,
+}
+
+impl Assets {
+    /// 前端构建目录，比如 frontend/dist
+    pub fn dir(dir: impl Into<PathBuf>) -> Self {
+        Self::new(Source::Dir(dir.into()))
+    }
+
+    /// 编进二进制的文件：
+    /// `#[derive(rust_embed::RustEmbed)] #[folder = "frontend/dist"] struct Frontend;`
+    /// 然后 `Assets::embedded::<Frontend>()`
+    pub fn embedded<E: RustEmbed>() -> Self {
+        Self::new(Source::Embedded(E::get))
+    }
+
+    fn new(source: Source) -> Self {
+        Assets {
+            source: Arc::new(source),
+            cache_rules: Vec::new(),
+            spa_fallback: true,
+        }
+    }
+
+    /// 比如带 hash 的构建产物：`cache_control(PathPattern::parse("/assets/**")?, "public, max-age=31536000, immutable")`
+    pub fn cache_control(mut self, path: PathPattern, value: &'static str) -> Self {
+        self.cache_rules
+            .push((path, HeaderValue::from_static(value)));
+        self
+    }
+
+    pub fn spa_fallback(mut self, enabled: bool) -> Self {
+        self.spa_fallback = enabled;
+        self
+    }
+
+    /// 只有一个 fallback，merge 到业务路由上，没匹配上的请求才会走到这里
+    pub fn router<S: Clone + Send + Sync + 'static>(self) -> Router<S> {
+        Router::new().fallback(move |req: Request| {
+            let assets = self.clone();
+            async move {
+                assets
+                    .serve(req.method(), req.uri().path(), req.headers())
+                    .await
+            }
+        })
+    }
+
+    fn cache_control_for(&self, path: &str) -> HeaderValue {
+        self.cache_rules
+            .iter()
+            .find(|(pattern, _)| pattern.matches(path))
+            .map(|(_, value)| value.clone())
+            .unwrap_or(HeaderValue::from_static("no-cache"))
+    }
+
+    // 先试客户端支持的压缩版本，再试原文件
+    async fn open(
+        &self,
+        path: &str,
+        headers: &HeaderMap,
+    ) -> std::io::Result<Option<(Found, Option<&'static str>)>> {
+        for (encoding, suffix) in accepted_encodings(headers) {
+            if let Some(mut found) = self.source.open(&format!("{}{}", path, suffix)).await? {
+                found.etag = format!("{}{}", found.etag, suffix.replace('.', "-"));
+                return Ok(Some((found, Some(encoding))));
+            }
+        }
+        Ok(self.source.open(path).await?.map(|found| (found, None)))
+    }
+
+    pub async fn serve(&self, method: &Method, path: &str, headers: &HeaderMap) -> Response {
+        if method != Method::GET && method != Method::HEAD {
+            return ApiError::NotFound(format!("{} {} 不存在", method, path)).into_response();
+        }
+        let Some(mut file) = normalize(path)
+            .filter(|file| !ENCODINGS.iter().any(|(_, suffix)| file.ends_with(suffix)))
+        else {
+            return ApiError::NotFound(format!("{} 不存在", path)).into_response();
+        };
+        let mut found = match self.open(&file, headers).await {
+            Ok(found) => found,
+            Err(e) => return ApiError::Internal(e.into()).into_response(),
+        };
+        let last = file.rsplit('/').next().unwrap_or_default();
+        if found.is_none() && self.spa_fallback && !last.contains('.') {
+            file = INDEX.to_string();
+            found = match self.open(&file, headers).await {
+                Ok(found) => found,
+                Err(e) => return ApiError::Internal(e.into()).into_response(),
+            };
+        }
+        let Some((found, encoding)) = found else {
+            return ApiError::NotFound(format!("{} 不存在", path)).into_response();
+        };
+
+        let etag = format!("\"{}\"", found.etag);
+        let mut resp_headers = HeaderMap::new();
+        resp_headers.insert(VARY, HeaderValue::from_static("accept-encoding"));
+        resp_headers.insert(CACHE_CONTROL, self.cache_control_for(&format!("/{}", file)));
+        if let Ok(value) = HeaderValue::from_str(&etag) {
+            resp_headers.insert(ETAG, value);
+        }
+        if etag_matches(headers.get(IF_NONE_MATCH), &etag) {
+            return (StatusCode::NOT_MODIFIED, resp_headers).into_response();
+        }
+
+        resp_headers.insert(CONTENT_TYPE, content_type(&file));
+        resp_headers.insert(CONTENT_LENGTH, HeaderValue::from(found.len));
+        if let Some(encoding) = encoding {
+            resp_headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding));
+        }
+        let body = if method == Method::HEAD {
+            Body::empty()
+        } else {
+            found.body
+        };
+        (resp_headers, body).into_response()
+    }
+}
+
+// 解码 %XX，去掉开头的 /，目录补上 index.html；有 .. 或者解出来不是 UTF-8 的一律不要
+fn normalize(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    let decoded = String::from_utf8(decoded).ok()?;
+    if decoded.contains(['\\', '\0']) || decoded.split('/').any(|s| s == "..") {
+        return None;
+    }
+    let mut path = decoded.trim_start_matches('/').to_string();
+    if path.is_empty() || path.ends_with('/') {
+        path.push_str(INDEX);
+    }
+    Some(path)
+}
+
+fn accepted_encodings(headers: &HeaderMap) -> Vec<(&'static str, &'static str)> {
+    let accept = headers
+        .get(ACCEPT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let quality = |name: &str| {
+        let mut wildcard = None;
+        for item in accept.split(',') {
+            let mut parts = item.split(';');
+            let coding = parts.next().unwrap_or_default().trim();
+            let q = parts
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if coding.eq_ignore_ascii_case(name) {
+                return q;
+            }
+            if coding == "*" {
+                wildcard = Some(q);
+            }
+        }
+        wildcard.unwrap_or(0.0)
+    };
+    let mut accepted: Vec<_> = ENCODINGS
+        .into_iter()
+        .map(|encoding| (encoding, quality(encoding.0)))
+        .filter(|(_, q)| *q > 0.0)
+        .collect();
+    // 稳定排序，q 一样时保持 ENCODINGS 里的顺序
+    accepted.sort_by(|a, b| b.1.total_cmp(&a.1));
+    accepted.into_iter().map(|(encoding, _)| encoding).collect()
+}
+
+fn content_type(path: &str) -> HeaderValue {
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+    let text = mime.type_() == mime_guess::mime::TEXT
+        || matches!(mime.subtype().as_str(), "javascript" | "json" | "xml");
+    let value = if text && mime.get_param("charset").is_none() {
+        format!("{}; charset=utf-8", mime)
+    } else {
+        mime.to_string()
+    };
+    HeaderValue::from_str(&value).unwrap_or(HeaderValue::from_static("application/octet-stream"))
+}
+
+#[cfg(test)]
+#[derive(RustEmbed)]
+#[folder = "testdata/assets/"]
+struct TestAssets;
+
+#[test]
+fn normalize_and_negotiate() {
+    assert_eq!(normalize("/").as_deref(), Some("index.html"));
+    assert_eq!(normalize("/docs/").as_deref(), Some("docs/index.html"));
+    assert_eq!(normalize("/a%20b.js").as_deref(), Some("a b.js"));
+    assert_eq!(normalize("/../secret"), None);
+    assert_eq!(normalize("/%2e%2e/secret"), None);
+    assert_eq!(normalize("/bad%zz"), None);
+
+    let encodings = |accept: &'static str| {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static(accept));
+        accepted_encodings(&headers)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(encodings("gzip, deflate, br"), ["br", "gzip"]);
+    assert_eq!(encodings("gzip;q=1, br;q=0.5"), ["gzip", "br"]);
+    assert_eq!(encodings("gzip, br;q=0"), ["gzip"]);
+    assert_eq!(encodings("*"), ["br", "gzip"]);
+    assert!(encodings("identity").is_empty());
+}
+
+#[tokio::test]
+async fn serves_dir_and_embedded_assets() {
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/assets");
+    let read = |path: &str| std::fs::read(format!("{}/{}", dir, path)).unwrap();
+    let immutable = "public, max-age=31536000, immutable";
+
+    for assets in [Assets::dir(dir), Assets::embedded::<TestAssets>()] {
+        let app: Router = Router::new()
+            .route("/api/ping", axum::routing::get(|| async { "pong" }))
+            .merge(
+                assets
+                    .cache_control(PathPattern::parse("/assets/**").unwrap(), immutable)
+                    .router(),
+            );
+        let call = |method: Method, uri: &str, headers: Vec<(&'static str, String)>| {
+            let mut req = Request::builder().method(method).uri(uri);
+            for (name, value) in headers {
+                req = req.header(name, value);
+            }
+            let app = app.clone();
+            let req = req.body(Body::empty()).unwrap();
+            async move {
+                let (parts, body) = app.oneshot(req).await.unwrap().into_parts();
+                (parts, body.collect().await.unwrap().to_bytes())
+            }
+        };
+
+        // 业务路由优先
+        let (_, body) = call(Method::GET, "/api/ping", vec![]).await;
+        assert_eq!(body, "pong");
+
+        let (parts, body) = call(Method::GET, "/", vec![]).await;
+        assert_eq!(parts.status, StatusCode::OK);
+        assert_eq!(parts.headers["content-type"], "text/html; charset=utf-8");
+        assert_eq!(parts.headers["cache-control"], "no-cache");
+        assert_eq!(body, read("index.html"));
+
+        // 前端路由回退到 index.html，带扩展名的不回退
+        let (parts, body) = call(Method::GET, "/users/42/edit", vec![]).await;
+        assert_eq!(parts.status, StatusCode::OK);
+        assert_eq!(body, read("index.html"));
+        let (parts, _) = call(Method::GET, "/assets/missing.js", vec![]).await;
+        assert_eq!(parts.status, StatusCode::NOT_FOUND);
+        let (parts, _) = call(Method::POST, "/users/42/edit", vec![]).await;
+        assert_eq!(parts.status, StatusCode::NOT_FOUND);
+
+        // 预压缩版本
+        let accept = |v: &str| vec![("accept-encoding", v.to_string())];
+        let (br, body) = call(Method::GET, "/assets/app.js", accept("gzip, br")).await;
+        assert_eq!(br.headers["content-encoding"], "br");
+        assert_eq!(br.headers["content-type"], "text/javascript; charset=utf-8");
+        assert_eq!(br.headers["cache-control"], immutable);
+        assert_eq!(br.headers["vary"], "accept-encoding");
+        assert_eq!(body, read("assets/app.js.br"));
+        let (gz, body) = call(Method::GET, "/assets/app.js", accept("gzip")).await;
+        assert_eq!(gz.headers["content-encoding"], "gzip");
+        assert_eq!(body, read("assets/app.js.gz"));
+        // 压缩文件不能直接拿
+        for uri in ["/assets/app.js.br", "/assets/app.js.gz"] {
+            let (parts, _) = call(Method::GET, uri, accept("gzip, br")).await;
+            assert_eq!(parts.status, StatusCode::NOT_FOUND, "{}", uri);
+        }
+        let (plain, body) = call(Method::GET, "/assets/app.js", vec![]).await;
+        assert!(plain.headers.get("content-encoding").is_none());
+        assert_eq!(body, read("assets/app.js"));
+        assert_eq!(plain.headers["content-length"], body.len().to_string());
+        // 没有压缩版本的文件给原文件
+        let (parts, body) = call(Method::GET, "/robots.txt", accept("br")).await;
+        assert!(parts.headers.get("content-encoding").is_none());
+        assert_eq!(body, read("robots.txt"));
+
+        // 每种编码各自的 ETag
+        let etags = [&br, &gz, &plain].map(|p| p.headers["etag"].to_str().unwrap().to_string());
+        assert!(
+            etags
+                .iter()
+                .all(|e| e.starts_with('"') && !e.starts_with("W/"))
+        );
+        assert_ne!(etags[0], etags[1]);
+        assert_ne!(etags[1], etags[2]);
+        let (parts, body) = call(
+            Method::GET,
+            "/assets/app.js",
+            vec![
+                ("accept-encoding", "br".to_string()),
+                ("if-none-match", etags[0].clone()),
+            ],
+        )
+        .await;
+        assert_eq!(parts.status, StatusCode::NOT_MODIFIED);
+        assert!(body.is_empty());
+        let (parts, _) = call(
+            Method::GET,
+            "/assets/app.js",
+            vec![("if-none-match", etags[0].clone())],
+        )
+        .await;
+        assert_eq!(parts.status, StatusCode::OK);
+
+        let (parts, body) = call(Method::HEAD, "/assets/app.js", vec![]).await;
+        assert_eq!(parts.status, StatusCode::OK);
+        assert_eq!(
+            parts.headers["content-length"],
+            read("assets/app.js").len().to_string()
+        );
+        assert!(body.is_empty());
+    }
+}
@@ -0,0 +1,980 @@
+// JWT 认证 + 基于角色的授权
+// 1. AuthKeys：HS256 用共享密钥；RS256 用私钥签发、公钥校验，只校验不签发的服务只配公钥就行
+// 2. AuthLayer 是一个 tower Layer：校验 Authorization: Bearer <token>，把 AuthUser 放进 request extensions
+//    .require_roles([..]) 有其中任意一个角色就行；.require_scopes([..]) 要全部都有；.optional() 不带 token 也放行
+// 3. handler 参数里写 AuthUser 就能拿到当前用户，没经过 AuthLayer 或者匿名访问时是 401
+// 4. POST /auth/login 用 users 表里的 Argon2 密码哈希校验，签发 access + refresh 两个 token
+//    POST /auth/refresh 用 refresh token 换一对新的，GET /auth/me 返回当前用户
+// 5. refresh token 带 jti，记在 refresh_tokens 表里，每个只能换一次(轮换)；
+//    一次登录派生出来的是同一个 family，用过的又被拿来换说明泄露了，整个 family 作废
+//    POST /auth/logout 作废当前这个 family
+// 6. 没配密钥启动直接失败，本地开发要显式配 auth.allow_dev_secret: true 才用内置的开发密钥
+
+use crate::config::AuthConfig;
+use crate::error::{ApiError, ErrorBody};
+use crate::state::AppState;
+use argon2::Argon2;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use axum::extract::{FromRequestParts, OptionalFromRequestParts, Request, State};
+use axum::http::StatusCode;
+use axum::http::header::AUTHORIZATION;
+use axum::http::request::Parts;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use dep_serialization::ApiResponse;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgExecutor, PgPool};
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, LazyLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tower::{Layer, Service};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+pub const DEFAULT_ACCESS_TTL: Duration = Duration::from_secs(15 * 60);
+pub const DEFAULT_REFRESH_TTL: Duration = Duration::from_secs(7 * 24 * 3600);
+
+// auth.allow_dev_secret 打开时的开发用密钥，启动时会打印警告
+const DEV_SECRET: &[u8] = b"dep_web-dev-secret-do-not-use-in-prod";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenType {
+    Access,
+    Refresh,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Claims {
+    // 用户 id
+    pub sub: String,
+    pub name: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    // OAuth 风格，空格分隔
+    #[serde(default)]
+    pub scope: String,
+    pub typ: TokenType,
+    pub iat: u64,
+    pub exp: u64,
+    // 只有 refresh token 有，对应 refresh_tokens 表里的一行
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+}
+
+/// 从 token 里解析出来的当前用户
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthUser {
+    pub id: i32,
+    pub name: String,
+    pub roles: Vec<String>,
+    pub scopes: Vec<String>,
+}
+
+impl AuthUser {
+    pub fn new(id: i32, name: impl Into<String>, roles: Vec<String>) -> Self {
+        let scopes = scopes_for(&roles);
+        AuthUser {
+            id,
+            name: name.into(),
+            roles,
+            scopes,
+        }
+    }
+
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|r| r == role)
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+}
+
+// 角色到权限范围的映射，签发 token 时写进 scope
+fn scopes_for(roles: &[String]) -> Vec<String> {
+    let mut scopes: Vec<String> = Vec::new();
+    for role in roles {
+        let granted: &[&str] = match role.as_str() {
+            "admin" => &["users:read", "users:write"],
+            "user" => &["users:read"],
+            _ => &[],
+        };
+        for scope in granted {
+            if !scopes.iter().any(|s| s == scope) {
+                scopes.push(scope.to_string());
+            }
+        }
+    }
+    scopes
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for AuthUser {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<AuthUser>()
+            .cloned()
+            .ok_or_else(|| ApiError::Unauthorized("需要登录".to_string()))
+    }
+}
+
+// 配合 AuthLayer::optional()，handler 里写 Option<AuthUser>，匿名访问时是 None
+impl<S: Send + Sync> OptionalFromRequestParts<S> for AuthUser {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        Ok(parts.extensions.get::<AuthUser>().cloned())
+    }
+}
+
+#[derive(Clone)]
+pub struct AuthKeys {
+    algorithm: Algorithm,
+    // 只有公钥时不能签发
+    encoding: Option<EncodingKey>,
+    decoding: DecodingKey,
+    pub access_ttl: Duration,
+    pub refresh_ttl: Duration,
+}
+
+fn now() -> u64 {
+    jsonwebtoken::get_current_timestamp()
+}
+
+impl AuthKeys {
+    pub fn hs256(secret: &[u8]) -> Self {
+        AuthKeys {
+            algorithm: Algorithm::HS256,
+            encoding: Some(EncodingKey::from_secret(secret)),
+            decoding: DecodingKey::from_secret(secret),
+            access_ttl: DEFAULT_ACCESS_TTL,
+            refresh_ttl: DEFAULT_REFRESH_TTL,
+        }
+    }
+
+    pub fn rs256(private_pem: Option<&[u8]>, public_pem: &[u8]) -> anyhow::Result<Self> {
+        Ok(AuthKeys {
+            algorithm: Algorithm::RS256,
+            encoding: private_pem.map(EncodingKey::from_rsa_pem).transpose()?,
+            decoding: DecodingKey::from_rsa_pem(public_pem)?,
+            access_ttl: DEFAULT_ACCESS_TTL,
+            refresh_ttl: DEFAULT_REFRESH_TTL,
+        })
+    }
+
+    /// 配置的含义见 config::AuthConfig
+    pub fn from_config(config: &AuthConfig) -> anyhow::Result<Self> {
+        if let Some(public) = &config.jwt_public_key {
+            let public = std::fs::read(public)?;
+            let private = config
+                .jwt_private_key
+                .as_ref()
+                .map(std::fs::read)
+                .transpose()?;
+            return Self::rs256(private.as_deref(), &public);
+        }
+        match &config.jwt_secret {
+            Some(secret) => Ok(Self::hs256(secret.expose().as_bytes())),
+            None if config.allow_dev_secret => {
+                log::warn!("auth.jwt_secret not set, using the built-in dev secret");
+                Ok(Self::hs256(DEV_SECRET))
+            }
+            None => anyhow::bail!(
+                "auth.jwt_secret or auth.jwt_public_key must be set \
+                 (auth.allow_dev_secret: true uses a built-in key for local development)"
+            ),
+        }
+    }
+
+    pub fn with_ttl(mut self, access: Duration, refresh: Duration) -> Self {
+        self.access_ttl = access;
+        self.refresh_ttl = refresh;
+        self
+    }
+
+    pub fn issue(&self, user: &AuthUser, typ: TokenType) -> Result<String, ApiError> {
+        self.issue_with_jti(user, typ, None)
+    }
+
+    fn issue_with_jti(
+        &self,
+        user: &AuthUser,
+        typ: TokenType,
+        jti: Option<String>,
+    ) -> Result<String, ApiError> {
+        let ttl = match typ {
+            TokenType::Access => self.access_ttl,
+            TokenType::Refresh => self.refresh_ttl,
+        };
+        let iat = now();
+        let claims = Claims {
+            sub: user.id.to_string(),
+            name: user.name.clone(),
+            roles: user.roles.clone(),
+            scope: user.scopes.join(" "),
+            typ,
+            iat,
+            exp: iat + ttl.as_secs(),
+            jti,
+        };
+        self.sign(&claims)
+    }
+
+    pub fn sign(&self, claims: &Claims) -> Result<String, ApiError> {
+        let key = self
+            .encoding
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("no private key configured, can not issue tokens"))?;
+        Ok(
+            jsonwebtoken::encode(&Header::new(self.algorithm), claims, key)
+                .map_err(anyhow::Error::from)?,
+        )
+    }
+
+    /// 校验签名、过期时间和 token 类型，refresh token 不能当 access token 用
+    pub fn verify(&self, token: &str, typ: TokenType) -> Result<AuthUser, ApiError> {
+        let claims = self.verify_claims(token, typ)?;
+        let id = claims
+            .sub
+            .parse()
+            .map_err(|_| ApiError::Unauthorized("无效的 token: sub".to_string()))?;
+        Ok(AuthUser {
+            id,
+            name: claims.name,
+            roles: claims.roles,
+            scopes: claims
+                .scope
+                .split_whitespace()
+                .map(str::to_string)
+                .collect(),
+        })
+    }
+
+    pub fn verify_claims(&self, token: &str, typ: TokenType) -> Result<Claims, ApiError> {
+        let mut validation = Validation::new(self.algorithm);
+        validation.leeway = 0;
+        validation.set_required_spec_claims(&["exp", "sub"]);
+        let claims = jsonwebtoken::decode::<Claims>(token, &self.decoding, &validation)
+            .map_err(|e| ApiError::Unauthorized(format!("无效的 token: {}", e)))?
+            .claims;
+        if claims.typ != typ {
+            return Err(ApiError::Unauthorized("token 类型不对".to_string()));
+        }
+        Ok(claims)
+    }
+
+    /// jti 要先记进 refresh_tokens 表，不然 refresh 时换不出来，见 issue_tokens
+    pub fn issue_pair(&self, user: &AuthUser, jti: &str) -> Result<TokenPair, ApiError> {
+        Ok(TokenPair {
+            access_token: self.issue(user, TokenType::Access)?,
+            refresh_token: self.issue_with_jti(user, TokenType::Refresh, Some(jti.to_string()))?,
+            token_type: "Bearer",
+            expires_in: self.access_ttl.as_secs(),
+        })
+    }
+}
+
+fn bearer(parts: &Parts) -> Result<Option<&str>, ApiError> {
+    let Some(value) = parts.headers.get(AUTHORIZATION) else {
+        return Ok(None);
+    };
+    value
+        .to_str()
+        .ok()
+        .and_then(|v| {
+            v.strip_prefix("Bearer ")
+                .or_else(|| v.strip_prefix("bearer "))
+        })
+        .map(|token| Some(token.trim()))
+        .ok_or_else(|| {
+            ApiError::Unauthorized("Authorization 头格式应为 Bearer <token>".to_string())
+        })
+}
+
+#[derive(Debug, Default, Clone)]
+struct Requirement {
+    optional: bool,
+    // 任意一个
+    roles: Vec<String>,
+    // 全部
+    scopes: Vec<String>,
+}
+
+/// 用法：`.route_layer(AuthLayer::new(keys).require_roles(["admin"]))`
+#[derive(Clone)]
+pub struct AuthLayer {
+    keys: AuthKeys,
+    requirement: Arc<Requirement>,
+}
+
+impl AuthLayer {
+    pub fn new(keys: AuthKeys) -> Self {
+        AuthLayer {
+            keys,
+            requirement: Arc::new(Requirement::default()),
+        }
+    }
+
+    fn update(mut self, f: impl FnOnce(&mut Requirement)) -> Self {
+        let mut requirement = Arc::unwrap_or_clone(self.requirement);
+        f(&mut requirement);
+        self.requirement = Arc::new(requirement);
+        self
+    }
+
+    pub fn optional(self) -> Self {
+        self.update(|r| r.optional = true)
+    }
+
+    pub fn require_roles<I: IntoIterator<Item = S>, S: Into<String>>(self, roles: I) -> Self {
+        self.update(|r| r.roles.extend(roles.into_iter().map(Into::into)))
+    }
+
+    pub fn require_scopes<I: IntoIterator<Item = S>, S: Into<String>>(self, scopes: I) -> Self {
+        self.update(|r| r.scopes.extend(scopes.into_iter().map(Into::into)))
+    }
+
+    fn authorize(&self, parts: &Parts) -> Result<Option<AuthUser>, ApiError> {
+        let requirement = &self.requirement;
+        let user = match bearer(parts)? {
+            Some(token) => self.keys.verify(token, TokenType::Access)?,
+            None if requirement.optional => return Ok(None),
+            None => return Err(ApiError::Unauthorized("需要登录".to_string())),
+        };
+        if !requirement.roles.is_empty() && !requirement.roles.iter().any(|r| user.has_role(r)) {
+            return Err(ApiError::Forbidden(format!(
+                "需要角色 {}",
+                requirement.roles.join(" 或 ")
+            )));
+        }
+        if let Some(missing) = requirement.scopes.iter().find(|s| !user.has_scope(s)) {
+            return Err(ApiError::Forbidden(format!("缺少权限 {}", missing)));
+        }
+        Ok(Some(user))
+    }
+}
+
+impl<S> Layer<S> for AuthLayer {
+    type Service = AuthService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AuthService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AuthService<S> {
+    inner: S,
+    layer: AuthLayer,
+}
+
+impl<S> Service<Request> for AuthService<S>
+where
+    S: Service<Request, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let (mut parts, body) = req.into_parts();
+        match self.layer.authorize(&parts) {
+            Ok(Some(user)) => {
+                parts.extensions.insert(user);
+            }
+            Ok(None) => {}
+            Err(err) => return Box::pin(async move { Ok(err.into_response()) }),
+        }
+        // poll_ready 过的是 self.inner，要把它换出来用，留一个新 clone 给下次
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move { inner.call(Request::from_parts(parts, body)).await })
+    }
+}
+
+pub fn hash_password(password: &str) -> Result<String, ApiError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!("hash password: {}", e))?
+        .to_string())
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|parsed| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &parsed)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+// 用户不存在时也校验一次，免得通过响应时间猜出哪些用户名存在
+static DUMMY_HASH: LazyLock<String> = LazyLock::new(|| hash_password("dummy-password").unwrap());
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: &'static str,
+    pub expires_in: u64,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct LoginRequest {
+    pub name: String,
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct Credentials {
+    id: i32,
+    name: String,
+    password_hash: Option<String>,
+    roles: Vec<String>,
+}
+
+pub fn routes(keys: &AuthKeys) -> Router<AppState> {
+    Router::new()
+        .route("/auth/login", post(login))
+        .route("/auth/refresh", post(refresh))
+        .route("/auth/logout", post(logout))
+        .route(
+            "/auth/me",
+            get(me).route_layer(AuthLayer::new(keys.clone())),
+        )
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/login",
+    tag = "auth",
+    request_body = LoginRequest,
+    responses(
+        (status = 200, description = "登录成功", body = ApiResponse<TokenPair>),
+        (status = 401, description = "用户名或密码错误", body = ErrorBody),
+    )
+)]
+async fn login(
+    State(state): State<AppState>,
+    Json(req): Json<LoginRequest>,
+) -> Result<Json<ApiResponse<TokenPair>>, ApiError> {
+    let user = authenticate(&state.pool, &req.name, req.password).await?;
+    let pair = issue_tokens(&state.pool, &state.auth, &user, None).await?;
+    Ok(Json(ApiResponse::success(pair)))
+}
+
+// 新 refresh token 的 jti 先入库再签发；family 为空表示新登录，自己开一个 family
+async fn issue_tokens(
+    db: impl PgExecutor<'_>,
+    keys: &AuthKeys,
+    user: &AuthUser,
+    family: Option<String>,
+) -> Result<TokenPair, ApiError> {
+    let jti = Uuid::new_v4().to_string();
+    sqlx::query(
+        "INSERT INTO refresh_tokens (jti, family, user_id, expires_at) \
+         VALUES ($1, $2, $3, now() + make_interval(secs => $4))",
+    )
+    .bind(&jti)
+    .bind(family.as_deref().unwrap_or(&jti))
+    .bind(user.id)
+    .bind(keys.refresh_ttl.as_secs_f64())
+    .execute(db)
+    .await?;
+    keys.issue_pair(user, &jti)
+}
+
+/// 过期的 refresh token 行，main 里定期调用
+pub async fn delete_expired_refresh_tokens(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    Ok(
+        sqlx::query("DELETE FROM refresh_tokens WHERE expires_at < now()")
+            .execute(pool)
+            .await?
+            .rows_affected(),
+    )
+}
+
+fn refresh_jti(claims: Claims) -> Result<String, ApiError> {
+    claims
+        .jti
+        .ok_or_else(|| ApiError::Unauthorized("无效的 token: jti".to_string()))
+}
+
+/// 用户名 + 密码换 AuthUser，token 登录和 session::login 共用
+pub(crate) async fn authenticate(
+    pool: &PgPool,
+    name: &str,
+    password: String,
+) -> Result<AuthUser, ApiError> {
+    let found: Option<Credentials> =
+        sqlx::query_as("SELECT id, name, password_hash, roles FROM users WHERE name = $1")
+            .bind(name.trim())
+            .fetch_optional(pool)
+            .await?;
+
+    // Argon2 很吃 CPU，放到阻塞线程池里算
+    let hash = found
+        .as_ref()
+        .and_then(|c| c.password_hash.clone())
+        .unwrap_or_else(|| DUMMY_HASH.clone());
+    let ok = tokio::task::spawn_blocking(move || verify_password(&password, &hash))
+        .await
+        .map_err(anyhow::Error::from)?;
+
+    match found {
+        Some(c) if ok && c.password_hash.is_some() => Ok(AuthUser::new(c.id, c.name, c.roles)),
+        _ => Err(ApiError::Unauthorized("用户名或密码错误".to_string())),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/refresh",
+    tag = "auth",
+    request_body = RefreshRequest,
+    responses(
+        (status = 200, description = "新的一对 token", body = ApiResponse<TokenPair>),
+        (status = 401, description = "refresh token 无效、过期或用户已删除", body = ErrorBody),
+    )
+)]
+async fn refresh(
+    State(state): State<AppState>,
+    Json(req): Json<RefreshRequest>,
+) -> Result<Json<ApiResponse<TokenPair>>, ApiError> {
+    let jti = refresh_jti(
+        state
+            .auth
+            .verify_claims(&req.refresh_token, TokenType::Refresh)?,
+    )?;
+    let mut tx = state.pool.begin().await?;
+    // 条件写在 UPDATE 里，同一个 token 并发来换也只有一个能换到
+    let rotated: Option<(String, i32)> = sqlx::query_as(
+        "UPDATE refresh_tokens SET used_at = now() \
+         WHERE jti = $1 AND used_at IS NULL AND NOT revoked AND expires_at > now() \
+         RETURNING family, user_id",
+    )
+    .bind(&jti)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some((family, user_id)) = rotated else {
+        // 用过的又拿来换：可能被人偷了，这个 family 全部作废，真正的用户也得重新登录
+        let revoked = sqlx::query(
+            "UPDATE refresh_tokens SET revoked = true \
+             WHERE family = (SELECT family FROM refresh_tokens WHERE jti = $1 AND used_at IS NOT NULL)",
+        )
+        .bind(&jti)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        tx.commit().await?;
+        if revoked > 0 {
+            log::warn!("refresh token reused, revoked its family");
+        }
+        return Err(ApiError::Unauthorized("refresh token 已失效".to_string()));
+    };
+    // 角色以数据库里的为准，用户被删了或者改了角色，新 token 立刻生效
+    let current: Option<Credentials> =
+        sqlx::query_as("SELECT id, name, password_hash, roles FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+    let c = current.ok_or_else(|| ApiError::Unauthorized("用户不存在".to_string()))?;
+    let user = AuthUser::new(c.id, c.name, c.roles);
+    let pair = issue_tokens(&mut *tx, &state.auth, &user, Some(family)).await?;
+    tx.commit().await?;
+    Ok(Json(ApiResponse::success(pair)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/logout",
+    tag = "auth",
+    request_body = RefreshRequest,
+    responses(
+        (status = 204, description = "这次登录派生出来的 refresh token 全部作废"),
+        (status = 401, description = "refresh token 无效或过期", body = ErrorBody),
+    )
+)]
+async fn logout(
+    State(state): State<AppState>,
+    Json(req): Json<RefreshRequest>,
+) -> Result<StatusCode, ApiError> {
+    let jti = refresh_jti(
+        state
+            .auth
+            .verify_claims(&req.refresh_token, TokenType::Refresh)?,
+    )?;
+    sqlx::query(
+        "UPDATE refresh_tokens SET revoked = true \
+         WHERE family = (SELECT family FROM refresh_tokens WHERE jti = $1)",
+    )
+    .bind(&jti)
+    .execute(&state.pool)
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Me {
+    id: i32,
+    name: String,
+    roles: Vec<String>,
+    scopes: Vec<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/auth/me",
+    tag = "auth",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "当前用户", body = ApiResponse<Me>),
+        (status = 401, description = "没登录", body = ErrorBody),
+    )
+)]
+async fn me(user: AuthUser) -> Json<ApiResponse<Me>> {
+    Json(ApiResponse::success(Me {
+        id: user.id,
+        name: user.name,
+        roles: user.roles,
+        scopes: user.scopes,
+    }))
+}
+
+#[test]
+fn issue_and_verify_tokens() {
+    let keys = AuthKeys::hs256(b"secret-1");
+    let alice = AuthUser::new(7, "alice", vec!["user".to_string()]);
+    let access = keys.issue(&alice, TokenType::Access).unwrap();
+    assert_eq!(keys.verify(&access, TokenType::Access).unwrap(), alice);
+
+    // refresh token 不能当 access token 用，反过来也不行
+    let refresh = keys.issue(&alice, TokenType::Refresh).unwrap();
+    assert!(keys.verify(&refresh, TokenType::Access).is_err());
+    assert!(keys.verify(&access, TokenType::Refresh).is_err());
+
+    // 密钥不对、过期、乱写的都不行
+    assert!(
+        AuthKeys::hs256(b"secret-2")
+            .verify(&access, TokenType::Access)
+            .is_err()
+    );
+    let expired = Claims {
+        sub: "7".to_string(),
+        name: "alice".to_string(),
+        roles: vec![],
+        scope: String::new(),
+        typ: TokenType::Access,
+        iat: now() - 120,
+        exp: now() - 60,
+        jti: None,
+    };
+    let expired = keys.sign(&expired).unwrap();
+    assert!(keys.verify(&expired, TokenType::Access).is_err());
+    assert!(keys.verify("not.a.jwt", TokenType::Access).is_err());
+
+    // RS256：私钥签发，只有公钥的服务也能校验，但不能签发
+    let private = include_bytes!("../testdata/jwt_rs256_private.pem");
+    let public = include_bytes!("../testdata/jwt_rs256_public.pem");
+    let issuer = AuthKeys::rs256(Some(private), public).unwrap();
+    let verifier = AuthKeys::rs256(None, public).unwrap();
+    let token = issuer.issue(&alice, TokenType::Access).unwrap();
+    assert_eq!(verifier.verify(&token, TokenType::Access).unwrap(), alice);
+    assert!(verifier.issue(&alice, TokenType::Access).is_err());
+    // HS256 的 token 拿到 RS256 这边也过不了
+    assert!(verifier.verify(&access, TokenType::Access).is_err());
+}
+
+#[tokio::test]
+async fn auth_layer_checks_roles_and_scopes() {
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    let keys = AuthKeys::hs256(b"layer-secret");
+    let whoami = |user: AuthUser| async move { user.name };
+    let app: Router = Router::new()
+        .route(
+            "/admin",
+            get(whoami).route_layer(AuthLayer::new(keys.clone()).require_roles(["admin", "ops"])),
+        )
+        .route(
+            "/write",
+            get(whoami).route_layer(AuthLayer::new(keys.clone()).require_scopes(["users:write"])),
+        )
+        .route(
+            "/maybe",
+            get(|user: Option<AuthUser>| async move {
+                user.map(|u| u.name).unwrap_or("anonymous".to_string())
+            })
+            .route_layer(AuthLayer::new(keys.clone()).optional()),
+        )
+        // 没挂 AuthLayer 的路由上用 AuthUser 一律 401
+        .route("/open", get(whoami));
+
+    let token = |roles: &[&str]| {
+        let user = AuthUser::new(
+            1,
+            roles.join("+"),
+            roles.iter().map(|r| r.to_string()).collect(),
+        );
+        keys.issue(&user, TokenType::Access).unwrap()
+    };
+    let call = |uri: &'static str, token: Option<String>| {
+        let app = app.clone();
+        async move {
+            let mut req = Request::get(uri);
+            if let Some(token) = token {
+                req = req.header(AUTHORIZATION, format!("Bearer {}", token));
+            }
+            app.oneshot(req.body(Body::empty()).unwrap()).await.unwrap()
+        }
+    };
+
+    let resp = call("/admin", None).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(resp.headers()["www-authenticate"], "Bearer");
+    assert_eq!(
+        call("/admin", Some(token(&["user"]))).await.status(),
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        call("/admin", Some(token(&["ops"]))).await.status(),
+        StatusCode::OK
+    );
+    assert_eq!(
+        call("/admin", Some("garbage".to_string())).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+
+    // scope 由角色推出来，user 只有 users:read
+    assert_eq!(
+        call("/write", Some(token(&["user"]))).await.status(),
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        call("/write", Some(token(&["admin"]))).await.status(),
+        StatusCode::OK
+    );
+
+    assert_eq!(call("/maybe", None).await.status(), StatusCode::OK);
+    assert_eq!(
+        call("/maybe", Some(token(&["user"]))).await.status(),
+        StatusCode::OK
+    );
+    // 带了就必须是有效的
+    assert_eq!(
+        call("/maybe", Some("garbage".to_string())).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+
+    assert_eq!(
+        call("/open", Some(token(&["admin"]))).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+// 需要本地 postgres，连接串见 state::get_pool
+#[tokio::test]
+async fn login_refresh_and_admin_routes() {
+    use axum::body::Body;
+    use http_body_util::BodyExt;
+    use serde_json::{Value, json};
+    use tower::ServiceExt;
+
+    let pool = crate::state::get_pool().await.unwrap();
+    crate::state::init_schema(&pool).await.unwrap();
+    let keys = AuthKeys::hs256(b"login-secret");
+    let app = crate::app(AppState::new(pool.clone(), keys.clone()));
+
+    let send = |method: &'static str, uri: String, token: Option<String>, body: Option<Value>| {
+        let app = app.clone();
+        async move {
+            let mut req = Request::builder().method(method).uri(uri);
+            if let Some(token) = token {
+                req = req.header(AUTHORIZATION, format!("Bearer {}", token));
+            }
+            let body = match body {
+                Some(json) => {
+                    req = req.header("content-type", "application/json");
+                    Body::from(json.to_string())
+                }
+                None => Body::empty(),
+            };
+            let resp = app.oneshot(req.body(body).unwrap()).await.unwrap();
+            let status = resp.status();
+            let bytes = resp.into_body().collect().await.unwrap().to_bytes();
+            (
+                status,
+                serde_json::from_slice::<Value>(&bytes).unwrap_or(Value::Null),
+            )
+        }
+    };
+
+    let name = format!("web-auth-{}", std::process::id());
+    let (status, created) = send(
+        "POST",
+        "/users".to_string(),
+        None,
+        Some(json!({"name": name, "age": 20, "password": "correct horse"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    // 密码和哈希都不会出现在响应里
+    assert!(created["data"].get("password").is_none());
+    let id = created["data"]["id"].as_i64().unwrap();
+
+    let login = |password: &'static str| {
+        send(
+            "POST",
+            "/auth/login".to_string(),
+            None,
+            Some(json!({"name": name, "password": password})),
+        )
+    };
+    let (status, body) = login("wrong password").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["code"], "unauthorized");
+    let (status, body) = send(
+        "POST",
+        "/auth/login".to_string(),
+        None,
+        Some(json!({"name": format!("{}-nobody", name), "password": "whatever"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, body) = login("correct horse").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["data"]["token_type"], "Bearer");
+    let access = body["data"]["access_token"].as_str().unwrap().to_string();
+    let refresh_token = body["data"]["refresh_token"].as_str().unwrap().to_string();
+
+    let (status, me) = send("GET", "/auth/me".to_string(), Some(access.clone()), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(me["data"]["id"], id);
+    assert_eq!(me["data"]["roles"], json!(["user"]));
+
+    // 普通用户不能改别人
+    let (status, body) = send(
+        "PUT",
+        format!("/users/{}", id),
+        Some(access.clone()),
+        Some(json!({"name": name, "age": 21})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "forbidden");
+
+    // 提升成 admin 以后，refresh 出来的新 token 带上新角色
+    sqlx::query("UPDATE users SET roles = '{user,admin}' WHERE id = $1")
+        .bind(id as i32)
+        .execute(&pool)
+        .await
+        .unwrap();
+    let (status, body) = send(
+        "POST",
+        "/auth/refresh".to_string(),
+        None,
+        Some(json!({"refresh_token": access})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, body) = send(
+        "POST",
+        "/auth/refresh".to_string(),
+        None,
+        Some(json!({"refresh_token": refresh_token})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let admin = body["data"]["access_token"].as_str().unwrap().to_string();
+    let rotated = body["data"]["refresh_token"].as_str().unwrap().to_string();
+    assert_ne!(rotated, refresh_token);
+
+    // 旧的 refresh token 换过一次就不能再用，再用会把整个 family 作废，轮换出来的新 token 也跟着失效
+    let refresh = |token: String| {
+        send(
+            "POST",
+            "/auth/refresh".to_string(),
+            None,
+            Some(json!({"refresh_token": token})),
+        )
+    };
+    assert_eq!(
+        refresh(refresh_token.clone()).await.0,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(refresh(rotated).await.0, StatusCode::UNAUTHORIZED);
+
+    // 退出登录以后这次登录的 refresh token 不能再换
+    let (_, body) = login("correct horse").await;
+    let token = body["data"]["refresh_token"].as_str().unwrap().to_string();
+    let (status, _) = send(
+        "POST",
+        "/auth/logout".to_string(),
+        None,
+        Some(json!({"refresh_token": token})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert_eq!(refresh(token).await.0, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = send(
+        "PUT",
+        format!("/users/{}", id),
+        Some(admin.clone()),
+        Some(json!({"name": name, "age": 21})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    // 更新时没传密码，原来的密码还能用
+    let (status, body) = login("correct horse").await;
+    assert_eq!(status, StatusCode::OK);
+    let token = body["data"]["refresh_token"].as_str().unwrap().to_string();
+
+    let (status, _) = send("DELETE", format!("/users/{}", id), Some(admin), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    // 用户删了，refresh 也换不出新 token
+    assert_eq!(refresh(token).await.0, StatusCode::UNAUTHORIZED);
+}
+
+#[test]
+fn from_config_fails_closed() {
+    let err = AuthKeys::from_config(&AuthConfig::default())
+        .err()
+        .unwrap()
+        .to_string();
+    assert!(err.contains("auth.jwt_secret"), "{}", err);
+    let dev = AuthConfig {
+        allow_dev_secret: true,
+        ..AuthConfig::default()
+    };
+    assert!(AuthKeys::from_config(&dev).is_ok());
+}
@@ -0,0 +1,75 @@
+// 按 gateway.yaml 转发请求的 API 网关，路由规则见 dep_web::gateway
+//
+// 用法：
+// cargo run -p dep_web --bin gateway -- --routes gateway.yaml --listen 0.0.0.0:8080 --timeout-ms 30000
+// 也可以写进 --config 指定的 YAML，或者用 GATEWAY__LISTEN 这样的环境变量，规则见 dep_serialization::ConfigLoader
+
+use dep_serialization::{ConfigLoader, Settings};
+use dep_web::gateway::{DEFAULT_UPSTREAM_TIMEOUT, Gateway, RouteTable, reload};
+use dep_web::request_id;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+struct GatewaySettings {
+    /// 路由规则文件，会被监听热加载
+    routes: PathBuf,
+    listen: SocketAddr,
+    timeout_ms: u64,
+}
+
+impl Default for GatewaySettings {
+    fn default() -> Self {
+        GatewaySettings {
+            routes: PathBuf::from("gateway.yaml"),
+            listen: "0.0.0.0:8080".parse().unwrap(),
+            timeout_ms: DEFAULT_UPSTREAM_TIMEOUT.as_millis() as u64,
+        }
+    }
+}
+
+impl Settings for GatewaySettings {
+    fn validate(&self) -> Vec<String> {
+        if self.timeout_ms == 0 {
+            return vec!["timeout_ms: must be at least 1".to_string()];
+        }
+        Vec::new()
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    // 转发出错、热加载成功失败都走 log，级别用 RUST_LOG 调
+    request_id::init_logging();
+    let settings: GatewaySettings = ConfigLoader::new("GATEWAY")
+        .args(env::args().skip(1))
+        .load()?;
+    log::info!("settings: {:?}", settings);
+    let table = RouteTable::load(&settings.routes)?;
+    log::info!(
+        "loaded routes {:?} from {}",
+        table.ids(),
+        settings.routes.display()
+    );
+
+    let gateway = Gateway::new(table).with_timeout(Duration::from_millis(settings.timeout_ms));
+    // 改了 gateway.yaml 或者 kill -HUP 都会重新加载路由
+    reload::spawn_watcher(gateway.clone(), &settings.routes)?;
+
+    let app = gateway.router();
+    let listener = TcpListener::bind(settings.listen).await?;
+    log::info!("Gateway running at http://{}", listener.local_addr()?);
+
+    // 带上对端地址，转发时写 X-Forwarded-For
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
+    Ok(())
+}
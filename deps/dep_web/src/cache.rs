@@ -0,0 +1,1018 @@
+// GET 响应缓存，缓不缓存、缓存多久都由 handler 自己的响应头决定
+// 1. 只缓存 200，而且要带 Cache-Control: max-age(或 s-maxage)，no-store / no-cache / private 不缓存；
+//    带 Set-Cookie、Vary: * 的不缓存，响应体长度要事先知道而且不超过 max_entry_bytes
+// 2. 缓存键 = 方法 + 路径 + query + 响应 Vary 里列出的请求头，所以 Vary 要在第一次响应后才知道，
+//    每个路径单独存一份 Vary 列表
+// 3. 带 Authorization 或 Cookie(会话 Cookie 也是凭证)的请求，只有在响应是 public / s-maxage，
+//    或者 Vary 里列了带着的这些请求头时才缓存，免得一个人的数据被别人读到
+// 4. 过了 max-age 但还在 stale-while-revalidate 内的，先返回旧的，后台重新算一次；
+//    后台那次带着原请求的扩展(鉴权用户、会话)和请求 ID，去掉条件请求头，免得 handler 回 304
+// 5. 同一个键同时有多个请求没命中时只有一个去调 handler，其他的等它算完再查缓存
+// 6. handler 用 Cache-Tag: users, user:42 给响应打标签，写接口调 CacheLayer::invalidate 让带这些标签的缓存全部作废；
+//    每个标签有个版本号，作废就是换版本号，存储只需要 GET / SET，RESP 服务端也能用；
+//    调 handler 之前先记下这个路径上次带的标签的版本，存的时候版本变了(中间有人作废过)就不存，
+//    上次没带过的标签只有从来没作废过才存，所以一个路径第一次加上已经作废过的标签时要多算一次
+// 7. 存储可以是进程内 LRU，也可以是 dep_async 的 key-value 服务端，多实例共享；存储出错时直接调 handler，不影响请求
+// 8. 命中时 If-None-Match(弱比较)、If-Modified-Since(和 Last-Modified 完全一样才算)满足就回 304；
+//    带 Range 的请求不走缓存，直接交给 handler
+
+use crate::files::etag_matches;
+use crate::request_id;
+use async_trait::async_trait;
+use axum::body::{Body, HttpBody};
+use axum::extract::Request;
+use axum::http::header::{
+    AGE, AUTHORIZATION, CACHE_CONTROL, CONTENT_LENGTH, COOKIE, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+    RANGE, SET_COOKIE, VARY,
+};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use axum::response::Response;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use bytes::Bytes;
+use dep_async::resp_client::KvClient;
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+use tower::{Layer, Service};
+
+pub const CACHE_TAG: HeaderName = HeaderName::from_static("cache-tag");
+/// HIT / STALE / MISS，调试用
+pub const X_CACHE: HeaderName = HeaderName::from_static("x-cache");
+
+pub const DEFAULT_CAPACITY: usize = 10_000;
+pub const DEFAULT_MAX_ENTRY_BYTES: u64 = 1024 * 1024;
+
+/// 值都是不透明的字节，ttl 为 None 表示不过期
+#[async_trait]
+pub trait CacheStore: Send + Sync {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Bytes>>;
+
+    async fn set(&self, key: &str, value: Bytes, ttl: Option<Duration>) -> anyhow::Result<()>;
+}
+
+/// 进程内 LRU，按条数算容量
+pub struct MemoryStore {
+    entries: Mutex<LruCache<String, (Bytes, Option<Instant>)>>,
+}
+
+impl MemoryStore {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        MemoryStore {
+            entries: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+#[async_trait]
+impl CacheStore for MemoryStore {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Bytes>> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some((_, Some(deadline))) if *deadline <= Instant::now() => {
+                entries.pop(key);
+                Ok(None)
+            }
+            Some((value, _)) => Ok(Some(value.clone())),
+            None => Ok(None),
+        }
+    }
+
+    async fn set(&self, key: &str, value: Bytes, ttl: Option<Duration>) -> anyhow::Result<()> {
+        let deadline = ttl.map(|ttl| Instant::now() + ttl);
+        self.entries
+            .lock()
+            .unwrap()
+            .put(key.to_string(), (value, deadline));
+        Ok(())
+    }
+}
+
+/// 存在 dep_async 的 key-value 服务端上，键都带 cache: 前缀
+pub struct RespStore {
+    client: KvClient,
+}
+
+impl RespStore {
+    pub async fn connect(addr: &str) -> anyhow::Result<Self> {
+        let client = KvClient::connect(addr)
+            .await
+            .map_err(|e| anyhow::anyhow!("connect {}: {}", addr, e))?;
+        Ok(RespStore { client })
+    }
+}
+
+#[async_trait]
+impl CacheStore for RespStore {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Bytes>> {
+        self.client
+            .get(&format!("cache:{}", key))
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))
+    }
+
+    async fn set(&self, key: &str, value: Bytes, ttl: Option<Duration>) -> anyhow::Result<()> {
+        self.client
+            .set(&format!("cache:{}", key), value, ttl)
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))
+    }
+}
+
+/// 存到 CacheStore 里的一条响应
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    status: u16,
+    headers: Vec<(String, String)>,
+    /// base64，JSON 里存字节数组太占地方
+    body: String,
+    stored_at: u64,
+    max_age: u64,
+    stale_while_revalidate: u64,
+    /// 存的时候每个标签的版本号，有一个对不上就作废
+    tags: Vec<(String, String)>,
+}
+
+impl Entry {
+    fn age(&self) -> Duration {
+        Duration::from_millis(now_ms().saturating_sub(self.stored_at))
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    // 有 If-None-Match 就不看 If-Modified-Since；日期不解析，客户端一般原样带回 Last-Modified
+    fn not_modified(&self, req_headers: &HeaderMap) -> bool {
+        if let Some(value) = req_headers.get(IF_NONE_MATCH) {
+            return self
+                .header("etag")
+                .is_some_and(|etag| etag_matches(Some(value), etag.trim_start_matches("W/")));
+        }
+        let since = req_headers
+            .get(IF_MODIFIED_SINCE)
+            .and_then(|v| v.to_str().ok());
+        match (since, self.header("last-modified")) {
+            (Some(since), Some(modified)) => since.trim() == modified,
+            _ => false,
+        }
+    }
+
+    fn into_response(self, state: &'static str, req_headers: &HeaderMap) -> Response {
+        let age = self.age().as_secs();
+        let not_modified = self.not_modified(req_headers);
+        let mut response = if not_modified {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::NOT_MODIFIED;
+            response
+        } else {
+            let body = STANDARD.decode(&self.body).unwrap_or_default();
+            let mut response = Response::new(Body::from(body));
+            *response.status_mut() = StatusCode::from_u16(self.status).unwrap_or(StatusCode::OK);
+            response
+        };
+        let headers = response.headers_mut();
+        for (name, value) in self.headers {
+            if let (Ok(name), Ok(value)) =
+                (HeaderName::try_from(name), HeaderValue::try_from(value))
+            {
+                headers.append(name, value);
+            }
+        }
+        if not_modified {
+            headers.remove(CONTENT_LENGTH);
+        }
+        headers.insert(AGE, HeaderValue::from(age));
+        headers.insert(X_CACHE, HeaderValue::from_static(state));
+        response
+    }
+}
+
+/// 每个路径一份：Vary 列表，以及上次响应带的标签
+#[derive(Debug, Serialize, Deserialize)]
+struct Route {
+    vary: Vec<String>,
+    tags: Vec<String>,
+}
+
+/// 调 handler 之前各标签的版本，None 是还没有版本(从来没作废过)
+type Snapshot = Vec<(String, Option<String>)>;
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn sha256_hex(parts: &[&[u8]]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part);
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// handler 的 Cache-Control 里和共享缓存有关的部分
+#[derive(Debug, Default, PartialEq)]
+struct Directives {
+    max_age: Option<u64>,
+    stale_while_revalidate: u64,
+    public: bool,
+    no_store: bool,
+}
+
+fn directives(headers: &HeaderMap) -> Directives {
+    let mut d = Directives::default();
+    let mut s_maxage = None;
+    for value in headers.get_all(CACHE_CONTROL) {
+        let Ok(value) = value.to_str() else {
+            d.no_store = true;
+            continue;
+        };
+        for directive in value.split(',') {
+            let (name, arg) = match directive.split_once('=') {
+                Some((name, arg)) => (name.trim(), Some(arg.trim().trim_matches('"'))),
+                None => (directive.trim(), None),
+            };
+            let secs = arg.and_then(|a| a.parse::<u64>().ok());
+            match name.to_ascii_lowercase().as_str() {
+                "max-age" => d.max_age = secs,
+                "s-maxage" => s_maxage = secs,
+                "stale-while-revalidate" => d.stale_while_revalidate = secs.unwrap_or(0),
+                "public" => d.public = true,
+                "no-store" | "no-cache" | "private" => d.no_store = true,
+                _ => {}
+            }
+        }
+    }
+    // s-maxage 是专门给共享缓存的，优先
+    if s_maxage.is_some() {
+        d.max_age = s_maxage;
+        d.public = true;
+    }
+    d
+}
+
+fn vary_names(headers: &HeaderMap) -> Vec<String> {
+    let mut names: Vec<String> = headers
+        .get_all(VARY)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|n| n.trim().to_ascii_lowercase())
+        .filter(|n| !n.is_empty())
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+fn cache_tags(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all(CACHE_TAG)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+#[derive(Clone)]
+struct Options {
+    store: Arc<dyn CacheStore>,
+    max_entry_bytes: u64,
+}
+
+struct Shared {
+    options: Options,
+    // 正在算的键；算的那个请求拿着 Sender，算完 drop 掉，等着的 Receiver 就醒了
+    inflight: Mutex<HashMap<String, watch::Receiver<()>>>,
+}
+
+enum Flight {
+    Leader(FlightGuard),
+    Follower(watch::Receiver<()>),
+}
+
+struct FlightGuard {
+    shared: Arc<Shared>,
+    key: String,
+    _done: watch::Sender<()>,
+}
+
+impl Drop for FlightGuard {
+    fn drop(&mut self) {
+        self.shared.inflight.lock().unwrap().remove(&self.key);
+    }
+}
+
+impl Shared {
+    fn join(self: &Arc<Self>, key: &str) -> Flight {
+        let mut inflight = self.inflight.lock().unwrap();
+        if let Some(rx) = inflight.get(key) {
+            return Flight::Follower(rx.clone());
+        }
+        let (tx, rx) = watch::channel(());
+        inflight.insert(key.to_string(), rx);
+        Flight::Leader(FlightGuard {
+            shared: self.clone(),
+            key: key.to_string(),
+            _done: tx,
+        })
+    }
+
+    fn primary_key(req: &Request) -> String {
+        let query = req.uri().query().unwrap_or_default();
+        sha256_hex(&[
+            req.method().as_str().as_bytes(),
+            req.uri().path().as_bytes(),
+            query.as_bytes(),
+        ])
+    }
+
+    fn entry_key(primary: &str, vary: &[String], headers: &HeaderMap) -> String {
+        let mut parts: Vec<Vec<u8>> = vec![primary.as_bytes().to_vec()];
+        for name in vary {
+            let values: Vec<&[u8]> = headers.get_all(name).iter().map(|v| v.as_bytes()).collect();
+            parts.push(name.as_bytes().to_vec());
+            parts.push(values.join(&b","[..]));
+        }
+        let parts: Vec<&[u8]> = parts.iter().map(Vec::as_slice).collect();
+        format!("entry:{}", sha256_hex(&parts))
+    }
+
+    async fn tag_version(&self, tag: &str) -> anyhow::Result<Option<String>> {
+        let version = self.options.store.get(&format!("tag:{}", tag)).await?;
+        Ok(version.map(|v| String::from_utf8_lossy(&v).into_owned()))
+    }
+
+    async fn route(&self, primary: &str) -> anyhow::Result<Option<Route>> {
+        match self
+            .options
+            .store
+            .get(&format!("route:{}", primary))
+            .await?
+        {
+            Some(route) => Ok(Some(serde_json::from_slice(&route)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// 调 handler 之前记下这个路径上次带的标签现在的版本
+    async fn snapshot(&self, primary: &str) -> Snapshot {
+        let snapshot = async {
+            let mut versions = Vec::new();
+            if let Some(route) = self.route(primary).await? {
+                for tag in route.tags {
+                    let version = self.tag_version(&tag).await?;
+                    versions.push((tag, version));
+                }
+            }
+            anyhow::Ok(versions)
+        };
+        // 拿不到就当什么都没记，有版本的标签这次都不存
+        snapshot.await.unwrap_or_else(|e| {
+            log::warn!("snapshot cache tags failed: {:#}", e);
+            Vec::new()
+        })
+    }
+
+    /// 查这个请求对应的缓存，Vary 列表还不知道时返回 None
+    async fn lookup(&self, primary: &str, headers: &HeaderMap) -> anyhow::Result<Lookup> {
+        let store = &self.options.store;
+        let Some(route) = self.route(primary).await? else {
+            return Ok(Lookup::Miss(None));
+        };
+        let key = Self::entry_key(primary, &route.vary, headers);
+        let Some(entry) = store.get(&key).await? else {
+            return Ok(Lookup::Miss(Some(key)));
+        };
+        let entry: Entry = serde_json::from_slice(&entry)?;
+        for (tag, version) in &entry.tags {
+            // 标签版本被 LRU 挤掉了也当作废，不能让旧缓存复活
+            if self.tag_version(tag).await?.as_ref() != Some(version) {
+                return Ok(Lookup::Miss(Some(key)));
+            }
+        }
+        let age = entry.age();
+        if age < Duration::from_secs(entry.max_age) {
+            Ok(Lookup::Fresh(entry))
+        } else if age < Duration::from_secs(entry.max_age + entry.stale_while_revalidate) {
+            Ok(Lookup::Stale(entry, key))
+        } else {
+            Ok(Lookup::Miss(Some(key)))
+        }
+    }
+
+    /// 能缓存的就存下来，返回给客户端的响应里去掉 Cache-Tag
+    async fn store(
+        &self,
+        primary: &str,
+        req_headers: &HeaderMap,
+        snapshot: &Snapshot,
+        response: Response,
+    ) -> Response {
+        let (mut parts, body) = response.into_parts();
+        let tags = cache_tags(&parts.headers);
+        parts.headers.remove(CACHE_TAG);
+
+        let d = directives(&parts.headers);
+        let vary = vary_names(&parts.headers);
+        // 带了凭证、响应又没按它区分的，只能是 public 才能给别人看
+        let personal = [AUTHORIZATION, COOKIE]
+            .iter()
+            .any(|name| req_headers.contains_key(name) && !vary.iter().any(|v| v == name.as_str()));
+        let size = body.size_hint().exact();
+        let cacheable = parts.status == StatusCode::OK
+            && !d.no_store
+            && d.max_age.is_some_and(|age| age > 0)
+            && !parts.headers.contains_key(SET_COOKIE)
+            && !vary.iter().any(|v| v == "*")
+            && (!personal || d.public)
+            && size.is_some_and(|size| size <= self.options.max_entry_bytes);
+        if !cacheable {
+            return Response::from_parts(parts, body);
+        }
+        let body = match axum::body::to_bytes(body, usize::MAX).await {
+            Ok(body) => body,
+            Err(e) => {
+                log::warn!("read response body for cache failed: {}", e);
+                return Response::from_parts(parts, Body::empty());
+            }
+        };
+        if let Err(e) = self
+            .save(
+                primary,
+                req_headers,
+                snapshot,
+                &parts.headers,
+                &d,
+                vary,
+                tags,
+                &body,
+            )
+            .await
+        {
+            log::warn!("save response to cache failed: {:#}", e);
+        }
+        parts
+            .headers
+            .insert(X_CACHE, HeaderValue::from_static("MISS"));
+        Response::from_parts(parts, Body::from(body))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn save(
+        &self,
+        primary: &str,
+        req_headers: &HeaderMap,
+        snapshot: &Snapshot,
+        headers: &HeaderMap,
+        d: &Directives,
+        vary: Vec<String>,
+        tags: Vec<String>,
+        body: &Bytes,
+    ) -> anyhow::Result<()> {
+        let store = &self.options.store;
+        let max_age = d.max_age.unwrap_or(0);
+        let ttl = Duration::from_secs(max_age + d.stale_while_revalidate);
+
+        // 这次的标签先记到路径上，这次存不了下次也能比对
+        let route = Route {
+            vary,
+            tags: tags.clone(),
+        };
+        store
+            .set(
+                &format!("route:{}", primary),
+                Bytes::from(serde_json::to_vec(&route)?),
+                Some(ttl),
+            )
+            .await?;
+
+        let mut versions = Vec::with_capacity(tags.len());
+        for tag in tags {
+            let current = self.tag_version(&tag).await?;
+            let unchanged = match snapshot.iter().find(|(t, _)| *t == tag) {
+                Some((_, before)) => *before == current,
+                // 上次没带这个标签，只有从来没作废过才能确定调 handler 期间也没有
+                None => current.is_none(),
+            };
+            if !unchanged {
+                log::debug!("cache tag {} changed while computing, not caching", tag);
+                return Ok(());
+            }
+            let version = match current {
+                Some(version) => version,
+                None => {
+                    let version = uuid::Uuid::new_v4().to_string();
+                    store
+                        .set(&format!("tag:{}", tag), Bytes::from(version.clone()), None)
+                        .await?;
+                    version
+                }
+            };
+            versions.push((tag, version));
+        }
+        let entry = Entry {
+            status: StatusCode::OK.as_u16(),
+            headers: headers
+                .iter()
+                .filter(|(name, _)| *name != AGE && *name != X_CACHE)
+                .filter_map(|(n, v)| Some((n.to_string(), v.to_str().ok()?.to_string())))
+                .collect(),
+            body: STANDARD.encode(body),
+            stored_at: now_ms(),
+            max_age,
+            stale_while_revalidate: d.stale_while_revalidate,
+            tags: versions,
+        };
+        let key = Self::entry_key(primary, &route.vary, req_headers);
+        store
+            .set(&key, Bytes::from(serde_json::to_vec(&entry)?), Some(ttl))
+            .await?;
+        Ok(())
+    }
+}
+
+enum Lookup {
+    Fresh(Entry),
+    Stale(Entry, String),
+    /// 带着完整的缓存键，Vary 列表还不知道时是 None
+    Miss(Option<String>),
+}
+
+/// 用法：`.layer(CacheLayer::new(MemoryStore::new(10_000)))`，写接口里 `state.cache.invalidate(&["users"])`
+#[derive(Clone)]
+pub struct CacheLayer {
+    shared: Arc<Shared>,
+}
+
+impl Default for CacheLayer {
+    fn default() -> Self {
+        Self::new(MemoryStore::default())
+    }
+}
+
+impl CacheLayer {
+    pub fn new(store: impl CacheStore + 'static) -> Self {
+        CacheLayer {
+            shared: Arc::new(Shared {
+                options: Options {
+                    store: Arc::new(store),
+                    max_entry_bytes: DEFAULT_MAX_ENTRY_BYTES,
+                },
+                inflight: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// 响应体超过这么大的不缓存
+    pub fn max_entry_bytes(self, max: u64) -> Self {
+        let mut options = self.shared.options.clone();
+        options.max_entry_bytes = max;
+        CacheLayer {
+            shared: Arc::new(Shared {
+                options,
+                inflight: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// 带这些标签的缓存全部作废
+    pub async fn invalidate(&self, tags: &[&str]) -> anyhow::Result<()> {
+        for tag in tags {
+            let version = Bytes::from(uuid::Uuid::new_v4().to_string());
+            let key = format!("tag:{}", tag);
+            self.shared.options.store.set(&key, version, None).await?;
+        }
+        Ok(())
+    }
+}
+
+impl<S> Layer<S> for CacheLayer {
+    type Service = CacheService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CacheService {
+            inner,
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CacheService<S> {
+    inner: S,
+    shared: Arc<Shared>,
+}
+
+impl<S> Service<Request> for CacheService<S>
+where
+    S: Service<Request, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let shared = self.shared.clone();
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            if req.method() != Method::GET || req.headers().contains_key(RANGE) {
+                return inner.call(req).await;
+            }
+            let primary = Shared::primary_key(&req);
+            let headers = req.headers().clone();
+
+            // 没命中的话最多等别人算一次，再没有就自己算
+            let mut waited = false;
+            let guard = loop {
+                let key = match shared.lookup(&primary, &headers).await {
+                    Ok(Lookup::Fresh(entry)) => return Ok(entry.into_response("HIT", &headers)),
+                    Ok(Lookup::Stale(entry, key)) => {
+                        if let Flight::Leader(guard) = shared.join(&key) {
+                            revalidate(shared.clone(), inner, &req, primary, guard);
+                        }
+                        return Ok(entry.into_response("STALE", &headers));
+                    }
+                    Ok(Lookup::Miss(key)) => key.unwrap_or_else(|| primary.clone()),
+                    Err(e) => {
+                        log::warn!("cache lookup failed: {:#}", e);
+                        return inner.call(req).await;
+                    }
+                };
+                if waited {
+                    break None;
+                }
+                match shared.join(&key) {
+                    Flight::Leader(guard) => break Some(guard),
+                    Flight::Follower(mut rx) => {
+                        let _ = rx.changed().await;
+                        waited = true;
+                    }
+                }
+            };
+            let snapshot = shared.snapshot(&primary).await;
+            let response = inner.call(req).await?;
+            let response = shared.store(&primary, &headers, &snapshot, response).await;
+            drop(guard);
+            Ok(response)
+        })
+    }
+}
+
+// 照着原请求再发一次，结果只用来更新缓存
+fn revalidate<S>(
+    shared: Arc<Shared>,
+    mut inner: S,
+    req: &Request,
+    primary: String,
+    guard: FlightGuard,
+) where
+    S: Service<Request, Response = Response, Error = Infallible> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    let mut fresh = Request::new(Body::empty());
+    *fresh.uri_mut() = req.uri().clone();
+    *fresh.version_mut() = req.version();
+    *fresh.headers_mut() = req.headers().clone();
+    fresh.headers_mut().remove(IF_NONE_MATCH);
+    fresh.headers_mut().remove(IF_MODIFIED_SINCE);
+    // 外层放进来的鉴权用户、会话这些，handler 要和原请求看到的一样
+    *fresh.extensions_mut() = req.extensions().clone();
+    let task = async move {
+        let headers = fresh.headers().clone();
+        let snapshot = shared.snapshot(&primary).await;
+        let Ok(response) = inner.call(fresh).await;
+        shared.store(&primary, &headers, &snapshot, response).await;
+        drop(guard);
+    };
+    // 后台这次的日志也能和触发它的请求对上
+    match request_id::current() {
+        Some(id) => tokio::spawn(request_id::scope(id, task)),
+        None => tokio::spawn(task),
+    };
+}
+
+#[test]
+fn cache_control_directives() {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        CACHE_CONTROL,
+        HeaderValue::from_static("max-age=10, stale-while-revalidate=30"),
+    );
+    let d = directives(&headers);
+    assert_eq!(
+        (d.max_age, d.stale_while_revalidate, d.public),
+        (Some(10), 30, false)
+    );
+
+    headers.insert(
+        CACHE_CONTROL,
+        HeaderValue::from_static("max-age=10, S-MAXAGE=60"),
+    );
+    let d = directives(&headers);
+    assert_eq!((d.max_age, d.public), (Some(60), true));
+
+    headers.insert(
+        CACHE_CONTROL,
+        HeaderValue::from_static("private, max-age=60"),
+    );
+    assert!(directives(&headers).no_store);
+
+    headers.insert(VARY, HeaderValue::from_static("Accept-Language, accept"));
+    assert_eq!(vary_names(&headers), vec!["accept", "accept-language"]);
+}
+
+#[tokio::test]
+async fn cache_layer_hits_varies_and_invalidates() {
+    use axum::Router;
+    use axum::routing::get;
+    use http_body_util::BodyExt;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use tower::ServiceExt;
+
+    let calls = Arc::new(AtomicU32::new(0));
+    let counter = |cache_control: &'static str, tag: &'static str, delay: u64| {
+        let calls = calls.clone();
+        move |headers: HeaderMap| async move {
+            let n = calls.fetch_add(1, Ordering::SeqCst) + 1;
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+            let lang = headers
+                .get("accept-language")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("en")
+                .to_string();
+            (
+                [
+                    (CACHE_CONTROL, cache_control),
+                    (VARY, "Accept-Language"),
+                    (CACHE_TAG, tag),
+                ],
+                format!("{} {}", lang, n),
+            )
+        }
+    };
+    let cache = CacheLayer::new(MemoryStore::new(100));
+    let app = Router::new()
+        .route("/fresh", get(counter("max-age=60", "users", 0)))
+        .route("/slow", get(counter("max-age=60", "slow", 200)))
+        .route(
+            "/swr",
+            get(counter("max-age=1, stale-while-revalidate=60", "swr", 0)),
+        )
+        .route("/nostore", get(counter("no-store", "", 0)))
+        .layer(cache.clone());
+    let get = |uri: &'static str, lang: Option<&'static str>, auth: bool| {
+        let app = app.clone();
+        async move {
+            let mut req = Request::get(uri);
+            if let Some(lang) = lang {
+                req = req.header("accept-language", lang);
+            }
+            if auth {
+                req = req.header(AUTHORIZATION, "Bearer x");
+            }
+            let response = app.oneshot(req.body(Body::empty()).unwrap()).await.unwrap();
+            assert!(!response.headers().contains_key(CACHE_TAG));
+            let state = response
+                .headers()
+                .get(X_CACHE)
+                .map(|v| v.to_str().unwrap().to_string())
+                .unwrap_or_default();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (String::from_utf8(body.to_vec()).unwrap(), state)
+        }
+    };
+
+    assert_eq!(
+        get("/fresh", None, false).await,
+        ("en 1".into(), "MISS".into())
+    );
+    assert_eq!(
+        get("/fresh", None, false).await,
+        ("en 1".into(), "HIT".into())
+    );
+    // Vary 的请求头不一样分开缓存，query 不一样也是
+    assert_eq!(get("/fresh", Some("zh"), false).await.0, "zh 2");
+    assert_eq!(get("/fresh", Some("zh"), false).await.0, "zh 2");
+    assert_eq!(get("/fresh?page=2", None, false).await.0, "en 3");
+    // 带 Authorization 的请求，响应既不是 public 也不按 Authorization 区分，不能缓存
+    assert_eq!(get("/fresh?auth", None, true).await.0, "en 4");
+    assert_eq!(get("/fresh?auth", None, true).await.0, "en 5");
+
+    // 作废标签以后重新算
+    cache.invalidate(&["users"]).await.unwrap();
+    assert_eq!(
+        get("/fresh", None, false).await,
+        ("en 6".into(), "MISS".into())
+    );
+    assert_eq!(get("/fresh", Some("zh"), false).await.0, "zh 7");
+
+    assert_eq!(get("/nostore", None, false).await.0, "en 8");
+    assert_eq!(
+        get("/nostore", None, false).await,
+        ("en 9".into(), String::new())
+    );
+
+    // 同时没命中只调一次 handler
+    let (a, b, c) = tokio::join!(
+        get("/slow", None, false),
+        get("/slow", None, false),
+        get("/slow", None, false)
+    );
+    assert_eq!(
+        (a.0.as_str(), b.0.as_str(), c.0.as_str()),
+        ("en 10", "en 10", "en 10")
+    );
+
+    // 过期但在 stale-while-revalidate 内：先给旧的，后台更新
+    assert_eq!(get("/swr", None, false).await.0, "en 11");
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(
+        get("/swr", None, false).await,
+        ("en 11".into(), "STALE".into())
+    );
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(
+        get("/swr", None, false).await,
+        ("en 12".into(), "HIT".into())
+    );
+    assert_eq!(calls.load(Ordering::SeqCst), 12);
+}
+
+#[tokio::test]
+async fn cache_layer_conditional_requests_and_races() {
+    use axum::http::header::{ETAG, IF_MODIFIED_SINCE, LAST_MODIFIED};
+    use axum::routing::get;
+    use axum::{Extension, Router};
+    use http_body_util::BodyExt;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use tower::ServiceExt;
+
+    #[derive(Clone)]
+    struct Caller(&'static str);
+
+    let cache = CacheLayer::new(MemoryStore::new(100));
+    let calls = Arc::new(AtomicU32::new(0));
+    // 第二次调用时模拟有个写接口同时作废了标签
+    let racy = {
+        let (calls, cache) = (calls.clone(), cache.clone());
+        move || async move {
+            let n = calls.fetch_add(1, Ordering::SeqCst) + 1;
+            if n == 2 {
+                cache.invalidate(&["racy"]).await.unwrap();
+            }
+            (
+                [(CACHE_CONTROL, "max-age=60"), (CACHE_TAG, "racy")],
+                n.to_string(),
+            )
+        }
+    };
+    let context = {
+        let calls = calls.clone();
+        move |Extension(caller): Extension<Caller>| async move {
+            let n = calls.fetch_add(1, Ordering::SeqCst) + 1;
+            let id = request_id::current().unwrap_or_default();
+            let body = format!("{} {} {}", n, caller.0, id);
+            (
+                [(CACHE_CONTROL, "max-age=1, stale-while-revalidate=60")],
+                body,
+            )
+        }
+    };
+    let app = Router::new()
+        .route(
+            "/doc",
+            get(|| async {
+                let headers = [
+                    (CACHE_CONTROL, "max-age=60"),
+                    (ETAG, "W/\"v1\""),
+                    (LAST_MODIFIED, "Tue, 15 Nov 1994 12:45:26 GMT"),
+                ];
+                (headers, "document")
+            }),
+        )
+        .route("/racy", get(racy))
+        .route("/context", get(context))
+        .layer(cache.clone())
+        .layer(Extension(Caller("alice")));
+    let send = |uri: &'static str, headers: Vec<(HeaderName, &'static str)>| {
+        let app = app.clone();
+        async move {
+            let mut req = Request::get(uri);
+            for (name, value) in headers {
+                req = req.header(name, value);
+            }
+            let response = app.oneshot(req.body(Body::empty()).unwrap()).await.unwrap();
+            let status = response.status();
+            let state = response
+                .headers()
+                .get(X_CACHE)
+                .map(|v| v.to_str().unwrap().to_string())
+                .unwrap_or_default();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (status, String::from_utf8(body.to_vec()).unwrap(), state)
+        }
+    };
+
+    // 命中时自己回 304，条件不满足照常 200
+    assert_eq!(send("/doc", vec![]).await.2, "MISS");
+    let revalidated = send("/doc", vec![(IF_NONE_MATCH, "\"v0\", \"v1\"")]).await;
+    assert_eq!(
+        revalidated,
+        (StatusCode::NOT_MODIFIED, String::new(), "HIT".into())
+    );
+    let since = vec![(IF_MODIFIED_SINCE, "Tue, 15 Nov 1994 12:45:26 GMT")];
+    assert_eq!(send("/doc", since).await.0, StatusCode::NOT_MODIFIED);
+    let changed = send("/doc", vec![(IF_NONE_MATCH, "\"v0\"")]).await;
+    assert_eq!(changed, (StatusCode::OK, "document".into(), "HIT".into()));
+    // 带会话 Cookie 的请求，响应不是 public 也没按 Cookie 区分，不存
+    let cookie = || vec![(COOKIE, "sid=alice")];
+    assert_eq!(send("/doc?session", cookie()).await.2, "");
+    assert_eq!(send("/doc?session", cookie()).await.2, "");
+    assert_eq!(send("/doc?session", vec![]).await.2, "MISS");
+    // Range 交给 handler，不拿整份缓存回
+    let range = send("/doc", vec![(RANGE, "bytes=0-2")]).await;
+    assert_eq!(range.2, "");
+
+    // 调 handler 期间标签被作废，算出来的可能是旧数据，不存
+    assert_eq!(send("/racy", vec![]).await.1, "1");
+    assert_eq!(send("/racy", vec![]).await.2, "HIT");
+    cache.invalidate(&["racy"]).await.unwrap();
+    assert_eq!(send("/racy", vec![]).await.1, "2");
+    assert_eq!(send("/racy", vec![]).await.1, "3");
+    assert_eq!(
+        send("/racy", vec![]).await,
+        (StatusCode::OK, "3".into(), "HIT".into())
+    );
+
+    // 后台重新算的那次带着原请求的扩展和请求 ID
+    calls.store(0, Ordering::SeqCst);
+    let first = request_id::scope("req-1".to_string(), send("/context", vec![])).await;
+    assert_eq!(first.1, "1 alice req-1");
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let stale = request_id::scope("req-2".to_string(), send("/context", vec![])).await;
+    assert_eq!(stale.2, "STALE");
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let fresh = send("/context", vec![]).await;
+    assert_eq!(
+        fresh,
+        (StatusCode::OK, "2 alice req-2".into(), "HIT".into())
+    );
+}
+
+#[tokio::test]
+async fn resp_cache_store() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(dep_async::resp_server::serve(listener));
+    let cache = CacheLayer::new(RespStore::connect(&addr).await.unwrap());
+    let store = &cache.shared.options.store;
+
+    assert_eq!(store.get("k").await.unwrap(), None);
+    store
+        .set("k", Bytes::from("v"), Some(Duration::from_millis(100)))
+        .await
+        .unwrap();
+    assert_eq!(store.get("k").await.unwrap(), Some(Bytes::from("v")));
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert_eq!(store.get("k").await.unwrap(), None);
+
+    cache.invalidate(&["users"]).await.unwrap();
+    let version = cache.shared.tag_version("users").await.unwrap().unwrap();
+    cache.invalidate(&["users"]).await.unwrap();
+    assert_ne!(
+        cache.shared.tag_version("users").await.unwrap().unwrap(),
+        version
+    );
+}
@@ -0,0 +1,456 @@
+// dep_web 服务端的配置，加载规则见 dep_serialization::ConfigLoader
+// 默认读当前目录的 app.yaml(没有就跳过)，环境变量前缀 APP，比如：
+//   APP__SERVER__LISTEN=0.0.0.0:8000 APP__PUBSUB__ADDR=127.0.0.1:6379 cargo run -p dep_web
+//   cargo run -p dep_web -- --config prod.yaml --server.drain-secs 30
+// 1. 数据库连接串不再写死密码，本地开发放在 .env 的 APP__DATABASE__URL 里(照 .env.example 复制一份)
+// 2. 没配的可选项(pub/sub、模板、静态文件、gRPC 上游)对应的功能就不开
+// 3. 启动时打印一遍生效的配置，Secret 字段打印出来是 ***
+
+use crate::error::ErrCodes;
+use axum::http::StatusCode;
+use dep_serialization::{ConfigError, ConfigLoader, Secret, Settings};
+use dep_template::EngineKind;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+pub const ENV_PREFIX: &str = "APP";
+pub const DEFAULT_FILE: &str = "app.yaml";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebConfig {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub auth: AuthConfig,
+    pub pubsub: PubSubConfig,
+    pub uploads: UploadsConfig,
+    pub templates: TemplatesConfig,
+    pub assets: AssetsConfig,
+    pub rate_limit: RateLimitFileConfig,
+    pub grpc_upstream: GrpcUpstreamConfig,
+    pub session: SessionConfig,
+    pub idempotency: IdempotencyConfig,
+    pub cache: CacheConfig,
+    pub webhooks: WebhooksConfig,
+    /// 业务错误(ErrCodes::coded 登记的)的 err_code 前缀 -> 状态码，比如 `"001": 400`，见 error::ErrCodes
+    pub error_codes: BTreeMap<String, u16>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub listen: SocketAddr,
+    /// 收到停机信号后 /health/ready 先返回 503，等这么多秒再停
+    pub drain_secs: u64,
+    /// drain 之后最多再等这么多秒让正在处理的请求收尾，还没完的连接直接断掉
+    pub shutdown_timeout_secs: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            listen: "0.0.0.0:3000".parse().unwrap(),
+            drain_secs: 5,
+            shutdown_timeout_secs: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DatabaseConfig {
+    pub url: Secret<String>,
+    pub max_connections: u32,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            url: Secret::new("postgres://postgres@localhost:5432/postgres".to_string()),
+            max_connections: 5,
+        }
+    }
+}
+
+/// jwt_public_key(+ jwt_private_key) 是 PEM 文件路径，配了就用 RS256；否则用 jwt_secret 走 HS256
+/// 两个都没配启动失败，除非 allow_dev_secret 打开(只给本地开发用)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub jwt_secret: Option<Secret<String>>,
+    pub jwt_public_key: Option<PathBuf>,
+    pub jwt_private_key: Option<PathBuf>,
+    pub allow_dev_secret: bool,
+}
+
+/// 多实例部署时 /ws、/sse 的频道通过 dep_async::pubsub 服务端共享
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PubSubConfig {
+    pub addr: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UploadsConfig {
+    pub dir: PathBuf,
+}
+
+impl Default for UploadsConfig {
+    fn default() -> Self {
+        UploadsConfig {
+            dir: PathBuf::from("uploads"),
+        }
+    }
+}
+
+/// 管理页面的模板目录，引擎是 minijinja / handlebars / tera
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TemplatesConfig {
+    pub dir: Option<PathBuf>,
+    pub engine: String,
+}
+
+impl Default for TemplatesConfig {
+    fn default() -> Self {
+        TemplatesConfig {
+            dir: None,
+            engine: "minijinja".to_string(),
+        }
+    }
+}
+
+/// 前端构建目录
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AssetsConfig {
+    pub dir: Option<PathBuf>,
+}
+
+/// 限流规则文件，格式见 rate_limit::RateLimitConfig，不配就不限流
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitFileConfig {
+    pub file: Option<PathBuf>,
+}
+
+/// 就绪检查要查的 gRPC 上游，比如 http://127.0.0.1:50051，service 留空表示查整个服务端
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GrpcUpstreamConfig {
+    pub endpoint: Option<String>,
+    pub service: String,
+}
+
+/// Cookie 会话，store 是 memory / postgres / resp，resp 要配 addr(dep_async 的 key-value 服务端)
+/// secret 用来派生 Cookie 的加密密钥，至少 32 字节；不配就每次启动随机生成，重启后所有人要重新登录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionConfig {
+    pub secret: Option<Secret<String>>,
+    pub store: String,
+    pub addr: Option<String>,
+    pub cookie_name: String,
+    pub idle_secs: u64,
+    pub absolute_secs: u64,
+    /// 本地用 http 调试时设成 false
+    pub secure: bool,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            secret: None,
+            store: "memory".to_string(),
+            addr: None,
+            cookie_name: "sid".to_string(),
+            idle_secs: 30 * 60,
+            absolute_secs: 12 * 60 * 60,
+            secure: true,
+        }
+    }
+}
+
+/// Idempotency-Key 的响应记录，store 是 memory / postgres
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IdempotencyConfig {
+    pub store: String,
+    pub retention_secs: u64,
+    pub lock_timeout_secs: u64,
+    pub max_body_bytes: usize,
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        IdempotencyConfig {
+            store: "memory".to_string(),
+            retention_secs: 24 * 60 * 60,
+            lock_timeout_secs: 60,
+            max_body_bytes: 2 * 1024 * 1024,
+        }
+    }
+}
+
+/// GET 响应缓存，store 是 memory(进程内 LRU，capacity 条) / resp(要配 addr)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub store: String,
+    pub addr: Option<String>,
+    pub capacity: usize,
+    pub max_entry_bytes: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            store: "memory".to_string(),
+            addr: None,
+            capacity: 10_000,
+            max_entry_bytes: 1024 * 1024,
+        }
+    }
+}
+
+/// 往外发的 webhook，端点一般写在 app.yaml 里：
+///
+/// ```yaml
+/// webhooks:
+///   endpoints:
+///     - { name: crm, url: http://crm.internal/hooks, secret: "...", events: [user.created] }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebhooksConfig {
+    pub endpoints: Vec<WebhookEndpointConfig>,
+    pub max_attempts: u32,
+    pub backoff_base_secs: u64,
+    pub backoff_max_secs: u64,
+    pub timeout_secs: u64,
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        WebhooksConfig {
+            endpoints: Vec::new(),
+            max_attempts: 8,
+            backoff_base_secs: 5,
+            backoff_max_secs: 60 * 60,
+            timeout_secs: 10,
+        }
+    }
+}
+
+/// events 为空表示订阅所有事件
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebhookEndpointConfig {
+    pub name: String,
+    pub url: String,
+    pub secret: Secret<String>,
+    pub events: Vec<String>,
+}
+
+impl Settings for WebConfig {
+    fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let url = self.database.url.expose();
+        if !url.starts_with("postgres://") && !url.starts_with("postgresql://") {
+            problems.push("database.url: must be a postgres:// URL".to_string());
+        }
+        if self.database.max_connections == 0 {
+            problems.push("database.max_connections: must be at least 1".to_string());
+        }
+        if self.auth.jwt_private_key.is_some() && self.auth.jwt_public_key.is_none() {
+            problems.push("auth.jwt_private_key: requires auth.jwt_public_key".to_string());
+        }
+        if let Err(e) = self.templates.engine.parse::<EngineKind>() {
+            problems.push(format!("templates.engine: {}", e));
+        }
+        if let Some(endpoint) = &self.grpc_upstream.endpoint
+            && !endpoint.starts_with("http://")
+            && !endpoint.starts_with("https://")
+        {
+            problems
+                .push("grpc_upstream.endpoint: must start with http:// or https://".to_string());
+        }
+        let session = &self.session;
+        if let Some(secret) = &session.secret
+            && secret.expose().len() < 32
+        {
+            problems.push("session.secret: must be at least 32 bytes".to_string());
+        }
+        match session.store.as_str() {
+            "memory" | "postgres" => {}
+            "resp" if session.addr.is_none() => {
+                problems.push("session.addr: required when session.store is resp".to_string())
+            }
+            "resp" => {}
+            other => problems.push(format!(
+                "session.store: unknown store {:?}, expected memory, postgres or resp",
+                other
+            )),
+        }
+        if session.idle_secs == 0 || session.absolute_secs == 0 {
+            problems
+                .push("session.idle_secs, session.absolute_secs: must be at least 1".to_string());
+        }
+        let idempotency = &self.idempotency;
+        if !matches!(idempotency.store.as_str(), "memory" | "postgres") {
+            problems.push(format!(
+                "idempotency.store: unknown store {:?}, expected memory or postgres",
+                idempotency.store
+            ));
+        }
+        if idempotency.retention_secs == 0 || idempotency.lock_timeout_secs == 0 {
+            problems.push(
+                "idempotency.retention_secs, idempotency.lock_timeout_secs: must be at least 1"
+                    .to_string(),
+            );
+        }
+        let webhooks = &self.webhooks;
+        for (i, endpoint) in webhooks.endpoints.iter().enumerate() {
+            if endpoint.name.is_empty() {
+                problems.push(format!("webhooks.endpoints.{}.name: must not be empty", i));
+            }
+            if webhooks.endpoints[..i]
+                .iter()
+                .any(|e| e.name == endpoint.name)
+            {
+                problems.push(format!(
+                    "webhooks.endpoints.{}.name: duplicate {:?}",
+                    i, endpoint.name
+                ));
+            }
+            if let Err(e) = crate::webhooks::parse_url(&endpoint.url) {
+                problems.push(format!("webhooks.endpoints.{}.url: {:#}", i, e));
+            }
+            if endpoint.secret.expose().is_empty() {
+                problems.push(format!(
+                    "webhooks.endpoints.{}.secret: must not be empty",
+                    i
+                ));
+            }
+        }
+        if webhooks.max_attempts == 0 {
+            problems.push("webhooks.max_attempts: must be at least 1".to_string());
+        }
+        match self.cache.store.as_str() {
+            "memory" => {}
+            "resp" if self.cache.addr.is_none() => {
+                problems.push("cache.addr: required when cache.store is resp".to_string())
+            }
+            "resp" => {}
+            other => problems.push(format!(
+                "cache.store: unknown store {:?}, expected memory or resp",
+                other
+            )),
+        }
+        for (prefix, status) in &self.error_codes {
+            if prefix.is_empty() {
+                problems.push("error_codes: prefix must not be empty".to_string());
+            }
+            if !(400..=599).contains(status) {
+                problems.push(format!(
+                    "error_codes.{}: must be a 4xx or 5xx status",
+                    prefix
+                ));
+            }
+        }
+        problems
+    }
+}
+
+impl WebConfig {
+    /// error_codes 转成 AppState 用的查表，validate 过的配置不会失败
+    pub fn err_codes(&self) -> ErrCodes {
+        self.error_codes
+            .iter()
+            .fold(ErrCodes::default(), |codes, (prefix, status)| {
+                let status =
+                    StatusCode::from_u16(*status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+                codes.map(prefix.clone(), status)
+            })
+    }
+
+    /// 服务端启动用：app.yaml、.env、环境变量、命令行都算上
+    pub fn load(args: impl IntoIterator<Item = String>) -> Result<Self, ConfigError> {
+        ConfigLoader::new(ENV_PREFIX)
+            .file(DEFAULT_FILE)
+            .args(args)
+            .load()
+    }
+
+    /// 只看 .env 和环境变量，测试里连数据库用
+    pub fn from_env() -> Result<Self, ConfigError> {
+        ConfigLoader::new(ENV_PREFIX).load()
+    }
+}
+
+#[test]
+fn web_config_layers_and_redacts() {
+    let vars = [
+        ("APP__DATABASE__URL", "postgres://app:hunter2@db/app"),
+        ("APP__AUTH__JWT_SECRET", "s3cret"),
+        ("APP__PUBSUB__ADDR", "127.0.0.1:6379"),
+    ];
+    let config: WebConfig = ConfigLoader::new(ENV_PREFIX)
+        .vars(vars.iter().map(|(k, v)| (k.to_string(), v.to_string())))
+        .args(["--server.listen", "127.0.0.1:8000"].map(String::from))
+        .load()
+        .unwrap();
+    assert_eq!(config.server.listen.port(), 8000);
+    assert_eq!(config.server.drain_secs, 5);
+    assert_eq!(config.pubsub.addr.as_deref(), Some("127.0.0.1:6379"));
+    assert_eq!(config.auth.jwt_secret.as_ref().unwrap().expose(), "s3cret");
+    let printed = format!("{:#?}", config);
+    assert!(!printed.contains("hunter2"), "{}", printed);
+    assert!(!printed.contains("s3cret"), "{}", printed);
+
+    let err = ConfigLoader::new(ENV_PREFIX)
+        .vars([("APP__TEMPLATES__ENGINE".to_string(), "jade".to_string())])
+        .load::<WebConfig>()
+        .unwrap_err();
+    assert!(err.to_string().contains("templates.engine: "), "{}", err);
+
+    let err = ConfigLoader::new(ENV_PREFIX)
+        .vars([("APP__SESSION__SECRET".to_string(), "short".to_string())])
+        .args(["--session.store", "resp"].map(String::from))
+        .load::<WebConfig>()
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("session.secret: "), "{}", err);
+    assert!(err.contains("session.addr: "), "{}", err);
+    assert!(!err.contains("short"), "{}", err);
+
+    let mut config = WebConfig::default();
+    for url in ["http://", "https://crm/hooks", "http://crm.internal/hooks"] {
+        config.webhooks.endpoints.push(WebhookEndpointConfig {
+            name: url.to_string(),
+            url: url.to_string(),
+            secret: Secret::new("s".to_string()),
+            events: Vec::new(),
+        });
+    }
+    let problems = config.validate();
+    assert_eq!(problems.len(), 2, "{:?}", problems);
+    assert!(problems[0].starts_with("webhooks.endpoints.0.url: "));
+    assert!(problems[1].starts_with("webhooks.endpoints.1.url: "));
+
+    let mut config = WebConfig::default();
+    config.error_codes.insert("001".to_string(), 400);
+    config.error_codes.insert("00100001".to_string(), 401);
+    assert!(config.validate().is_empty());
+    let codes = config.err_codes();
+    assert_eq!(codes.status_for("00100001"), StatusCode::UNAUTHORIZED);
+    assert_eq!(codes.status_for("00100002"), StatusCode::BAD_REQUEST);
+    config.error_codes.insert("002".to_string(), 200);
+    assert_eq!(
+        config.validate(),
+        ["error_codes.002: must be a 4xx or 5xx status"]
+    );
+}
@@ -0,0 +1,430 @@
+// 统一的接口错误
+// 1. handler 返回 Result<_, ApiError>，用 ? 就能把 sqlx::Error / JsonRejection / anyhow::Error 转成合适的状态码
+// 2. 带 err_code 的业务错误(比如 wjj_std 的 FmtErr / RawErr)按 err_code 前缀查表得到状态码，没登记的前缀一律 500；
+//    表是 AppState 里的 ErrCodes(启动时按 config 的 error_codes 登记)，app() 挂的 layer 放进 task_local
+// 3. 这个 crate 不依赖 wjj_std：业务错误类型用 ErrCodes::coded 登记，anyhow 链里认出来就转成 ApiError::Coded
+// 4. 响应体统一是 {"code": "...", "message": "...", "request_id": "...", "details": ...}，前端只需要处理一种格式
+
+use crate::request_id;
+use axum::Json;
+use axum::extract::multipart::{MultipartError, MultipartRejection};
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::future::Future;
+use std::sync::Arc;
+use thiserror::Error;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq, ToSchema)]
+pub struct FieldError {
+    #[schema(value_type = String)]
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &'static str, message: impl Into<String>) -> Self {
+        FieldError {
+            field,
+            message: message.into(),
+        }
+    }
+}
+
+/// 从 anyhow 链里认出一种业务错误，返回 (err_code, message)
+pub type CodedDowncast = fn(&anyhow::Error) -> Option<(String, String)>;
+
+/// err_code 前缀 -> HTTP 状态码，比如 "001" 是用户模块，"00100001" 登录失败
+#[derive(Debug, Clone, Default)]
+pub struct ErrCodes {
+    rules: Arc<Vec<(String, StatusCode)>>,
+    coded: Arc<Vec<CodedDowncast>>,
+}
+
+impl ErrCodes {
+    /// 登记 err_code 前缀对应的状态码，同一个前缀再登记会覆盖
+    pub fn map(mut self, prefix: impl Into<String>, status: StatusCode) -> Self {
+        let prefix = prefix.into();
+        let rules = Arc::make_mut(&mut self.rules);
+        rules.retain(|(p, _)| *p != prefix);
+        rules.push((prefix, status));
+        self
+    }
+
+    /// 登记一种业务错误，比如
+    /// `.coded(|err| err.downcast_ref::<FmtErr>().map(|e| (e.err_code.to_string(), e.to_string())))`；
+    /// downcast_ref 能穿过 context，和 dep_error 里的 fmt_err_test 一样
+    pub fn coded(mut self, downcast: CodedDowncast) -> Self {
+        Arc::make_mut(&mut self.coded).push(downcast);
+        self
+    }
+
+    /// 取匹配上的最长前缀，这样 "001" 整个模块 400，"00100001" 单独 401 也能配
+    pub fn status_for(&self, code: &str) -> StatusCode {
+        self.rules
+            .iter()
+            .filter(|(prefix, _)| code.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, status)| *status)
+            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    /// 在 f 里产生的 ApiError 都按这张表查状态码
+    pub async fn scope<F: Future>(self, f: F) -> F::Output {
+        ERR_CODES.scope(self, f).await
+    }
+}
+
+tokio::task_local! {
+    static ERR_CODES: ErrCodes;
+}
+
+/// 按当前请求的 ErrCodes 查状态码，不在请求上下文里时一律 500
+pub fn status_for_err_code(code: &str) -> StatusCode {
+    ERR_CODES
+        .try_with(|codes| codes.status_for(code))
+        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// 用法：`router.layer(axum::middleware::from_fn_with_state(codes, error::layer))`
+pub async fn layer(State(codes): State<ErrCodes>, req: Request, next: Next) -> Response {
+    codes.scope(next.run(req)).await
+}
+
+#[derive(Debug, Error)]
+pub enum ApiError {
+    #[error("{0}")]
+    NotFound(String),
+
+    #[error("{0}")]
+    Conflict(String),
+
+    // 没带 token / token 无效或过期
+    #[error("{0}")]
+    Unauthorized(String),
+
+    // 身份没问题，但角色或权限范围不够
+    #[error("{0}")]
+    Forbidden(String),
+
+    // 被限流，Retry-After 等响应头由限流中间件加
+    #[error("{0}")]
+    TooManyRequests(String),
+
+    #[error("请求参数校验失败")]
+    Validation(Vec<FieldError>),
+
+    // 格式没问题但没法处理，比如同一个 Idempotency-Key 配了不同的请求体
+    #[error("{0}")]
+    UnprocessableEntity(String),
+
+    // 上传的文件或整个请求超过大小限制
+    #[error("{0}")]
+    PayloadTooLarge(String),
+
+    // 按文件内容识别出来的类型不在白名单里
+    #[error("{0}")]
+    UnsupportedMediaType(String),
+
+    // 请求体不是合法 JSON、Content-Type 不对、query 参数类型不对等，状态码沿用 axum 的判断
+    #[error(transparent)]
+    Json(#[from] JsonRejection),
+
+    #[error(transparent)]
+    Query(#[from] QueryRejection),
+
+    // 不是 multipart 请求 / multipart 格式不对
+    #[error(transparent)]
+    Multipart(#[from] MultipartRejection),
+
+    #[error(transparent)]
+    MultipartStream(#[from] MultipartError),
+
+    #[error("数据库错误: {0}")]
+    Database(sqlx::Error),
+
+    // 网关转发时上游连不上 / 超时
+    #[error("{0}")]
+    BadGateway(String),
+
+    #[error("{0}")]
+    GatewayTimeout(String),
+
+    // 登记过的业务错误(见 ErrCodes::coded)，message 是按模板渲染好的，可以直接给调用方看
+    #[error("{message}")]
+    Coded { code: String, message: String },
+
+    // 没认出来的错误
+    #[error("{0:#}")]
+    Internal(anyhow::Error),
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(err: sqlx::Error) -> Self {
+        match err {
+            sqlx::Error::RowNotFound => ApiError::NotFound("资源不存在".to_string()),
+            // 数据库原文里有约束名和冲突的值，只写日志不给调用方
+            sqlx::Error::Database(ref db) if db.is_unique_violation() => {
+                log::warn!("unique violation: {}", db.message());
+                ApiError::Conflict("资源已存在".to_string())
+            }
+            err => ApiError::Database(err),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        // 当前请求的 ErrCodes 里登记过的业务错误，不在请求上下文里时认不出来
+        let coded = ERR_CODES
+            .try_with(|codes| codes.coded.iter().find_map(|downcast| downcast(&err)))
+            .ok()
+            .flatten();
+        if let Some((code, message)) = coded {
+            return ApiError::Coded { code, message };
+        }
+        let err = match err.downcast::<ApiError>() {
+            Ok(api) => return api,
+            Err(err) => err,
+        };
+        match err.downcast::<sqlx::Error>() {
+            Ok(db) => db.into(),
+            Err(err) => ApiError::Internal(err),
+        }
+    }
+}
+
+/// 所有错误响应的格式，OpenAPI 文档里也引用它
+#[derive(Serialize, ToSchema)]
+pub struct ErrorBody {
+    /// not_found / conflict / invalid_request / unauthorized / forbidden / rate_limited / internal ...，
+    /// 登记过的业务错误是它自己的 err_code
+    pub code: String,
+    pub message: String,
+    pub request_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<ErrorDetails>,
+}
+
+/// ErrorBody.details，按形状区分，不带类型标签
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum ErrorDetails {
+    /// invalid_request 的字段校验错误
+    Fields(Vec<FieldError>),
+    /// 其他错误附带的结构化信息
+    #[schema(value_type = Object)]
+    Object(Map<String, Value>),
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Validation(_) | ApiError::UnprocessableEntity(_) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::Json(rejection) => rejection.status(),
+            ApiError::Query(rejection) => rejection.status(),
+            ApiError::Multipart(rejection) => rejection.status(),
+            ApiError::MultipartStream(err) => err.status(),
+            ApiError::Database(_) | ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::BadGateway(_) => StatusCode::BAD_GATEWAY,
+            ApiError::GatewayTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            ApiError::Coded { code, .. } => status_for_err_code(code),
+        }
+    }
+
+    pub fn code(&self) -> &str {
+        match self {
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::TooManyRequests(_) => "rate_limited",
+            ApiError::Validation(_)
+            | ApiError::Json(_)
+            | ApiError::Query(_)
+            | ApiError::Multipart(_)
+            | ApiError::MultipartStream(_) => "invalid_request",
+            ApiError::UnprocessableEntity(_) => "unprocessable_entity",
+            ApiError::PayloadTooLarge(_) => "payload_too_large",
+            ApiError::UnsupportedMediaType(_) => "unsupported_media_type",
+            ApiError::Database(_) | ApiError::Internal(_) => "internal",
+            ApiError::BadGateway(_) => "bad_gateway",
+            ApiError::GatewayTimeout(_) => "gateway_timeout",
+            ApiError::Coded { code, .. } => code,
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let code = self.code().to_string();
+        let request_id = request_id::current();
+        // 数据库错误和未知错误的原始信息只打日志，不暴露给调用方
+        let message = match &self {
+            ApiError::Database(_) | ApiError::Internal(_) => {
+                // 日志格式里已经带了请求 ID
+                log::error!("internal error: {}", self);
+                "服务器内部错误".to_string()
+            }
+            ApiError::Json(rejection) => rejection.body_text(),
+            ApiError::Query(rejection) => rejection.body_text(),
+            ApiError::Multipart(rejection) => rejection.body_text(),
+            ApiError::MultipartStream(err) => err.body_text(),
+            other => other.to_string(),
+        };
+        let unauthorized = matches!(self, ApiError::Unauthorized(_));
+        let details = match self {
+            ApiError::Validation(fields) => Some(ErrorDetails::Fields(fields)),
+            _ => None,
+        };
+        let body = ErrorBody {
+            code,
+            message,
+            request_id,
+            details,
+        };
+        let mut resp = (status, Json(body)).into_response();
+        // RFC 6750：401 要告诉客户端用什么方式认证
+        if unauthorized {
+            resp.headers_mut().insert(
+                axum::http::header::WWW_AUTHENTICATE,
+                axum::http::HeaderValue::from_static("Bearer"),
+            );
+        }
+        resp
+    }
+}
+
+#[test]
+fn err_code_longest_prefix() {
+    let codes = ErrCodes::default()
+        .map("990", StatusCode::BAD_REQUEST)
+        .map("99012", StatusCode::FORBIDDEN);
+    assert_eq!(codes.status_for("99000001"), StatusCode::BAD_REQUEST);
+    assert_eq!(codes.status_for("99012001"), StatusCode::FORBIDDEN);
+    assert_eq!(
+        codes.status_for("98000001"),
+        StatusCode::INTERNAL_SERVER_ERROR
+    );
+
+    // 覆盖已有的前缀，原来那份不受影响
+    let overridden = codes.clone().map("990", StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(
+        overridden.status_for("99000001"),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(codes.status_for("99000001"), StatusCode::BAD_REQUEST);
+
+    // 不在请求上下文里
+    assert_eq!(
+        status_for_err_code("99000001"),
+        StatusCode::INTERNAL_SERVER_ERROR
+    );
+}
+
+#[tokio::test]
+async fn coded_errors_into_response() {
+    use anyhow::Context;
+    use axum::Router;
+    use axum::body::Body;
+    use axum::http::Request;
+    use axum::routing::get;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    // 相当于 wjj_std 的 FmtErr
+    #[derive(Debug, Error)]
+    #[error("user login failed Error cause: {cause}")]
+    struct LoginError {
+        err_code: &'static str,
+        cause: &'static str,
+    }
+
+    fn login() -> anyhow::Result<()> {
+        Err(LoginError {
+            err_code: "00100001",
+            cause: "network error",
+        }
+        .into())
+    }
+
+    let codes = ErrCodes::default()
+        .map("001", StatusCode::SERVICE_UNAVAILABLE)
+        .coded(|err| {
+            err.downcast_ref::<LoginError>()
+                .map(|e| (e.err_code.to_string(), e.to_string()))
+        });
+
+    let app = Router::new()
+        .route(
+            "/fmt",
+            get(|| async { Ok::<(), ApiError>(login().context("调用下游失败")?) }),
+        )
+        .route(
+            "/raw",
+            get(|| async {
+                Err::<(), ApiError>(ApiError::Coded {
+                    code: "00200001".to_string(),
+                    message: "user login limited".to_string(),
+                })
+            }),
+        )
+        .route(
+            "/io",
+            get(|| async {
+                let content = std::fs::read_to_string("/").context("读取配置失败")?;
+                Ok::<String, ApiError>(content)
+            }),
+        )
+        .layer(axum::middleware::from_fn_with_state(codes, layer))
+        .layer(axum::middleware::from_fn(request_id::layer));
+
+    let call = |uri: &'static str| {
+        let app = app.clone();
+        async move {
+            let req = Request::get(uri)
+                .header("x-request-id", "req-42")
+                .body(Body::empty())
+                .unwrap();
+            let resp = app.oneshot(req).await.unwrap();
+            let status = resp.status();
+            assert_eq!(resp.headers()["x-request-id"], "req-42");
+            let bytes = resp.into_body().collect().await.unwrap().to_bytes();
+            (status, serde_json::from_slice::<Value>(&bytes).unwrap())
+        }
+    };
+
+    // context 包了一层也能向下转型拿到原始错误
+    let (status, body) = call("/fmt").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["code"], "00100001");
+    assert!(body["message"].as_str().unwrap().contains("network error"));
+    assert_eq!(body["request_id"], "req-42");
+    assert!(body.get("details").is_none());
+
+    // 没登记的前缀
+    let (status, body) = call("/raw").await;
+    assert_eq!(body["code"], "00200001");
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+
+    // 不认识的错误不能把内部信息漏出去
+    let (status, body) = call("/io").await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(body["code"], "internal");
+    assert_eq!(body["message"], "服务器内部错误");
+}
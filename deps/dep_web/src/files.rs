@@ -0,0 +1,780 @@
+// 文件上传下载
+// POST /files       multipart/form-data，可以一次传多个文件，要登录
+// GET  /files/{id}  下载，支持 Range 断点续传和 ETag 缓存，也要登录(id 不当作访问凭证)
+// 1. 上传时每个文件边收边写临时文件，同时算 SHA-256，内存里最多放一个 chunk，不会整个文件读进来
+// 2. 单个文件和整个请求各有大小上限，超了立刻停止读取返回 413，已经写了一半的临时文件会删掉
+// 3. 文件类型看开头的魔数，不信客户端给的 Content-Type 和扩展名，不在白名单里返回 415
+// 4. 全部文件都收完、检查过以后才一起落盘(改名)和写数据库，一个失败整个请求都不生效
+// 5. ETag 就是内容的 SHA-256，If-None-Match 命中返回 304；Range 只支持单个区间，多个区间时返回整个文件
+
+use crate::auth::{AuthKeys, AuthLayer, AuthUser};
+use crate::error::{ApiError, ErrorBody};
+use crate::state::AppState;
+use axum::body::Body;
+use axum::extract::multipart::MultipartRejection;
+use axum::extract::{DefaultBodyLimit, Multipart, Path, State};
+use axum::http::header::{
+    ACCEPT_RANGES, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG,
+    IF_NONE_MATCH, IF_RANGE, RANGE,
+};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use dep_serialization::ApiResponse;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use std::io::SeekFrom;
+use std::path::PathBuf;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufWriter};
+use tokio_util::io::ReaderStream;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+// 识别类型要看的开头字节数，常见格式的魔数都在这个范围里
+const SNIFF_LEN: usize = 512;
+
+#[derive(Debug, Clone)]
+pub struct UploadConfig {
+    pub dir: PathBuf,
+    pub max_file_size: u64,
+    pub max_request_size: u64,
+    /// 按魔数识别出来的 MIME 类型白名单
+    pub allowed_types: Vec<String>,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        UploadConfig {
+            dir: PathBuf::from("uploads"),
+            max_file_size: 10 * 1024 * 1024,
+            max_request_size: 32 * 1024 * 1024,
+            allowed_types: [
+                "image/png",
+                "image/jpeg",
+                "image/gif",
+                "image/webp",
+                "application/pdf",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, FromRow, PartialEq, Eq, ToSchema)]
+pub struct StoredFile {
+    pub id: String,
+    pub filename: String,
+    pub content_type: String,
+    pub size: i64,
+    /// 十六进制
+    pub sha256: String,
+}
+
+// 只用于 OpenAPI 文档描述表单
+#[derive(ToSchema)]
+struct UploadForm {
+    /// 一个或多个文件，字段名随意
+    #[schema(value_type = Vec<String>, format = Binary)]
+    files: Vec<Vec<u8>>,
+}
+
+pub fn routes(keys: &AuthKeys) -> Router<AppState> {
+    Router::new()
+        .route(
+            "/files",
+            post(upload)
+                .route_layer(AuthLayer::new(keys.clone()))
+                // 大小限制自己按 UploadConfig 数，不用 axum 默认的 2MB
+                .layer(DefaultBodyLimit::disable()),
+        )
+        .route(
+            "/files/{id}",
+            get(download).route_layer(AuthLayer::new(keys.clone())),
+        )
+}
+
+// 还没落盘的文件，drop 的时候删掉，出错提前返回时不会留下垃圾
+struct TempPath(Option<PathBuf>);
+
+impl TempPath {
+    fn path(&self) -> &std::path::Path {
+        self.0.as_deref().unwrap()
+    }
+
+    fn keep(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        if let Some(path) = self.0.take() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+struct Staged {
+    temp: TempPath,
+    file: StoredFile,
+}
+
+// 只留最后一段，去掉路径和控制字符，防止客户端传 ../../etc/passwd 之类的名字
+fn sanitize_filename(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name: String = name.chars().filter(|c| !c.is_control()).take(255).collect();
+    match name.trim() {
+        "" | "." | ".." => "file".to_string(),
+        name => name.to_string(),
+    }
+}
+
+fn sniff(head: &[u8], allowed: &[String]) -> Result<String, ApiError> {
+    let mime = infer::get(head).map(|kind| kind.mime_type());
+    match mime {
+        Some(mime) if allowed.iter().any(|a| a == mime) => Ok(mime.to_string()),
+        Some(mime) => Err(ApiError::UnsupportedMediaType(format!(
+            "不支持的文件类型 {}",
+            mime
+        ))),
+        None => Err(ApiError::UnsupportedMediaType(
+            "无法识别的文件类型".to_string(),
+        )),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/files",
+    tag = "files",
+    request_body(content = UploadForm, content_type = "multipart/form-data"),
+    security(("bearer" = [])),
+    responses(
+        (status = 201, description = "上传成功，按表单里的顺序返回", body = ApiResponse<Vec<StoredFile>>),
+        (status = 401, description = "没登录", body = ErrorBody),
+        (status = 413, description = "文件或请求太大", body = ErrorBody),
+        (status = 415, description = "文件类型不允许", body = ErrorBody),
+    )
+)]
+async fn upload(
+    State(state): State<AppState>,
+    user: AuthUser,
+    headers: HeaderMap,
+    multipart: Result<Multipart, MultipartRejection>,
+) -> Result<(StatusCode, Json<ApiResponse<Vec<StoredFile>>>), ApiError> {
+    let config = &state.uploads;
+    let too_large =
+        || ApiError::PayloadTooLarge(format!("请求不能超过 {} 字节", config.max_request_size));
+    // 声明的长度已经超了就不用读了
+    let declared = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok()?.parse::<u64>().ok());
+    if declared.is_some_and(|len| len > config.max_request_size) {
+        return Err(too_large());
+    }
+    let mut multipart = multipart?;
+    tokio::fs::create_dir_all(&config.dir)
+        .await
+        .map_err(anyhow::Error::from)?;
+
+    let mut total = 0u64;
+    let mut staged = Vec::new();
+    while let Some(mut field) = multipart.next_field().await? {
+        // 普通表单字段不存，但也算进请求大小
+        let Some(filename) = field.file_name().map(sanitize_filename) else {
+            while let Some(chunk) = field.chunk().await? {
+                total += chunk.len() as u64;
+                if total > config.max_request_size {
+                    return Err(too_large());
+                }
+            }
+            continue;
+        };
+
+        let id = Uuid::new_v4().to_string();
+        let temp = TempPath(Some(config.dir.join(format!(".tmp-{}", id))));
+        let mut out = BufWriter::new(
+            File::create(temp.path())
+                .await
+                .map_err(anyhow::Error::from)?,
+        );
+        let mut hasher = Sha256::new();
+        let mut size = 0u64;
+        let mut head = Vec::new();
+        let mut content_type = None;
+        while let Some(chunk) = field.chunk().await? {
+            size += chunk.len() as u64;
+            total += chunk.len() as u64;
+            if size > config.max_file_size {
+                return Err(ApiError::PayloadTooLarge(format!(
+                    "文件 {} 超过 {} 字节",
+                    filename, config.max_file_size
+                )));
+            }
+            if total > config.max_request_size {
+                return Err(too_large());
+            }
+            hasher.update(&chunk);
+            // 攒够开头的字节先判断类型，不允许的类型后面的内容就不用写了
+            if content_type.is_none() {
+                head.extend_from_slice(&chunk);
+                if head.len() >= SNIFF_LEN {
+                    content_type = Some(sniff(&head, &config.allowed_types)?);
+                    out.write_all(&head).await.map_err(anyhow::Error::from)?;
+                    head = Vec::new();
+                }
+                continue;
+            }
+            out.write_all(&chunk).await.map_err(anyhow::Error::from)?;
+        }
+        let content_type = match content_type {
+            Some(content_type) => content_type,
+            // 文件比 SNIFF_LEN 还短
+            None => {
+                let content_type = sniff(&head, &config.allowed_types)?;
+                out.write_all(&head).await.map_err(anyhow::Error::from)?;
+                content_type
+            }
+        };
+        out.flush().await.map_err(anyhow::Error::from)?;
+
+        staged.push(Staged {
+            temp,
+            file: StoredFile {
+                id,
+                filename,
+                content_type,
+                size: size as i64,
+                sha256: hex::encode(hasher.finalize()),
+            },
+        });
+    }
+    if staged.is_empty() {
+        return Err(ApiError::Validation(vec![crate::error::FieldError::new(
+            "files",
+            "至少要上传一个文件",
+        )]));
+    }
+
+    // 先改名再提交事务：提交失败的话文件跟着 TempPath 一起删掉
+    let mut tx = state.pool.begin().await?;
+    let mut stored = Vec::with_capacity(staged.len());
+    let mut finals = Vec::with_capacity(staged.len());
+    for Staged { temp, file } in staged {
+        let path = config.dir.join(&file.id);
+        tokio::fs::rename(temp.path(), &path)
+            .await
+            .map_err(anyhow::Error::from)?;
+        temp.keep();
+        finals.push(TempPath(Some(path)));
+        sqlx::query(
+            "INSERT INTO files (id, filename, content_type, size, sha256, uploaded_by) VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(&file.id)
+        .bind(&file.filename)
+        .bind(&file.content_type)
+        .bind(file.size)
+        .bind(&file.sha256)
+        .bind(user.id)
+        .execute(&mut *tx)
+        .await?;
+        stored.push(file);
+    }
+    tx.commit().await?;
+    finals.into_iter().for_each(TempPath::keep);
+
+    Ok((StatusCode::CREATED, Json(ApiResponse::success(stored))))
+}
+
+// If-None-Match 用弱比较，W/ 前缀忽略
+pub(crate) fn etag_matches(header: Option<&axum::http::HeaderValue>, etag: &str) -> bool {
+    let Some(value) = header.and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+    value
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+enum RangeRequest {
+    Full,
+    Partial { start: u64, end: u64 },
+    Unsatisfiable,
+}
+
+// 只认 bytes=start-end / bytes=start- / bytes=-suffix 单个区间，格式不对就当没有 Range
+fn parse_range(header: &str, size: u64) -> RangeRequest {
+    let Some(spec) = header.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+    if spec.contains(',') {
+        return RangeRequest::Full;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return RangeRequest::Full;
+    };
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", "") => return RangeRequest::Full,
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => return RangeRequest::Unsatisfiable,
+            Ok(suffix) => (size.saturating_sub(suffix), size.saturating_sub(1)),
+            Err(_) => return RangeRequest::Full,
+        },
+        (start, end) => {
+            let Ok(start) = start.parse::<u64>() else {
+                return RangeRequest::Full;
+            };
+            let end = match end {
+                "" => size.saturating_sub(1),
+                end => match end.parse::<u64>() {
+                    Ok(end) if end >= start => end.min(size.saturating_sub(1)),
+                    _ => return RangeRequest::Full,
+                },
+            };
+            (start, end)
+        }
+    };
+    if size == 0 || start >= size {
+        return RangeRequest::Unsatisfiable;
+    }
+    RangeRequest::Partial { start, end }
+}
+
+// filename 给老客户端用，只留 ASCII；filename* 是 RFC 5987 编码的原名
+fn content_disposition(filename: &str) -> String {
+    let ascii: String = filename
+        .chars()
+        .map(|c| {
+            if c.is_ascii_graphic() && c != '"' && c != '\\' || c == ' ' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let encoded: String = filename
+        .bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+                (b as char).to_string()
+            } else {
+                format!("%{:02X}", b)
+            }
+        })
+        .collect();
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        ascii, encoded
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/files/{id}",
+    tag = "files",
+    params(
+        ("id" = String, Path, description = "上传时返回的文件 id"),
+        ("Range" = Option<String>, Header, description = "单个区间，比如 bytes=0-1023"),
+        ("If-None-Match" = Option<String>, Header, description = "上次拿到的 ETag"),
+    ),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "整个文件", content_type = "application/octet-stream", body = Vec<u8>),
+        (status = 206, description = "Range 指定的那一段", content_type = "application/octet-stream", body = Vec<u8>),
+        (status = 304, description = "ETag 没变"),
+        (status = 401, description = "没登录", body = ErrorBody),
+        (status = 404, description = "文件不存在，或者不是自己上传的(admin 除外)", body = ErrorBody),
+        (status = 416, description = "Range 超出文件大小"),
+    )
+)]
+async fn download(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    // 别人的文件也回 404，不让人拿 id 试探文件存不存在
+    let file: StoredFile = sqlx::query_as(
+        "SELECT id, filename, content_type, size, sha256 FROM files WHERE id = $1 AND (uploaded_by = $2 OR $3)",
+    )
+    .bind(&id)
+    .bind(user.id)
+    .bind(user.has_role("admin"))
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("文件 {} 不存在", id)))?;
+    let path = state.uploads.dir.join(&file.id);
+    serve_file(&file, &path, &headers).await
+}
+
+// 按 ETag / Range 头返回整个文件、一段、304 或者 416，不碰数据库
+async fn serve_file(
+    file: &StoredFile,
+    path: &std::path::Path,
+    headers: &HeaderMap,
+) -> Result<Response, ApiError> {
+    let etag = format!("\"{}\"", file.sha256);
+    if etag_matches(headers.get(IF_NONE_MATCH), &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, [(ETAG, etag)]).into_response());
+    }
+
+    let size = file.size as u64;
+    // If-Range 对不上说明客户端手里那部分已经过期，给整个文件
+    let if_range_ok = headers
+        .get(IF_RANGE)
+        .is_none_or(|v| v.to_str().is_ok_and(|v| v == etag));
+    let range = match headers.get(RANGE).and_then(|v| v.to_str().ok()) {
+        Some(range) if if_range_ok => parse_range(range, size),
+        _ => RangeRequest::Full,
+    };
+    let (status, start, len) = match range {
+        RangeRequest::Full => (StatusCode::OK, 0, size),
+        RangeRequest::Partial { start, end } => {
+            (StatusCode::PARTIAL_CONTENT, start, end - start + 1)
+        }
+        RangeRequest::Unsatisfiable => {
+            return Ok((
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(CONTENT_RANGE, format!("bytes */{}", size))],
+            )
+                .into_response());
+        }
+    };
+
+    let mut reader = File::open(path)
+        .await
+        .map_err(|e| anyhow::anyhow!("open stored file {}: {}", file.id, e))?;
+    if start > 0 {
+        reader
+            .seek(SeekFrom::Start(start))
+            .await
+            .map_err(anyhow::Error::from)?;
+    }
+    let body = Body::from_stream(ReaderStream::new(reader.take(len)));
+
+    let mut resp = Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, &file.content_type)
+        .header(CONTENT_LENGTH, len)
+        .header(ETAG, &etag)
+        .header(ACCEPT_RANGES, "bytes")
+        .header(CONTENT_DISPOSITION, content_disposition(&file.filename));
+    if status == StatusCode::PARTIAL_CONTENT {
+        resp = resp.header(
+            CONTENT_RANGE,
+            format!("bytes {}-{}/{}", start, start + len - 1, size),
+        );
+    }
+    Ok(resp.body(body).map_err(anyhow::Error::from)?)
+}
+
+#[test]
+fn range_and_filename_helpers() {
+    let partial = |header: &str, size: u64| match parse_range(header, size) {
+        RangeRequest::Partial { start, end } => Some((start, end)),
+        _ => None,
+    };
+    assert_eq!(partial("bytes=0-9", 100), Some((0, 9)));
+    assert_eq!(partial("bytes=90-", 100), Some((90, 99)));
+    assert_eq!(partial("bytes=-10", 100), Some((90, 99)));
+    assert_eq!(partial("bytes=-500", 100), Some((0, 99)));
+    assert_eq!(partial("bytes=50-500", 100), Some((50, 99)));
+    // 格式不对、多个区间都当成要整个文件
+    assert!(matches!(parse_range("bytes=9-0", 100), RangeRequest::Full));
+    assert!(matches!(
+        parse_range("bytes=0-1,5-6", 100),
+        RangeRequest::Full
+    ));
+    assert!(matches!(parse_range("items=0-1", 100), RangeRequest::Full));
+    assert!(matches!(
+        parse_range("bytes=100-", 100),
+        RangeRequest::Unsatisfiable
+    ));
+    assert!(matches!(
+        parse_range("bytes=-0", 100),
+        RangeRequest::Unsatisfiable
+    ));
+
+    assert_eq!(sanitize_filename("../../etc/passwd"), "passwd");
+    assert_eq!(sanitize_filename("C:\\temp\\报告.pdf"), "报告.pdf");
+    assert_eq!(sanitize_filename(".."), "file");
+    assert_eq!(
+        content_disposition("报告 \"v2\".pdf"),
+        "attachment; filename=\"__ _v2_.pdf\"; filename*=UTF-8''%E6%8A%A5%E5%91%8A%20%22v2%22.pdf"
+    );
+}
+
+#[tokio::test]
+async fn serve_file_honours_etag_and_range() {
+    use axum::http::HeaderValue;
+    use http_body_util::BodyExt;
+
+    let content: Vec<u8> = (0..100u8).collect();
+    let path = std::env::temp_dir().join(format!("dep_web-serve-{}", Uuid::new_v4()));
+    std::fs::write(&path, &content).unwrap();
+    let file = StoredFile {
+        id: "f1".to_string(),
+        filename: "data.bin".to_string(),
+        content_type: "application/octet-stream".to_string(),
+        size: content.len() as i64,
+        sha256: "abc".to_string(),
+    };
+    let serve = |pairs: &[(&'static str, &'static str)]| {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_static(value));
+        }
+        let (file, path) = (&file, &path);
+        async move {
+            let resp = serve_file(file, path, &headers).await.unwrap();
+            let (parts, body) = resp.into_parts();
+            (parts, body.collect().await.unwrap().to_bytes())
+        }
+    };
+
+    let (parts, body) = serve(&[]).await;
+    assert_eq!(parts.status, StatusCode::OK);
+    assert_eq!(parts.headers["etag"], "\"abc\"");
+    assert_eq!(parts.headers["content-length"], "100");
+    assert_eq!(body, content);
+
+    // 弱比较、列表、* 都算命中
+    for tag in ["\"abc\"", "W/\"abc\"", "\"x\", \"abc\"", "*"] {
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_NONE_MATCH, HeaderValue::from_static(tag));
+        let resp = serve_file(&file, &path, &headers).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED, "{}", tag);
+    }
+    let (parts, _) = serve(&[("if-none-match", "\"old\"")]).await;
+    assert_eq!(parts.status, StatusCode::OK);
+
+    let (parts, body) = serve(&[("range", "bytes=10-19")]).await;
+    assert_eq!(parts.status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(parts.headers["content-range"], "bytes 10-19/100");
+    assert_eq!(parts.headers["content-length"], "10");
+    assert_eq!(body, content[10..20]);
+
+    // If-Range 对得上才给区间
+    let (parts, body) = serve(&[("range", "bytes=-5"), ("if-range", "\"abc\"")]).await;
+    assert_eq!(parts.status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(body, content[95..]);
+    let (parts, body) = serve(&[("range", "bytes=-5"), ("if-range", "\"old\"")]).await;
+    assert_eq!(parts.status, StatusCode::OK);
+    assert_eq!(body.len(), 100);
+
+    let (parts, body) = serve(&[("range", "bytes=100-")]).await;
+    assert_eq!(parts.status, StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(parts.headers["content-range"], "bytes */100");
+    assert!(body.is_empty());
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(test)]
+fn multipart_body(boundary: &str, parts: &[(&str, Option<&str>, &[u8])]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for (name, filename, content) in parts {
+        body.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+        let disposition = match filename {
+            Some(filename) => format!(
+                "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n",
+                name, filename
+            ),
+            None => format!("Content-Disposition: form-data; name=\"{}\"\r\n", name),
+        };
+        body.extend_from_slice(disposition.as_bytes());
+        // 故意说谎，类型要按内容判断
+        body.extend_from_slice(b"Content-Type: application/octet-stream\r\n\r\n");
+        body.extend_from_slice(content);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+    body
+}
+
+#[tokio::test]
+async fn upload_and_download_files() {
+    use crate::auth::{AuthUser, TokenType};
+    use axum::http::Request;
+    use http_body_util::BodyExt;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    let pool = crate::state::get_pool().await.unwrap();
+    crate::state::init_schema(&pool).await.unwrap();
+    let keys = AuthKeys::hs256(b"files-secret");
+    let dir = std::env::temp_dir().join(format!("dep_web-files-{}", Uuid::new_v4()));
+    let config = UploadConfig {
+        dir: dir.clone(),
+        max_file_size: 2048,
+        max_request_size: 3000,
+        ..UploadConfig::default()
+    };
+    let app = crate::app(AppState::new(pool, keys.clone()).with_uploads(config));
+    let token = keys
+        .issue(&AuthUser::new(1, "uploader", vec![]), TokenType::Access)
+        .unwrap();
+
+    let upload = |parts: Vec<(&'static str, Option<&'static str>, Vec<u8>)>,
+                  token: Option<&str>| {
+        let parts: Vec<_> = parts
+            .iter()
+            .map(|(name, filename, content)| (*name, *filename, content.as_slice()))
+            .collect();
+        let mut req =
+            Request::post("/files").header("content-type", "multipart/form-data; boundary=XyZ");
+        if let Some(token) = token {
+            req = req.header("authorization", format!("Bearer {}", token));
+        }
+        let req = req.body(Body::from(multipart_body("XyZ", &parts))).unwrap();
+        let app = app.clone();
+        async move {
+            let resp = app.oneshot(req).await.unwrap();
+            let status = resp.status();
+            let body = resp.into_body().collect().await.unwrap().to_bytes();
+            (status, serde_json::from_slice::<Value>(&body).unwrap())
+        }
+    };
+    let get = |uri: String, headers: Vec<(&'static str, String)>| {
+        let mut req = Request::get(uri).header("authorization", format!("Bearer {}", token));
+        for (name, value) in headers {
+            req = req.header(name, value);
+        }
+        let app = app.clone();
+        async move {
+            let resp = app.oneshot(req.body(Body::empty()).unwrap()).await.unwrap();
+            let (parts, body) = resp.into_parts();
+            (parts, body.collect().await.unwrap().to_bytes())
+        }
+    };
+    let leftovers = || std::fs::read_dir(&dir).unwrap().count();
+
+    // png 比 SNIFF_LEN 长，pdf 比它短
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    png.extend((0..1000u32).map(|i| i as u8));
+    let pdf = b"%PDF-1.7\n%tiny\n".to_vec();
+
+    let (status, _) = upload(vec![("file", Some("a.png"), png.clone())], None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, body) = upload(
+        vec![
+            ("note", None, b"hello".to_vec()),
+            ("file", Some("../a.png"), png.clone()),
+            ("file", Some("b.pdf"), pdf.clone()),
+        ],
+        Some(&token),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED, "{}", body);
+    let files = body["data"].as_array().unwrap();
+    assert_eq!(files.len(), 2);
+    assert_eq!(files[0]["filename"], "a.png");
+    assert_eq!(files[0]["content_type"], "image/png");
+    assert_eq!(files[0]["size"], png.len());
+    assert_eq!(files[0]["sha256"], hex::encode(Sha256::digest(&png)));
+    assert_eq!(files[1]["content_type"], "application/pdf");
+    assert_eq!(leftovers(), 2);
+
+    // 类型不对、太大的都不留文件
+    let (status, body) = upload(
+        vec![("file", Some("x.png"), b"just text".to_vec())],
+        Some(&token),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert_eq!(body["code"], "unsupported_media_type");
+    let mut big = png.clone();
+    big.resize(2049, 0);
+    let (status, body) = upload(vec![("file", Some("big.png"), big)], Some(&token)).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(body["code"], "payload_too_large");
+    // 单个不超，加起来超
+    let (status, _) = upload(
+        vec![
+            ("file", Some("1.png"), png.clone()),
+            ("file", Some("2.png"), png.clone()),
+            ("file", Some("3.png"), png.clone()),
+        ],
+        Some(&token),
+    )
+    .await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(leftovers(), 2);
+
+    let uri = format!("/files/{}", files[0]["id"].as_str().unwrap());
+    // 下载也要登录
+    let anonymous = Request::get(uri.as_str()).body(Body::empty()).unwrap();
+    let resp = app.clone().oneshot(anonymous).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    // 别的用户拿不到，和文件不存在一样是 404；admin 可以
+    let other = |user: AuthUser| {
+        let token = keys.issue(&user, TokenType::Access).unwrap();
+        let req = Request::get(uri.as_str())
+            .header("authorization", format!("Bearer {}", token))
+            .body(Body::empty())
+            .unwrap();
+        app.clone().oneshot(req)
+    };
+    let resp = other(AuthUser::new(2, "someone", vec![])).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let admin = AuthUser::new(3, "admin", vec!["admin".to_string()]);
+    assert_eq!(other(admin).await.unwrap().status(), StatusCode::OK);
+
+    let (parts, body) = get(uri.clone(), vec![]).await;
+    assert_eq!(parts.status, StatusCode::OK);
+    assert_eq!(body, png);
+    assert_eq!(parts.headers["content-type"], "image/png");
+    assert_eq!(parts.headers["accept-ranges"], "bytes");
+    assert!(
+        parts.headers["content-disposition"]
+            .to_str()
+            .unwrap()
+            .contains("a.png")
+    );
+    let etag = parts.headers["etag"].to_str().unwrap().to_string();
+
+    let (parts, body) = get(uri.clone(), vec![("if-none-match", etag.clone())]).await;
+    assert_eq!(parts.status, StatusCode::NOT_MODIFIED);
+    assert!(body.is_empty());
+
+    let (parts, body) = get(uri.clone(), vec![("range", "bytes=2-5".to_string())]).await;
+    assert_eq!(parts.status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(
+        parts.headers["content-range"],
+        format!("bytes 2-5/{}", png.len())
+    );
+    assert_eq!(body, png[2..6]);
+
+    let (parts, body) = get(uri.clone(), vec![("range", "bytes=-3".to_string())]).await;
+    assert_eq!(parts.status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(body, png[png.len() - 3..]);
+
+    // If-Range 不是当前的 ETag，给整个文件
+    let (parts, _) = get(
+        uri.clone(),
+        vec![
+            ("range", "bytes=2-5".to_string()),
+            ("if-range", "\"stale\"".to_string()),
+        ],
+    )
+    .await;
+    assert_eq!(parts.status, StatusCode::OK);
+
+    let (parts, _) = get(uri, vec![("range", "bytes=5000-".to_string())]).await;
+    assert_eq!(parts.status, StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(
+        parts.headers["content-range"],
+        format!("bytes */{}", png.len())
+    );
+
+    let (parts, _) = get("/files/nope".to_string(), vec![]).await;
+    assert_eq!(parts.status, StatusCode::NOT_FOUND);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,814 @@
+// 配置驱动的 API 网关，路由规则就是 dep_serialization 里的 GatewayConfig(gateway.yaml)
+// 1. predicates：Path=/a/**,/b/*  Method=GET,POST  Header=X-Id,\d+  Query=name,val.*
+//    Header/Query 的第二个参数是正则，要整个值都匹配上；不写就是只要求存在
+//    参数按 ',' 分开，带逗号的参数用双引号括起来，比如 Header=X-Id,"\d{1,3}"，引号本身写两遍 ""
+// 2. filters：AddRequestHeader=名字,值  RemoveRequestHeader=名字
+//             AddResponseHeader=名字,值  RemoveResponseHeader=名字
+//             StripPrefix=去掉几段路径  PrefixPath=/加在前面的路径
+// 3. 按 routes 的顺序匹配，第一个所有 predicate 都满足的路由生效，都不满足 404
+// 4. 用 hyper_util 带连接池的 client 转发到 uri，上游连不上 502，响应头超时 504；
+//    超时算的是整个响应，响应头回来以后响应体没在期限内发完就断开
+//
+// 配置在加载时就全部解析好，写错了启动直接失败，而不是等请求进来才报错
+
+pub mod reload;
+
+use crate::error::ApiError;
+use anyhow::{Context, anyhow, bail};
+use axum::BoxError;
+use axum::body::Body;
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use axum::http::request::Parts;
+use axum::http::{Method, Uri};
+use axum::response::{IntoResponse, Response};
+use bytes::Bytes;
+use dep_serialization::{GatewayConfig, Route};
+use hyper::body::Frame;
+use hyper_util::client::legacy::Client;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::rt::TokioExecutor;
+use regex::Regex;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::task::{Context as TaskContext, Poll};
+use std::time::Duration;
+use tokio::time::Sleep;
+
+pub const DEFAULT_UPSTREAM_TIMEOUT: Duration = Duration::from_secs(30);
+
+// 逐跳头只对当前这一段连接有效，不能转发
+const HOP_BY_HOP: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+#[derive(Debug, Clone)]
+enum Segment {
+    // **，匹配剩下的任意多段(包括 0 段)
+    Rest,
+    // 段内可以带 *，比如 *.json；{id} 等价于 *
+    Glob(Regex),
+}
+
+/// Path 断言里的一个模式，按 '/' 分段匹配
+#[derive(Debug, Clone)]
+pub struct PathPattern {
+    raw: String,
+    segments: Vec<Segment>,
+}
+
+impl PathPattern {
+    pub fn parse(raw: &str) -> anyhow::Result<Self> {
+        if !raw.starts_with('/') {
+            bail!("path pattern must start with '/': {}", raw);
+        }
+        let mut segments = Vec::new();
+        for part in raw.split('/').filter(|s| !s.is_empty()) {
+            if part == "**" {
+                segments.push(Segment::Rest);
+                continue;
+            }
+            let regex = if part.starts_with('{') && part.ends_with('}') {
+                "[^/]+".to_string()
+            } else {
+                part.split('*')
+                    .map(regex::escape)
+                    .collect::<Vec<_>>()
+                    .join("[^/]*")
+            };
+            segments.push(Segment::Glob(Regex::new(&format!("^{}$", regex))?));
+        }
+        Ok(PathPattern {
+            raw: raw.to_string(),
+            segments,
+        })
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        match_segments(&self.segments, &parts)
+    }
+}
+
+fn match_segments(pattern: &[Segment], parts: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => parts.is_empty(),
+        Some((Segment::Rest, rest)) => (0..=parts.len()).any(|i| match_segments(rest, &parts[i..])),
+        Some((Segment::Glob(regex), rest)) => match parts.split_first() {
+            Some((part, others)) => regex.is_match(part) && match_segments(rest, others),
+            None => false,
+        },
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Predicate {
+    Path(Vec<PathPattern>),
+    Method(Vec<Method>),
+    Header {
+        name: HeaderName,
+        value: Option<Regex>,
+    },
+    Query {
+        name: String,
+        value: Option<Regex>,
+    },
+}
+
+// "Name=arg1,arg2" 拆成 ("Name", ["arg1", "arg2"])，参数两边的空格去掉
+fn split_rule(rule: &str) -> anyhow::Result<(&str, Vec<String>)> {
+    let (name, args) = rule
+        .split_once('=')
+        .ok_or_else(|| anyhow!("expected Name=args: {}", rule))?;
+    Ok((name.trim(), split_args(args)?))
+}
+
+// 双引号里的 ',' 不算分隔符，"" 是引号本身
+fn split_args(args: &str) -> anyhow::Result<Vec<String>> {
+    let mut out = Vec::new();
+    let mut chars = args.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let mut arg = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next() {
+                    Some('"') if chars.next_if_eq(&'"').is_some() => arg.push('"'),
+                    Some('"') => break,
+                    Some(c) => arg.push(c),
+                    None => bail!("unterminated quote: {}", args),
+                }
+            }
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            if chars.peek().is_some_and(|c| *c != ',') {
+                bail!("unexpected text after closing quote: {}", args);
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != ',') {
+                arg.push(c);
+            }
+            arg.truncate(arg.trim_end().len());
+        }
+        out.push(arg);
+        if chars.next().is_none() {
+            return Ok(out);
+        }
+    }
+}
+
+fn full_match(pattern: &str) -> anyhow::Result<Regex> {
+    Ok(Regex::new(&format!("^(?:{})$", pattern))?)
+}
+
+impl Predicate {
+    pub fn parse(rule: &str) -> anyhow::Result<Self> {
+        let (name, args) = split_rule(rule)?;
+        let predicate = match name {
+            "Path" => Predicate::Path(
+                args.iter()
+                    .map(|p| PathPattern::parse(p))
+                    .collect::<anyhow::Result<_>>()?,
+            ),
+            "Method" => Predicate::Method(
+                args.iter()
+                    .map(|m| Method::from_bytes(m.to_ascii_uppercase().as_bytes()))
+                    .collect::<Result<_, _>>()?,
+            ),
+            "Header" => Predicate::Header {
+                name: HeaderName::from_bytes(args[0].as_bytes())?,
+                value: args.get(1).map(|v| full_match(v)).transpose()?,
+            },
+            "Query" => Predicate::Query {
+                name: args[0].to_string(),
+                value: args.get(1).map(|v| full_match(v)).transpose()?,
+            },
+            other => bail!("unknown predicate: {}", other),
+        };
+        Ok(predicate)
+    }
+
+    pub fn matches(&self, parts: &Parts) -> bool {
+        match self {
+            Predicate::Path(patterns) => patterns.iter().any(|p| p.matches(parts.uri.path())),
+            Predicate::Method(methods) => methods.contains(&parts.method),
+            Predicate::Header { name, value } => {
+                parts
+                    .headers
+                    .get_all(name)
+                    .iter()
+                    .any(|v| match (value, v.to_str()) {
+                        (None, _) => true,
+                        (Some(regex), Ok(v)) => regex.is_match(v),
+                        (Some(_), Err(_)) => false,
+                    })
+            }
+            Predicate::Query { name, value } => {
+                let query = parts.uri.query().unwrap_or("");
+                serde_urlencoded::from_str::<Vec<(String, String)>>(query)
+                    .unwrap_or_default()
+                    .iter()
+                    .any(|(k, v)| k == name && value.as_ref().is_none_or(|r| r.is_match(v)))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Filter {
+    AddRequestHeader(HeaderName, HeaderValue),
+    RemoveRequestHeader(HeaderName),
+    AddResponseHeader(HeaderName, HeaderValue),
+    RemoveResponseHeader(HeaderName),
+    StripPrefix(usize),
+    PrefixPath(String),
+}
+
+fn header_pair(args: &[String]) -> anyhow::Result<(HeaderName, HeaderValue)> {
+    let [name, value] = args else {
+        bail!("expected header name and value: {:?}", args);
+    };
+    Ok((
+        HeaderName::from_bytes(name.as_bytes())?,
+        HeaderValue::from_str(value)?,
+    ))
+}
+
+impl Filter {
+    pub fn parse(rule: &str) -> anyhow::Result<Self> {
+        let (name, args) = split_rule(rule)?;
+        let filter = match name {
+            "AddRequestHeader" => {
+                let (name, value) = header_pair(&args)?;
+                Filter::AddRequestHeader(name, value)
+            }
+            "AddResponseHeader" => {
+                let (name, value) = header_pair(&args)?;
+                Filter::AddResponseHeader(name, value)
+            }
+            "RemoveRequestHeader" => {
+                Filter::RemoveRequestHeader(HeaderName::from_bytes(args[0].as_bytes())?)
+            }
+            "RemoveResponseHeader" => {
+                Filter::RemoveResponseHeader(HeaderName::from_bytes(args[0].as_bytes())?)
+            }
+            "StripPrefix" => Filter::StripPrefix(args[0].parse()?),
+            "PrefixPath" => {
+                let prefix = args[0].trim_end_matches('/');
+                if !prefix.starts_with('/') {
+                    bail!("PrefixPath must start with '/': {}", prefix);
+                }
+                Filter::PrefixPath(prefix.to_string())
+            }
+            other => bail!("unknown filter: {}", other),
+        };
+        Ok(filter)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CompiledRoute {
+    pub id: String,
+    // 只保留 scheme://host:port 和可选的基础路径
+    upstream: Uri,
+    predicates: Vec<Predicate>,
+    filters: Vec<Filter>,
+}
+
+impl CompiledRoute {
+    pub fn compile(route: &Route) -> anyhow::Result<Self> {
+        let upstream: Uri = route.uri.parse()?;
+        match upstream.scheme_str() {
+            Some("http") => {}
+            // 只用了 HttpConnector，没有 TLS
+            other => bail!("only http upstreams are supported, got {:?}", other),
+        }
+        if upstream.authority().is_none() {
+            bail!("upstream uri has no host: {}", route.uri);
+        }
+        Ok(CompiledRoute {
+            id: route.id.clone(),
+            upstream,
+            predicates: route
+                .predicates
+                .iter()
+                .map(|p| Predicate::parse(p).with_context(|| format!("predicate {:?}", p)))
+                .collect::<anyhow::Result<_>>()?,
+            filters: route
+                .filters
+                .iter()
+                .map(|f| Filter::parse(f).with_context(|| format!("filter {:?}", f)))
+                .collect::<anyhow::Result<_>>()?,
+        })
+    }
+
+    pub fn matches(&self, parts: &Parts) -> bool {
+        self.predicates.iter().all(|p| p.matches(parts))
+    }
+
+    // 按顺序执行路径改写，返回转发给上游的完整 uri
+    fn upstream_uri(&self, uri: &Uri) -> anyhow::Result<Uri> {
+        let mut path = uri.path().to_string();
+        for filter in &self.filters {
+            match filter {
+                Filter::StripPrefix(n) => {
+                    let rest: Vec<&str> =
+                        path.split('/').filter(|s| !s.is_empty()).skip(*n).collect();
+                    let trailing = path.ends_with('/') && !rest.is_empty();
+                    path = format!("/{}{}", rest.join("/"), if trailing { "/" } else { "" });
+                }
+                Filter::PrefixPath(prefix) => path = format!("{}{}", prefix, path),
+                _ => {}
+            }
+        }
+        let base = self.upstream.path().trim_end_matches('/');
+        let path_and_query = match uri.query() {
+            Some(query) => format!("{}{}?{}", base, path, query),
+            None => format!("{}{}", base, path),
+        };
+        Ok(Uri::builder()
+            .scheme(self.upstream.scheme_str().unwrap_or("http"))
+            .authority(self.upstream.authority().unwrap().as_str())
+            .path_and_query(path_and_query)
+            .build()?)
+    }
+
+    fn apply_request_filters(&self, headers: &mut HeaderMap) {
+        for filter in &self.filters {
+            match filter {
+                Filter::AddRequestHeader(name, value) => {
+                    headers.append(name.clone(), value.clone());
+                }
+                Filter::RemoveRequestHeader(name) => {
+                    headers.remove(name);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn apply_response_filters(&self, headers: &mut HeaderMap) {
+        for filter in &self.filters {
+            match filter {
+                Filter::AddResponseHeader(name, value) => {
+                    headers.append(name.clone(), value.clone());
+                }
+                Filter::RemoveResponseHeader(name) => {
+                    headers.remove(name);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// 编译好的整张路由表
+#[derive(Debug, Clone, Default)]
+pub struct RouteTable {
+    routes: Vec<CompiledRoute>,
+}
+
+impl RouteTable {
+    pub fn compile(config: &GatewayConfig) -> anyhow::Result<Self> {
+        let routes = config
+            .gateway
+            .routes
+            .iter()
+            .map(|r| CompiledRoute::compile(r).with_context(|| format!("route {}", r.id)))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(RouteTable { routes })
+    }
+
+    pub fn from_yaml(yaml: &str) -> anyhow::Result<Self> {
+        let config: GatewayConfig = serde_yaml::from_str(yaml)?;
+        Self::compile(&config)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let yaml =
+            std::fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+        Self::from_yaml(&yaml).with_context(|| format!("parse {}", path.display()))
+    }
+
+    pub fn find(&self, parts: &Parts) -> Option<&CompiledRoute> {
+        self.routes.iter().find(|r| r.matches(parts))
+    }
+
+    pub fn ids(&self) -> Vec<&str> {
+        self.routes.iter().map(|r| r.id.as_str()).collect()
+    }
+}
+
+fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    // Connection 头里列出来的也是逐跳头
+    let listed: Vec<HeaderName> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect();
+    for name in listed {
+        headers.remove(name);
+    }
+    for name in HOP_BY_HOP {
+        headers.remove(name);
+    }
+}
+
+#[derive(Clone)]
+pub struct Gateway {
+    // 热加载时整体换掉里面的 Arc，正在处理的请求还拿着旧表，见 reload.rs
+    table: Arc<RwLock<Arc<RouteTable>>>,
+    client: Client<HttpConnector, Body>,
+    timeout: Duration,
+}
+
+impl Gateway {
+    pub fn new(table: RouteTable) -> Self {
+        Gateway {
+            table: Arc::new(RwLock::new(Arc::new(table))),
+            client: Client::builder(TokioExecutor::new()).build_http(),
+            timeout: DEFAULT_UPSTREAM_TIMEOUT,
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn table(&self) -> Arc<RouteTable> {
+        self.table.read().unwrap().clone()
+    }
+
+    /// 原子地换上新路由表，返回旧表
+    pub fn swap_table(&self, table: RouteTable) -> Arc<RouteTable> {
+        std::mem::replace(&mut *self.table.write().unwrap(), Arc::new(table))
+    }
+
+    /// 网关自己不定义任何路由，所有请求都走 fallback 转发
+    pub fn router(self) -> axum::Router {
+        axum::Router::new().fallback(proxy).with_state(self)
+    }
+
+    pub async fn forward(&self, req: Request) -> Result<Response, ApiError> {
+        let (mut parts, body) = req.into_parts();
+        // 整个请求用同一张表，中途热加载也不影响
+        let table = self.table();
+        let route = table
+            .find(&parts)
+            .ok_or_else(|| ApiError::NotFound(format!("没有匹配 {} 的路由", parts.uri.path())))?;
+
+        let uri = route.upstream_uri(&parts.uri)?;
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        let host = parts.headers.remove(header::HOST);
+
+        let mut headers = std::mem::take(&mut parts.headers);
+        strip_hop_by_hop(&mut headers);
+        if let Some(Ok(ip)) = peer.map(|ip| HeaderValue::from_str(&ip.to_string())) {
+            headers.append("x-forwarded-for", ip);
+        }
+        if let Some(host) = host {
+            headers.insert("x-forwarded-host", host);
+        }
+        route.apply_request_filters(&mut headers);
+
+        let mut upstream_req = hyper::Request::builder()
+            .method(parts.method)
+            .uri(uri)
+            .body(body)
+            .map_err(anyhow::Error::from)?;
+        *upstream_req.headers_mut() = headers;
+
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        let resp = match tokio::time::timeout_at(deadline, self.client.request(upstream_req)).await
+        {
+            Ok(Ok(resp)) => resp,
+            Ok(Err(err)) => {
+                log::warn!("route {} upstream error: {:?}", route.id, err);
+                return Err(ApiError::BadGateway(format!(
+                    "上游服务 {} 不可用",
+                    route.id
+                )));
+            }
+            Err(_) => {
+                return Err(ApiError::GatewayTimeout(format!(
+                    "上游服务 {} 响应超时",
+                    route.id
+                )));
+            }
+        };
+
+        let (mut parts, body) = resp.into_parts();
+        strip_hop_by_hop(&mut parts.headers);
+        route.apply_response_filters(&mut parts.headers);
+        let body = DeadlineBody {
+            inner: body,
+            sleep: Box::pin(tokio::time::sleep_until(deadline)),
+        };
+        Ok(Response::from_parts(parts, Body::new(body)))
+    }
+}
+
+// 上游响应体，过了 deadline 还没发完就报错，下游连接随之断开
+struct DeadlineBody<B> {
+    inner: B,
+    sleep: Pin<Box<Sleep>>,
+}
+
+impl<B> hyper::body::Body for DeadlineBody<B>
+where
+    B: hyper::body::Body<Data = Bytes> + Unpin,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        if let Poll::Ready(frame) = Pin::new(&mut self.inner).poll_frame(cx) {
+            return Poll::Ready(frame.map(|f| f.map_err(Into::into)));
+        }
+        match self.sleep.as_mut().poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Some(Err("upstream response body timed out".into()))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> hyper::body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+async fn proxy(State(gateway): State<Gateway>, req: Request) -> Response {
+    match gateway.forward(req).await {
+        Ok(resp) => resp,
+        Err(err) => err.into_response(),
+    }
+}
+
+#[test]
+fn path_pattern_matching() {
+    let p = PathPattern::parse("/service1/**").unwrap();
+    assert!(p.matches("/service1"));
+    assert!(p.matches("/service1/"));
+    assert!(p.matches("/service1/a/b/c"));
+    assert!(!p.matches("/service10/a"));
+    assert!(!p.matches("/other/service1"));
+
+    let p = PathPattern::parse("/api/{version}/users/*.json").unwrap();
+    assert!(p.matches("/api/v1/users/list.json"));
+    assert!(!p.matches("/api/v1/users/list.xml"));
+    assert!(!p.matches("/api/users/list.json"));
+
+    let p = PathPattern::parse("/**/health").unwrap();
+    assert!(p.matches("/health"));
+    assert!(p.matches("/a/b/health"));
+    assert!(!p.matches("/a/b/healthz"));
+}
+
+#[test]
+fn predicates_and_filters_parse() {
+    let parts = |req: hyper::Request<()>| req.into_parts().0;
+    let req = parts(
+        hyper::Request::post("/orders/1?status=paid&x=1")
+            .header("x-tenant", "t-42")
+            .body(())
+            .unwrap(),
+    );
+
+    let check = |rule: &str| Predicate::parse(rule).unwrap().matches(&req);
+    assert!(check("Path=/users/**, /orders/**"));
+    assert!(check("Method=get,POST"));
+    assert!(!check("Method=GET"));
+    assert!(check("Header=X-Tenant"));
+    assert!(check(r"Header=X-Tenant, t-\d+"));
+    assert!(!check(r"Header=X-Tenant, \d+"));
+    assert!(check("Query=status"));
+    assert!(check("Query=status,pa.*"));
+    assert!(!check("Query=status,pa"));
+    assert!(!check("Query=missing"));
+
+    // 带逗号的正则要加引号
+    assert!(check(r#"Header=X-Tenant, "t-\d{1,3}""#));
+    assert!(!check(r#"Header=X-Tenant, "t-\d{3,5}""#));
+    assert_eq!(
+        split_args(r#" a , "b,c" ,"say ""hi""",d "#).unwrap(),
+        ["a", "b,c", r#"say "hi""#, "d"]
+    );
+    assert!(split_args(r#""open"#).is_err());
+    assert!(split_args(r#""a"b"#).is_err());
+
+    assert!(Predicate::parse("Cookie=a,b").is_err());
+    assert!(Predicate::parse("Path").is_err());
+    assert!(Filter::parse("AddRequestHeader=X-Only-Name").is_err());
+    assert!(Filter::parse("StripPrefix=two").is_err());
+
+    let route = CompiledRoute::compile(&Route {
+        id: "r".to_string(),
+        uri: "http://127.0.0.1:9000/base/".to_string(),
+        predicates: vec![],
+        filters: vec!["StripPrefix=1".to_string(), "PrefixPath=/v2".to_string()],
+    })
+    .unwrap();
+    let uri = route
+        .upstream_uri(&"/svc/users/1?x=1".parse().unwrap())
+        .unwrap();
+    assert_eq!(uri.to_string(), "http://127.0.0.1:9000/base/v2/users/1?x=1");
+
+    let https = Route {
+        uri: "https://example.com".to_string(),
+        ..route_stub()
+    };
+    assert!(CompiledRoute::compile(&https).is_err());
+}
+
+#[cfg(test)]
+fn route_stub() -> Route {
+    Route {
+        id: "stub".to_string(),
+        uri: "http://127.0.0.1:1".to_string(),
+        predicates: vec![],
+        filters: vec![],
+    }
+}
+
+// 起一个把请求原样回显成 JSON 的上游
+#[cfg(test)]
+async fn spawn_echo_upstream() -> SocketAddr {
+    use axum::http::HeaderMap;
+    use axum::routing::any;
+
+    let app = axum::Router::new().fallback(any(
+        |method: Method, uri: Uri, headers: HeaderMap| async move {
+            let headers: serde_json::Map<String, serde_json::Value> = headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or("").into()))
+                .collect();
+            (
+                [("x-upstream", "echo"), ("x-internal", "secret")],
+                axum::Json(serde_json::json!({
+                    "method": method.as_str(),
+                    "uri": uri.to_string(),
+                    "headers": headers,
+                })),
+            )
+        },
+    ));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    addr
+}
+
+#[tokio::test]
+async fn gateway_routes_and_proxies() {
+    use axum::http::StatusCode;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    let upstream = spawn_echo_upstream().await;
+    let yaml = format!(
+        r#"
+gateway:
+  routes:
+    - id: admin
+      uri: http://{upstream}
+      predicates:
+        - Path=/service1/**
+        - Header=X-Role, admin
+      filters:
+        - AddRequestHeader=X-Route,admin
+    - id: service1
+      uri: http://{upstream}
+      predicates:
+        - Path=/service1/**
+        - Method=GET
+      filters:
+        - AddRequestHeader=X-Request-Id,12345
+        - RemoveRequestHeader=Cookie
+        - StripPrefix=1
+        - AddResponseHeader=X-Response-Time,100ms
+        - RemoveResponseHeader=X-Internal
+    - id: down
+      uri: http://127.0.0.1:1
+      predicates:
+        - Path=/down/**
+"#
+    );
+    let table = RouteTable::from_yaml(&yaml).unwrap();
+    assert_eq!(table.ids(), vec!["admin", "service1", "down"]);
+    let app = Gateway::new(table).router();
+
+    let call = |req: hyper::Request<Body>| {
+        let app = app.clone();
+        async move {
+            let resp = app.oneshot(req).await.unwrap();
+            let status = resp.status();
+            let headers = resp.headers().clone();
+            let bytes = resp.into_body().collect().await.unwrap().to_bytes();
+            let json: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            (status, headers, json)
+        }
+    };
+
+    let (status, headers, body) = call(
+        hyper::Request::get("/service1/users/7?active=true")
+            .header("cookie", "session=1")
+            .header("connection", "keep-alive")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["uri"], "/users/7?active=true");
+    assert_eq!(body["headers"]["x-request-id"], "12345");
+    assert!(body["headers"].get("cookie").is_none());
+    assert_eq!(headers["x-upstream"], "echo");
+    assert_eq!(headers["x-response-time"], "100ms");
+    assert!(headers.get("x-internal").is_none());
+
+    // 先声明的路由优先
+    let (_, _, body) = call(
+        hyper::Request::post("/service1/x")
+            .header("x-role", "admin")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(body["method"], "POST");
+    assert_eq!(body["uri"], "/service1/x");
+    assert_eq!(body["headers"]["x-route"], "admin");
+
+    // 方法不匹配就没有路由
+    let (status, _, body) = call(
+        hyper::Request::post("/service1/x")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "not_found");
+
+    let (status, _, body) = call(hyper::Request::get("/down/x").body(Body::empty()).unwrap()).await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert_eq!(body["code"], "bad_gateway");
+}
+
+#[tokio::test]
+async fn gateway_timeout_covers_body() {
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use futures_util::StreamExt;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    // 响应头马上回来，响应体发一段以后就卡住
+    let app = axum::Router::new().route(
+        "/slow",
+        get(|| async {
+            let chunks = futures_util::stream::iter([Ok::<_, std::io::Error>("first")])
+                .chain(futures_util::stream::pending());
+            Body::from_stream(chunks)
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let yaml = format!(
+        "gateway:\n  routes:\n    - id: slow\n      uri: http://{upstream}\n      predicates: [Path=/slow]\n"
+    );
+    let gateway = Gateway::new(RouteTable::from_yaml(&yaml).unwrap())
+        .with_timeout(Duration::from_millis(200));
+    let resp = gateway
+        .router()
+        .oneshot(hyper::Request::get("/slow").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let collected = tokio::time::timeout(Duration::from_secs(5), resp.into_body().collect())
+        .await
+        .expect("body should be cut off by the gateway timeout");
+    assert!(collected.is_err());
+}
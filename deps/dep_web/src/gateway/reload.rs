@@ -0,0 +1,274 @@
+// gateway.yaml 热加载，改路由不用重启
+// 1. notify(Linux 上就是 inotify)监听配置文件所在的目录，而不是文件本身：
+//    vim 之类先写临时文件再 rename 过来的编辑方式，旧文件的 watch 会失效
+// 2. kill -HUP <pid> 也会触发一次重新加载
+// 3. 新配置先完整解析校验，有错就打日志，继续用旧路由
+// 4. 校验通过后 swap_table 整体替换，已经在处理的请求拿着旧表的 Arc 跑完，新请求走新表
+// 5. 保存一次文件通常会连着来好几个事件，等一小会儿合并成一次加载
+
+use super::{Gateway, RouteTable};
+use notify::event::{AccessKind, AccessMode};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// 重新读取并校验配置，成功才替换路由表，返回新的路由 id
+pub fn reload(gateway: &Gateway, path: &Path) -> anyhow::Result<Vec<String>> {
+    let table = RouteTable::load(path)?;
+    let ids = table.ids().iter().map(|id| id.to_string()).collect();
+    gateway.swap_table(table);
+    Ok(ids)
+}
+
+fn reload_and_log(gateway: &Gateway, path: &Path, reason: &str) {
+    match reload(gateway, path) {
+        Ok(ids) => log::info!(
+            "{}: reloaded routes {:?} from {}",
+            reason,
+            ids,
+            path.display()
+        ),
+        Err(err) => log::error!(
+            "{}: keep old routes, invalid config {}: {:#}",
+            reason,
+            path.display(),
+            err
+        ),
+    }
+}
+
+// 读文件本身也会产生 Access 事件，只关心真正改动了内容的那些，不然会自己触发自己
+fn is_change(kind: &EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Create(_)
+            | EventKind::Modify(_)
+            | EventKind::Remove(_)
+            | EventKind::Access(AccessKind::Close(AccessMode::Write))
+    )
+}
+
+// SIGHUP 只有 unix 上有，其它平台上永远等不到；测试里用 channel 代替信号
+enum Hangup {
+    #[cfg(unix)]
+    Signal(tokio::signal::unix::Signal),
+    #[cfg(not(unix))]
+    Never,
+    #[cfg(test)]
+    Channel(mpsc::UnboundedReceiver<()>),
+}
+
+impl Hangup {
+    fn new() -> std::io::Result<Self> {
+        #[cfg(unix)]
+        return Ok(Hangup::Signal(tokio::signal::unix::signal(
+            tokio::signal::unix::SignalKind::hangup(),
+        )?));
+        #[cfg(not(unix))]
+        Ok(Hangup::Never)
+    }
+
+    async fn recv(&mut self) -> Option<()> {
+        match self {
+            #[cfg(unix)]
+            Hangup::Signal(signal) => signal.recv().await,
+            #[cfg(not(unix))]
+            Hangup::Never => std::future::pending().await,
+            #[cfg(test)]
+            Hangup::Channel(rx) => rx.recv().await,
+        }
+    }
+}
+
+/// 启动后台任务监听 path 的变化和 SIGHUP，返回的 JoinHandle abort 掉就停止监听
+pub fn spawn_watcher(gateway: Gateway, path: impl Into<PathBuf>) -> anyhow::Result<JoinHandle<()>> {
+    // 在 spawn 之前注册好，函数返回以后 kill -HUP 就不会把进程杀掉了
+    spawn_with(gateway, path.into(), Hangup::new()?)
+}
+
+fn spawn_with(
+    gateway: Gateway,
+    path: PathBuf,
+    mut hangup: Hangup,
+) -> anyhow::Result<JoinHandle<()>> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("not a file: {}", path.display()))?
+        .to_os_string();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    // notify 的回调跑在它自己的线程里，用 channel 转到 tokio 里处理
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(event) = res
+            && is_change(&event.kind)
+            && event
+                .paths
+                .iter()
+                .any(|p| p.file_name() == Some(&file_name))
+        {
+            let _ = tx.send(());
+        }
+    })?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+
+    Ok(tokio::spawn(async move {
+        // watcher drop 掉就不再有事件了，让它跟着任务一起活着
+        let _watcher = watcher;
+        loop {
+            tokio::select! {
+                changed = rx.recv() => {
+                    if changed.is_none() {
+                        break;
+                    }
+                    tokio::time::sleep(DEBOUNCE).await;
+                    while rx.try_recv().is_ok() {}
+                    reload_and_log(&gateway, &path, "file changed");
+                }
+                Some(()) = hangup.recv() => reload_and_log(&gateway, &path, "SIGHUP"),
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+fn yaml(routes: &[(&str, &str, &str)]) -> String {
+    let mut yaml = "gateway:\n  routes:\n".to_string();
+    for (id, uri, path) in routes {
+        yaml.push_str(&format!(
+            "    - id: {}\n      uri: {}\n      predicates:\n        - Path={}\n",
+            id, uri, path
+        ));
+    }
+    yaml
+}
+
+#[cfg(test)]
+fn temp_config(name: &str, content: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("dep_web_gateway_{}_{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("gateway.yaml");
+    std::fs::write(&path, content).unwrap();
+    path
+}
+
+#[test]
+fn reload_keeps_old_routes_on_invalid_config() {
+    let path = temp_config("invalid", &yaml(&[("a", "http://127.0.0.1:1", "/a/**")]));
+    let gateway = Gateway::new(RouteTable::load(&path).unwrap());
+
+    std::fs::write(&path, yaml(&[("b", "ftp://nope", "/b/**")])).unwrap();
+    assert!(reload(&gateway, &path).is_err());
+    std::fs::write(&path, "gateway: [").unwrap();
+    assert!(reload(&gateway, &path).is_err());
+    assert_eq!(gateway.table().ids(), vec!["a"]);
+
+    std::fs::write(&path, yaml(&[("b", "http://127.0.0.1:1", "/b/**")])).unwrap();
+    assert_eq!(reload(&gateway, &path).unwrap(), vec!["b"]);
+    assert_eq!(gateway.table().ids(), vec!["b"]);
+}
+
+#[tokio::test]
+async fn in_flight_request_finishes_on_old_routes() {
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    // 旧上游故意慢一点，保证请求还没结束路由就换掉了
+    let slow = axum::Router::new().route(
+        "/slow",
+        get(|| async {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            "old upstream"
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let old_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, slow).await.unwrap() });
+
+    let gateway = Gateway::new(
+        RouteTable::from_yaml(&yaml(&[("old", &format!("http://{}", old_addr), "/**")])).unwrap(),
+    );
+    let app = gateway.clone().router();
+
+    let in_flight = tokio::spawn(
+        app.clone().oneshot(
+            axum::http::Request::get("/slow")
+                .body(Body::empty())
+                .unwrap(),
+        ),
+    );
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let old = gateway
+        .swap_table(RouteTable::from_yaml(&yaml(&[("new", "http://127.0.0.1:1", "/**")])).unwrap());
+    assert_eq!(old.ids(), vec!["old"]);
+
+    let resp = in_flight.await.unwrap().unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = resp.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&body[..], b"old upstream");
+
+    // 新请求已经走新路由了(新上游是连不上的端口)
+    let resp = app
+        .oneshot(
+            axum::http::Request::get("/slow")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+}
+
+#[tokio::test]
+async fn watcher_reloads_on_file_change() {
+    let path = temp_config("watch", &yaml(&[("v1", "http://127.0.0.1:1", "/v1/**")]));
+    let gateway = Gateway::new(RouteTable::load(&path).unwrap());
+    let watcher = spawn_watcher(gateway.clone(), &path).unwrap();
+
+    let wait_for = |ids: Vec<&'static str>| {
+        let gateway = gateway.clone();
+        async move {
+            for _ in 0..50 {
+                if gateway.table().ids() == ids {
+                    return true;
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            false
+        }
+    };
+
+    std::fs::write(&path, yaml(&[("v2", "http://127.0.0.1:1", "/v2/**")])).unwrap();
+    assert!(wait_for(vec!["v2"]).await);
+
+    // 写坏了不生效
+    std::fs::write(&path, "gateway: [").unwrap();
+    tokio::time::sleep(DEBOUNCE * 3).await;
+    assert_eq!(gateway.table().ids(), vec!["v2"]);
+
+    // 先写临时文件再 rename 过来
+    let tmp = path.with_extension("yaml.tmp");
+    std::fs::write(&tmp, yaml(&[("v3", "http://127.0.0.1:1", "/v3/**")])).unwrap();
+    std::fs::rename(&tmp, &path).unwrap();
+    assert!(wait_for(vec!["v3"]).await);
+    watcher.abort();
+
+    // 没有 watcher 时改文件不会生效，重新起一个再发 SIGHUP(用 channel 代替真的信号)
+    std::fs::write(&path, yaml(&[("v4", "http://127.0.0.1:1", "/v4/**")])).unwrap();
+    let (hangup, rx) = mpsc::unbounded_channel();
+    let watcher = spawn_with(gateway.clone(), path.clone(), Hangup::Channel(rx)).unwrap();
+    assert_eq!(gateway.table().ids(), vec!["v3"]);
+    hangup.send(()).unwrap();
+    assert!(wait_for(vec!["v4"]).await);
+    watcher.abort();
+}
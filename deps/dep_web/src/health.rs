@@ -0,0 +1,460 @@
+// 健康检查，给编排系统和负载均衡用
+// GET /health/live   进程还活着就是 200，不查任何依赖，失败了编排系统会重启实例
+// GET /health/ready  依赖都正常才是 200，否则 503，负载均衡据此决定要不要往这里转发
+// 1. 依赖检查可插拔，实现 HealthCheck 就行；自带数据库、RESP 服务端(PING)、gRPC 上游(grpc.health.v1)三种
+// 2. 每个检查有自己的超时，所有检查并发跑，响应里列出每一项的结果和耗时
+// 3. 优雅停机：收到信号先把 ready 翻成 draining(503)，等负载均衡摘掉这个实例后 axum::serve 才停，
+//    正在处理的请求不会被掐断
+// 4. 这两个路由挂在限流外面，探针再频繁也不会被 429
+// 5. 检查在单独的任务里跑，但带着探针请求的 ID，gRPC 上游的检查请求也会带上 x-request-id
+
+use crate::request_id;
+use crate::state::AppState;
+use async_trait::async_trait;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+use tonic_health::ServingStatus;
+use tonic_health::pb::HealthCheckRequest;
+use tonic_health::pb::health_client::HealthClient;
+use utoipa::ToSchema;
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[async_trait]
+pub trait HealthCheck: Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn check(&self) -> anyhow::Result<()>;
+}
+
+pub struct PgPoolCheck(pub PgPool);
+
+#[async_trait]
+impl HealthCheck for PgPoolCheck {
+    fn name(&self) -> &str {
+        "database"
+    }
+
+    async fn check(&self) -> anyhow::Result<()> {
+        sqlx::query("SELECT 1").execute(&self.0).await?;
+        Ok(())
+    }
+}
+
+/// 限流存储、pub/sub 这类 RESP 服务端，每次新开一条连接 PING
+pub struct RespPingCheck {
+    pub name: String,
+    pub addr: String,
+}
+
+#[async_trait]
+impl HealthCheck for RespPingCheck {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn check(&self) -> anyhow::Result<()> {
+        ping(&self.addr)
+            .await
+            .map_err(|e| anyhow::anyhow!("ping {}: {:#}", self.addr, e))
+    }
+}
+
+// 只发一条 PING，直接写 RESP 报文：redis / mini-redis / dep_async::resp_server 都回 +PONG
+async fn ping(addr: &str) -> anyhow::Result<()> {
+    let mut stream = BufReader::new(TcpStream::connect(addr).await?);
+    stream.get_mut().write_all(b"*1\r\n$4\r\nPING\r\n").await?;
+    let mut line = String::new();
+    stream.read_line(&mut line).await?;
+    // 有的实现回的是 bulk string，长度行后面才是内容
+    if line.starts_with('$') {
+        line.clear();
+        stream.read_line(&mut line).await?;
+        line.insert(0, '+');
+    }
+    match line.trim_end() {
+        pong if pong.eq_ignore_ascii_case("+PONG") => Ok(()),
+        "" => anyhow::bail!("connection closed by server"),
+        error if error.starts_with('-') => anyhow::bail!("{}", &error[1..]),
+        other => anyhow::bail!("unexpected ping response {:?}", other),
+    }
+}
+
+/// 标准的 grpc.health.v1.Health/Check，service 为空表示整个服务端
+pub struct GrpcHealthCheck {
+    name: String,
+    service: String,
+    channel: tonic::transport::Channel,
+}
+
+impl GrpcHealthCheck {
+    /// 不会立刻连接，第一次检查时才连
+    pub fn new(
+        name: impl Into<String>,
+        endpoint: impl Into<String>,
+        service: impl Into<String>,
+    ) -> anyhow::Result<Self> {
+        let channel = tonic::transport::Endpoint::from_shared(endpoint.into())?.connect_lazy();
+        Ok(GrpcHealthCheck {
+            name: name.into(),
+            service: service.into(),
+            channel,
+        })
+    }
+}
+
+#[async_trait]
+impl HealthCheck for GrpcHealthCheck {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn check(&self) -> anyhow::Result<()> {
+        let mut client =
+            HealthClient::with_interceptor(self.channel.clone(), request_id::grpc_interceptor);
+        let request = HealthCheckRequest {
+            service: self.service.clone(),
+        };
+        let status = client.check(request).await?.into_inner().status();
+        if status != ServingStatus::Serving.into() {
+            anyhow::bail!("upstream reports {:?}", status);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadyStatus {
+    Ready,
+    NotReady,
+    /// 正在停机，等负载均衡摘流量
+    Draining,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CheckResult {
+    pub name: String,
+    pub status: CheckStatus,
+    pub latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Readiness {
+    pub status: ReadyStatus,
+    pub checks: Vec<CheckResult>,
+}
+
+#[derive(Clone, Default)]
+pub struct Health {
+    checks: Vec<(Arc<dyn HealthCheck>, Duration)>,
+    draining: Arc<AtomicBool>,
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_check(mut self, check: impl HealthCheck + 'static, timeout: Duration) -> Self {
+        self.checks.push((Arc::new(check), timeout));
+        self
+    }
+
+    /// 之后 /health/ready 一直返回 503，不再跑检查
+    pub fn start_draining(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    pub async fn readiness(&self) -> Readiness {
+        if self.is_draining() {
+            return Readiness {
+                status: ReadyStatus::Draining,
+                checks: Vec::new(),
+            };
+        }
+        let mut set = JoinSet::new();
+        let id = request_id::current();
+        for (i, (check, timeout)) in self.checks.iter().enumerate() {
+            let (check, timeout) = (check.clone(), *timeout);
+            let probe = async move {
+                let start = Instant::now();
+                let error = match tokio::time::timeout(timeout, check.check()).await {
+                    Ok(Ok(())) => None,
+                    Ok(Err(e)) => Some(format!("{:#}", e)),
+                    Err(_) => Some(format!("timed out after {:?}", timeout)),
+                };
+                let result = CheckResult {
+                    name: check.name().to_string(),
+                    status: match error {
+                        None => CheckStatus::Up,
+                        Some(_) => CheckStatus::Down,
+                    },
+                    latency_ms: start.elapsed().as_secs_f64() * 1000.0,
+                    error,
+                };
+                (i, result)
+            };
+            match &id {
+                Some(id) => set.spawn(request_id::scope(id.clone(), probe)),
+                None => set.spawn(probe),
+            };
+        }
+        // 按注册顺序返回，方便看
+        let mut checks: Vec<(usize, CheckResult)> = set.join_all().await;
+        checks.sort_by_key(|(i, _)| *i);
+        let checks: Vec<CheckResult> = checks.into_iter().map(|(_, r)| r).collect();
+        let status = if checks.iter().all(|c| c.status == CheckStatus::Up) {
+            ReadyStatus::Ready
+        } else {
+            ReadyStatus::NotReady
+        };
+        Readiness { status, checks }
+    }
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/health/live", get(live))
+        .route("/health/ready", get(ready))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Liveness {
+    status: &'static str,
+}
+
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    tag = "health",
+    responses((status = 200, description = "进程活着", body = Liveness))
+)]
+async fn live() -> Json<Liveness> {
+    Json(Liveness { status: "ok" })
+}
+
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "health",
+    responses(
+        (status = 200, description = "所有依赖正常", body = Readiness),
+        (status = 503, description = "有依赖不正常，或者正在停机", body = Readiness),
+    )
+)]
+async fn ready(State(state): State<AppState>) -> impl IntoResponse {
+    let readiness = state.health.readiness().await;
+    let status = match readiness.status {
+        ReadyStatus::Ready => StatusCode::OK,
+        _ => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (status, Json(readiness))
+}
+
+/// 给 axum::serve(..).with_graceful_shutdown 用：
+/// 等到 Ctrl-C 或 SIGTERM，先翻成 draining，再等 drain 这么久让负载均衡发现，然后才返回让服务停止
+pub async fn shutdown_signal(health: Health, drain: Duration) {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    log::info!("shutdown signal received, draining for {:?}", drain);
+    health.start_draining();
+    tokio::time::sleep(drain).await;
+}
+
+#[cfg(test)]
+struct FnCheck<F>(&'static str, F);
+
+#[cfg(test)]
+#[async_trait]
+impl<F, Fut> HealthCheck for FnCheck<F>
+where
+    F: Fn() -> Fut + Send + Sync,
+    Fut: std::future::Future<Output = anyhow::Result<()>> + Send,
+{
+    fn name(&self) -> &str {
+        self.0
+    }
+
+    async fn check(&self) -> anyhow::Result<()> {
+        (self.1)().await
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn readiness_aggregates_checks_with_timeouts() {
+    use axum::body::Body;
+    use axum::http::Request;
+    use http_body_util::BodyExt;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    let slow = || async {
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        Ok(())
+    };
+    let ok = || async { Ok(()) };
+    let failing = || async { anyhow::bail!("disk full") };
+    let health = Health::new()
+        .with_check(FnCheck("ok", ok), DEFAULT_TIMEOUT)
+        .with_check(FnCheck("slow", slow), Duration::from_secs(1))
+        .with_check(FnCheck("failing", failing), DEFAULT_TIMEOUT);
+
+    let pool = sqlx::PgPool::connect_lazy("postgres://postgres@localhost:1/none").unwrap();
+    let keys = crate::auth::AuthKeys::hs256(b"health-secret");
+    let app = crate::app(AppState::new(pool, keys).with_health(health.clone()));
+    let get = |uri: &'static str| {
+        let app = app.clone();
+        async move {
+            let resp = app
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            let status = resp.status();
+            let body = resp.into_body().collect().await.unwrap().to_bytes();
+            (status, serde_json::from_slice::<Value>(&body).unwrap())
+        }
+    };
+
+    let (status, body) = get("/health/live").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ok");
+
+    let start = tokio::time::Instant::now();
+    let (status, body) = get("/health/ready").await;
+    // 超时的那个最多等 1 秒，不会等满 10 秒
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["status"], "not_ready");
+    let checks = body["checks"].as_array().unwrap();
+    assert_eq!(checks[0]["name"], "ok");
+    assert_eq!(checks[0]["status"], "up");
+    assert!(checks[0].get("error").is_none());
+    assert_eq!(checks[1]["status"], "down");
+    assert!(checks[1]["error"].as_str().unwrap().contains("timed out"));
+    assert_eq!(checks[2]["error"], "disk full");
+
+    // 只剩正常的检查就是 ready
+    let healthy = Health::new().with_check(FnCheck("ok", ok), DEFAULT_TIMEOUT);
+    assert_eq!(healthy.readiness().await.status, ReadyStatus::Ready);
+    assert_eq!(Health::new().readiness().await.status, ReadyStatus::Ready);
+
+    // 停机中：ready 503，live 不受影响
+    health.start_draining();
+    let (status, body) = get("/health/ready").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["status"], "draining");
+    let (status, _) = get("/health/live").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn builtin_checks() {
+    // 数据库
+    let pool = crate::state::get_pool().await.unwrap();
+    PgPoolCheck(pool).check().await.unwrap();
+    let dead = sqlx::postgres::PgPoolOptions::new()
+        .acquire_timeout(Duration::from_millis(500))
+        .connect_lazy("postgres://postgres@127.0.0.1:1/none")
+        .unwrap();
+    assert!(PgPoolCheck(dead).check().await.is_err());
+
+    // RESP 服务端
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(dep_async::resp_server::serve(listener));
+    let resp = |addr: &str| RespPingCheck {
+        name: "pubsub".to_string(),
+        addr: addr.to_string(),
+    };
+    resp(&addr).check().await.unwrap();
+    assert!(resp("127.0.0.1:1").check().await.is_err());
+
+    // gRPC 上游，用 tonic-health 起一个标准的健康检查服务
+    let (reporter, service) = tonic_health::server::health_reporter();
+    reporter
+        .set_service_status("demo.UserService", ServingStatus::Serving)
+        .await;
+    // 记下上游收到的 x-request-id
+    let seen = Arc::new(std::sync::Mutex::new(Vec::<String>::new()));
+    let record = seen.clone();
+    let service = tonic::service::interceptor::InterceptedService::new(
+        service,
+        move |req: tonic::Request<()>| {
+            if let Some(id) = req.metadata().get("x-request-id") {
+                record
+                    .lock()
+                    .unwrap()
+                    .push(id.to_str().unwrap().to_string());
+            }
+            Ok(req)
+        },
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        tonic::transport::Server::builder()
+            .add_service(service)
+            .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+    );
+    let grpc =
+        GrpcHealthCheck::new("users", format!("http://{}", addr), "demo.UserService").unwrap();
+    grpc.check().await.unwrap();
+    assert!(seen.lock().unwrap().is_empty());
+    // 探针请求的 ID 跟着检查任务一起带到上游
+    let again =
+        GrpcHealthCheck::new("users", format!("http://{}", addr), "demo.UserService").unwrap();
+    let health = Health::new().with_check(again, DEFAULT_TIMEOUT);
+    let readiness = request_id::scope("ready-1".to_string(), health.readiness()).await;
+    assert_eq!(readiness.status, ReadyStatus::Ready);
+    assert_eq!(*seen.lock().unwrap(), ["ready-1"]);
+    reporter
+        .set_service_status("demo.UserService", ServingStatus::NotServing)
+        .await;
+    let err = grpc.check().await.unwrap_err();
+    assert!(err.to_string().contains("NotServing"), "{}", err);
+    let unknown =
+        GrpcHealthCheck::new("other", format!("http://{}", addr), "demo.Missing").unwrap();
+    assert!(unknown.check().await.is_err());
+}
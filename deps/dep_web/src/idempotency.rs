@@ -0,0 +1,774 @@
+// Idempotency-Key：客户端重试 POST 时不会重复下单、重复建用户
+// 1. 只管 POST / PUT / PATCH / DELETE 这些非安全方法，而且要带 Idempotency-Key 头，不带就照常处理
+// 2. 记录的键是 用户 + 方法 + 路径 + Idempotency-Key，不同用户用同一个 key 互不影响；
+//    用户先看 Bearer token(和 rate_limit 一样自己解)，再看 SessionLayer 的会话；
+//    没登录的请求分不清是谁，存下来别人拿同一个 key 就能回放，所以照常处理，不存也不回放
+// 3. 第一次请求执行完把响应(状态码、响应头、响应体)存下来，保留 retention，之后同样的请求直接回放，
+//    回放的响应带 Idempotent-Replayed: true
+// 4. 第一次还没执行完又来一个返回 409；同一个 key 但 query 或请求体不一样返回 422
+// 5. 5xx 不存，key 直接释放，客户端可以拿同一个 key 重试；客户端断开、handler 的 future 被丢掉时也马上释放；
+//    执行中进程挂了的话 lock_timeout 后也会释放
+// 6. 请求体要整个读进来算指纹，超过 max_body 返回 413；存储有进程内和 Postgres 两种
+
+use crate::auth::{AuthKeys, AuthUser, TokenType};
+use crate::error::{ApiError, FieldError};
+use crate::session::Session;
+use async_trait::async_trait;
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::header::AUTHORIZATION;
+use axum::http::{HeaderName, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tower::{Layer, Service};
+
+pub const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+pub const IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(60);
+pub const DEFAULT_MAX_BODY: usize = 2 * 1024 * 1024;
+
+// 和 Stripe 一样，客户端一般用 UUID，够长了
+const MAX_KEY_LEN: usize = 255;
+
+/// 存下来的响应，响应头只留能转成字符串的
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl SavedResponse {
+    fn into_response(self) -> Response {
+        let mut response = Response::new(Body::from(self.body));
+        *response.status_mut() = StatusCode::from_u16(self.status).unwrap_or(StatusCode::OK);
+        let headers = response.headers_mut();
+        for (name, value) in self.headers {
+            if let (Ok(name), Ok(value)) =
+                (HeaderName::try_from(name), HeaderValue::try_from(value))
+            {
+                headers.append(name, value);
+            }
+        }
+        headers.insert(IDEMPOTENT_REPLAYED, HeaderValue::from_static("true"));
+        response
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Begin {
+    /// 第一次见到这个 key，已经占上了，接着执行
+    Started,
+    /// 之前有过，response 是 None 表示还在执行
+    Existing {
+        fingerprint: String,
+        response: Option<SavedResponse>,
+    },
+}
+
+/// begin 要是原子的：同一个 key 并发进来只能有一个拿到 Started
+#[async_trait]
+pub trait IdempotencyStore: Send + Sync {
+    /// 过期的记录当作不存在；占上的 key 在 lock_timeout 后自动释放
+    async fn begin(
+        &self,
+        key: &str,
+        fingerprint: &str,
+        lock_timeout: Duration,
+    ) -> anyhow::Result<Begin>;
+
+    async fn complete(
+        &self,
+        key: &str,
+        response: &SavedResponse,
+        retention: Duration,
+    ) -> anyhow::Result<()>;
+
+    /// 不存结果，直接删掉，下次同一个 key 重新执行
+    async fn release(&self, key: &str) -> anyhow::Result<()>;
+}
+
+struct Entry {
+    fingerprint: String,
+    response: Option<SavedResponse>,
+    expires_at: Instant,
+}
+
+/// 进程内，多实例部署时要用 PgStore
+#[derive(Default)]
+pub struct MemoryStore {
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+#[async_trait]
+impl IdempotencyStore for MemoryStore {
+    async fn begin(
+        &self,
+        key: &str,
+        fingerprint: &str,
+        lock_timeout: Duration,
+    ) -> anyhow::Result<Begin> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        // 顺手清掉过期的，省得一直涨
+        entries.retain(|_, e| e.expires_at > now);
+        if let Some(e) = entries.get(key) {
+            return Ok(Begin::Existing {
+                fingerprint: e.fingerprint.clone(),
+                response: e.response.clone(),
+            });
+        }
+        let entry = Entry {
+            fingerprint: fingerprint.to_string(),
+            response: None,
+            expires_at: now + lock_timeout,
+        };
+        entries.insert(key.to_string(), entry);
+        Ok(Begin::Started)
+    }
+
+    async fn complete(
+        &self,
+        key: &str,
+        response: &SavedResponse,
+        retention: Duration,
+    ) -> anyhow::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(e) = entries.get_mut(key) {
+            e.response = Some(response.clone());
+            e.expires_at = Instant::now() + retention;
+        }
+        Ok(())
+    }
+
+    async fn release(&self, key: &str) -> anyhow::Result<()> {
+        self.entries.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+/// idempotency_keys 表，建表语句在 state::init_schema；过期的行靠 delete_expired 定期清理
+pub struct PgStore(pub PgPool);
+
+impl PgStore {
+    pub async fn delete_expired(&self) -> anyhow::Result<u64> {
+        let result = sqlx::query("DELETE FROM idempotency_keys WHERE expires_at <= now()")
+            .execute(&self.0)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct Row {
+    fingerprint: String,
+    status: Option<i32>,
+    headers: Option<String>,
+    body: Option<Vec<u8>>,
+}
+
+#[async_trait]
+impl IdempotencyStore for PgStore {
+    async fn begin(
+        &self,
+        key: &str,
+        fingerprint: &str,
+        lock_timeout: Duration,
+    ) -> anyhow::Result<Begin> {
+        // 插入和"过期了就覆盖"是同一条语句，并发时只有一个能拿到 RETURNING 的行
+        // 读的时候那一行刚好过期被删了就再来一次
+        for _ in 0..2 {
+            let started: Option<String> = sqlx::query_scalar(
+                r#"
+                INSERT INTO idempotency_keys (key, fingerprint, expires_at)
+                VALUES ($1, $2, now() + make_interval(secs => $3))
+                ON CONFLICT (key) DO UPDATE
+                    SET fingerprint = EXCLUDED.fingerprint, status = NULL, headers = NULL,
+                        body = NULL, expires_at = EXCLUDED.expires_at
+                    WHERE idempotency_keys.expires_at <= now()
+                RETURNING key
+                "#,
+            )
+            .bind(key)
+            .bind(fingerprint)
+            .bind(lock_timeout.as_secs_f64())
+            .fetch_optional(&self.0)
+            .await?;
+            if started.is_some() {
+                return Ok(Begin::Started);
+            }
+            let row: Option<Row> = sqlx::query_as(
+                r#"
+                SELECT fingerprint, status, headers::text AS headers, body
+                FROM idempotency_keys
+                WHERE key = $1 AND expires_at > now()
+                "#,
+            )
+            .bind(key)
+            .fetch_optional(&self.0)
+            .await?;
+            let Some(row) = row else {
+                continue;
+            };
+            let response = match (row.status, row.headers, row.body) {
+                (Some(status), Some(headers), Some(body)) => Some(SavedResponse {
+                    status: status as u16,
+                    headers: serde_json::from_str(&headers)?,
+                    body,
+                }),
+                _ => None,
+            };
+            return Ok(Begin::Existing {
+                fingerprint: row.fingerprint,
+                response,
+            });
+        }
+        anyhow::bail!("idempotency key {} keeps expiring", key)
+    }
+
+    async fn complete(
+        &self,
+        key: &str,
+        response: &SavedResponse,
+        retention: Duration,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE idempotency_keys
+            SET status = $2, headers = $3::jsonb, body = $4,
+                expires_at = now() + make_interval(secs => $5)
+            WHERE key = $1
+            "#,
+        )
+        .bind(key)
+        .bind(i32::from(response.status))
+        .bind(serde_json::to_string(&response.headers)?)
+        .bind(&response.body)
+        .bind(retention.as_secs_f64())
+        .execute(&self.0)
+        .await?;
+        Ok(())
+    }
+
+    async fn release(&self, key: &str) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM idempotency_keys WHERE key = $1")
+            .bind(key)
+            .execute(&self.0)
+            .await?;
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct Options {
+    store: Arc<dyn IdempotencyStore>,
+    retention: Duration,
+    lock_timeout: Duration,
+    max_body: usize,
+    // 外层拿不到 AuthUser，和 RateLimitLayer 一样自己解 token
+    auth: Option<AuthKeys>,
+}
+
+impl Options {
+    // 同一个用户不管用 token 还是会话登录，记录是同一份
+    fn user(&self, req: &Request) -> Option<i32> {
+        if let Some(user) = req.extensions().get::<AuthUser>() {
+            return Some(user.id);
+        }
+        let bearer = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .and_then(|token| {
+                let keys = self.auth.as_ref()?;
+                keys.verify(token.trim(), TokenType::Access).ok()
+            });
+        bearer
+            .or_else(|| req.extensions().get::<Session>()?.user())
+            .map(|user| user.id)
+    }
+}
+
+// begin 占上 key 以后一直拿着，complete / release 之前被 drop(客户端断开、外层超时把 future 丢了)
+// 就在后台释放，不用等 lock_timeout
+struct Claim {
+    store: Arc<dyn IdempotencyStore>,
+    key: Option<String>,
+}
+
+impl Claim {
+    async fn complete(mut self, response: &SavedResponse, retention: Duration) {
+        let Some(key) = self.key.as_deref() else {
+            return;
+        };
+        // 已经执行过了，存不上也要把结果返回去，只是重试时会再执行一次
+        if let Err(e) = self.store.complete(key, response, retention).await {
+            log::error!("save idempotent response failed: {:#}", e);
+        }
+        self.key = None;
+    }
+
+    async fn release(mut self) {
+        let Some(key) = self.key.as_deref() else {
+            return;
+        };
+        if let Err(e) = self.store.release(key).await {
+            log::warn!("release idempotency key failed: {:#}", e);
+        }
+        self.key = None;
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        let Some(key) = self.key.take() else {
+            return;
+        };
+        let store = self.store.clone();
+        // 运行时都关了就只能等 lock_timeout
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            handle.spawn(async move {
+                if let Err(e) = store.release(&key).await {
+                    log::warn!("release idempotency key failed: {:#}", e);
+                }
+            });
+        }
+    }
+}
+
+/// 用法：`.layer(IdempotencyLayer::new(PgStore(pool)).auth_keys(keys))`
+#[derive(Clone)]
+pub struct IdempotencyLayer {
+    options: Arc<Options>,
+}
+
+impl Default for IdempotencyLayer {
+    fn default() -> Self {
+        Self::new(MemoryStore::default())
+    }
+}
+
+impl IdempotencyLayer {
+    pub fn new(store: impl IdempotencyStore + 'static) -> Self {
+        IdempotencyLayer {
+            options: Arc::new(Options {
+                store: Arc::new(store),
+                retention: DEFAULT_RETENTION,
+                lock_timeout: DEFAULT_LOCK_TIMEOUT,
+                max_body: DEFAULT_MAX_BODY,
+                auth: None,
+            }),
+        }
+    }
+
+    fn update(mut self, f: impl FnOnce(&mut Options)) -> Self {
+        let mut options = Arc::unwrap_or_clone(self.options);
+        f(&mut options);
+        self.options = Arc::new(options);
+        self
+    }
+
+    /// 响应保留多久，过了之后同一个 key 会重新执行
+    pub fn retention(self, retention: Duration) -> Self {
+        self.update(|o| o.retention = retention)
+    }
+
+    /// 执行中的请求最多占着 key 多久，应该比最慢的 handler 长
+    pub fn lock_timeout(self, timeout: Duration) -> Self {
+        self.update(|o| o.lock_timeout = timeout)
+    }
+
+    pub fn max_body(self, max_body: usize) -> Self {
+        self.update(|o| o.max_body = max_body)
+    }
+
+    pub fn auth_keys(self, keys: AuthKeys) -> Self {
+        self.update(|o| o.auth = Some(keys))
+    }
+}
+
+impl<S> Layer<S> for IdempotencyLayer {
+    type Service = IdempotencyService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        IdempotencyService {
+            inner,
+            options: self.options.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct IdempotencyService<S> {
+    inner: S,
+    options: Arc<Options>,
+}
+
+fn sha256_hex(parts: &[&[u8]]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        // 带上长度，免得 "ab" + "c" 和 "a" + "bc" 撞上
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part);
+    }
+    hex::encode(hasher.finalize())
+}
+
+impl<S> Service<Request> for IdempotencyService<S>
+where
+    S: Service<Request, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let options = self.options.clone();
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            if req.method().is_safe() {
+                return inner.call(req).await;
+            }
+            let Some(key) = req.headers().get(IDEMPOTENCY_KEY).cloned() else {
+                return inner.call(req).await;
+            };
+            match execute(&options, inner, key, req).await {
+                Ok(response) => Ok(response),
+                Err(e) => Ok(e.into_response()),
+            }
+        })
+    }
+}
+
+async fn execute<S>(
+    options: &Options,
+    mut inner: S,
+    key: HeaderValue,
+    req: Request,
+) -> Result<Response, ApiError>
+where
+    S: Service<Request, Response = Response, Error = Infallible>,
+{
+    let Some(user) = options.user(&req) else {
+        let Ok(response) = inner.call(req).await;
+        return Ok(response);
+    };
+    let user = user.to_string();
+    let key = key
+        .to_str()
+        .ok()
+        .filter(|k| !k.is_empty() && k.len() <= MAX_KEY_LEN)
+        .ok_or_else(|| {
+            ApiError::Validation(vec![FieldError::new(
+                "Idempotency-Key",
+                format!("应为 1 到 {} 个可见字符", MAX_KEY_LEN),
+            )])
+        })?;
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+    let store_key = sha256_hex(&[
+        user.as_bytes(),
+        method.as_bytes(),
+        path.as_bytes(),
+        key.as_bytes(),
+    ]);
+
+    let (parts, body) = req.into_parts();
+    let body = axum::body::to_bytes(body, options.max_body)
+        .await
+        .map_err(|_| {
+            ApiError::PayloadTooLarge(format!(
+                "带 Idempotency-Key 的请求体不能超过 {} 字节",
+                options.max_body
+            ))
+        })?;
+    let query = parts.uri.query().unwrap_or_default();
+    let fingerprint = sha256_hex(&[query.as_bytes(), &body]);
+
+    match options
+        .store
+        .begin(&store_key, &fingerprint, options.lock_timeout)
+        .await?
+    {
+        Begin::Started => {}
+        Begin::Existing {
+            fingerprint: existing,
+            ..
+        } if existing != fingerprint => {
+            return Err(ApiError::UnprocessableEntity(
+                "Idempotency-Key 已经用于另一个不同的请求".to_string(),
+            ));
+        }
+        Begin::Existing { response: None, .. } => {
+            return Err(ApiError::Conflict(
+                "同一个 Idempotency-Key 的请求还在处理中".to_string(),
+            ));
+        }
+        Begin::Existing {
+            response: Some(saved),
+            ..
+        } => return Ok(saved.into_response()),
+    }
+    let claim = Claim {
+        store: options.store.clone(),
+        key: Some(store_key),
+    };
+
+    let Ok(response) = inner
+        .call(Request::from_parts(parts, Body::from(body)))
+        .await;
+    if response.status().is_server_error() {
+        claim.release().await;
+        return Ok(response);
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(e) => {
+            // 响应体读到一半出错，结果不确定，不存
+            claim.release().await;
+            return Err(ApiError::Internal(anyhow::anyhow!(
+                "read response body: {}",
+                e
+            )));
+        }
+    };
+    let saved = SavedResponse {
+        status: parts.status.as_u16(),
+        headers: parts
+            .headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect(),
+        body: body.to_vec(),
+    };
+    claim.complete(&saved, options.retention).await;
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+#[tokio::test]
+async fn idempotency_key_replays_and_rejects() {
+    use crate::session::{SessionLayer, SessionUserInfo, USER_KEY};
+    use axum::Router;
+    use axum::http::header::{COOKIE, SET_COOKIE};
+    use axum::routing::post;
+    use http_body_util::BodyExt;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use tower::ServiceExt;
+
+    let calls = Arc::new(AtomicU32::new(0));
+    let keys = AuthKeys::hs256(b"idempotency-secret");
+    let handler = {
+        let calls = calls.clone();
+        move |body: String| async move {
+            let n = calls.fetch_add(1, Ordering::SeqCst) + 1;
+            if body == "slow" {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+            }
+            if body == "fail" {
+                return (StatusCode::INTERNAL_SERVER_ERROR, format!("fail {}", n));
+            }
+            (StatusCode::CREATED, format!("order {} for {}", n, body))
+        }
+    };
+    // 会话登录：和 /session/login 一样把用户放进会话
+    let login = |session: Session| async move {
+        let user = SessionUserInfo {
+            id: 7,
+            name: "alice".to_string(),
+            roles: vec!["user".to_string()],
+        };
+        session.insert(USER_KEY, user);
+    };
+    let app = Router::new()
+        .route("/orders", post(handler.clone()).get(handler))
+        .layer(
+            IdempotencyLayer::default()
+                .retention(Duration::from_millis(400))
+                .auth_keys(keys.clone()),
+        )
+        .route("/login", post(login))
+        .layer(SessionLayer::default());
+    let token = |id: i32, name: &str| {
+        let user = AuthUser::new(id, name, vec!["user".to_string()]);
+        let token = keys.issue(&user, TokenType::Access).unwrap();
+        format!("Bearer {}", token)
+    };
+    let alice = Some(token(7, "alice"));
+    let bob = Some(token(8, "bob"));
+    let send = |key: Option<&'static str>, body: &'static str, auth: Option<String>| {
+        let app = app.clone();
+        async move {
+            let mut req = Request::builder().method("POST").uri("/orders");
+            if let Some(key) = key {
+                req = req.header(IDEMPOTENCY_KEY, key);
+            }
+            if let Some(auth) = auth {
+                // 不是 Bearer 开头的就当 Cookie 发
+                let name = if auth.starts_with("Bearer ") {
+                    AUTHORIZATION
+                } else {
+                    COOKIE
+                };
+                req = req.header(name, auth);
+            }
+            let response = app
+                .oneshot(req.body(Body::from(body)).unwrap())
+                .await
+                .unwrap();
+            let status = response.status();
+            let replayed = response.headers().contains_key(IDEMPOTENT_REPLAYED);
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (status, String::from_utf8(body.to_vec()).unwrap(), replayed)
+        }
+    };
+
+    // 不带 key 照常执行
+    assert_eq!(send(None, "a", alice.clone()).await.1, "order 1 for a");
+    assert_eq!(send(None, "a", alice.clone()).await.1, "order 2 for a");
+
+    // 同一个 key 回放第一次的结果
+    let first = send(Some("k1"), "a", alice.clone()).await;
+    assert_eq!(
+        first,
+        (StatusCode::CREATED, "order 3 for a".to_string(), false)
+    );
+    let again = send(Some("k1"), "a", alice.clone()).await;
+    assert_eq!(
+        again,
+        (StatusCode::CREATED, "order 3 for a".to_string(), true)
+    );
+
+    // 请求体不一样 422
+    let (status, body, _) = send(Some("k1"), "b", alice.clone()).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(body.contains("unprocessable_entity"), "{}", body);
+
+    // 别的用户用同一个 key 不受影响
+    let (status, body, replayed) = send(Some("k1"), "a", bob.clone()).await;
+    assert_eq!((status, replayed), (StatusCode::CREATED, false));
+    assert_eq!(body, "order 4 for a");
+    assert!(send(Some("k1"), "a", bob).await.2);
+
+    // 会话登录的同一个用户，和他用 token 时共用记录
+    let response = app
+        .clone()
+        .oneshot(Request::post("/login").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let cookie = response.headers()[SET_COOKIE].to_str().unwrap();
+    let cookie = Some(cookie.split(';').next().unwrap().to_string());
+    let replayed = send(Some("k1"), "a", cookie.clone()).await;
+    assert_eq!(
+        replayed,
+        (StatusCode::CREATED, "order 3 for a".to_string(), true)
+    );
+
+    // 没登录的不存也不回放
+    assert_eq!(send(Some("k1"), "a", None).await.1, "order 5 for a");
+    let again = send(Some("k1"), "a", None).await;
+    assert_eq!(
+        again,
+        (StatusCode::CREATED, "order 6 for a".to_string(), false)
+    );
+    // 没登录时 key 格式也不管
+    assert_eq!(send(Some(""), "a", None).await.1, "order 7 for a");
+
+    // 还在执行的时候来了重复请求 409
+    let slow = tokio::spawn(send(Some("k2"), "slow", cookie.clone()));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(
+        send(Some("k2"), "slow", alice.clone()).await.0,
+        StatusCode::CONFLICT
+    );
+    assert_eq!(slow.await.unwrap().1, "order 8 for slow");
+    assert!(send(Some("k2"), "slow", alice.clone()).await.2);
+
+    // 5xx 不存，可以重试
+    assert_eq!(send(Some("k3"), "fail", alice.clone()).await.1, "fail 9");
+    assert_eq!(send(Some("k3"), "fail", alice.clone()).await.1, "fail 10");
+
+    // 执行到一半请求被丢掉，key 马上释放，不用等 lock_timeout
+    let dropped = tokio::time::timeout(
+        Duration::from_millis(50),
+        send(Some("k4"), "slow", alice.clone()),
+    );
+    assert!(dropped.await.is_err());
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let retried = send(Some("k4"), "slow", alice.clone()).await;
+    assert_eq!(
+        retried,
+        (StatusCode::CREATED, "order 12 for slow".to_string(), false)
+    );
+
+    // 过了 retention 重新执行
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(send(Some("k1"), "a", alice).await.1, "order 13 for a");
+
+    // 安全方法不管
+    let req = Request::get("/orders")
+        .header(IDEMPOTENCY_KEY, "k1")
+        .body(Body::from("a"))
+        .unwrap();
+    let response = app.clone().oneshot(req).await.unwrap();
+    assert!(!response.headers().contains_key(IDEMPOTENT_REPLAYED));
+    assert_eq!(calls.load(Ordering::SeqCst), 14);
+}
+
+#[tokio::test]
+async fn pg_idempotency_store() {
+    let pool = crate::state::get_pool().await.unwrap();
+    crate::state::init_schema(&pool).await.unwrap();
+    let store = PgStore(pool);
+    let key = uuid::Uuid::new_v4().to_string();
+    let lock = Duration::from_secs(60);
+
+    assert_eq!(store.begin(&key, "f1", lock).await.unwrap(), Begin::Started);
+    let in_flight = Begin::Existing {
+        fingerprint: "f1".to_string(),
+        response: None,
+    };
+    assert_eq!(store.begin(&key, "f2", lock).await.unwrap(), in_flight);
+
+    let saved = SavedResponse {
+        status: 201,
+        headers: vec![("content-type".to_string(), "application/json".to_string())],
+        body: b"{\"id\":1}".to_vec(),
+    };
+    store
+        .complete(&key, &saved, Duration::from_millis(200))
+        .await
+        .unwrap();
+    let done = Begin::Existing {
+        fingerprint: "f1".to_string(),
+        response: Some(saved),
+    };
+    assert_eq!(store.begin(&key, "f1", lock).await.unwrap(), done);
+
+    // 过期以后重新占上
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(store.begin(&key, "f3", lock).await.unwrap(), Begin::Started);
+    store.release(&key).await.unwrap();
+    assert_eq!(store.begin(&key, "f4", lock).await.unwrap(), Begin::Started);
+    store.release(&key).await.unwrap();
+    store.delete_expired().await.unwrap();
+}
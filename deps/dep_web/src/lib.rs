@@ -0,0 +1,74 @@
+#![allow(unused, dead_code, unreachable_code, path_statements, unexpected_cfgs)]
+
+pub mod assets;
+pub mod auth;
+pub mod cache;
+pub mod config;
+pub mod error;
+pub mod files;
+pub mod gateway;
+pub mod health;
+pub mod idempotency;
+pub mod live;
+pub mod openapi;
+pub mod pagination;
+pub mod pubsub;
+pub mod rate_limit;
+pub mod request_id;
+pub mod session;
+pub mod state;
+pub mod template;
+pub mod users;
+pub mod validate;
+pub mod webhooks;
+
+use axum::Router;
+use axum::middleware;
+use axum::routing::get;
+use state::AppState;
+
+// 所有路由在这里组装，main.rs 和测试共用同一个 Router
+pub fn app(state: AppState) -> Router {
+    let mut router = Router::new()
+        .route("/", get(root))
+        .merge(users::routes(&state.auth))
+        .merge(auth::routes(&state.auth))
+        .merge(files::routes(&state.auth))
+        .merge(live::routes(&state.auth))
+        .merge(session::routes())
+        .merge(webhooks::routes(&state.auth))
+        .merge(openapi::routes());
+    // 前端静态文件兜底，上面的路由都没匹配上才会走到
+    if let Some(assets) = &state.assets {
+        router = router.merge(assets.clone().router());
+    }
+    router
+        // 缓存只存 handler 自己给的响应头
+        .layer(state.cache.clone())
+        // 回放的响应里不会存下外层加的 Set-Cookie、RateLimit-* 这些头
+        .layer(state.idempotency.clone())
+        // 会话在限流里面，被 429 的请求不用去查会话存储
+        .layer(state.sessions.clone())
+        // 在 request_id 里面，429 的响应体也能带上 request_id
+        .layer(state.rate_limit.clone())
+        // 探针挂在限流外面，不会被 429
+        .merge(health::routes())
+        // 业务错误转响应时按 AppState 里登记的前缀查状态码
+        .layer(middleware::from_fn_with_state(
+            state.err_codes.clone(),
+            error::layer,
+        ))
+        .layer(middleware::from_fn(request_id::layer))
+        .with_state(state)
+}
+
+// 处理函数
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "root",
+    responses((status = 200, description = "问候语", body = String))
+)]
+async fn root() -> &'static str {
+    "Hello, Axum!"
+}
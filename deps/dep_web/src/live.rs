@@ -0,0 +1,346 @@
+// 实时推送：把 pubsub::Hub 的频道接到 WebSocket 和 SSE 上
+// GET /ws/{channel}   WebSocket，收频道消息，客户端发来的文本/二进制消息发布到同一个频道
+// GET /sse/{channel}  Server-Sent Events，只收不发，浏览器 EventSource 直接能用
+// 1. 心跳：WebSocket 每隔 heartbeat 发一个 ping，两个周期内没有任何回应就断开；SSE 定时发注释行
+// 2. 慢客户端：落后超过 Hub 的 buffer 条就断开(WebSocket 关闭码 1013，SSE 发一条 error 事件后结束)，
+//    单次发送超过 heartbeat 也算慢，不让一个卡住的连接占着资源
+// 3. 要登录：Bearer token 或者 /session/login 的会话 Cookie 都行，
+//    浏览器的 WebSocket / EventSource 带不了 Authorization 头，只能靠 Cookie
+
+use crate::auth::{AuthKeys, AuthLayer, AuthUser};
+use crate::error::{ApiError, ErrorBody};
+use crate::pubsub::Subscription;
+use crate::request_id;
+use crate::session::SessionUser;
+use crate::state::AppState;
+use axum::Router;
+use axum::extract::ws::{CloseFrame, Message, Utf8Bytes, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, State};
+use axum::response::Response;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::routing::get;
+use bytes::Bytes;
+use std::convert::Infallible;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{Instant, MissedTickBehavior, interval, timeout};
+use tokio_stream::Stream;
+
+// RFC 6455：服务端暂时过载，稍后重连
+const CLOSE_TRY_AGAIN_LATER: u16 = 1013;
+
+pub fn routes(keys: &AuthKeys) -> Router<AppState> {
+    Router::new()
+        .route("/ws/{channel}", get(ws))
+        .route("/sse/{channel}", get(sse))
+        // 带了 token 就校验，没带再看会话
+        .route_layer(AuthLayer::new(keys.clone()).optional())
+}
+
+fn require_user(
+    bearer: Option<AuthUser>,
+    session: Result<SessionUser, ApiError>,
+) -> Result<AuthUser, ApiError> {
+    match bearer {
+        Some(user) => Ok(user),
+        None => session.map(|SessionUser(user)| user),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/ws/{channel}",
+    tag = "live",
+    params(("channel" = String, Path, description = "频道名")),
+    security(("bearer" = [])),
+    responses(
+        (status = 101, description = "升级成 WebSocket，收发的都是频道消息"),
+        (status = 401, description = "没带 token 也没登录会话", body = ErrorBody),
+        (status = 502, description = "连不上 pub/sub 服务端", body = ErrorBody),
+    )
+)]
+async fn ws(
+    State(state): State<AppState>,
+    Path(channel): Path<String>,
+    bearer: Option<AuthUser>,
+    session: Result<SessionUser, ApiError>,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response, ApiError> {
+    require_user(bearer, session)?;
+    // 先订阅再升级，订阅失败(比如 RESP 服务端连不上)还能返回正常的错误响应
+    let rx = subscribe(&state, &channel).await?;
+    // 升级后的会话在另一个任务里跑，带上同一个请求 ID，会话里的日志还能和这次请求对上
+    let id = request_id::current().unwrap_or_default();
+    Ok(upgrade
+        .on_upgrade(move |socket| request_id::scope(id, ws_session(socket, state, channel, rx))))
+}
+
+// 订阅失败只可能是 RESP 服务端那边的问题
+async fn subscribe(state: &AppState, channel: &str) -> Result<Subscription, ApiError> {
+    state
+        .hub
+        .subscribe(channel)
+        .await
+        .map_err(|e| ApiError::BadGateway(format!("{:#}", e)))
+}
+
+fn to_message(content: Bytes) -> Message {
+    match Utf8Bytes::try_from(content.clone()) {
+        Ok(text) => Message::Text(text),
+        Err(_) => Message::Binary(content),
+    }
+}
+
+async fn ws_session(mut socket: WebSocket, state: AppState, channel: String, mut rx: Subscription) {
+    let hub = &state.hub;
+    let mut ticker = interval(hub.heartbeat);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut last_seen = Instant::now();
+
+    let close = loop {
+        let outgoing = tokio::select! {
+            msg = rx.recv() => match msg {
+                Ok(content) => to_message(content),
+                Err(RecvError::Lagged(skipped)) => {
+                    log::warn!("ws {}: slow consumer skipped {} messages, disconnect", channel, skipped);
+                    break Some(CloseFrame {
+                        code: CLOSE_TRY_AGAIN_LATER,
+                        reason: "slow consumer".into(),
+                    });
+                }
+                Err(RecvError::Closed) => break None,
+            },
+            _ = ticker.tick() => {
+                if last_seen.elapsed() > hub.heartbeat * 2 {
+                    log::info!("ws {}: heartbeat timeout, disconnect", channel);
+                    break None;
+                }
+                Message::Ping(Bytes::new())
+            }
+            incoming = socket.recv() => {
+                let msg = match incoming {
+                    Some(Ok(msg)) => msg,
+                    // 对方断开或者协议错误
+                    _ => return,
+                };
+                last_seen = Instant::now();
+                let content = match msg {
+                    Message::Text(text) => Bytes::from(text),
+                    Message::Binary(bytes) => bytes,
+                    Message::Close(_) => return,
+                    // ping 由 axum 自动回 pong
+                    Message::Ping(_) | Message::Pong(_) => continue,
+                };
+                if let Err(e) = hub.publish(&channel, content).await {
+                    log::warn!("ws {}: publish failed: {:#}", channel, e);
+                }
+                continue;
+            }
+        };
+        match timeout(hub.heartbeat, socket.send(outgoing)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(_)) => return,
+            Err(_) => {
+                log::warn!("ws {}: send timeout, disconnect", channel);
+                return;
+            }
+        }
+    };
+    let _ = socket.send(Message::Close(close)).await;
+}
+
+#[utoipa::path(
+    get,
+    path = "/sse/{channel}",
+    tag = "live",
+    params(("channel" = String, Path, description = "频道名")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "频道消息，每条一个 data 事件；跟不上时发一个 error 事件后结束", content_type = "text/event-stream", body = String),
+        (status = 401, description = "没带 token 也没登录会话", body = ErrorBody),
+        (status = 502, description = "连不上 pub/sub 服务端", body = ErrorBody),
+    )
+)]
+async fn sse(
+    State(state): State<AppState>,
+    Path(channel): Path<String>,
+    bearer: Option<AuthUser>,
+    session: Result<SessionUser, ApiError>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    require_user(bearer, session)?;
+    let mut rx = subscribe(&state, &channel).await?;
+    let events = async_stream::stream! {
+        loop {
+            match rx.recv().await {
+                Ok(content) => yield Ok(Event::default().data(String::from_utf8_lossy(&content))),
+                Err(RecvError::Lagged(_)) => {
+                    yield Ok(Event::default().event("error").data("slow consumer"));
+                    break;
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    };
+    Ok(Sse::new(events).keep_alive(KeepAlive::new().interval(state.hub.heartbeat)))
+}
+
+// 这些路由用不到数据库，连接池给个不会真去连的
+#[cfg(test)]
+fn test_app(hub: crate::pubsub::Hub) -> Router {
+    let pool = sqlx::PgPool::connect_lazy("postgres://postgres@localhost:1/none").unwrap();
+    let keys = AuthKeys::hs256(b"live-secret");
+    crate::app(AppState::new(pool, keys).with_hub(hub))
+}
+
+#[cfg(test)]
+fn test_bearer() -> String {
+    let user = AuthUser::new(1, "alice", vec![]);
+    let token = AuthKeys::hs256(b"live-secret")
+        .issue(&user, crate::auth::TokenType::Access)
+        .unwrap();
+    format!("Bearer {}", token)
+}
+
+#[tokio::test]
+async fn sse_streams_channel_messages() {
+    use crate::pubsub::Hub;
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    let hub = Hub::local().with_buffer(2);
+    let app = test_app(hub.clone());
+    let get = |uri: &'static str| {
+        let req = Request::get(uri)
+            .header("authorization", test_bearer())
+            .body(Body::empty())
+            .unwrap();
+        app.clone().oneshot(req)
+    };
+
+    // 没带 token 也没会话
+    let anonymous = Request::get("/sse/news").body(Body::empty()).unwrap();
+    let resp = app.clone().oneshot(anonymous).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(hub.publish("news", "nobody".into()).await.unwrap(), 0);
+
+    let resp = get("/sse/news").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["content-type"], "text/event-stream");
+    let mut body = resp.into_body();
+    // 响应返回时已经订阅好了
+    assert_eq!(hub.publish("news", "hello".into()).await.unwrap(), 1);
+    let frame = body.frame().await.unwrap().unwrap().into_data().unwrap();
+    assert_eq!(&frame[..], b"data: hello\n\n");
+
+    // 不读的客户端落后超过 buffer 条，收到一条 error 事件后流结束
+    let mut slow = get("/sse/news").await.unwrap().into_body();
+    for i in 0..4 {
+        hub.publish("news", i.to_string().into()).await.unwrap();
+    }
+    let frame = slow.frame().await.unwrap().unwrap().into_data().unwrap();
+    assert_eq!(&frame[..], b"event: error\ndata: slow consumer\n\n");
+    assert!(slow.frame().await.is_none());
+
+    // 停机时关掉 Hub，还连着的 SSE 流跟着结束
+    drop(body);
+    let mut open = get("/sse/news").await.unwrap().into_body();
+    hub.close();
+    assert!(open.frame().await.is_none());
+    let resp = get("/sse/news").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+}
+
+#[tokio::test]
+async fn websocket_publishes_and_receives() {
+    use crate::pubsub::Hub;
+    use futures_util::{SinkExt, StreamExt};
+    use std::time::Duration;
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+
+    let hub = Hub::local()
+        .with_buffer(4)
+        .with_heartbeat(Duration::from_millis(200));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(axum::serve(listener, test_app(hub.clone())).into_future());
+
+    let url = format!("ws://{}/ws/chat", addr);
+    // 不带 token 升级前就被 401 拒掉
+    let rejected = tokio_tungstenite::connect_async(&url).await.unwrap_err();
+    assert!(
+        matches!(&rejected, tokio_tungstenite::tungstenite::Error::Http(resp) if resp.status() == 401),
+        "{:?}",
+        rejected
+    );
+    let request = || {
+        let mut req = url.as_str().into_client_request().unwrap();
+        req.headers_mut()
+            .insert("authorization", test_bearer().parse().unwrap());
+        req
+    };
+    let (mut a, _) = tokio_tungstenite::connect_async(request()).await.unwrap();
+    let (mut b, _) = tokio_tungstenite::connect_async(request()).await.unwrap();
+
+    // 跳过心跳，只看数据消息
+    async fn next_text<S>(ws: &mut S) -> String
+    where
+        S: futures_util::Stream<Item = Result<WsMessage, tokio_tungstenite::tungstenite::Error>>
+            + Unpin,
+    {
+        loop {
+            match ws.next().await.unwrap().unwrap() {
+                WsMessage::Text(text) => return text.to_string(),
+                WsMessage::Ping(_) | WsMessage::Pong(_) => continue,
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    }
+
+    // a 发的消息，订阅了同一频道的 a 和 b 都能收到
+    a.send(WsMessage::text("hi from a")).await.unwrap();
+    assert_eq!(next_text(&mut a).await, "hi from a");
+    assert_eq!(next_text(&mut b).await, "hi from a");
+
+    // 服务端发布的也一样
+    assert_eq!(hub.publish("chat", "server news".into()).await.unwrap(), 2);
+    assert_eq!(next_text(&mut b).await, "server news");
+    assert_eq!(next_text(&mut a).await, "server news");
+
+    // 心跳：过一个周期会收到 ping
+    let ping = tokio::time::timeout(Duration::from_secs(2), async {
+        loop {
+            if let WsMessage::Ping(_) = b.next().await.unwrap().unwrap() {
+                return;
+            }
+        }
+    })
+    .await;
+    assert!(ping.is_ok());
+
+    // 一次性塞进去远超 buffer 条，会话跟不上被 1013 关掉
+    for i in 0..64 {
+        hub.publish("chat", i.to_string().into()).await.unwrap();
+    }
+    let close = tokio::time::timeout(Duration::from_secs(2), async {
+        loop {
+            match a.next().await {
+                Some(Ok(WsMessage::Close(frame))) => return frame,
+                Some(Ok(_)) => continue,
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    })
+    .await
+    .unwrap();
+    let close = close.unwrap();
+    assert_eq!(close.code, CloseCode::Again);
+    assert_eq!(close.reason.as_str(), "slow consumer");
+
+    // 重新连上来就正常了
+    let (mut c, _) = tokio_tungstenite::connect_async(request()).await.unwrap();
+    hub.publish("chat", "welcome back".into()).await.unwrap();
+    assert_eq!(next_text(&mut c).await, "welcome back");
+}
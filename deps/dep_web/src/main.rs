@@ -1,4 +1,21 @@
-use axum::{Router, routing::get};
+use dep_template::EngineKind;
+use dep_web::app;
+use dep_web::assets::Assets;
+use dep_web::auth::{self, AuthKeys};
+use dep_web::cache::{self, CacheLayer};
+use dep_web::config::WebConfig;
+use dep_web::files::UploadConfig;
+use dep_web::gateway::PathPattern;
+use dep_web::health::{self, GrpcHealthCheck, Health, PgPoolCheck, RespPingCheck};
+use dep_web::idempotency::{self, IdempotencyLayer};
+use dep_web::pubsub::Hub;
+use dep_web::rate_limit::{RateLimitConfig, RateLimitLayer};
+use dep_web::request_id;
+use dep_web::session::{self, MemoryStore, PgStore, RespStore, SessionLayer};
+use dep_web::state::{AppState, connect_pool, init_schema};
+use dep_web::webhooks::{Endpoint, Webhooks};
+use std::net::SocketAddr;
+use std::time::Duration;
 use tokio::net::TcpListener;
 
 #[tokio::main]
@@ -7,21 +24,268 @@ async fn main() {
 }
 
 async fn axum_hello_world() {
+    // 日志每行带请求 ID，外加每个请求一行 JSON 访问日志
+    request_id::init_logging();
+
+    // 配置来源和优先级见 dep_web::config
+    let config = match WebConfig::load(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    log::info!("config: {:#?}", config);
+
+    // 创建连接池并确保表存在
+    let pool = connect_pool(&config.database).await.unwrap();
+    init_schema(&pool).await.unwrap();
+
+    // 没配密钥不能用默认的凑合，直接退出
+    let auth = match AuthKeys::from_config(&config.auth) {
+        Ok(auth) => auth,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(2);
+        }
+    };
+    // 过期的 refresh token 行定期清掉
+    let cleanup = pool.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+        loop {
+            interval.tick().await;
+            if let Err(e) = auth::delete_expired_refresh_tokens(&cleanup).await {
+                log::warn!("delete expired refresh tokens failed: {:#}", e);
+            }
+        }
+    });
+    let mut state = AppState::new(pool, auth.clone())
+        .with_uploads(UploadConfig {
+            dir: config.uploads.dir.clone(),
+            ..UploadConfig::default()
+        })
+        .with_err_codes(config.err_codes());
+    // 下面这些配了但起不来的，和配置错误一样报出配置项直接退出
+    if let Some(path) = &config.rate_limit.file {
+        let rules = std::fs::read_to_string(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|yaml| RateLimitConfig::from_yaml(&yaml));
+        let layer = match rules {
+            Ok(rules) => RateLimitLayer::from_config(&rules, Some(auth)).await,
+            Err(e) => Err(e),
+        };
+        match layer {
+            Ok(layer) => state = state.with_rate_limit(layer),
+            Err(e) => {
+                eprintln!("rate_limit.file {}: {:#}", path.display(), e);
+                std::process::exit(2);
+            }
+        }
+    }
+    if let Some(addr) = &config.pubsub.addr {
+        match Hub::connect(addr.clone()).await {
+            Ok(hub) => state = state.with_hub(hub),
+            Err(e) => {
+                eprintln!("pubsub.addr {}: {:#}", addr, e);
+                std::process::exit(2);
+            }
+        }
+    }
+    if let Some(dir) = &config.templates.dir {
+        let templates = config
+            .templates
+            .engine
+            .parse::<EngineKind>()
+            .and_then(|engine| engine.load(dir));
+        match templates {
+            Ok(templates) => state = state.with_templates(templates),
+            Err(e) => {
+                eprintln!(
+                    "templates.dir {} ({}): {:#}",
+                    dir.display(),
+                    config.templates.engine,
+                    e
+                );
+                std::process::exit(2);
+            }
+        }
+    }
+
+    // 文件名带 hash 的 /assets/** 可以永久缓存
+    if let Some(dir) = &config.assets.dir {
+        let hashed = PathPattern::parse("/assets/**").unwrap();
+        let assets = Assets::dir(dir).cache_control(hashed, "public, max-age=31536000, immutable");
+        state = state.with_assets(assets);
+    }
+
+    // Cookie 会话，postgres 存储要定期清掉过期的行
+    let key = match &config.session.secret {
+        Some(secret) => session::key_from_secret(secret.expose().as_bytes()),
+        None => {
+            log::warn!("session.secret is not set, sessions will not survive a restart");
+            cookie::Key::generate()
+        }
+    };
+    let sessions = match config.session.store.as_str() {
+        "postgres" => {
+            let pool = state.pool.clone();
+            tokio::spawn(async move {
+                let store = PgStore(pool);
+                let mut interval = tokio::time::interval(Duration::from_secs(10 * 60));
+                loop {
+                    interval.tick().await;
+                    if let Err(e) = store.delete_expired().await {
+                        log::warn!("delete expired sessions failed: {:#}", e);
+                    }
+                }
+            });
+            SessionLayer::new(PgStore(state.pool.clone()), key)
+        }
+        "resp" => {
+            let addr = config.session.addr.as_deref().unwrap();
+            match RespStore::connect(addr).await {
+                Ok(store) => SessionLayer::new(store, key),
+                Err(e) => {
+                    eprintln!("session.addr {}: {:#}", addr, e);
+                    std::process::exit(2);
+                }
+            }
+        }
+        _ => SessionLayer::new(MemoryStore::default(), key),
+    };
+    state = state.with_sessions(
+        sessions
+            .cookie_name(config.session.cookie_name.clone())
+            .idle_timeout(Duration::from_secs(config.session.idle_secs))
+            .absolute_timeout(Duration::from_secs(config.session.absolute_secs))
+            .secure(config.session.secure),
+    );
+
+    // Idempotency-Key，postgres 存储同样要定期清理
+    let keys = state.auth.clone();
+    let idempotency = match config.idempotency.store.as_str() {
+        "postgres" => {
+            let pool = state.pool.clone();
+            tokio::spawn(async move {
+                let store = idempotency::PgStore(pool);
+                let mut interval = tokio::time::interval(Duration::from_secs(10 * 60));
+                loop {
+                    interval.tick().await;
+                    if let Err(e) = store.delete_expired().await {
+                        log::warn!("delete expired idempotency keys failed: {:#}", e);
+                    }
+                }
+            });
+            IdempotencyLayer::new(idempotency::PgStore(state.pool.clone()))
+        }
+        _ => IdempotencyLayer::new(idempotency::MemoryStore::default()),
+    };
+    state = state.with_idempotency(
+        idempotency
+            .retention(Duration::from_secs(config.idempotency.retention_secs))
+            .lock_timeout(Duration::from_secs(config.idempotency.lock_timeout_secs))
+            .max_body(config.idempotency.max_body_bytes)
+            .auth_keys(keys),
+    );
+
+    // GET 响应缓存，多实例时放到 RESP 服务端上共享，作废也是共享的
+    let cache = match config.cache.store.as_str() {
+        "resp" => {
+            let addr = config.cache.addr.as_deref().unwrap();
+            match cache::RespStore::connect(addr).await {
+                Ok(store) => CacheLayer::new(store),
+                Err(e) => {
+                    eprintln!("cache.addr {}: {:#}", addr, e);
+                    std::process::exit(2);
+                }
+            }
+        }
+        _ => CacheLayer::new(cache::MemoryStore::new(config.cache.capacity)),
+    };
+    state = state.with_cache(cache.max_entry_bytes(config.cache.max_entry_bytes));
+
+    // webhook 投递在后台跑，没配端点也照样起，死信重放后能发出去
+    let mut webhooks = Webhooks::new(state.pool.clone())
+        .max_attempts(config.webhooks.max_attempts)
+        .backoff(
+            Duration::from_secs(config.webhooks.backoff_base_secs),
+            Duration::from_secs(config.webhooks.backoff_max_secs),
+        )
+        .timeout(Duration::from_secs(config.webhooks.timeout_secs));
+    for endpoint in &config.webhooks.endpoints {
+        let e = match Endpoint::new(
+            endpoint.name.clone(),
+            &endpoint.url,
+            endpoint.secret.expose().clone(),
+        ) {
+            Ok(e) => e,
+            Err(e) => {
+                eprintln!("webhooks.endpoints.{}: {:#}", endpoint.name, e);
+                std::process::exit(2);
+            }
+        };
+        webhooks = webhooks.endpoint(e.events(endpoint.events.iter().cloned()));
+    }
+    tokio::spawn(webhooks.clone().run());
+    state = state.with_webhooks(webhooks);
+
+    // 就绪检查：数据库一定查，pub/sub 服务端和 gRPC 上游配了才查
+    let mut health =
+        Health::new().with_check(PgPoolCheck(state.pool.clone()), health::DEFAULT_TIMEOUT);
+    if let Some(addr) = &config.pubsub.addr {
+        let check = RespPingCheck {
+            name: "pubsub".to_string(),
+            addr: addr.clone(),
+        };
+        health = health.with_check(check, health::DEFAULT_TIMEOUT);
+    }
+    if let Some(endpoint) = &config.grpc_upstream.endpoint {
+        let service = config.grpc_upstream.service.clone();
+        let check = match GrpcHealthCheck::new("grpc", endpoint.clone(), service) {
+            Ok(check) => check,
+            Err(e) => {
+                eprintln!("grpc_upstream.endpoint {}: {:#}", endpoint, e);
+                std::process::exit(2);
+            }
+        };
+        health = health.with_check(check, health::DEFAULT_TIMEOUT);
+    }
+    state = state.with_health(health.clone());
+
     // 定义路由
-    let app = Router::new().route("/", get(root));
+    let hub = state.hub.clone();
+    let app = app(state);
 
     // 创建 TcpListener
-    let listener = TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    println!(
+    let listener = TcpListener::bind(config.server.listen).await.unwrap();
+    log::info!(
         "Server running at http://{}",
         listener.local_addr().unwrap()
     );
 
-    // 启动服务
-    axum::serve(listener, app).await.unwrap();
-}
-
-// 处理函数
-async fn root() -> &'static str {
-    "Hello, Axum!"
+    // 启动服务，带上对端地址给限流按 IP 用；停机时等正在处理的请求做完
+    let drain = Duration::from_secs(config.server.drain_secs);
+    let (stopping, mut stopped) = tokio::sync::watch::channel(false);
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(async move {
+        health::shutdown_signal(health, drain).await;
+        // SSE / WebSocket 不会自己结束，关掉频道让它们收尾
+        hub.close();
+        let _ = stopping.send(true);
+    });
+    // 优雅停机也有上限，卡住的连接不能让进程一直不退出
+    let timeout = Duration::from_secs(config.server.shutdown_timeout_secs);
+    let deadline = async {
+        if stopped.wait_for(|stopping| *stopping).await.is_ok() {
+            tokio::time::sleep(timeout).await;
+        }
+    };
+    tokio::select! {
+        result = server => result.unwrap(),
+        _ = deadline => log::warn!("connections still open {:?} after shutdown, exiting", timeout),
+    }
 }
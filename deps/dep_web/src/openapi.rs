@@ -0,0 +1,193 @@
+// OpenAPI 3.1 文档，由各处理函数上的 #[utoipa::path] 和类型上的 ToSchema 生成
+// GET /openapi.json  文档本身
+// GET /docs          Swagger UI，静态资源编译进二进制，不依赖外网
+// 1. 新加路由时要把处理函数登记到下面的 paths 里，不然文档里没有
+// 2. 文档和 testdata/openapi.json 比对，接口有改动时测试会失败：
+//    确认改动是预期的以后用 UPDATE_SNAPSHOTS=1 cargo test 重新生成，和代码一起提交
+// 3. 限流挂在除探针以外的所有路由上，429 由 RateLimited 统一补到每个接口上，处理函数上不用重复写
+
+use crate::state::AppState;
+use crate::{auth, error, files, health, live, session, users, webhooks};
+use axum::Router;
+use utoipa::openapi::header::HeaderBuilder;
+use utoipa::openapi::schema::{ObjectBuilder, Type};
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::{Content, Ref, ResponseBuilder};
+use utoipa::{Modify, OpenApi};
+use utoipa_swagger_ui::SwaggerUi;
+
+pub const SPEC_PATH: &str = "/openapi.json";
+pub const DOCS_PATH: &str = "/docs";
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "dep_web", description = "用户、登录、文件和实时推送接口"),
+    paths(
+        crate::root,
+        users::list_users,
+        users::create_user,
+        users::get_user,
+        users::update_user,
+        users::delete_user,
+        auth::login,
+        auth::refresh,
+        auth::logout,
+        auth::me,
+        files::upload,
+        files::download,
+        live::ws,
+        live::sse,
+        health::live,
+        health::ready,
+        session::login,
+        session::logout,
+        session::me,
+        webhooks::list_dead_letters,
+        webhooks::replay_dead_letter,
+    ),
+    components(schemas(
+        error::ErrorBody,
+        error::ErrorDetails,
+        error::FieldError,
+        dep_serialization::PageMeta
+    )),
+    modifiers(&BearerAuth, &RateLimited),
+    tags(
+        (name = "users", description = "用户增删改查，改和删要 admin"),
+        (name = "auth", description = "JWT 登录和续期"),
+        (name = "files", description = "文件上传下载"),
+        (name = "live", description = "WebSocket / SSE 频道推送"),
+        (name = "health", description = "存活和就绪探针"),
+        (name = "session", description = "管理页面用的 Cookie 会话登录"),
+        (name = "webhooks", description = "webhook 死信查看和重放，要 admin"),
+    )
+)]
+pub struct ApiDoc;
+
+// 对应 AuthLayer 校验的 Authorization: Bearer <access token>
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        // Cargo.toml 里没写 license，utoipa 会生成一个空名字的
+        openapi.info.license = None;
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+    }
+}
+
+// 对应 rate_limit::RateLimit 返回的 429，探针在限流外面
+struct RateLimited;
+
+impl Modify for RateLimited {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let response = ResponseBuilder::new()
+            .description("请求太频繁，等 Retry-After 秒后再试")
+            .header(
+                "Retry-After",
+                HeaderBuilder::new()
+                    .schema(ObjectBuilder::new().schema_type(Type::Integer))
+                    .description(Some("距离可以再次请求的秒数"))
+                    .build(),
+            )
+            .content(
+                "application/json",
+                Content::new(Some(Ref::from_schema_name("ErrorBody"))),
+            )
+            .build();
+        for (path, item) in openapi.paths.paths.iter_mut() {
+            if path.starts_with("/health/") {
+                continue;
+            }
+            let operations = [
+                &mut item.get,
+                &mut item.put,
+                &mut item.post,
+                &mut item.delete,
+                &mut item.patch,
+            ];
+            for operation in operations.into_iter().flatten() {
+                operation
+                    .responses
+                    .responses
+                    .entry("429".to_string())
+                    .or_insert_with(|| response.clone().into());
+            }
+        }
+    }
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new().merge(SwaggerUi::new(DOCS_PATH).url(SPEC_PATH, ApiDoc::openapi()))
+}
+
+#[test]
+fn openapi_matches_snapshot() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/openapi.json");
+    let spec = ApiDoc::openapi().to_pretty_json().unwrap() + "\n";
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        std::fs::write(path, &spec).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(path).unwrap_or_default();
+    assert!(
+        spec == expected,
+        "OpenAPI 文档和 testdata/openapi.json 不一致，确认改动后用 UPDATE_SNAPSHOTS=1 cargo test 更新"
+    );
+}
+
+#[tokio::test]
+async fn serves_spec_and_swagger_ui() {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use http_body_util::BodyExt;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    let pool = sqlx::PgPool::connect_lazy("postgres://postgres@localhost:1/none").unwrap();
+    let keys = crate::auth::AuthKeys::hs256(b"openapi-secret");
+    let app = crate::app(AppState::new(pool, keys));
+    let get = |uri: &'static str| {
+        app.clone()
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+    };
+
+    let resp = get(SPEC_PATH).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = resp.into_body().collect().await.unwrap().to_bytes();
+    let spec: Value = serde_json::from_slice(&body).unwrap();
+    assert!(spec["openapi"].as_str().unwrap().starts_with("3.1"));
+    for path in ["/users", "/users/{id}", "/auth/login", "/sse/{channel}"] {
+        assert!(spec["paths"][path].is_object(), "missing {}", path);
+    }
+    let schemas = &spec["components"]["schemas"];
+    assert!(schemas["ErrorBody"].is_object());
+    assert!(schemas["PageMeta"].is_object());
+    assert_eq!(
+        spec["paths"]["/users/{id}"]["delete"]["security"][0]["bearer"][0],
+        "admin"
+    );
+    // 限流的 429 每个接口都有，探针没有
+    let limited = &spec["paths"]["/users"]["post"]["responses"]["429"];
+    assert_eq!(
+        limited["content"]["application/json"]["schema"]["$ref"],
+        "#/components/schemas/ErrorBody"
+    );
+    assert!(limited["headers"]["Retry-After"].is_object());
+    assert!(spec["paths"]["/health/ready"]["get"]["responses"]["429"].is_null());
+    // details 是字段错误列表或者对象，不是只有列表
+    assert!(schemas["ErrorDetails"]["oneOf"].is_array());
+
+    let resp = get("/docs/").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = resp.into_body().collect().await.unwrap().to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("swagger-ui"));
+}
@@ -0,0 +1,265 @@
+// 列表接口通用的排序和分页
+// 1. sort=-age,name 这样的多字段排序，'-' 表示倒序，字段必须在白名单里
+// 2. offset 分页：limit + offset，响应里带 total
+// 3. keyset 游标分页：cursor 是上一页最后一行的排序字段值，base64 编码后对调用方不透明
+//    翻页时 WHERE (age, name, id) 在排序意义上"大于"游标，不受中间插入/删除的影响，深翻页也不慢
+// 4. 排序最后总是补上主键，保证顺序是全序的，游标才不会跳过或重复
+
+use crate::error::{ApiError, FieldError};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use dep_serialization::PageMeta;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{Postgres, QueryBuilder};
+
+pub const DEFAULT_LIMIT: u32 = 20;
+pub const MAX_LIMIT: u32 = 100;
+/// OFFSET 在 SQL 里是 bigint，再大就溢出成负数了
+pub const MAX_OFFSET: u64 = i64::MAX as u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Asc,
+    Desc,
+}
+
+/// 可以参与排序的列，每张表自己实现一个枚举
+pub trait SortField: Copy + Eq + Sized {
+    type Row;
+
+    /// 总是追加在排序最后的唯一列，一般就是主键
+    const TIEBREAKER: Self;
+
+    fn parse(name: &str) -> Option<Self>;
+
+    fn name(&self) -> &'static str;
+
+    /// 取出一行里这一列的值，写进游标
+    fn value_of(&self, row: &Self::Row) -> Value;
+
+    /// 把游标里的值按这一列的类型绑定到 SQL 上，类型对不上返回 false
+    fn push_bind(&self, qb: &mut QueryBuilder<Postgres>, value: &Value) -> bool;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortKey<F> {
+    pub field: F,
+    pub direction: Direction,
+}
+
+/// 解析 "-age,name"，空字符串表示只按主键排序
+pub fn parse_sort<F: SortField>(raw: &str) -> Result<Vec<SortKey<F>>, FieldError> {
+    let mut keys: Vec<SortKey<F>> = Vec::new();
+    for part in raw.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (direction, name) = match part.strip_prefix('-') {
+            Some(name) => (Direction::Desc, name),
+            None => (Direction::Asc, part.strip_prefix('+').unwrap_or(part)),
+        };
+        let field = F::parse(name)
+            .ok_or_else(|| FieldError::new("sort", format!("不支持按 {} 排序", name)))?;
+        if keys.iter().any(|k| k.field == field) {
+            return Err(FieldError::new("sort", format!("{} 重复出现", name)));
+        }
+        keys.push(SortKey { field, direction });
+    }
+    if !keys.iter().any(|k| k.field == F::TIEBREAKER) {
+        keys.push(SortKey {
+            field: F::TIEBREAKER,
+            direction: Direction::Asc,
+        });
+    }
+    Ok(keys)
+}
+
+/// 规范化的排序串，写进游标用来校验翻页时排序没有变
+pub fn sort_spec<F: SortField>(keys: &[SortKey<F>]) -> String {
+    keys.iter()
+        .map(|k| match k.direction {
+            Direction::Asc => k.field.name().to_string(),
+            Direction::Desc => format!("-{}", k.field.name()),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+pub fn push_order_by<F: SortField>(qb: &mut QueryBuilder<Postgres>, keys: &[SortKey<F>]) {
+    qb.push(" ORDER BY ");
+    for (i, key) in keys.iter().enumerate() {
+        if i > 0 {
+            qb.push(", ");
+        }
+        qb.push(key.field.name());
+        if key.direction == Direction::Desc {
+            qb.push(" DESC");
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cursor {
+    #[serde(rename = "s")]
+    pub sort: String,
+    #[serde(rename = "v")]
+    pub values: Vec<Value>,
+}
+
+impl Cursor {
+    pub fn after<F: SortField>(keys: &[SortKey<F>], row: &F::Row) -> Self {
+        Cursor {
+            sort: sort_spec(keys),
+            values: keys.iter().map(|k| k.field.value_of(row)).collect(),
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap())
+    }
+
+    pub fn decode(raw: &str) -> Result<Self, FieldError> {
+        URL_SAFE_NO_PAD
+            .decode(raw)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or_else(|| FieldError::new("cursor", "无效的游标"))
+    }
+
+    /// 追加 keyset 条件，(a, b, id) 升降序混合时不能用行比较，只能展开成：
+    /// a > x OR (a = x AND b < y) OR (a = x AND b = y AND id > z)
+    pub fn push_where<F: SortField>(
+        &self,
+        qb: &mut QueryBuilder<Postgres>,
+        keys: &[SortKey<F>],
+    ) -> Result<(), FieldError> {
+        let invalid = || FieldError::new("cursor", "游标和当前排序不匹配");
+        if self.sort != sort_spec(keys) || self.values.len() != keys.len() {
+            return Err(invalid());
+        }
+        qb.push("(");
+        for i in 0..keys.len() {
+            if i > 0 {
+                qb.push(" OR ");
+            }
+            qb.push("(");
+            for (j, key) in keys[..=i].iter().enumerate() {
+                if j > 0 {
+                    qb.push(" AND ");
+                }
+                let op = match (j == i, key.direction) {
+                    (false, _) => " = ",
+                    (true, Direction::Asc) => " > ",
+                    (true, Direction::Desc) => " < ",
+                };
+                qb.push(key.field.name()).push(op);
+                if !key.field.push_bind(qb, &self.values[j]) {
+                    return Err(invalid());
+                }
+            }
+            qb.push(")");
+        }
+        qb.push(")");
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Page {
+    Offset(u64),
+    After(Cursor),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageRequest {
+    pub limit: u32,
+    pub page: Page,
+}
+
+impl PageRequest {
+    /// offset 和 cursor 只能二选一，都不给就是第一页
+    pub fn new(
+        limit: Option<u32>,
+        offset: Option<u64>,
+        cursor: Option<&str>,
+    ) -> Result<Self, ApiError> {
+        let limit = limit.unwrap_or(DEFAULT_LIMIT);
+        if limit == 0 || limit > MAX_LIMIT {
+            return Err(ApiError::Validation(vec![FieldError::new(
+                "limit",
+                format!("必须在 1 到 {} 之间", MAX_LIMIT),
+            )]));
+        }
+        let page = match (offset, cursor) {
+            (Some(_), Some(_)) => {
+                return Err(ApiError::Validation(vec![FieldError::new(
+                    "cursor",
+                    "不能和 offset 同时使用",
+                )]));
+            }
+            (_, Some(raw)) => {
+                Page::After(Cursor::decode(raw).map_err(|e| ApiError::Validation(vec![e]))?)
+            }
+            (Some(offset), None) if offset > MAX_OFFSET => {
+                return Err(ApiError::Validation(vec![FieldError::new(
+                    "offset",
+                    format!("不能超过 {}", MAX_OFFSET),
+                )]));
+            }
+            (offset, None) => Page::Offset(offset.unwrap_or(0)),
+        };
+        Ok(PageRequest { limit, page })
+    }
+
+    pub fn cursor(&self) -> Option<&Cursor> {
+        match &self.page {
+            Page::After(cursor) => Some(cursor),
+            Page::Offset(_) => None,
+        }
+    }
+
+    /// 多查一行用来判断还有没有下一页
+    pub fn fetch_limit(&self) -> i64 {
+        self.limit as i64 + 1
+    }
+
+    /// rows 是按 fetch_limit 查出来的结果，截掉多出来的那一行并生成分页信息
+    pub fn finish<F: SortField>(
+        &self,
+        keys: &[SortKey<F>],
+        mut rows: Vec<F::Row>,
+        total: Option<u64>,
+    ) -> (Vec<F::Row>, PageMeta) {
+        let has_more = rows.len() > self.limit as usize;
+        rows.truncate(self.limit as usize);
+        let next_cursor = if has_more {
+            rows.last().map(|row| Cursor::after(keys, row).encode())
+        } else {
+            None
+        };
+        let offset = match self.page {
+            Page::Offset(offset) => Some(offset),
+            Page::After(_) => None,
+        };
+        let meta = PageMeta {
+            limit: self.limit,
+            offset,
+            total,
+            next_cursor,
+            has_more,
+        };
+        (rows, meta)
+    }
+}
+
+#[test]
+fn page_request_bounds() {
+    assert_eq!(
+        PageRequest::new(None, Some(MAX_OFFSET), None).unwrap().page,
+        Page::Offset(MAX_OFFSET)
+    );
+    assert!(matches!(
+        PageRequest::new(None, Some(MAX_OFFSET + 1), None),
+        Err(ApiError::Validation(_))
+    ));
+    assert!(PageRequest::new(Some(0), None, None).is_err());
+    assert!(PageRequest::new(Some(MAX_LIMIT + 1), None, None).is_err());
+}
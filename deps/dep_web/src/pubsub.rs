@@ -0,0 +1,464 @@
+// 频道广播，/ws 和 /sse 都从这里订阅
+// 1. 进程内每个频道一个 broadcast::Sender，容量就是每个客户端的发送缓冲：
+//    某个客户端落后超过这么多条会收到 Lagged，由上层断开它，不会拖慢别人
+// 2. subscribe 返回 Subscription，按频道记引用计数，最后一个 Subscription drop 掉频道马上清理
+// 3. 配了 RESP 服务端(dep_async::resp_server)时，发布走服务端 PUBLISH，断了自动重连；
+//    整个 Hub 只开一条 SUBSCRIBE 连接，由一个后台任务管：频道第一个订阅者来时发 SUBSCRIBE，
+//    最后一个走时发 UNSUBSCRIBE，同一频道后来的订阅者也要等服务端确认过才返回
+// 4. 订阅连接断了按退避重连，连上后把还有人听的频道重新订阅一遍；断开期间发布的消息收不到
+// 5. 停机时 close()：所有 Subscription 收到 Closed，/ws 和 /sse 的长连接跟着结束，不会拖住优雅停机
+
+use bytes::Bytes;
+use dep_async::pubsub::{Event, Publisher, Subscriber};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+pub const DEFAULT_BUFFER: usize = 64;
+pub const DEFAULT_HEARTBEAT: Duration = Duration::from_secs(15);
+// 等服务端确认订阅的上限，订阅连接正在重连时不让请求一直挂着
+const SUBSCRIBE_TIMEOUT: Duration = Duration::from_secs(5);
+const RECONNECT_MIN: Duration = Duration::from_millis(100);
+const RECONNECT_MAX: Duration = Duration::from_secs(5);
+
+struct Entry {
+    tx: broadcast::Sender<Bytes>,
+    subscribers: usize,
+}
+
+type Channels = Arc<Mutex<HashMap<String, Entry>>>;
+
+enum Command {
+    Subscribe(String, oneshot::Sender<()>),
+    Unsubscribe(String),
+}
+
+struct Remote {
+    addr: String,
+    publisher: Publisher,
+    // 发给订阅连接的后台任务，所有 Hub 和 Subscription 都没了任务就退出
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+#[derive(Clone)]
+pub struct Hub {
+    channels: Channels,
+    remote: Option<Arc<Remote>>,
+    closed: Arc<AtomicBool>,
+    /// 每个客户端最多积压多少条消息
+    pub buffer: usize,
+    /// WebSocket ping / SSE 注释行的间隔
+    pub heartbeat: Duration,
+}
+
+impl Default for Hub {
+    fn default() -> Self {
+        Self::local()
+    }
+}
+
+impl Hub {
+    pub fn local() -> Self {
+        Hub {
+            channels: Arc::new(Mutex::new(HashMap::new())),
+            remote: None,
+            closed: Arc::new(AtomicBool::new(false)),
+            buffer: DEFAULT_BUFFER,
+            heartbeat: DEFAULT_HEARTBEAT,
+        }
+    }
+
+    /// 多个实例连同一个 RESP 服务端共享频道；连不上直接报错
+    pub async fn connect(addr: impl Into<String>) -> anyhow::Result<Self> {
+        let addr = addr.into();
+        let connect_err = |e| anyhow::anyhow!("connect pubsub server {}: {}", addr, e);
+        let publisher = Publisher::connect(addr.as_str())
+            .await
+            .map_err(connect_err)?;
+        let subscriber = Subscriber::connect(addr.as_str())
+            .await
+            .map_err(connect_err)?;
+        let hub = Self::local();
+        let (commands, rx) = mpsc::unbounded_channel();
+        tokio::spawn(run_subscriber(
+            addr.clone(),
+            subscriber,
+            rx,
+            hub.channels.clone(),
+        ));
+        Ok(Hub {
+            remote: Some(Arc::new(Remote {
+                addr,
+                publisher,
+                commands,
+            })),
+            ..hub
+        })
+    }
+
+    pub fn with_buffer(mut self, buffer: usize) -> Self {
+        self.buffer = buffer;
+        self
+    }
+
+    pub fn with_heartbeat(mut self, heartbeat: Duration) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    pub async fn subscribe(&self, channel: &str) -> anyhow::Result<Subscription> {
+        // 计数和发命令都在锁里，和 Subscription::drop 发的 UNSUBSCRIBE 不会乱序
+        let (rx, ack) = {
+            let mut channels = self.channels.lock().unwrap();
+            // 在锁里看，和 close 清空频道不会错开
+            if self.closed.load(Ordering::SeqCst) {
+                anyhow::bail!("pubsub hub is closed");
+            }
+            let entry = channels
+                .entry(channel.to_string())
+                .or_insert_with(|| Entry {
+                    tx: broadcast::channel(self.buffer).0,
+                    subscribers: 0,
+                });
+            entry.subscribers += 1;
+            let ack = self.remote.as_ref().map(|remote| {
+                let (tx, rx) = oneshot::channel();
+                let _ = remote
+                    .commands
+                    .send(Command::Subscribe(channel.to_string(), tx));
+                rx
+            });
+            (entry.tx.subscribe(), ack)
+        };
+        // 先建好 Subscription，下面等确认失败返回时 drop 掉它，计数跟着退回去
+        let subscription = Subscription {
+            rx,
+            channel: channel.to_string(),
+            channels: self.channels.clone(),
+            remote: self.remote.clone(),
+        };
+        if let (Some(ack), Some(remote)) = (ack, &self.remote) {
+            // 服务端确认订阅以后才返回，之后发布的消息一定能转过来
+            if !matches!(
+                tokio::time::timeout(SUBSCRIBE_TIMEOUT, ack).await,
+                Ok(Ok(()))
+            ) {
+                anyhow::bail!("subscribe {} on {}: not confirmed", channel, remote.addr);
+            }
+        }
+        Ok(subscription)
+    }
+
+    /// 关掉所有频道，订阅者的 recv 返回 Closed，之后 subscribe 都失败；停机时用
+    pub fn close(&self) {
+        let mut channels = self.channels.lock().unwrap();
+        self.closed.store(true, Ordering::SeqCst);
+        // Sender 都 drop 掉，Receiver 读完剩下的就是 Closed
+        channels.clear();
+    }
+
+    /// 返回收到消息的订阅者数量；走远端时是订阅了这个频道的实例数
+    pub async fn publish(&self, channel: &str, message: Bytes) -> anyhow::Result<usize> {
+        if let Some(remote) = &self.remote {
+            let receivers = remote
+                .publisher
+                .publish(channel, message)
+                .await
+                .map_err(|e| anyhow::anyhow!("publish to {}: {}", remote.addr, e))?;
+            return Ok(receivers as usize);
+        }
+        let channels = self.channels.lock().unwrap();
+        Ok(channels
+            .get(channel)
+            .and_then(|entry| entry.tx.send(message).ok())
+            .unwrap_or(0))
+    }
+}
+
+/// 一个频道上的一个订阅者，drop 掉就退订
+pub struct Subscription {
+    rx: broadcast::Receiver<Bytes>,
+    channel: String,
+    channels: Channels,
+    remote: Option<Arc<Remote>>,
+}
+
+impl Subscription {
+    pub async fn recv(&mut self) -> Result<Bytes, RecvError> {
+        self.rx.recv().await
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let mut channels = self.channels.lock().unwrap();
+        let Some(entry) = channels.get_mut(&self.channel) else {
+            return;
+        };
+        entry.subscribers -= 1;
+        if entry.subscribers == 0 {
+            channels.remove(&self.channel);
+            if let Some(remote) = &self.remote {
+                let _ = remote
+                    .commands
+                    .send(Command::Unsubscribe(self.channel.clone()));
+            }
+        }
+    }
+}
+
+// 已经发了 SUBSCRIBE、还没等到确认的频道
+#[derive(Default)]
+struct Pending {
+    // 还没回确认的 SUBSCRIBE 条数，全部回来了才算订阅好
+    outstanding: usize,
+    // 最后发出去的是 SUBSCRIBE 还是 UNSUBSCRIBE
+    wanted: bool,
+    waiters: Vec<oneshot::Sender<()>>,
+}
+
+async fn run_subscriber(
+    addr: String,
+    mut subscriber: Subscriber,
+    mut commands: mpsc::UnboundedReceiver<Command>,
+    channels: Channels,
+) {
+    let mut confirmed = HashSet::new();
+    let mut pending: HashMap<String, Pending> = HashMap::new();
+    loop {
+        let lost = loop {
+            tokio::select! {
+                command = commands.recv() => match command {
+                    None => return,
+                    Some(Command::Subscribe(channel, ack)) => {
+                        if confirmed.contains(&channel) {
+                            let _ = ack.send(());
+                            continue;
+                        }
+                        let p = pending.entry(channel.clone()).or_default();
+                        p.waiters.push(ack);
+                        if !p.wanted {
+                            p.wanted = true;
+                            p.outstanding += 1;
+                            if let Err(e) = subscriber.send_subscribe(&[channel]).await {
+                                break e.to_string();
+                            }
+                        }
+                    }
+                    Some(Command::Unsubscribe(channel)) => {
+                        confirmed.remove(&channel);
+                        // 发 Unsubscribe 时这个频道的订阅者都已经 drop 了，等着的也不用通知
+                        if let Some(p) = pending.get_mut(&channel) {
+                            p.wanted = false;
+                            p.waiters.clear();
+                        }
+                        if let Err(e) = subscriber.send_unsubscribe(&[channel]).await {
+                            break e.to_string();
+                        }
+                    }
+                },
+                event = subscriber.next_event() => match event {
+                    Ok(Some(Event::Message(message))) => {
+                        if let Some(entry) = channels.lock().unwrap().get(&message.channel) {
+                            let _ = entry.tx.send(message.content);
+                        }
+                    }
+                    Ok(Some(Event::Subscribed(channel))) => {
+                        let Some(p) = pending.get_mut(&channel) else { continue };
+                        p.outstanding = p.outstanding.saturating_sub(1);
+                        if p.outstanding == 0 && let Some(p) = pending.remove(&channel) && p.wanted {
+                            for waiter in p.waiters {
+                                let _ = waiter.send(());
+                            }
+                            confirmed.insert(channel);
+                        }
+                    }
+                    Ok(Some(Event::Unsubscribed(_))) => {}
+                    Ok(None) => break "connection closed by server".to_string(),
+                    Err(e) => break e.to_string(),
+                },
+            }
+        };
+        log::warn!("pubsub subscription to {} lost: {}", addr, lost);
+        confirmed.clear();
+
+        let mut delay = RECONNECT_MIN;
+        subscriber = loop {
+            tokio::time::sleep(delay).await;
+            if commands.is_closed() {
+                return;
+            }
+            match Subscriber::connect(addr.as_str()).await {
+                Ok(subscriber) => break subscriber,
+                Err(e) => {
+                    log::warn!("pubsub reconnect to {} failed: {}", addr, e);
+                    delay = (delay * 2).min(RECONNECT_MAX);
+                }
+            }
+        };
+
+        // 新连接上什么都没订阅，按本地还有人听的频道重来；等着确认的订阅者继续等
+        let wanted: Vec<String> = channels.lock().unwrap().keys().cloned().collect();
+        pending.retain(|channel, _| wanted.contains(channel));
+        for channel in &wanted {
+            let p = pending.entry(channel.clone()).or_default();
+            p.wanted = true;
+            p.outstanding = 1;
+        }
+        if let Err(e) = subscriber.send_subscribe(&wanted).await {
+            log::warn!("pubsub resubscribe on {} failed: {}", addr, e);
+        }
+        log::info!(
+            "pubsub reconnected to {}, resubscribed {} channels",
+            addr,
+            wanted.len()
+        );
+    }
+}
+
+#[tokio::test]
+async fn local_hub_fan_out() {
+    let hub = Hub::local().with_buffer(2);
+    assert_eq!(hub.publish("news", "nobody".into()).await.unwrap(), 0);
+
+    let mut a = hub.subscribe("news").await.unwrap();
+    let mut b = hub.subscribe("news").await.unwrap();
+    assert_eq!(hub.publish("news", "hello".into()).await.unwrap(), 2);
+    assert_eq!(a.recv().await.unwrap(), "hello");
+    assert_eq!(b.recv().await.unwrap(), "hello");
+
+    // b 不读，超过缓冲就 Lagged，a 不受影响
+    for i in 0..3 {
+        hub.publish("news", Bytes::from(i.to_string()))
+            .await
+            .unwrap();
+        assert_eq!(a.recv().await.unwrap(), i.to_string());
+    }
+    assert!(matches!(b.recv().await, Err(RecvError::Lagged(1))));
+
+    // 最后一个走了频道马上清理，不用等下一条消息
+    drop(a);
+    assert_eq!(hub.channels.lock().unwrap().len(), 1);
+    drop(b);
+    assert!(hub.channels.lock().unwrap().is_empty());
+    assert_eq!(hub.publish("news", "bye".into()).await.unwrap(), 0);
+
+    // 停机：还在听的订阅者收完已有的消息后拿到 Closed，新的订阅被拒绝
+    let mut c = hub.subscribe("news").await.unwrap();
+    hub.publish("news", "last".into()).await.unwrap();
+    hub.close();
+    assert_eq!(c.recv().await.unwrap(), "last");
+    assert!(matches!(c.recv().await, Err(RecvError::Closed)));
+    assert!(hub.subscribe("news").await.is_err());
+    drop(c);
+}
+
+#[cfg(test)]
+async fn publish_until(hub: &Hub, channel: &str, receivers: usize) {
+    // 退订 / 重连是异步生效的，发到接收者数对上为止
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let Ok(n) = hub.publish(channel, "probe".into()).await
+                && n == receivers
+            {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .unwrap_or_else(|_| panic!("{} never reached {} receivers", channel, receivers));
+}
+
+#[tokio::test]
+async fn remote_hub_shared_between_instances() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(dep_async::resp_server::serve(listener));
+
+    let a = Hub::connect(addr.clone()).await.unwrap();
+    let b = Hub::connect(addr).await.unwrap();
+    // 同一频道的两个订阅者一起来，后来的那个也要等服务端确认
+    let (first, second) = tokio::join!(a.subscribe("chat"), a.subscribe("chat"));
+    let (mut first, mut second) = (first.unwrap(), second.unwrap());
+    let mut on_b = b.subscribe("chat").await.unwrap();
+    let mut news = a.subscribe("news").await.unwrap();
+
+    // 每个实例一条订阅连接，不管有几个频道、几个订阅者
+    assert_eq!(a.publish("chat", "from a".into()).await.unwrap(), 2);
+    assert_eq!(first.recv().await.unwrap(), "from a");
+    assert_eq!(second.recv().await.unwrap(), "from a");
+    assert_eq!(on_b.recv().await.unwrap(), "from a");
+    assert_eq!(b.publish("news", "headline".into()).await.unwrap(), 1);
+    assert_eq!(news.recv().await.unwrap(), "headline");
+
+    // a 上最后一个 chat 订阅者走了才退订，news 不受影响
+    drop(first);
+    assert_eq!(b.publish("chat", "still here".into()).await.unwrap(), 2);
+    assert_eq!(second.recv().await.unwrap(), "still here");
+    drop(second);
+    publish_until(&b, "chat", 1).await;
+    assert_eq!(b.publish("news", "more".into()).await.unwrap(), 1);
+    assert_eq!(news.recv().await.unwrap(), "more");
+
+    assert!(Hub::connect("127.0.0.1:1").await.is_err());
+}
+
+#[tokio::test]
+async fn remote_hub_resubscribes_after_server_restart() {
+    use tokio::net::TcpSocket;
+
+    // 服务端跑在单独的运行时里，关掉运行时就把它和所有连接一起停了
+    async fn start(addr: std::net::SocketAddr) -> tokio::runtime::Runtime {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .unwrap();
+        let (ready, listening) = oneshot::channel();
+        runtime.spawn(async move {
+            // 旧连接还在 TIME_WAIT，同一个端口要 SO_REUSEADDR 才绑得上；
+            // shutdown_background 不等旧的任务真的停下，旧的监听 socket 还没关时等一会再绑
+            let listener = loop {
+                let socket = TcpSocket::new_v4().unwrap();
+                socket.set_reuseaddr(true).unwrap();
+                match socket.bind(addr).and_then(|()| socket.listen(64)) {
+                    Ok(listener) => break listener,
+                    Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+                }
+            };
+            let _ = ready.send(());
+            dep_async::resp_server::serve(listener).await
+        });
+        listening.await.unwrap();
+        runtime
+    }
+
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let server = start(addr).await;
+    let hub = Hub::connect(addr.to_string()).await.unwrap();
+    let mut sub = hub.subscribe("chat").await.unwrap();
+    assert_eq!(hub.publish("chat", "before".into()).await.unwrap(), 1);
+    assert_eq!(sub.recv().await.unwrap(), "before");
+
+    server.shutdown_background();
+    let server = start(addr).await;
+    // 订阅连接重连后重新订阅，本地的 Subscription 不用动
+    publish_until(&hub, "chat", 1).await;
+    hub.publish("chat", "after".into()).await.unwrap();
+    loop {
+        let message = sub.recv().await.unwrap();
+        if message == "after" {
+            break;
+        }
+        assert_eq!(message, "probe");
+    }
+    // 在异步上下文里直接 drop 运行时会 panic
+    server.shutdown_background();
+}
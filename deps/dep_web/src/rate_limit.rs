@@ -0,0 +1,719 @@
+// 按客户端限流的 tower 中间件，算法和存储直接复用 dep_async::rate_limit
+// 1. 客户端按 key_by 的顺序识别：登录用户 > API key > IP，前面的拿不到就用下一个；
+//    API key 只认 api_keys 里配过的，按配置里的名字计数，随便编一个头换不到新额度；
+//    IP 之类的 key 额度恢复满了以后状态会被限流器清掉，不会一直占内存
+// 2. 每条规则一个限流器，可以按路径(和 gateway 一样的 glob)+方法单独配额度，没匹配上的走 default
+// 3. 限流器可以是进程内的 LocalRateLimiter，也可以是 RESP 服务端上的 RespRateLimiter，多个实例共享额度
+// 4. 每个响应都带 RateLimit-Limit / RateLimit-Remaining / RateLimit-Reset，超了返回 429 + Retry-After
+// 5. 限流器本身出错(比如 RESP 服务端挂了)时放行并打日志，不能因为限流把整个服务拖垮
+
+use crate::auth::{AuthKeys, AuthUser, TokenType};
+use crate::error::ApiError;
+use crate::gateway::PathPattern;
+use axum::extract::{ConnectInfo, Request};
+use axum::http::header::{AUTHORIZATION, RETRY_AFTER};
+use axum::http::{HeaderName, HeaderValue, Method};
+use axum::response::{IntoResponse, Response};
+use dep_async::rate_limit::{Algorithm, Decision, LocalRateLimiter, RateLimiter, RespRateLimiter};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tower::{Layer, Service};
+
+pub const RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+pub const RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+pub const RATELIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyBy {
+    Ip,
+    ApiKey,
+    User,
+}
+
+/// 一条额度，yaml 里写成 `{ algorithm: token_bucket, capacity: 10, refill_per_sec: 1 }`
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "algorithm", rename_all = "snake_case")]
+pub enum Quota {
+    TokenBucket { capacity: u32, refill_per_sec: f64 },
+    LeakyBucket { capacity: u32, leak_per_sec: f64 },
+    SlidingWindowLog { limit: u32, window_secs: f64 },
+}
+
+impl TryFrom<Quota> for Algorithm {
+    type Error = String;
+
+    /// 负数、NaN 之类的参数在这里报错，不会到 Duration::from_secs_f64 里 panic
+    fn try_from(quota: Quota) -> Result<Self, String> {
+        let algorithm = match quota {
+            Quota::TokenBucket {
+                capacity,
+                refill_per_sec,
+            } => Algorithm::TokenBucket {
+                capacity,
+                refill_per_sec,
+            },
+            Quota::LeakyBucket {
+                capacity,
+                leak_per_sec,
+            } => Algorithm::LeakyBucket {
+                capacity,
+                leak_per_sec,
+            },
+            Quota::SlidingWindowLog { limit, window_secs } => Algorithm::SlidingWindowLog {
+                limit,
+                window: Duration::try_from_secs_f64(window_secs).map_err(|_| {
+                    format!(
+                        "sliding_window_log window_secs must be positive, got {}",
+                        window_secs
+                    )
+                })?,
+            },
+        };
+        algorithm.validate()?;
+        Ok(algorithm)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouteQuota {
+    pub id: String,
+    pub path: String,
+    // 不写就是所有方法
+    #[serde(default)]
+    pub methods: Vec<String>,
+    #[serde(flatten)]
+    pub quota: Quota,
+}
+
+fn default_key_by() -> Vec<KeyBy> {
+    vec![KeyBy::User, KeyBy::ApiKey, KeyBy::Ip]
+}
+
+fn default_api_key_header() -> String {
+    "x-api-key".to_string()
+}
+
+/// 对应配置文件里的 rate_limit 段：
+///
+/// ```yaml
+/// rate_limit:
+///   key_by: [user, api_key, ip]
+///   api_keys: { partner-a: "k-123" }   # 名字 -> key，不在这里的 x-api-key 不算数
+///   store: 127.0.0.1:6379        # 不写就是进程内
+///   default: { algorithm: token_bucket, capacity: 100, refill_per_sec: 10 }
+///   routes:
+///     - id: login
+///       path: /auth/login
+///       methods: [POST]
+///       algorithm: sliding_window_log
+///       limit: 5
+///       window_secs: 60
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct RateLimitConfig {
+    #[serde(default = "default_key_by")]
+    pub key_by: Vec<KeyBy>,
+    #[serde(default = "default_api_key_header")]
+    pub api_key_header: String,
+    #[serde(default)]
+    pub api_keys: BTreeMap<String, String>,
+    // 前面有 gateway 之类的反向代理时才打开，否则客户端可以随便伪造 IP
+    #[serde(default)]
+    pub trust_forwarded_for: bool,
+    // RESP 服务端地址
+    #[serde(default)]
+    pub store: Option<String>,
+    #[serde(default)]
+    pub default: Option<Quota>,
+    #[serde(default)]
+    pub routes: Vec<RouteQuota>,
+}
+
+#[derive(Deserialize)]
+struct ConfigFile {
+    rate_limit: RateLimitConfig,
+}
+
+impl RateLimitConfig {
+    pub fn from_yaml(yaml: &str) -> anyhow::Result<Self> {
+        let file: ConfigFile = serde_yaml::from_str(yaml)?;
+        Ok(file.rate_limit)
+    }
+}
+
+#[derive(Clone)]
+struct Rule {
+    id: String,
+    path: Option<PathPattern>,
+    methods: Vec<Method>,
+    limiter: Arc<dyn RateLimiter>,
+}
+
+impl Rule {
+    fn matches(&self, method: &Method, path: &str) -> bool {
+        (self.methods.is_empty() || self.methods.contains(method))
+            && self.path.as_ref().is_none_or(|p| p.matches(path))
+    }
+}
+
+#[derive(Clone)]
+struct Limits {
+    key_by: Vec<KeyBy>,
+    api_key_header: HeaderName,
+    // key -> 名字
+    api_keys: HashMap<String, String>,
+    trust_forwarded_for: bool,
+    // 外层拿不到 AuthUser(AuthLayer 一般挂在具体路由上)，有密钥就自己解一下 token
+    auth: Option<AuthKeys>,
+    routes: Vec<Rule>,
+    default: Option<Rule>,
+}
+
+impl Limits {
+    fn rule_for(&self, method: &Method, path: &str) -> Option<&Rule> {
+        self.routes
+            .iter()
+            .find(|r| r.matches(method, path))
+            .or(self.default.as_ref())
+    }
+
+    fn client_key(&self, req: &Request) -> String {
+        for key_by in &self.key_by {
+            let key = match key_by {
+                KeyBy::User => self.user_id(req).map(|id| format!("user:{}", id)),
+                KeyBy::ApiKey => req
+                    .headers()
+                    .get(&self.api_key_header)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| self.api_keys.get(v))
+                    .map(|name| format!("key:{}", name)),
+                KeyBy::Ip => self.client_ip(req).map(|ip| format!("ip:{}", ip)),
+            };
+            if let Some(key) = key {
+                return key;
+            }
+        }
+        "anonymous".to_string()
+    }
+
+    fn user_id(&self, req: &Request) -> Option<i32> {
+        if let Some(user) = req.extensions().get::<AuthUser>() {
+            return Some(user.id);
+        }
+        // token 无效就当没登录，拒绝请求是 AuthLayer 的事
+        let token = req
+            .headers()
+            .get(AUTHORIZATION)?
+            .to_str()
+            .ok()?
+            .strip_prefix("Bearer ")?;
+        let user = self
+            .auth
+            .as_ref()?
+            .verify(token.trim(), TokenType::Access)
+            .ok()?;
+        Some(user.id)
+    }
+
+    fn client_ip(&self, req: &Request) -> Option<String> {
+        if self.trust_forwarded_for
+            && let Some(ip) = req
+                .headers()
+                .get("x-forwarded-for")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.split(',').next())
+                .map(str::trim)
+                .filter(|ip| !ip.is_empty())
+        {
+            return Some(ip.to_string());
+        }
+        req.extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string())
+    }
+}
+
+/// 用法：
+///
+/// ```ignore
+/// let layer = RateLimitLayer::new()
+///     .route("login", PathPattern::parse("/auth/login")?, [Method::POST], login_limiter)
+///     .default_limiter(Arc::new(LocalRateLimiter::new(algorithm)?));
+/// ```
+///
+/// 没有任何规则时直接放行；按 IP 限流要用 `into_make_service_with_connect_info::<SocketAddr>()` 启动
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    limits: Arc<Limits>,
+}
+
+impl Default for RateLimitLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RateLimitLayer {
+    pub fn new() -> Self {
+        RateLimitLayer {
+            limits: Arc::new(Limits {
+                key_by: default_key_by(),
+                api_key_header: HeaderName::from_static("x-api-key"),
+                api_keys: HashMap::new(),
+                trust_forwarded_for: false,
+                auth: None,
+                routes: Vec::new(),
+                default: None,
+            }),
+        }
+    }
+
+    fn update(mut self, f: impl FnOnce(&mut Limits)) -> Self {
+        let mut limits = Arc::unwrap_or_clone(self.limits);
+        f(&mut limits);
+        self.limits = Arc::new(limits);
+        self
+    }
+
+    pub fn key_by(self, key_by: impl IntoIterator<Item = KeyBy>) -> Self {
+        let key_by = key_by.into_iter().collect();
+        self.update(|l| l.key_by = key_by)
+    }
+
+    pub fn api_key_header(self, name: HeaderName) -> Self {
+        self.update(|l| l.api_key_header = name)
+    }
+
+    /// 登记一个有效的 API key，额度按 name 算
+    pub fn api_key(self, name: impl Into<String>, key: impl Into<String>) -> Self {
+        let (name, key) = (name.into(), key.into());
+        self.update(|l| {
+            l.api_keys.insert(key, name);
+        })
+    }
+
+    pub fn trust_forwarded_for(self) -> Self {
+        self.update(|l| l.trust_forwarded_for = true)
+    }
+
+    pub fn auth_keys(self, keys: AuthKeys) -> Self {
+        self.update(|l| l.auth = Some(keys))
+    }
+
+    /// 按配置顺序匹配，第一条匹配上的生效
+    pub fn route(
+        self,
+        id: impl Into<String>,
+        path: PathPattern,
+        methods: impl IntoIterator<Item = Method>,
+        limiter: Arc<dyn RateLimiter>,
+    ) -> Self {
+        let rule = Rule {
+            id: id.into(),
+            path: Some(path),
+            methods: methods.into_iter().collect(),
+            limiter,
+        };
+        self.update(|l| l.routes.push(rule))
+    }
+
+    pub fn default_limiter(self, limiter: Arc<dyn RateLimiter>) -> Self {
+        let rule = Rule {
+            id: "default".to_string(),
+            path: None,
+            methods: Vec::new(),
+            limiter,
+        };
+        self.update(|l| l.default = Some(rule))
+    }
+
+    /// 按配置建好每条规则的限流器，配了 store 的会连上 RESP 服务端
+    pub async fn from_config(
+        config: &RateLimitConfig,
+        auth: Option<AuthKeys>,
+    ) -> anyhow::Result<Self> {
+        let limiter = async |quota: Quota| -> anyhow::Result<Arc<dyn RateLimiter>> {
+            let algorithm = Algorithm::try_from(quota).map_err(anyhow::Error::msg)?;
+            let limiter: Arc<dyn RateLimiter> = match &config.store {
+                Some(addr) => Arc::new(
+                    RespRateLimiter::connect(addr.as_str(), algorithm)
+                        .await
+                        .map_err(|e| anyhow::anyhow!("connect rate limit store {}: {}", addr, e))?,
+                ),
+                None => Arc::new(LocalRateLimiter::new(algorithm).map_err(anyhow::Error::msg)?),
+            };
+            Ok(limiter)
+        };
+
+        let mut layer = RateLimitLayer::new()
+            .key_by(config.key_by.iter().copied())
+            .api_key_header(HeaderName::try_from(config.api_key_header.as_str())?);
+        for (name, key) in &config.api_keys {
+            layer = layer.api_key(name.clone(), key.clone());
+        }
+        if config.trust_forwarded_for {
+            layer = layer.trust_forwarded_for();
+        }
+        if let Some(keys) = auth {
+            layer = layer.auth_keys(keys);
+        }
+        for route in &config.routes {
+            let methods = route
+                .methods
+                .iter()
+                .map(|m| Method::from_bytes(m.to_ascii_uppercase().as_bytes()))
+                .collect::<Result<Vec<_>, _>>()?;
+            layer = layer.route(
+                route.id.clone(),
+                PathPattern::parse(&route.path)?,
+                methods,
+                limiter(route.quota).await?,
+            );
+        }
+        if let Some(quota) = config.default {
+            layer = layer.default_limiter(limiter(quota).await?);
+        }
+        Ok(layer)
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            limits: self.limits.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    limits: Arc<Limits>,
+}
+
+// 向上取整，不然 0.3 秒会变成 "0"，客户端马上重试还是 429
+fn ceil_secs(d: Duration) -> u64 {
+    d.as_secs() + u64::from(d.subsec_nanos() > 0)
+}
+
+fn set_headers(resp: &mut Response, decision: &Decision) {
+    let headers = resp.headers_mut();
+    headers.insert(RATELIMIT_LIMIT, HeaderValue::from(decision.limit));
+    headers.insert(RATELIMIT_REMAINING, HeaderValue::from(decision.remaining));
+    headers.insert(
+        RATELIMIT_RESET,
+        HeaderValue::from(ceil_secs(decision.reset_after)),
+    );
+    if !decision.allowed {
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from(ceil_secs(decision.retry_after)),
+        );
+    }
+}
+
+impl<S> Service<Request> for RateLimitService<S>
+where
+    S: Service<Request, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let limits = self.limits.clone();
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            let Some(rule) = limits.rule_for(req.method(), req.uri().path()) else {
+                return inner.call(req).await;
+            };
+            // 不同规则的额度互不影响
+            let key = format!("{}:{}", rule.id, limits.client_key(&req));
+            let decision = match rule.limiter.try_acquire(&key, 1).await {
+                Ok(decision) => decision,
+                Err(err) => {
+                    log::warn!(
+                        "rate limiter {} failed, let request through: {}",
+                        rule.id,
+                        err
+                    );
+                    return inner.call(req).await;
+                }
+            };
+            let mut resp = if decision.allowed {
+                inner.call(req).await?
+            } else {
+                ApiError::TooManyRequests(format!(
+                    "请求太频繁，{} 秒后再试",
+                    ceil_secs(decision.retry_after)
+                ))
+                .into_response()
+            };
+            set_headers(&mut resp, &decision);
+            Ok(resp)
+        })
+    }
+}
+
+#[test]
+fn config_from_yaml() {
+    let config = RateLimitConfig::from_yaml(
+        r#"
+rate_limit:
+  key_by: [api_key, ip]
+  api_keys: { partner-a: k-123 }
+  default: { algorithm: token_bucket, capacity: 100, refill_per_sec: 10 }
+  routes:
+    - id: login
+      path: /auth/login
+      methods: [post]
+      algorithm: sliding_window_log
+      limit: 5
+      window_secs: 60
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.key_by, vec![KeyBy::ApiKey, KeyBy::Ip]);
+    assert_eq!(config.api_key_header, "x-api-key");
+    assert_eq!(config.api_keys["partner-a"], "k-123");
+    assert!(config.store.is_none());
+    assert_eq!(
+        Algorithm::try_from(config.default.unwrap()).unwrap(),
+        Algorithm::TokenBucket {
+            capacity: 100,
+            refill_per_sec: 10.0
+        }
+    );
+    assert_eq!(config.routes[0].methods, vec!["post"]);
+    assert_eq!(
+        Algorithm::try_from(config.routes[0].quota).unwrap(),
+        Algorithm::SlidingWindowLog {
+            limit: 5,
+            window: Duration::from_secs(60)
+        }
+    );
+
+    assert!(
+        RateLimitConfig::from_yaml("rate_limit:\n  default: { algorithm: fixed_window }").is_err()
+    );
+
+    // 窗口是负数或 NaN 时报错，不会 panic
+    for window_secs in [-1.0, f64::NAN, 0.0, f64::INFINITY] {
+        let quota = Quota::SlidingWindowLog {
+            limit: 5,
+            window_secs,
+        };
+        assert!(Algorithm::try_from(quota).is_err(), "{}", window_secs);
+    }
+}
+
+#[cfg(test)]
+async fn hit(
+    app: &axum::Router,
+    method: Method,
+    uri: &str,
+    ip: [u8; 4],
+    headers: &[(&str, String)],
+) -> Response {
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    let mut req = Request::builder().method(method).uri(uri);
+    for (name, value) in headers {
+        req = req.header(*name, value);
+    }
+    let mut req = req.body(Body::empty()).unwrap();
+    req.extensions_mut()
+        .insert(ConnectInfo(SocketAddr::from((ip, 40000))));
+    app.clone().oneshot(req).await.unwrap()
+}
+
+#[tokio::test(start_paused = true)]
+async fn limits_per_client_and_route() {
+    use crate::auth::AuthUser;
+    use axum::http::StatusCode;
+    use axum::routing::{get, post};
+    use http_body_util::BodyExt;
+
+    let keys = AuthKeys::hs256(b"rate-limit-secret");
+    let layer = RateLimitLayer::new()
+        .auth_keys(keys.clone())
+        .api_key("partner-a", "k-123")
+        .route(
+            "login",
+            PathPattern::parse("/auth/login").unwrap(),
+            [Method::POST],
+            Arc::new(
+                LocalRateLimiter::new(Algorithm::SlidingWindowLog {
+                    limit: 2,
+                    window: Duration::from_secs(60),
+                })
+                .unwrap(),
+            ),
+        )
+        .default_limiter(Arc::new(
+            LocalRateLimiter::new(Algorithm::TokenBucket {
+                capacity: 3,
+                refill_per_sec: 1.0,
+            })
+            .unwrap(),
+        ));
+    let app = axum::Router::new()
+        .route("/auth/login", post(|| async { "token" }))
+        .route("/items", get(|| async { "items" }))
+        .layer(layer);
+
+    let a = [10, 0, 0, 1];
+    let b = [10, 0, 0, 2];
+
+    let resp = hit(&app, Method::POST, "/auth/login", a, &[]).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["ratelimit-limit"], "2");
+    assert_eq!(resp.headers()["ratelimit-remaining"], "1");
+    assert_eq!(resp.headers()["ratelimit-reset"], "60");
+    assert!(resp.headers().get("retry-after").is_none());
+    hit(&app, Method::POST, "/auth/login", a, &[]).await;
+
+    let resp = hit(&app, Method::POST, "/auth/login", a, &[]).await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(resp.headers()["retry-after"], "60");
+    assert_eq!(resp.headers()["ratelimit-remaining"], "0");
+    let body = resp.into_body().collect().await.unwrap().to_bytes();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["code"], "rate_limited");
+
+    // 换个 IP、换个路由，都是各自的额度
+    assert_eq!(
+        hit(&app, Method::POST, "/auth/login", b, &[])
+            .await
+            .status(),
+        StatusCode::OK
+    );
+    let resp = hit(&app, Method::GET, "/items", a, &[]).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["ratelimit-limit"], "3");
+
+    // 同一个 API key 从不同 IP 来，共享额度
+    let api_key = [("x-api-key", "k-123".to_string())];
+    for ip in [a, b, a] {
+        assert_eq!(
+            hit(&app, Method::GET, "/items", ip, &api_key)
+                .await
+                .status(),
+            StatusCode::OK
+        );
+    }
+    assert_eq!(
+        hit(&app, Method::GET, "/items", b, &api_key).await.status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    // 没登记过的 key 不算数，还是按 IP(a 已经用掉 1 个)，每次换一个 key 也拿不到新额度
+    let mut statuses = Vec::new();
+    for i in 0..3 {
+        let forged = [("x-api-key", format!("forged-{}", i))];
+        statuses.push(hit(&app, Method::GET, "/items", a, &forged).await.status());
+    }
+    assert_eq!(
+        statuses,
+        [
+            StatusCode::OK,
+            StatusCode::OK,
+            StatusCode::TOO_MANY_REQUESTS
+        ]
+    );
+
+    // 登录用户按用户 id，优先于 API key；token 无效就退回到 API key
+    let token = keys
+        .issue(&AuthUser::new(42, "bob", vec![]), TokenType::Access)
+        .unwrap();
+    let as_user = [
+        ("authorization", format!("Bearer {}", token)),
+        ("x-api-key", "k-123".to_string()),
+    ];
+    assert_eq!(
+        hit(&app, Method::GET, "/items", a, &as_user).await.status(),
+        StatusCode::OK
+    );
+    let bogus = [
+        ("authorization", "Bearer nope".to_string()),
+        ("x-api-key", "k-123".to_string()),
+    ];
+    assert_eq!(
+        hit(&app, Method::GET, "/items", a, &bogus).await.status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    // 令牌桶每秒补一个
+    tokio::time::advance(Duration::from_secs(1)).await;
+    assert_eq!(
+        hit(&app, Method::GET, "/items", b, &api_key).await.status(),
+        StatusCode::OK
+    );
+}
+
+#[tokio::test]
+async fn resp_store_shares_quota_between_instances() {
+    use axum::http::StatusCode;
+    use axum::routing::get;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(dep_async::resp_server::serve(listener));
+
+    let yaml = format!(
+        "rate_limit:\n  key_by: [ip]\n  store: {}\n  default: {{ algorithm: token_bucket, capacity: 3, refill_per_sec: 0.001 }}\n",
+        addr
+    );
+    let config = RateLimitConfig::from_yaml(&yaml).unwrap();
+    // 两个实例各自连到同一个服务端
+    let mut apps = Vec::new();
+    for _ in 0..2 {
+        let layer = RateLimitLayer::from_config(&config, None).await.unwrap();
+        apps.push(
+            axum::Router::new()
+                .route("/", get(|| async { "ok" }))
+                .layer(layer),
+        );
+    }
+
+    let mut statuses = Vec::new();
+    for i in 0..5 {
+        let resp = hit(&apps[i % 2], Method::GET, "/", [10, 0, 0, 9], &[]).await;
+        statuses.push(resp.status());
+    }
+    assert_eq!(
+        statuses,
+        vec![
+            StatusCode::OK,
+            StatusCode::OK,
+            StatusCode::OK,
+            StatusCode::TOO_MANY_REQUESTS,
+            StatusCode::TOO_MANY_REQUESTS
+        ]
+    );
+
+    // 连不上存储时启动就报错，不会悄悄退化成不限流
+    let bad = RateLimitConfig::from_yaml(
+        "rate_limit:\n  store: 127.0.0.1:1\n  default: { algorithm: token_bucket, capacity: 1, refill_per_sec: 1 }\n",
+    )
+    .unwrap();
+    assert!(RateLimitLayer::from_config(&bad, None).await.is_err());
+}
@@ -0,0 +1,234 @@
+// 请求 ID 和访问日志
+// 1. 调用方带了 x-request-id 就沿用，没有就生成一个 uuid
+// 2. 放进 task_local，handler 和 ApiError 里随时能拿到，不用一层层传参；
+//    init_logging 装的日志格式会从这里取 ID，请求里打的每一行 log 都自动带上
+// 3. 响应头里原样带回 x-request-id，方便调用方和日志对上
+// 4. 每个请求结束打一行 JSON 访问日志(target 是 access)：方法、路由模板、状态码、耗时、响应字节数
+// 5. 调下游 gRPC 时用 grpc_interceptor，把同一个 ID 放进 metadata，整条调用链能串起来
+
+use axum::extract::{MatchedPath, Request};
+use axum::http::{HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use hyper::body::Body as _;
+use serde::Serialize;
+use std::future::Future;
+use std::io::Write;
+use std::time::Instant;
+use tonic::metadata::MetadataValue;
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+/// 访问日志的 log target，可以用 RUST_LOG=access=off 单独关掉
+pub const ACCESS_LOG_TARGET: &str = "access";
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// 当前请求的 ID，不在请求上下文里(比如测试里直接调用)时返回 None
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// 在请求之外继续用同一个 ID，比如 WebSocket 升级以后 spawn 出去的会话
+pub async fn scope<F: Future>(id: String, f: F) -> F::Output {
+    REQUEST_ID.scope(id, f).await
+}
+
+// 太长或者有奇怪字符的 ID 不要，防止日志注入
+fn incoming(req: &Request) -> Option<String> {
+    let value = req.headers().get(&REQUEST_ID_HEADER)?.to_str().ok()?;
+    let valid = !value.is_empty()
+        && value.len() <= 128
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    valid.then(|| value.to_string())
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccessLog {
+    pub request_id: String,
+    pub method: String,
+    /// 路由模板，比如 /users/{id}；没匹配上任何路由时是原始路径
+    pub path: String,
+    pub status: u16,
+    pub latency_ms: f64,
+    /// 流式响应(SSE、文件下载等)事先不知道长度，是 null
+    pub bytes: Option<u64>,
+}
+
+/// 用法：`Router::new().layer(axum::middleware::from_fn(request_id::layer))`
+pub async fn layer(req: Request, next: Next) -> Response {
+    let id = incoming(&req).unwrap_or_else(|| Uuid::new_v4().to_string());
+    let start = Instant::now();
+    let method = req.method().to_string();
+    let path = match req.extensions().get::<MatchedPath>() {
+        Some(matched) => matched.as_str().to_string(),
+        None => req.uri().path().to_string(),
+    };
+
+    let mut resp = REQUEST_ID.scope(id.clone(), next.run(req)).await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        resp.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+
+    let access = AccessLog {
+        request_id: id,
+        method,
+        path,
+        status: resp.status().as_u16(),
+        latency_ms: start.elapsed().as_secs_f64() * 1000.0,
+        bytes: resp.body().size_hint().exact(),
+    };
+    if let Ok(line) = serde_json::to_string(&access) {
+        log::info!(target: ACCESS_LOG_TARGET, "{}", line);
+    }
+    resp
+}
+
+// 访问日志本身就是 JSON，原样输出；其它日志前面加上时间、级别、模块和请求 ID
+fn format_record(
+    out: &mut impl Write,
+    timestamp: impl std::fmt::Display,
+    record: &log::Record,
+) -> std::io::Result<()> {
+    if record.target() == ACCESS_LOG_TARGET {
+        return writeln!(out, "{}", record.args());
+    }
+    writeln!(
+        out,
+        "[{} {:<5} {}] request_id={} {}",
+        timestamp,
+        record.level(),
+        record.target(),
+        current().as_deref().unwrap_or("-"),
+        record.args()
+    )
+}
+
+/// 服务启动时调一次，级别默认 info，用 RUST_LOG 调
+pub fn init_logging() {
+    let env = env_logger::Env::default().default_filter_or("info");
+    let _ = env_logger::Builder::from_env(env)
+        .format(|buf, record| {
+            let timestamp = buf.timestamp_millis();
+            format_record(buf, timestamp, record)
+        })
+        .try_init();
+}
+
+/// 给 tonic 客户端用，把当前请求的 ID 带到下游(health::GrpcHealthCheck 就是这么用的)：
+/// `UserServiceClient::with_interceptor(channel, request_id::grpc_interceptor)`
+pub fn grpc_interceptor(mut req: tonic::Request<()>) -> Result<tonic::Request<()>, tonic::Status> {
+    if let Some(value) = current().and_then(|id| MetadataValue::try_from(id).ok()) {
+        // gRPC metadata 的 key 和 HTTP 头一样
+        req.metadata_mut().insert("x-request-id", value);
+    }
+    Ok(req)
+}
+
+// 测试里按任务收集日志：记到打日志的那个任务的 capture 里，并发跑的测试互不干扰；
+// 全局 logger 只装一次，别处已经装过了也不 panic
+#[cfg(test)]
+type Captured = std::sync::Arc<std::sync::Mutex<Vec<String>>>;
+
+#[cfg(test)]
+tokio::task_local! {
+    static CAPTURE: Captured;
+}
+
+#[cfg(test)]
+struct CaptureLogger;
+
+#[cfg(test)]
+impl log::Log for CaptureLogger {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        let _ = CAPTURE.try_with(|lines| {
+            let mut line = Vec::new();
+            format_record(&mut line, "-", record).unwrap();
+            lines.lock().unwrap().push(String::from_utf8(line).unwrap());
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(test)]
+async fn capture_logs<F: Future>(f: F) -> (F::Output, Vec<String>) {
+    static INSTALL: std::sync::Once = std::sync::Once::new();
+    INSTALL.call_once(|| {
+        if log::set_logger(&CaptureLogger).is_ok() {
+            log::set_max_level(log::LevelFilter::Info);
+        }
+    });
+    let lines = Captured::default();
+    let output = CAPTURE.scope(lines.clone(), f).await;
+    let lines = lines.lock().unwrap().clone();
+    (output, lines)
+}
+
+#[tokio::test]
+async fn request_id_in_logs_and_access_log() {
+    use axum::Router;
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    let app = Router::new()
+        .route(
+            "/users/{id}",
+            get(|| async {
+                log::info!("loading user");
+                "hello"
+            }),
+        )
+        .layer(axum::middleware::from_fn(layer));
+
+    let req = Request::get("/users/7")
+        .header("x-request-id", "access-1")
+        .body(Body::empty())
+        .unwrap();
+    let (resp, lines) = capture_logs(app.clone().oneshot(req)).await;
+    assert_eq!(resp.unwrap().headers()["x-request-id"], "access-1");
+    assert_eq!(lines.len(), 2, "{:?}", lines);
+    assert!(lines[0].contains("request_id=access-1 loading user"));
+    let access: Value = serde_json::from_str(&lines[1]).unwrap();
+    assert_eq!(access["method"], "GET");
+    assert_eq!(access["path"], "/users/{id}");
+    assert_eq!(access["status"], 200);
+    assert_eq!(access["bytes"], 5);
+    assert!(access["latency_ms"].as_f64().unwrap() >= 0.0);
+
+    // 没带 ID 的生成一个，404 也有访问日志
+    let (resp, lines) =
+        capture_logs(app.oneshot(Request::get("/nope").body(Body::empty()).unwrap())).await;
+    let resp = resp.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let id = resp.headers()["x-request-id"].to_str().unwrap().to_string();
+    assert!(Uuid::parse_str(&id).is_ok());
+    assert_eq!(lines.len(), 1, "{:?}", lines);
+    let access: Value = serde_json::from_str(&lines[0]).unwrap();
+    assert_eq!(access["request_id"], id.as_str());
+    assert_eq!(access["path"], "/nope");
+    assert_eq!(access["status"], 404);
+}
+
+#[tokio::test]
+async fn grpc_interceptor_propagates_request_id() {
+    let req = grpc_interceptor(tonic::Request::new(())).unwrap();
+    assert!(req.metadata().get("x-request-id").is_none());
+
+    let req = scope("grpc-1".to_string(), async {
+        grpc_interceptor(tonic::Request::new(())).unwrap()
+    })
+    .await;
+    assert_eq!(req.metadata().get("x-request-id").unwrap(), "grpc-1");
+}
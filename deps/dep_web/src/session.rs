@@ -0,0 +1,904 @@
+// 基于 Cookie 的服务端会话，给管理页面这种浏览器直接访问、不方便带 Bearer token 的地方用
+// 1. Cookie 里只放会话 ID，默认加密(AES-GCM)，也可以只签名；数据放在 SessionStore 里，
+//    自带进程内、Postgres、RESP 服务端(dep_async::resp_client::KvClient)三种
+// 2. handler 参数写 Session，get / insert 按类型存取；没写过东西的匿名请求不会建会话、也不会下发 Cookie
+// 3. 过期有两种：idle_timeout 内没有请求，或者从创建起超过 absolute_timeout，任何一个到了都作废
+// 4. 登录成功后 cycle_id() 换一个新 ID，防止会话固定攻击；destroy() 删掉会话并让浏览器删 Cookie
+// 5. POST /session/login 是给登录表单用的，成功后 303 跳到 next；页面 handler 用 SessionUser 拿当前用户
+
+use crate::auth::{AuthUser, authenticate};
+use crate::error::{ApiError, ErrorBody};
+use crate::state::AppState;
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use async_trait::async_trait;
+use axum::extract::{FromRequestParts, Request, State};
+use axum::http::header::{COOKIE, LOCATION, SET_COOKIE};
+use axum::http::request::Parts;
+use axum::http::{HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Form, Json, Router};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use bytes::Bytes;
+use cookie::{Cookie, CookieJar, Key, SameSite};
+use dep_async::resp_client::KvClient;
+use dep_serialization::ApiResponse;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tower::{Layer, Service};
+use utoipa::ToSchema;
+
+pub const DEFAULT_COOKIE_NAME: &str = "sid";
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+pub const DEFAULT_ABSOLUTE_TIMEOUT: Duration = Duration::from_secs(12 * 60 * 60);
+
+// 登录用户在会话里的键
+pub(crate) const USER_KEY: &str = "user";
+
+/// 存到 SessionStore 里的一条会话，时间都是 Unix 毫秒
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub data: Map<String, Value>,
+    pub created_at: u64,
+    pub last_seen: u64,
+}
+
+/// save 的 ttl 是这条会话最多还能活多久，存储可以用它自动清理
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    async fn load(&self, id: &str) -> anyhow::Result<Option<Record>>;
+
+    async fn save(&self, id: &str, record: &Record, ttl: Duration) -> anyhow::Result<()>;
+
+    async fn delete(&self, id: &str) -> anyhow::Result<()>;
+}
+
+/// 进程内，重启就没了，多实例部署时不能用
+#[derive(Default)]
+pub struct MemoryStore {
+    sessions: Mutex<HashMap<String, (Record, std::time::Instant)>>,
+}
+
+#[async_trait]
+impl SessionStore for MemoryStore {
+    async fn load(&self, id: &str) -> anyhow::Result<Option<Record>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.get(id) {
+            Some((_, deadline)) if *deadline <= std::time::Instant::now() => {
+                sessions.remove(id);
+                Ok(None)
+            }
+            Some((record, _)) => Ok(Some(record.clone())),
+            None => Ok(None),
+        }
+    }
+
+    async fn save(&self, id: &str, record: &Record, ttl: Duration) -> anyhow::Result<()> {
+        let deadline = std::time::Instant::now() + ttl;
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.insert(id.to_string(), (record.clone(), deadline));
+        Ok(())
+    }
+
+    async fn delete(&self, id: &str) -> anyhow::Result<()> {
+        self.sessions.lock().unwrap().remove(id);
+        Ok(())
+    }
+}
+
+/// sessions 表，建表语句在 state::init_schema；过期的行靠 delete_expired 定期清理
+pub struct PgStore(pub PgPool);
+
+impl PgStore {
+    pub async fn delete_expired(&self) -> anyhow::Result<u64> {
+        let result = sqlx::query("DELETE FROM sessions WHERE expires_at <= now()")
+            .execute(&self.0)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl SessionStore for PgStore {
+    async fn load(&self, id: &str) -> anyhow::Result<Option<Record>> {
+        let data: Option<String> = sqlx::query_scalar(
+            "SELECT data::text FROM sessions WHERE id = $1 AND expires_at > now()",
+        )
+        .bind(id)
+        .fetch_optional(&self.0)
+        .await?;
+        Ok(data.map(|data| serde_json::from_str(&data)).transpose()?)
+    }
+
+    async fn save(&self, id: &str, record: &Record, ttl: Duration) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO sessions (id, data, expires_at)
+            VALUES ($1, $2::jsonb, now() + make_interval(secs => $3))
+            ON CONFLICT (id) DO UPDATE SET data = EXCLUDED.data, expires_at = EXCLUDED.expires_at
+            "#,
+        )
+        .bind(id)
+        .bind(serde_json::to_string(record)?)
+        .bind(ttl.as_secs_f64())
+        .execute(&self.0)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, id: &str) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM sessions WHERE id = $1")
+            .bind(id)
+            .execute(&self.0)
+            .await?;
+        Ok(())
+    }
+}
+
+/// 存在 dep_async 的 key-value 服务端上，键是 session:<id>，过期交给服务端
+pub struct RespStore {
+    client: KvClient,
+}
+
+impl RespStore {
+    pub async fn connect(addr: &str) -> anyhow::Result<Self> {
+        let client = KvClient::connect(addr)
+            .await
+            .map_err(|e| anyhow::anyhow!("connect {}: {}", addr, e))?;
+        Ok(RespStore { client })
+    }
+
+    fn key(id: &str) -> String {
+        format!("session:{}", id)
+    }
+}
+
+#[async_trait]
+impl SessionStore for RespStore {
+    async fn load(&self, id: &str) -> anyhow::Result<Option<Record>> {
+        let value = self
+            .client
+            .get(&Self::key(id))
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        Ok(value.map(|v| serde_json::from_slice(&v)).transpose()?)
+    }
+
+    async fn save(&self, id: &str, record: &Record, ttl: Duration) -> anyhow::Result<()> {
+        let value = Bytes::from(serde_json::to_vec(record)?);
+        self.client
+            .set(&Self::key(id), value, Some(ttl))
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))
+    }
+
+    async fn delete(&self, id: &str) -> anyhow::Result<()> {
+        self.client
+            .del(&Self::key(id))
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        Ok(())
+    }
+}
+
+/// 从配置里的密钥派生 Cookie 加密/签名用的 Key，密钥至少 32 字节
+pub fn key_from_secret(secret: &[u8]) -> Key {
+    Key::derive_from(secret)
+}
+
+#[derive(Clone)]
+struct SessionConfig {
+    store: Arc<dyn SessionStore>,
+    key: Key,
+    cookie_name: String,
+    idle_timeout: Duration,
+    absolute_timeout: Duration,
+    secure: bool,
+    encrypt: bool,
+}
+
+/// 用法：`.layer(SessionLayer::new(PgStore(pool), key).idle_timeout(..))`
+#[derive(Clone)]
+pub struct SessionLayer {
+    config: Arc<SessionConfig>,
+}
+
+impl Default for SessionLayer {
+    /// 进程内存储 + 随机密钥，重启后所有会话失效，开发和测试够用
+    fn default() -> Self {
+        Self::new(MemoryStore::default(), Key::generate())
+    }
+}
+
+impl SessionLayer {
+    pub fn new(store: impl SessionStore + 'static, key: Key) -> Self {
+        SessionLayer {
+            config: Arc::new(SessionConfig {
+                store: Arc::new(store),
+                key,
+                cookie_name: DEFAULT_COOKIE_NAME.to_string(),
+                idle_timeout: DEFAULT_IDLE_TIMEOUT,
+                absolute_timeout: DEFAULT_ABSOLUTE_TIMEOUT,
+                secure: true,
+                encrypt: true,
+            }),
+        }
+    }
+
+    fn update(mut self, f: impl FnOnce(&mut SessionConfig)) -> Self {
+        let mut config = Arc::unwrap_or_clone(self.config);
+        f(&mut config);
+        self.config = Arc::new(config);
+        self
+    }
+
+    pub fn cookie_name(self, name: impl Into<String>) -> Self {
+        self.update(|c| c.cookie_name = name.into())
+    }
+
+    pub fn idle_timeout(self, timeout: Duration) -> Self {
+        self.update(|c| c.idle_timeout = timeout)
+    }
+
+    pub fn absolute_timeout(self, timeout: Duration) -> Self {
+        self.update(|c| c.absolute_timeout = timeout)
+    }
+
+    /// 本地用 http 调试时关掉 Secure，否则浏览器不会带上 Cookie
+    pub fn secure(self, secure: bool) -> Self {
+        self.update(|c| c.secure = secure)
+    }
+
+    /// Cookie 只签名不加密，会话 ID 本身是随机数，看到了也没关系
+    pub fn signed_only(self) -> Self {
+        self.update(|c| c.encrypt = false)
+    }
+}
+
+impl SessionConfig {
+    fn session_id(&self, parts: &Parts) -> Option<String> {
+        let mut jar = CookieJar::new();
+        for header in parts.headers.get_all(COOKIE) {
+            let Ok(header) = header.to_str() else {
+                continue;
+            };
+            for cookie in Cookie::split_parse(header.to_string()).flatten() {
+                jar.add_original(cookie);
+            }
+        }
+        // 被篡改或者用别的密钥签出来的 Cookie 在这里就拿不到了
+        let cookie = if self.encrypt {
+            jar.private(&self.key).get(&self.cookie_name)
+        } else {
+            jar.signed(&self.key).get(&self.cookie_name)
+        };
+        cookie.map(|c| c.value().to_string())
+    }
+
+    fn set_cookie(&self, id: Option<&str>) -> HeaderValue {
+        let mut cookie =
+            Cookie::build((self.cookie_name.clone(), id.unwrap_or_default().to_string()))
+                .path("/")
+                .http_only(true)
+                .secure(self.secure)
+                .same_site(SameSite::Lax)
+                .build();
+        let mut jar = CookieJar::new();
+        match id {
+            Some(_) if self.encrypt => jar.private_mut(&self.key).add(cookie),
+            Some(_) => jar.signed_mut(&self.key).add(cookie),
+            None => {
+                cookie.make_removal();
+                jar.add(cookie);
+            }
+        }
+        let cookie = jar.get(&self.cookie_name).unwrap();
+        HeaderValue::from_str(&cookie.to_string()).unwrap()
+    }
+
+    fn expired(&self, record: &Record, now: u64) -> bool {
+        let idle = now.saturating_sub(record.last_seen);
+        let age = now.saturating_sub(record.created_at);
+        idle >= self.idle_timeout.as_millis() as u64
+            || age >= self.absolute_timeout.as_millis() as u64
+    }
+
+    async fn load(&self, id: Option<String>) -> (Option<String>, Record) {
+        let Some(id) = id else {
+            return (None, Record::default());
+        };
+        match self.store.load(&id).await {
+            Ok(Some(record)) if !self.expired(&record, now_ms()) => (Some(id), record),
+            Ok(Some(_)) => {
+                if let Err(e) = self.store.delete(&id).await {
+                    log::warn!("delete expired session failed: {:#}", e);
+                }
+                (None, Record::default())
+            }
+            Ok(None) => (None, Record::default()),
+            // 存储出问题时当成没登录，不要让整个请求失败
+            Err(e) => {
+                log::warn!("load session failed: {:#}", e);
+                (None, Record::default())
+            }
+        }
+    }
+
+    async fn commit(&self, state: Inner) -> anyhow::Result<Option<HeaderValue>> {
+        let Inner {
+            id,
+            mut record,
+            rotate,
+            destroy,
+        } = state;
+        if destroy {
+            if let Some(id) = id {
+                self.store.delete(&id).await?;
+                return Ok(Some(self.set_cookie(None)));
+            }
+            return Ok(None);
+        }
+        // 匿名访问又什么都没存，不建会话
+        if id.is_none() && record.data.is_empty() {
+            return Ok(None);
+        }
+        let now = now_ms();
+        if id.is_none() {
+            record.created_at = now;
+        }
+        record.last_seen = now;
+        let age = Duration::from_millis(now.saturating_sub(record.created_at));
+        let ttl = self
+            .idle_timeout
+            .min(self.absolute_timeout.saturating_sub(age));
+
+        let new_id = match &id {
+            Some(old) if rotate => {
+                self.store.delete(old).await?;
+                Some(new_session_id())
+            }
+            Some(_) => None,
+            None => Some(new_session_id()),
+        };
+        let current = new_id.as_deref().or(id.as_deref()).unwrap();
+        self.store.save(current, &record, ttl).await?;
+        // ID 没变的话浏览器里的 Cookie 还能用，不用重新下发
+        Ok(new_id.map(|id| self.set_cookie(Some(&id))))
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+// 32 字节随机数
+fn new_session_id() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+struct Inner {
+    id: Option<String>,
+    record: Record,
+    rotate: bool,
+    destroy: bool,
+}
+
+/// 当前请求的会话，需要路由上挂了 SessionLayer
+#[derive(Clone)]
+pub struct Session(Arc<Mutex<Inner>>);
+
+impl Session {
+    /// 没有这个键，或者存的值不是这个类型，都返回 None
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let inner = self.0.lock().unwrap();
+        let value = inner.record.data.get(key)?;
+        serde_json::from_value(value.clone()).ok()
+    }
+
+    /// 值要能序列化成 JSON，比如 map 的键必须是字符串
+    pub fn insert<T: Serialize>(&self, key: &str, value: T) {
+        let value = serde_json::to_value(value).expect("session value serializes to JSON");
+        let mut inner = self.0.lock().unwrap();
+        inner.record.data.insert(key.to_string(), value);
+    }
+
+    pub fn remove(&self, key: &str) -> Option<Value> {
+        self.0.lock().unwrap().record.data.remove(key)
+    }
+
+    /// 数据保留，换一个新的会话 ID，旧 ID 立刻失效；权限变化(登录、提权)后调用
+    pub fn cycle_id(&self) {
+        self.0.lock().unwrap().rotate = true;
+    }
+
+    /// 删除会话，响应里让浏览器删掉 Cookie
+    pub fn destroy(&self) {
+        let mut inner = self.0.lock().unwrap();
+        inner.destroy = true;
+        inner.record.data.clear();
+    }
+
+    /// 通过 /session/login 登录的用户，没登录是 None
+    pub fn user(&self) -> Option<AuthUser> {
+        let info: SessionUserInfo = self.get(USER_KEY)?;
+        Some(AuthUser::new(info.id, info.name, info.roles))
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Session {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Session>()
+            .cloned()
+            .ok_or_else(|| ApiError::Internal(anyhow::anyhow!("SessionLayer is not installed")))
+    }
+}
+
+impl<S> Layer<S> for SessionLayer {
+    type Service = SessionService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SessionService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SessionService<S> {
+    inner: S,
+    layer: SessionLayer,
+}
+
+impl<S> Service<Request> for SessionService<S>
+where
+    S: Service<Request, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let config = self.layer.config.clone();
+        // poll_ready 过的是 self.inner，要把它换出来用，留一个新 clone 给下次
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            let (mut parts, body) = req.into_parts();
+            let (id, record) = config.load(config.session_id(&parts)).await;
+            let session = Session(Arc::new(Mutex::new(Inner {
+                id,
+                record,
+                rotate: false,
+                destroy: false,
+            })));
+            parts.extensions.insert(session.clone());
+            let mut response = inner.call(Request::from_parts(parts, body)).await?;
+
+            // handler 可能把 Session clone 到别的任务里去了，这里只拿当前的快照
+            let state = {
+                let inner = session.0.lock().unwrap();
+                Inner {
+                    id: inner.id.clone(),
+                    record: inner.record.clone(),
+                    rotate: inner.rotate,
+                    destroy: inner.destroy,
+                }
+            };
+            match config.commit(state).await {
+                Ok(Some(cookie)) => {
+                    response.headers_mut().append(SET_COOKIE, cookie);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    log::error!("save session failed: {:#}", e);
+                    return Ok(ApiError::Internal(e).into_response());
+                }
+            }
+            Ok(response)
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SessionUserInfo {
+    pub id: i32,
+    pub name: String,
+    pub roles: Vec<String>,
+}
+
+/// 通过 /session/login 登录的用户，没登录是 401
+pub struct SessionUser(pub AuthUser);
+
+impl SessionUser {
+    /// 有其中任意一个角色就行，和 AuthLayer::require_roles 一样
+    pub fn require_roles(&self, roles: &[&str]) -> Result<(), ApiError> {
+        if roles.iter().any(|r| self.0.has_role(r)) {
+            return Ok(());
+        }
+        Err(ApiError::Forbidden(format!(
+            "需要角色 {}",
+            roles.join(" 或 ")
+        )))
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for SessionUser {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let session = Session::from_request_parts(parts, state).await?;
+        let user = session
+            .user()
+            .ok_or_else(|| ApiError::Unauthorized("需要登录".to_string()))?;
+        Ok(SessionUser(user))
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct LoginForm {
+    pub name: String,
+    pub password: String,
+    /// 登录后跳转的站内路径，默认 /
+    pub next: Option<String>,
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/session/login", post(login))
+        .route("/session/logout", post(logout))
+        .route("/session/me", get(me))
+}
+
+// 只允许站内路径，防止被拿来做开放重定向
+fn safe_next(next: Option<&str>) -> &str {
+    match next {
+        Some(next) if next.starts_with('/') && !next.starts_with("//") && !next.contains('\\') => {
+            next
+        }
+        _ => "/",
+    }
+}
+
+fn see_other(location: &str) -> Response {
+    (StatusCode::SEE_OTHER, [(LOCATION, location.to_string())]).into_response()
+}
+
+#[utoipa::path(
+    post,
+    path = "/session/login",
+    tag = "session",
+    request_body(content = LoginForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 303, description = "登录成功，Set-Cookie 下发会话，跳转到 next"),
+        (status = 401, description = "用户名或密码错误", body = ErrorBody),
+    )
+)]
+async fn login(
+    State(state): State<AppState>,
+    session: Session,
+    Form(form): Form<LoginForm>,
+) -> Result<Response, ApiError> {
+    let user = authenticate(&state.pool, &form.name, form.password).await?;
+    session.cycle_id();
+    session.insert(
+        USER_KEY,
+        SessionUserInfo {
+            id: user.id,
+            name: user.name,
+            roles: user.roles,
+        },
+    );
+    Ok(see_other(safe_next(form.next.as_deref())))
+}
+
+#[utoipa::path(
+    post,
+    path = "/session/logout",
+    tag = "session",
+    responses((status = 303, description = "会话已删除，跳转到 /"))
+)]
+async fn logout(session: Session) -> Response {
+    session.destroy();
+    see_other("/")
+}
+
+#[utoipa::path(
+    get,
+    path = "/session/me",
+    tag = "session",
+    responses(
+        (status = 200, description = "当前会话的用户", body = ApiResponse<SessionUserInfo>),
+        (status = 401, description = "没登录或会话已过期", body = ErrorBody),
+    )
+)]
+async fn me(SessionUser(user): SessionUser) -> Json<ApiResponse<SessionUserInfo>> {
+    Json(ApiResponse::success(SessionUserInfo {
+        id: user.id,
+        name: user.name,
+        roles: user.roles,
+    }))
+}
+
+#[cfg(test)]
+fn cookie_of(response: &Response) -> Option<String> {
+    let header = response.headers().get(SET_COOKIE)?.to_str().unwrap();
+    Some(header.split(';').next().unwrap().to_string())
+}
+
+#[tokio::test]
+async fn session_cookie_lifecycle() {
+    use axum::body::Body;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    let store = Arc::new(MemoryStore::default());
+    struct Shared(Arc<MemoryStore>);
+    #[async_trait]
+    impl SessionStore for Shared {
+        async fn load(&self, id: &str) -> anyhow::Result<Option<Record>> {
+            self.0.load(id).await
+        }
+        async fn save(&self, id: &str, record: &Record, ttl: Duration) -> anyhow::Result<()> {
+            self.0.save(id, record, ttl).await
+        }
+        async fn delete(&self, id: &str) -> anyhow::Result<()> {
+            self.0.delete(id).await
+        }
+    }
+    let layer = SessionLayer::new(Shared(store.clone()), Key::generate())
+        .idle_timeout(Duration::from_millis(300))
+        .absolute_timeout(Duration::from_millis(700));
+    let app =
+        Router::new()
+            .route("/", get(|| async { "anonymous" }))
+            .route(
+                "/count",
+                post(|session: Session| async move {
+                    let count = session.get::<u32>("count").unwrap_or(0) + 1;
+                    session.insert("count", count);
+                    count.to_string()
+                }),
+            )
+            .route(
+                "/count",
+                get(|session: Session| async move {
+                    session.get::<u32>("count").unwrap_or(0).to_string()
+                }),
+            )
+            .route(
+                "/rotate",
+                post(|session: Session| async move { session.cycle_id() }),
+            )
+            .route(
+                "/logout",
+                post(|session: Session| async move { session.destroy() }),
+            )
+            .layer(layer);
+    let call = |method: &'static str, uri: &'static str, cookie: Option<String>| {
+        let app = app.clone();
+        async move {
+            let mut req = Request::builder().method(method).uri(uri);
+            if let Some(cookie) = cookie {
+                req = req.header(COOKIE, cookie);
+            }
+            let response = app.oneshot(req.body(Body::empty()).unwrap()).await.unwrap();
+            let cookie = cookie_of(&response);
+            let header = response
+                .headers()
+                .get(SET_COOKIE)
+                .map(|h| h.to_str().unwrap().to_string());
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (String::from_utf8(body.to_vec()).unwrap(), cookie, header)
+        }
+    };
+
+    // 匿名访问不建会话
+    let (_, cookie, _) = call("GET", "/", None).await;
+    assert!(cookie.is_none());
+    assert!(store.sessions.lock().unwrap().is_empty());
+
+    // 第一次写入时下发 Cookie，值是加密过的，看不出会话 ID
+    let (body, cookie, header) = call("POST", "/count", None).await;
+    assert_eq!(body, "1");
+    let cookie = cookie.unwrap();
+    let header = header.unwrap();
+    assert!(header.contains("HttpOnly"), "{}", header);
+    assert!(header.contains("Secure"), "{}", header);
+    assert!(header.contains("SameSite=Lax"), "{}", header);
+    let id = store
+        .sessions
+        .lock()
+        .unwrap()
+        .keys()
+        .next()
+        .unwrap()
+        .clone();
+    assert!(!cookie.contains(&id));
+    let (body, again, _) = call("POST", "/count", Some(cookie.clone())).await;
+    assert_eq!(body, "2");
+    assert!(again.is_none());
+
+    // 改过的 Cookie 不认
+    let tampered = format!("{}x", cookie);
+    assert_eq!(call("GET", "/count", Some(tampered)).await.0, "0");
+
+    // 换 ID：数据还在，旧 Cookie 作废
+    let (_, rotated, _) = call("POST", "/rotate", Some(cookie.clone())).await;
+    let rotated = rotated.unwrap();
+    assert_ne!(rotated, cookie);
+    assert_eq!(call("GET", "/count", Some(rotated.clone())).await.0, "2");
+    assert_eq!(call("GET", "/count", Some(cookie)).await.0, "0");
+
+    // 空闲超过 idle_timeout 作废
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert_eq!(call("GET", "/count", Some(rotated)).await.0, "0");
+
+    // 一直有访问也撑不过 absolute_timeout
+    let (_, cookie, _) = call("POST", "/count", None).await;
+    let cookie = cookie.unwrap();
+    for _ in 0..3 {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(call("GET", "/count", Some(cookie.clone())).await.0, "1");
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(call("GET", "/count", Some(cookie)).await.0, "0");
+
+    // 退出：删掉会话，让浏览器删 Cookie
+    let (_, cookie, _) = call("POST", "/count", None).await;
+    let cookie = cookie.unwrap();
+    let (_, removal, header) = call("POST", "/logout", Some(cookie.clone())).await;
+    assert_eq!(removal.unwrap(), "sid=");
+    assert!(header.unwrap().contains("Max-Age=0"));
+    assert_eq!(call("GET", "/count", Some(cookie)).await.0, "0");
+}
+
+#[tokio::test]
+async fn session_stores_and_login() {
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    let pool = crate::state::get_pool().await.unwrap();
+    crate::state::init_schema(&pool).await.unwrap();
+
+    // 三种存储行为一致
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(dep_async::resp_server::serve(listener));
+    let stores: Vec<Box<dyn SessionStore>> = vec![
+        Box::new(MemoryStore::default()),
+        Box::new(PgStore(pool.clone())),
+        Box::new(RespStore::connect(&addr).await.unwrap()),
+    ];
+    for store in &stores {
+        let id = new_session_id();
+        let mut record = Record {
+            created_at: 1,
+            last_seen: 2,
+            ..Record::default()
+        };
+        record.data.insert("k".to_string(), Value::from("v"));
+        assert_eq!(store.load(&id).await.unwrap(), None);
+        store
+            .save(&id, &record, Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert_eq!(store.load(&id).await.unwrap(), Some(record.clone()));
+        store.delete(&id).await.unwrap();
+        assert_eq!(store.load(&id).await.unwrap(), None);
+        // 存储自己也按 ttl 过期
+        store
+            .save(&id, &record, Duration::from_millis(50))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(store.load(&id).await.unwrap(), None);
+    }
+    PgStore(pool.clone()).delete_expired().await.unwrap();
+
+    // 表单登录 -> 会话里的用户 -> 退出
+    let name = format!("session-{}", uuid::Uuid::new_v4());
+    let hash = crate::auth::hash_password("s3cret-pass").unwrap();
+    sqlx::query(
+        "INSERT INTO users (name, age, password_hash, roles) VALUES ($1, 30, $2, '{user,admin}')",
+    )
+    .bind(&name)
+    .bind(hash)
+    .execute(&pool)
+    .await
+    .unwrap();
+    let keys = crate::auth::AuthKeys::hs256(b"session-secret");
+    let state =
+        AppState::new(pool.clone(), keys).with_sessions(SessionLayer::default().secure(false));
+    let app = crate::app(state);
+    let send =
+        |method: &'static str, uri: &'static str, cookie: Option<String>, form: Option<String>| {
+            let app = app.clone();
+            async move {
+                let mut req = Request::builder().method(method).uri(uri);
+                if let Some(cookie) = cookie {
+                    req = req.header(COOKIE, cookie);
+                }
+                let body = match form {
+                    Some(form) => {
+                        req = req.header("content-type", "application/x-www-form-urlencoded");
+                        Body::from(form)
+                    }
+                    None => Body::empty(),
+                };
+                app.oneshot(req.body(body).unwrap()).await.unwrap()
+            }
+        };
+
+    let resp = send("GET", "/session/me", None, None).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let form = |password: &str, next: &str| {
+        serde_urlencoded::to_string([
+            ("name", name.as_str()),
+            ("password", password),
+            ("next", next),
+        ])
+        .unwrap()
+    };
+    let resp = send("POST", "/session/login", None, Some(form("wrong", "/"))).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert!(cookie_of(&resp).is_none());
+
+    let resp = send(
+        "POST",
+        "/session/login",
+        None,
+        Some(form("s3cret-pass", "/admin/users")),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+    assert_eq!(resp.headers()[LOCATION], "/admin/users");
+    let cookie = cookie_of(&resp).unwrap();
+    let resp = send("GET", "/session/me", Some(cookie.clone()), None).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // 站外地址不跳
+    let resp = send(
+        "POST",
+        "/session/login",
+        None,
+        Some(form("s3cret-pass", "//evil.example")),
+    )
+    .await;
+    assert_eq!(resp.headers()[LOCATION], "/");
+
+    let resp = send("POST", "/session/logout", Some(cookie.clone()), None).await;
+    assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+    let resp = send("GET", "/session/me", Some(cookie), None).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    sqlx::query("DELETE FROM users WHERE name = $1")
+        .bind(&name)
+        .execute(&pool)
+        .await
+        .unwrap();
+}
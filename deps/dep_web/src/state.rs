@@ -0,0 +1,272 @@
+// 所有 handler 共享的应用状态
+// 1. PgPool 内部已经是 Arc，Clone 很便宜，axum 每个请求都会 clone 一份 State
+// 2. 连接串见 config::DatabaseConfig，本地开发写在 .env 的 APP__DATABASE__URL 里
+// 3. JWT 密钥也放这里，登录接口签发 token、AuthLayer 校验 token 用的是同一份
+// 4. 限流器要在 app() 组装路由时挂上去，默认不限流
+// 5. Hub 是 /ws、/sse 共用的频道，默认进程内
+// 6. 模板引擎给 template::Views 用，没配的话只能返回 JSON
+// 7. 上传文件的目录和大小限制，见 files::UploadConfig
+// 8. 前端静态文件，配了才挂到路由最后
+// 9. 就绪检查要跑的依赖检查和停机标记，见 health::Health
+// 10. Cookie 会话，默认存在进程内，见 session::SessionLayer
+// 11. Idempotency-Key 的响应记录，默认存在进程内，见 idempotency::IdempotencyLayer
+// 12. GET 响应缓存，写接口通过它按标签作废，见 cache::CacheLayer
+// 13. 往外发的 webhook，默认没有端点，入队什么都不做，见 webhooks::Webhooks
+// 14. 业务错误的 err_code 前缀对应的状态码，默认是空表(一律 500)，见 error::ErrCodes
+
+use crate::assets::Assets;
+use crate::auth::AuthKeys;
+use crate::cache::CacheLayer;
+use crate::config::{DatabaseConfig, WebConfig};
+use crate::error::ErrCodes;
+use crate::files::UploadConfig;
+use crate::health::Health;
+use crate::idempotency::IdempotencyLayer;
+use crate::pubsub::Hub;
+use crate::rate_limit::RateLimitLayer;
+use crate::session::SessionLayer;
+use crate::webhooks::Webhooks;
+use dep_template::TemplateEngine;
+use sqlx::PgPool;
+use sqlx::postgres::PgPoolOptions;
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct AppState {
+    pub pool: PgPool,
+    pub auth: AuthKeys,
+    pub rate_limit: RateLimitLayer,
+    pub hub: Hub,
+    pub templates: Option<Arc<dyn TemplateEngine>>,
+    pub uploads: UploadConfig,
+    pub assets: Option<Assets>,
+    pub health: Health,
+    pub sessions: SessionLayer,
+    pub idempotency: IdempotencyLayer,
+    pub cache: CacheLayer,
+    pub webhooks: Webhooks,
+    pub err_codes: ErrCodes,
+}
+
+impl AppState {
+    pub fn new(pool: PgPool, auth: AuthKeys) -> Self {
+        AppState {
+            pool: pool.clone(),
+            auth: auth.clone(),
+            rate_limit: RateLimitLayer::new(),
+            hub: Hub::local(),
+            templates: None,
+            uploads: UploadConfig::default(),
+            assets: None,
+            health: Health::new(),
+            sessions: SessionLayer::default(),
+            idempotency: IdempotencyLayer::default().auth_keys(auth),
+            cache: CacheLayer::default(),
+            webhooks: Webhooks::new(pool),
+            err_codes: ErrCodes::default(),
+        }
+    }
+
+    pub fn with_rate_limit(mut self, rate_limit: RateLimitLayer) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    pub fn with_hub(mut self, hub: Hub) -> Self {
+        self.hub = hub;
+        self
+    }
+
+    pub fn with_templates(mut self, templates: Arc<dyn TemplateEngine>) -> Self {
+        self.templates = Some(templates);
+        self
+    }
+
+    pub fn with_uploads(mut self, uploads: UploadConfig) -> Self {
+        self.uploads = uploads;
+        self
+    }
+
+    pub fn with_assets(mut self, assets: Assets) -> Self {
+        self.assets = Some(assets);
+        self
+    }
+
+    pub fn with_health(mut self, health: Health) -> Self {
+        self.health = health;
+        self
+    }
+
+    pub fn with_sessions(mut self, sessions: SessionLayer) -> Self {
+        self.sessions = sessions;
+        self
+    }
+
+    pub fn with_idempotency(mut self, idempotency: IdempotencyLayer) -> Self {
+        self.idempotency = idempotency;
+        self
+    }
+
+    pub fn with_cache(mut self, cache: CacheLayer) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    pub fn with_webhooks(mut self, webhooks: Webhooks) -> Self {
+        self.webhooks = webhooks;
+        self
+    }
+
+    pub fn with_err_codes(mut self, err_codes: ErrCodes) -> Self {
+        self.err_codes = err_codes;
+        self
+    }
+}
+
+pub async fn connect_pool(config: &DatabaseConfig) -> Result<PgPool, sqlx::Error> {
+    PgPoolOptions::new()
+        .max_connections(config.max_connections)
+        .connect(config.url.expose())
+        .await
+}
+
+// 测试用，只按 .env 和环境变量里的配置连
+pub async fn get_pool() -> Result<PgPool, sqlx::Error> {
+    let config = WebConfig::from_env().map_err(|e| sqlx::Error::Configuration(e.into()))?;
+    connect_pool(&config.database).await
+}
+
+// 和 dep_orm_sqlx/migrations 里的建表语句保持一致，单独跑 dep_web 时也能用
+pub async fn init_schema(pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS users
+        (
+            id   SERIAL PRIMARY KEY,
+            name TEXT NOT NULL,
+            age  INT  NOT NULL
+        );
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    // 用户名唯一靠数据库保证，先查再插并发时会插进两个同名的；冲突由 ApiError 转成 409
+    sqlx::query("CREATE UNIQUE INDEX IF NOT EXISTS users_name_key ON users (name)")
+        .execute(pool)
+        .await?;
+    // 登录用的列，老库上也能补上；没设置密码的用户不能登录
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS password_hash TEXT")
+        .execute(pool)
+        .await?;
+    sqlx::query(
+        "ALTER TABLE users ADD COLUMN IF NOT EXISTS roles TEXT[] NOT NULL DEFAULT '{user}'",
+    )
+    .execute(pool)
+    .await?;
+    // auth::refresh 轮换用，每个签发过的 refresh token 一行，用户删了跟着删
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS refresh_tokens
+        (
+            jti        TEXT PRIMARY KEY,
+            family     TEXT        NOT NULL,
+            user_id    INT         NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+            expires_at TIMESTAMPTZ NOT NULL,
+            used_at    TIMESTAMPTZ,
+            revoked    BOOLEAN     NOT NULL DEFAULT false
+        );
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS refresh_tokens_family ON refresh_tokens (family)")
+        .execute(pool)
+        .await?;
+    // 上传文件的元数据，文件本身在 UploadConfig::dir 下，文件名就是 id
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS files
+        (
+            id           TEXT PRIMARY KEY,
+            filename     TEXT        NOT NULL,
+            content_type TEXT        NOT NULL,
+            size         BIGINT      NOT NULL,
+            sha256       TEXT        NOT NULL,
+            uploaded_by  INT,
+            created_at   TIMESTAMPTZ NOT NULL DEFAULT now()
+        );
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    // session::PgStore 用，过期的行由 PgStore::delete_expired 清理
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS sessions
+        (
+            id         TEXT PRIMARY KEY,
+            data       JSONB       NOT NULL,
+            expires_at TIMESTAMPTZ NOT NULL
+        );
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    // idempotency::PgStore 用，status 为空表示还在执行
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS idempotency_keys
+        (
+            key         TEXT PRIMARY KEY,
+            fingerprint TEXT        NOT NULL,
+            status      INT,
+            headers     JSONB,
+            body        BYTEA,
+            expires_at  TIMESTAMPTZ NOT NULL
+        );
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    // webhooks 用：待投递的和重试够了进死信的，死信重放时带着原来的 id 回到投递表
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS webhook_deliveries
+        (
+            id              BIGSERIAL PRIMARY KEY,
+            event           TEXT        NOT NULL,
+            endpoint        TEXT        NOT NULL,
+            payload         JSONB       NOT NULL,
+            attempts        INT         NOT NULL DEFAULT 0,
+            last_error      TEXT,
+            next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+            created_at      TIMESTAMPTZ NOT NULL DEFAULT now()
+        );
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS webhook_deliveries_due ON webhook_deliveries (next_attempt_at)",
+    )
+    .execute(pool)
+    .await?;
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS webhook_dead_letters
+        (
+            id         BIGINT PRIMARY KEY,
+            event      TEXT        NOT NULL,
+            endpoint   TEXT        NOT NULL,
+            payload    JSONB       NOT NULL,
+            attempts   INT         NOT NULL,
+            last_error TEXT        NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL,
+            failed_at  TIMESTAMPTZ NOT NULL DEFAULT now()
+        );
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,253 @@
+// 服务端渲染 HTML：处理函数返回 Template，按 Accept 头决定渲染成页面还是返回 JSON
+// 1. 引擎是 dep_template::TemplateEngine，Handlebars / Tera / MiniJinja 都行，用 AppState::with_templates 配进来
+// 2. 处理函数加一个 Views 参数，返回 views.render("users.html", data)：
+//    浏览器(Accept 里 text/html 优先)拿到渲染好的页面，Accept: application/json 拿到 ApiResponse 包着的同一份数据
+// 3. 转义交给引擎，.html 模板里的 {{ }} 都会转义
+// 4. 模板渲染失败是服务端的问题，按 ApiError::Internal 返回 500，细节只打日志
+
+use crate::error::ApiError;
+use crate::state::AppState;
+use axum::Json;
+use axum::extract::FromRequestParts;
+use axum::http::header::{ACCEPT, VARY};
+use axum::http::request::Parts;
+use axum::http::{HeaderValue, header};
+use axum::response::{Html, IntoResponse, Response};
+use dep_serialization::ApiResponse;
+use dep_template::TemplateEngine;
+use serde::Serialize;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Html,
+    Json,
+}
+
+// 某个具体类型在 Accept 里的 (具体程度, q 值)，q 没写就是 1；按最具体的那一项算
+// (text/html 先于 text/*，再是 */*)，所以 text/html;q=0, */* 是明确不要 HTML
+fn quality(accept: &str, media_type: &str) -> Option<(u8, f32)> {
+    let main = media_type.split('/').next().unwrap_or_default();
+    let mut best: Option<(u8, f32)> = None;
+    for item in accept.split(',') {
+        let mut parts = item.split(';');
+        let range = parts.next().unwrap_or_default().trim();
+        let specificity = if range.eq_ignore_ascii_case(media_type) {
+            3
+        } else if range
+            .strip_suffix("/*")
+            .is_some_and(|t| t.eq_ignore_ascii_case(main))
+        {
+            2
+        } else if range == "*/*" {
+            1
+        } else {
+            continue;
+        };
+        let q = parts
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if best.is_none_or(|(s, _)| specificity > s) {
+            best = Some((specificity, q));
+        }
+    }
+    best
+}
+
+/// 明确更想要 JSON 才返回 JSON；没有 Accept、*/* 或者两个一样时给 HTML
+pub fn negotiate(accept: Option<&HeaderValue>) -> Format {
+    let Some(accept) = accept.and_then(|v| v.to_str().ok()) else {
+        return Format::Html;
+    };
+    // 两种 HTML 类型里取写得更具体的那个，不然 text/html;q=0, */* 会被 */* 匹配到的 xhtml 盖掉
+    let html = [
+        quality(accept, "text/html"),
+        quality(accept, "application/xhtml+xml"),
+    ]
+    .into_iter()
+    .flatten()
+    .max_by(|a, b| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)))
+    .map_or(0.0, |(_, q)| q);
+    let json = quality(accept, "application/json").map_or(0.0, |(_, q)| q);
+    if json > html {
+        Format::Json
+    } else {
+        Format::Html
+    }
+}
+
+pub struct Views {
+    engine: Option<Arc<dyn TemplateEngine>>,
+    format: Format,
+}
+
+impl FromRequestParts<AppState> for Views {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Views {
+            engine: state.templates.clone(),
+            format: negotiate(parts.headers.get(ACCEPT)),
+        })
+    }
+}
+
+impl Views {
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn render<T: Serialize>(&self, name: impl Into<String>, data: T) -> Template<T> {
+        Template {
+            engine: self.engine.clone(),
+            name: name.into(),
+            data,
+            format: self.format,
+        }
+    }
+}
+
+pub struct Template<T> {
+    engine: Option<Arc<dyn TemplateEngine>>,
+    name: String,
+    data: T,
+    format: Format,
+}
+
+impl<T> Template<T> {
+    /// 不看 Accept，固定用某种格式
+    pub fn with_format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+}
+
+impl<T: Serialize> Template<T> {
+    fn render_html(&self) -> Result<String, ApiError> {
+        let engine = self.engine.as_ref().ok_or_else(|| {
+            ApiError::Internal(anyhow::anyhow!(
+                "template engine not configured, cannot render {}",
+                self.name
+            ))
+        })?;
+        let data = serde_json::to_value(&self.data).map_err(anyhow::Error::from)?;
+        Ok(engine.render(&self.name, &data)?)
+    }
+}
+
+impl<T: Serialize> IntoResponse for Template<T> {
+    fn into_response(self) -> Response {
+        let mut resp = match self.format {
+            Format::Json => Json(ApiResponse::success(self.data)).into_response(),
+            Format::Html => match self.render_html() {
+                Ok(html) => Html(html).into_response(),
+                Err(e) => e.into_response(),
+            },
+        };
+        // 同一个 URL 按 Accept 返回不同内容，缓存要分开存
+        resp.headers_mut()
+            .append(VARY, HeaderValue::from_static("accept"));
+        resp
+    }
+}
+
+#[test]
+fn negotiate_accept_header() {
+    let accept = |v: &'static str| negotiate(Some(&HeaderValue::from_static(v)));
+    assert_eq!(negotiate(None), Format::Html);
+    assert_eq!(accept("*/*"), Format::Html);
+    assert_eq!(
+        accept("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"),
+        Format::Html
+    );
+    assert_eq!(accept("application/json"), Format::Json);
+    assert_eq!(
+        accept("text/html;q=0.5, application/json;q=0.9"),
+        Format::Json
+    );
+    assert_eq!(accept("application/json;q=0.1, */*"), Format::Html);
+    // q=0 是明确拒绝，更宽的 */* 不能把它盖掉
+    assert_eq!(accept("text/html;q=0, */*"), Format::Json);
+    assert_eq!(accept("text/*;q=0, application/json;q=0.2"), Format::Json);
+    assert_eq!(accept("application/json;q=0, */*"), Format::Html);
+    assert_eq!(
+        accept("text/html;q=0.3, text/*;q=0.9, */*;q=0.5"),
+        Format::Json
+    );
+}
+
+#[tokio::test]
+async fn template_renders_html_or_json() {
+    use axum::Router;
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use axum::routing::get;
+    use dep_template::EngineKind;
+    use http_body_util::BodyExt;
+    use serde_json::{Value, json};
+    use tower::ServiceExt;
+
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/templates");
+    let pool = sqlx::PgPool::connect_lazy("postgres://postgres@localhost:1/none").unwrap();
+    let keys = crate::auth::AuthKeys::hs256(b"template-secret");
+    let state = AppState::new(pool, keys);
+    let data = || json!({"users": [{"name": "<script>x</script>", "age": 3}]});
+    let app = |state: AppState| {
+        Router::new()
+            .route(
+                "/admin/users",
+                get(move |views: Views| async move { views.render("admin/users.html", data()) }),
+            )
+            .route(
+                "/broken",
+                get(|views: Views| async move { views.render("missing.html", json!({})) }),
+            )
+            .with_state(state)
+    };
+    let call = |app: Router, uri: &'static str, accept: &'static str| async move {
+        let req = Request::get(uri)
+            .header("accept", accept)
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.oneshot(req).await.unwrap();
+        let status = resp.status();
+        let content_type = resp.headers()["content-type"].to_str().unwrap().to_string();
+        assert_eq!(resp.headers()["vary"], "accept");
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        (
+            status,
+            content_type,
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    };
+
+    // 没配引擎：JSON 照样能用，HTML 是 500
+    let (status, _, _) = call(app(state.clone()), "/admin/users", "application/json").await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _, _) = call(app(state.clone()), "/admin/users", "text/html").await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+
+    let app = app(state.with_templates(EngineKind::MiniJinja.load(dir).unwrap()));
+    let (status, content_type, html) = call(app.clone(), "/admin/users", "text/html").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(content_type.starts_with("text/html"));
+    assert!(html.contains("<title>Users · Admin</title>"), "{}", html);
+    assert!(html.contains("<li>&lt;script&gt;x"), "{}", html);
+    assert!(!html.contains("<script>"));
+
+    // 同一份数据按 Accept 返回 JSON
+    let (status, content_type, body) = call(app.clone(), "/admin/users", "application/json").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(content_type, "application/json");
+    let body: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(body["status"], "success");
+    assert_eq!(body["data"], data());
+
+    let (status, _, body) = call(app, "/broken", "text/html").await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(!body.contains("missing.html"));
+}
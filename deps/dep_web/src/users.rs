@@ -0,0 +1,766 @@
+// users 表的 REST CRUD，表结构和 dep_orm_sqlx 里的 users(id, name, age) 一致
+// POST   /users       创建，201 + 新用户
+// GET    /users       列表，支持过滤/排序/分页，见 ListQuery
+// GET    /users/{id}  查询，不存在 404
+// PUT    /users/{id}  整体更新，不存在 404，需要 admin 角色
+// DELETE /users/{id}  删除，204，不存在 404，需要 admin 角色
+// 请求体校验失败 422，名字重复 409，没登录 401，角色不够 403
+// 注意：加上认证(auth.rs)以前 PUT/DELETE 谁都能调，现在要带 admin 的 access token，老的调用方要先登录
+// 成功的响应统一包在 dep_serialization::ApiResponse 里，列表接口带 meta 分页信息
+// 两个 GET 允许 cache::CacheLayer 缓存，增删改成功后按标签作废
+// 创建成功会发 user.created webhook，见 webhooks::Webhooks
+
+use crate::auth::{AuthKeys, AuthLayer, hash_password};
+use crate::cache::CACHE_TAG;
+use crate::error::{ApiError, ErrorBody, FieldError};
+use crate::pagination::{Page, PageRequest, SortField, parse_sort, push_order_by};
+use crate::state::AppState;
+use crate::validate::{ValidJson, Validate};
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Path, Query, State};
+use axum::http::header::CACHE_CONTROL;
+use axum::http::{HeaderName, StatusCode};
+use axum::routing::{get, put};
+use axum::{Json, Router};
+use dep_serialization::ApiResponse;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{FromRow, Postgres, QueryBuilder};
+use utoipa::{IntoParams, ToSchema};
+
+const NAME_MAX_CHARS: usize = 64;
+const PASSWORD_MIN_CHARS: usize = 8;
+// s-maxage 只对服务端缓存生效，浏览器不缓存，改完马上能看到
+const CACHE_POLICY: &str = "s-maxage=30, stale-while-revalidate=30";
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, PartialEq, Eq, ToSchema)]
+pub struct User {
+    pub id: i32,
+    pub name: String,
+    pub age: i32,
+}
+
+// 创建和更新共用同一个请求体
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct UserInput {
+    #[schema(min_length = 1, max_length = 64)]
+    pub name: String,
+    #[schema(minimum = 0, maximum = 150)]
+    pub age: i32,
+    // 可选，设置了才能用 /auth/login 登录；更新时不传就保持原密码
+    #[serde(default, skip_serializing)]
+    #[schema(write_only, min_length = 8)]
+    pub password: Option<String>,
+}
+
+impl Validate for UserInput {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        let name = self.name.trim();
+        if name.is_empty() {
+            errors.push(FieldError::new("name", "不能为空"));
+        } else if name.chars().count() > NAME_MAX_CHARS {
+            errors.push(FieldError::new(
+                "name",
+                format!("长度不能超过 {} 个字符", NAME_MAX_CHARS),
+            ));
+        }
+        if !(0..=150).contains(&self.age) {
+            errors.push(FieldError::new("age", "必须在 0 到 150 之间"));
+        }
+        if let Some(password) = &self.password
+            && password.chars().count() < PASSWORD_MIN_CHARS
+        {
+            errors.push(FieldError::new(
+                "password",
+                format!("至少 {} 个字符", PASSWORD_MIN_CHARS),
+            ));
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// GET /users 的查询参数，例如 ?age_gt=18&name_like=al&sort=-age,name&limit=10&cursor=...
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListQuery {
+    /// 只要年龄大于这个值的
+    pub age_gt: Option<i32>,
+    /// 名字包含，不区分大小写
+    pub name_like: Option<String>,
+    /// 逗号分隔的排序字段，前缀 - 表示降序，可选 id / name / age，例如 `-age,name`
+    pub sort: Option<String>,
+    /// 每页条数，1 到 100，默认 20
+    pub limit: Option<u32>,
+    /// offset 分页，不能和 cursor 一起用
+    pub offset: Option<u64>,
+    /// 上一页 meta.next_cursor 原样传回来
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserSort {
+    Id,
+    Name,
+    Age,
+}
+
+impl SortField for UserSort {
+    type Row = User;
+
+    const TIEBREAKER: Self = UserSort::Id;
+
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "id" => Some(UserSort::Id),
+            "name" => Some(UserSort::Name),
+            "age" => Some(UserSort::Age),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            UserSort::Id => "id",
+            UserSort::Name => "name",
+            UserSort::Age => "age",
+        }
+    }
+
+    fn value_of(&self, row: &User) -> Value {
+        match self {
+            UserSort::Id => row.id.into(),
+            UserSort::Name => row.name.clone().into(),
+            UserSort::Age => row.age.into(),
+        }
+    }
+
+    fn push_bind(&self, qb: &mut QueryBuilder<Postgres>, value: &Value) -> bool {
+        match self {
+            UserSort::Id | UserSort::Age => {
+                match value.as_i64().and_then(|v| i32::try_from(v).ok()) {
+                    Some(v) => {
+                        qb.push_bind(v);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            UserSort::Name => match value.as_str() {
+                Some(v) => {
+                    qb.push_bind(v.to_string());
+                    true
+                }
+                None => false,
+            },
+        }
+    }
+}
+
+// 过滤条件，前面已经有 WHERE，这里至少要产出一个条件
+fn push_filters(qb: &mut QueryBuilder<Postgres>, query: &ListQuery) {
+    qb.push("TRUE");
+    if let Some(age) = query.age_gt {
+        qb.push(" AND age > ").push_bind(age);
+    }
+    if let Some(name) = query.name_like.as_deref().filter(|n| !n.is_empty()) {
+        // 用户输入里的 % _ 要转义，不然会被当成通配符
+        let escaped = name
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        qb.push(" AND name ILIKE ")
+            .push_bind(format!("%{}%", escaped));
+    }
+}
+
+pub fn routes(keys: &AuthKeys) -> Router<AppState> {
+    // 只有改和删要 admin，查询和注册不用登录
+    let admin = AuthLayer::new(keys.clone()).require_roles(["admin"]);
+    Router::new()
+        .route("/users", get(list_users).post(create_user))
+        .route(
+            "/users/{id}",
+            get(get_user).merge(put(update_user).delete(delete_user).route_layer(admin)),
+        )
+}
+
+// Argon2 很吃 CPU，放到阻塞线程池里算
+async fn hash_optional(password: Option<String>) -> Result<Option<String>, ApiError> {
+    match password {
+        Some(password) => {
+            let hash = tokio::task::spawn_blocking(move || hash_password(&password))
+                .await
+                .map_err(anyhow::Error::from)??;
+            Ok(Some(hash))
+        }
+        None => Ok(None),
+    }
+}
+
+fn cache_headers(tags: String) -> [(HeaderName, String); 2] {
+    [(CACHE_CONTROL, CACHE_POLICY.to_string()), (CACHE_TAG, tags)]
+}
+
+// 数据已经改了，缓存作废失败只打日志，最多 30 秒后自己过期
+async fn invalidate(state: &AppState, tags: &[&str]) {
+    if let Err(e) = state.cache.invalidate(tags).await {
+        log::warn!("invalidate cache {:?} failed: {:#}", tags, e);
+    }
+}
+
+// users.name 上有唯一索引(见 state::init_schema)，并发创建也只会成功一个；
+// 状态码照常是 409，只是把约束名换成看得懂的提示
+fn name_taken(name: &str) -> impl FnOnce(sqlx::Error) -> ApiError + '_ {
+    move |err| match &err {
+        sqlx::Error::Database(db) if db.constraint() == Some("users_name_key") => {
+            ApiError::Conflict(format!("用户名 {} 已存在", name))
+        }
+        _ => err.into(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/users",
+    tag = "users",
+    request_body = UserInput,
+    responses(
+        (status = 201, description = "创建成功", body = ApiResponse<User>),
+        (status = 409, description = "用户名已存在", body = ErrorBody),
+        (status = 422, description = "请求体校验失败", body = ErrorBody),
+    )
+)]
+async fn create_user(
+    State(state): State<AppState>,
+    ValidJson(input): ValidJson<UserInput>,
+) -> Result<(StatusCode, Json<ApiResponse<User>>), ApiError> {
+    let name = input.name.trim();
+    let password_hash = hash_optional(input.password).await?;
+    // user.created 和用户一起提交，不会建了用户没发通知，也不会回滚了还发出去
+    let mut tx = state.pool.begin().await?;
+    let user: User = sqlx::query_as(
+        "INSERT INTO users (name, age, password_hash) VALUES ($1, $2, $3) RETURNING id, name, age",
+    )
+    .bind(name)
+    .bind(input.age)
+    .bind(password_hash)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(name_taken(name))?;
+    state
+        .webhooks
+        .enqueue(&mut *tx, "user.created", &user)
+        .await?;
+    tx.commit().await?;
+    state.webhooks.notify();
+    invalidate(&state, &["users"]).await;
+    Ok((StatusCode::CREATED, Json(ApiResponse::success(user))))
+}
+
+#[utoipa::path(
+    get,
+    path = "/users",
+    tag = "users",
+    params(ListQuery),
+    responses(
+        (status = 200, description = "一页用户，meta 里是分页信息", body = ApiResponse<Vec<User>>),
+        (status = 422, description = "查询参数不合法", body = ErrorBody),
+    )
+)]
+async fn list_users(
+    State(state): State<AppState>,
+    query: Result<Query<ListQuery>, QueryRejection>,
+) -> Result<([(HeaderName, String); 2], Json<ApiResponse<Vec<User>>>), ApiError> {
+    let Query(query) = query?;
+    let page = PageRequest::new(query.limit, query.offset, query.cursor.as_deref())?;
+    // 带游标翻页时可以不再传 sort，沿用游标里记下的排序
+    let sort = match (&query.sort, page.cursor()) {
+        (Some(sort), _) => sort.as_str(),
+        (None, Some(cursor)) => cursor.sort.as_str(),
+        (None, None) => "",
+    };
+    let keys = parse_sort::<UserSort>(sort).map_err(|e| ApiError::Validation(vec![e]))?;
+
+    let mut qb = QueryBuilder::<Postgres>::new("SELECT id, name, age FROM users WHERE ");
+    push_filters(&mut qb, &query);
+    if let Some(cursor) = page.cursor() {
+        qb.push(" AND ");
+        cursor
+            .push_where(&mut qb, &keys)
+            .map_err(|e| ApiError::Validation(vec![e]))?;
+    }
+    push_order_by(&mut qb, &keys);
+    qb.push(" LIMIT ").push_bind(page.fetch_limit());
+    if let Page::Offset(offset) = page.page {
+        qb.push(" OFFSET ").push_bind(offset as i64);
+    }
+    let rows: Vec<User> = qb.build_query_as().fetch_all(&state.pool).await?;
+
+    // 游标分页不算总数，count(*) 在大表上很贵，这也是用游标的原因之一
+    let total = match page.page {
+        Page::Offset(_) => {
+            let mut qb = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM users WHERE ");
+            push_filters(&mut qb, &query);
+            let total: i64 = qb.build_query_scalar().fetch_one(&state.pool).await?;
+            Some(total as u64)
+        }
+        Page::After(_) => None,
+    };
+
+    let (users, meta) = page.finish(&keys, rows, total);
+    let body = Json(ApiResponse::success(users).with_meta(meta));
+    Ok((cache_headers("users".to_string()), body))
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/{id}",
+    tag = "users",
+    params(("id" = i32, Path, description = "用户 id")),
+    responses(
+        (status = 200, description = "用户", body = ApiResponse<User>),
+        (status = 404, description = "用户不存在", body = ErrorBody),
+    )
+)]
+async fn get_user(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<([(HeaderName, String); 2], Json<ApiResponse<User>>), ApiError> {
+    let user: User = sqlx::query_as("SELECT id, name, age FROM users WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&state.pool)
+        .await?
+        .ok_or_else(|| not_found(id))?;
+    let tags = format!("user:{}", id);
+    Ok((cache_headers(tags), Json(ApiResponse::success(user))))
+}
+
+#[utoipa::path(
+    put,
+    path = "/users/{id}",
+    tag = "users",
+    params(("id" = i32, Path, description = "用户 id")),
+    request_body = UserInput,
+    security(("bearer" = ["admin"])),
+    responses(
+        (status = 200, description = "更新后的用户", body = ApiResponse<User>),
+        (status = 401, description = "没登录", body = ErrorBody),
+        (status = 403, description = "不是 admin", body = ErrorBody),
+        (status = 404, description = "用户不存在", body = ErrorBody),
+        (status = 409, description = "用户名已存在", body = ErrorBody),
+        (status = 422, description = "请求体校验失败", body = ErrorBody),
+    )
+)]
+async fn update_user(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    ValidJson(input): ValidJson<UserInput>,
+) -> Result<Json<ApiResponse<User>>, ApiError> {
+    let name = input.name.trim();
+    let password_hash = hash_optional(input.password).await?;
+    let user: User = sqlx::query_as(
+        "UPDATE users SET name = $1, age = $2, password_hash = COALESCE($4, password_hash) \
+         WHERE id = $3 RETURNING id, name, age",
+    )
+    .bind(name)
+    .bind(input.age)
+    .bind(id)
+    .bind(password_hash)
+    .fetch_optional(&state.pool)
+    .await
+    .map_err(name_taken(name))?
+    .ok_or_else(|| not_found(id))?;
+    invalidate(&state, &["users", &format!("user:{}", id)]).await;
+    Ok(Json(ApiResponse::success(user)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/users/{id}",
+    tag = "users",
+    params(("id" = i32, Path, description = "用户 id")),
+    security(("bearer" = ["admin"])),
+    responses(
+        (status = 204, description = "已删除"),
+        (status = 401, description = "没登录", body = ErrorBody),
+        (status = 403, description = "不是 admin", body = ErrorBody),
+        (status = 404, description = "用户不存在", body = ErrorBody),
+    )
+)]
+async fn delete_user(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, ApiError> {
+    let result = sqlx::query("DELETE FROM users WHERE id = $1")
+        .bind(id)
+        .execute(&state.pool)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Err(not_found(id));
+    }
+    invalidate(&state, &["users", &format!("user:{}", id)]).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn not_found(id: i32) -> ApiError {
+    ApiError::NotFound(format!("用户 {} 不存在", id))
+}
+
+#[test]
+fn user_input_validation() {
+    let ok = UserInput {
+        name: "Alice".to_string(),
+        age: 30,
+        password: None,
+    };
+    assert!(ok.validate().is_ok());
+
+    let bad = UserInput {
+        name: "   ".to_string(),
+        age: 200,
+        password: Some("short".to_string()),
+    };
+    let fields: Vec<&str> = bad
+        .validate()
+        .unwrap_err()
+        .iter()
+        .map(|e| e.field)
+        .collect();
+    assert_eq!(fields, vec!["name", "age", "password"]);
+}
+
+// 下面的测试需要本地 postgres，连接串见 state::get_pool
+#[cfg(test)]
+async fn send(
+    app: &Router,
+    method: &str,
+    uri: &str,
+    body: Option<serde_json::Value>,
+) -> (StatusCode, serde_json::Value) {
+    send_as(app, None, method, uri, body).await
+}
+
+#[cfg(test)]
+async fn send_as(
+    app: &Router,
+    token: Option<&str>,
+    method: &str,
+    uri: &str,
+    body: Option<serde_json::Value>,
+) -> (StatusCode, serde_json::Value) {
+    use axum::body::Body;
+    use axum::http::Request;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    let mut req = Request::builder().method(method).uri(uri);
+    if let Some(token) = token {
+        req = req.header("authorization", format!("Bearer {}", token));
+    }
+    let body = match body {
+        Some(json) => {
+            req = req.header("content-type", "application/json");
+            Body::from(json.to_string())
+        }
+        None => Body::empty(),
+    };
+    let resp = app.clone().oneshot(req.body(body).unwrap()).await.unwrap();
+    let status = resp.status();
+    let bytes = resp.into_body().collect().await.unwrap().to_bytes();
+    let json = serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null);
+    (status, json)
+}
+
+#[cfg(test)]
+fn test_keys() -> AuthKeys {
+    AuthKeys::hs256(b"dep_web-test-secret")
+}
+
+// 直接签一个 admin 的 token，不走登录
+#[cfg(test)]
+fn admin_token() -> String {
+    use crate::auth::{AuthUser, TokenType};
+
+    let admin = AuthUser::new(0, "test-admin", vec!["admin".to_string()]);
+    test_keys().issue(&admin, TokenType::Access).unwrap()
+}
+
+#[cfg(test)]
+async fn test_app() -> Router {
+    let pool = crate::state::get_pool().await.unwrap();
+    crate::state::init_schema(&pool).await.unwrap();
+    crate::app(AppState::new(pool, test_keys()))
+}
+
+#[tokio::test]
+async fn users_crud_flow() {
+    use serde_json::json;
+
+    let app = test_app().await;
+    let name = format!("web-crud-{}", std::process::id());
+
+    let (status, created) = send(
+        &app,
+        "POST",
+        "/users",
+        Some(json!({"name": name, "age": 30})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(created["status"], "success");
+    let id = created["data"]["id"].as_i64().unwrap();
+    assert_eq!(created["data"]["name"], name);
+
+    // 同名再建一次
+    let (status, body) = send(
+        &app,
+        "POST",
+        "/users",
+        Some(json!({"name": name, "age": 31})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "conflict");
+    assert_eq!(body["message"], format!("用户名 {} 已存在", name));
+    // 没带 x-request-id 时中间件会生成一个
+    assert!(body["request_id"].is_string());
+
+    let (status, got) = send(&app, "GET", &format!("/users/{}", id), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(got, created);
+
+    // 改和删要 admin
+    let (status, body) = send(
+        &app,
+        "PUT",
+        &format!("/users/{}", id),
+        Some(json!({"name": name, "age": 35})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["code"], "unauthorized");
+
+    let admin = admin_token();
+    let (status, updated) = send_as(
+        &app,
+        Some(&admin),
+        "PUT",
+        &format!("/users/{}", id),
+        Some(json!({"name": name, "age": 35})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(updated["data"]["age"], 35);
+    // 前面 GET 过的缓存已经作废
+    let (_, got) = send(&app, "GET", &format!("/users/{}", id), None).await;
+    assert_eq!(got, updated);
+
+    let (status, list) = send(&app, "GET", &format!("/users?name_like={}", name), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(list["data"][0]["id"], id);
+
+    let (status, _) = send_as(
+        &app,
+        Some(&admin),
+        "DELETE",
+        &format!("/users/{}", id),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, body) = send(&app, "GET", &format!("/users/{}", id), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "not_found");
+    let (status, _) = send_as(
+        &app,
+        Some(&admin),
+        "DELETE",
+        &format!("/users/{}", id),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn concurrent_creates_with_same_name() {
+    use serde_json::json;
+
+    let app = test_app().await;
+    let name = format!("web-race-{}", std::process::id());
+    let body = json!({"name": name, "age": 20});
+    let results = futures_util::future::join_all(
+        (0..5).map(|_| send(&app, "POST", "/users", Some(body.clone()))),
+    )
+    .await;
+
+    let created: Vec<&Value> = results
+        .iter()
+        .filter(|(status, _)| *status == StatusCode::CREATED)
+        .map(|(_, body)| body)
+        .collect();
+    assert_eq!(created.len(), 1);
+    assert!(
+        results
+            .iter()
+            .all(|(status, _)| *status == StatusCode::CREATED || *status == StatusCode::CONFLICT)
+    );
+
+    let id = created[0]["data"]["id"].as_i64().unwrap();
+    let admin = admin_token();
+    send_as(
+        &app,
+        Some(&admin),
+        "DELETE",
+        &format!("/users/{}", id),
+        None,
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn users_reject_invalid_body() {
+    use serde_json::json;
+
+    let app = test_app().await;
+
+    let (status, body) = send(&app, "POST", "/users", Some(json!({"name": "", "age": -1}))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["details"].as_array().unwrap().len(), 2);
+
+    // 缺字段是反序列化失败，axum 同样给 422
+    let (status, body) = send(&app, "POST", "/users", Some(json!({"name": "x"}))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["code"], "invalid_request");
+}
+
+#[test]
+fn user_sort_and_cursor() {
+    use crate::pagination::{Cursor, Direction, sort_spec};
+
+    let keys = parse_sort::<UserSort>("-age, name").unwrap();
+    // 主键自动补在最后
+    assert_eq!(sort_spec(&keys), "-age,name,id");
+    assert_eq!(keys[0].direction, Direction::Desc);
+    assert!(parse_sort::<UserSort>("email").is_err());
+    assert!(parse_sort::<UserSort>("age,-age").is_err());
+
+    let row = User {
+        id: 7,
+        name: "Bob".to_string(),
+        age: 40,
+    };
+    let cursor = Cursor::after(&keys, &row);
+    let decoded = Cursor::decode(&cursor.encode()).unwrap();
+    assert_eq!(decoded, cursor);
+    assert!(Cursor::decode("not-a-cursor").is_err());
+
+    let mut qb = QueryBuilder::<Postgres>::new("");
+    decoded.push_where(&mut qb, &keys).unwrap();
+    assert_eq!(
+        qb.sql().as_str(),
+        "((age < $1) OR (age = $2 AND name > $3) OR (age = $4 AND name = $5 AND id > $6))"
+    );
+    // 换了排序以后旧游标不能再用
+    let other = parse_sort::<UserSort>("name").unwrap();
+    assert!(decoded.push_where(&mut qb, &other).is_err());
+}
+
+#[tokio::test]
+async fn users_list_filter_sort_and_paginate() {
+    use serde_json::json;
+
+    let app = test_app().await;
+    // 名字带上进程号，只看这次插入的数据
+    let tag = format!("web-page-{}", std::process::id());
+    let mut ids = Vec::new();
+    for (suffix, age) in [("a", 20), ("b", 30), ("c", 30), ("d", 40), ("e", 10)] {
+        let (status, body) = send(
+            &app,
+            "POST",
+            "/users",
+            Some(json!({"name": format!("{}-{}", tag, suffix), "age": age})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        ids.push(body["data"]["id"].as_i64().unwrap());
+    }
+    let names = |body: &Value| -> Vec<String> {
+        body["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|u| {
+                u["name"]
+                    .as_str()
+                    .unwrap()
+                    .rsplit('-')
+                    .next()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect()
+    };
+
+    // offset 分页 + 过滤 + 多字段排序
+    let base = format!("/users?name_like={}&age_gt=15&sort=-age,name", tag);
+    let (status, page) = send(&app, "GET", &format!("{}&limit=3", base), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(names(&page), vec!["d", "b", "c"]);
+    assert_eq!(page["meta"]["total"], 4);
+    assert_eq!(page["meta"]["offset"], 0);
+    assert_eq!(page["meta"]["has_more"], true);
+    let (_, page2) = send(&app, "GET", &format!("{}&limit=3&offset=3", base), None).await;
+    assert_eq!(names(&page2), vec!["a"]);
+    assert_eq!(page2["meta"]["has_more"], false);
+
+    // 游标分页：第二页开始只带 cursor，排序从游标里恢复
+    let (_, first) = send(&app, "GET", &format!("{}&limit=2", base), None).await;
+    assert_eq!(names(&first), vec!["d", "b"]);
+    let cursor = first["meta"]["next_cursor"].as_str().unwrap().to_string();
+    let (status, second) = send(
+        &app,
+        "GET",
+        &format!(
+            "/users?name_like={}&age_gt=15&limit=2&cursor={}",
+            tag, cursor
+        ),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(names(&second), vec!["c", "a"]);
+    assert!(second["meta"].get("total").is_none());
+    assert!(second["meta"].get("next_cursor").is_none());
+
+    // 参数错误都是 422
+    for query in [
+        format!("cursor={}&offset=1", cursor),
+        format!("cursor={}&sort=name", cursor),
+        "cursor=bogus".to_string(),
+        "sort=password".to_string(),
+        "limit=0".to_string(),
+        format!("offset={}", u64::MAX),
+    ] {
+        let (status, body) = send(&app, "GET", &format!("/users?{}", query), None).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", query);
+        assert_eq!(body["code"], "invalid_request");
+    }
+
+    let admin = admin_token();
+    for id in ids {
+        send_as(
+            &app,
+            Some(&admin),
+            "DELETE",
+            &format!("/users/{}", id),
+            None,
+        )
+        .await;
+    }
+}
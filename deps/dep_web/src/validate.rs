@@ -0,0 +1,28 @@
+// 带校验的 JSON 提取器
+// axum 自带的 Json<T> 只保证能反序列化，业务规则(名字不能为空、年龄范围等)放到 Validate 里
+// handler 参数写 ValidJson<T>，进到 handler 里的数据就一定是合法的
+
+use crate::error::{ApiError, FieldError};
+use axum::Json;
+use axum::extract::{FromRequest, Request};
+use serde::de::DeserializeOwned;
+
+pub trait Validate {
+    fn validate(&self) -> Result<(), Vec<FieldError>>;
+}
+
+pub struct ValidJson<T>(pub T);
+
+impl<S, T> FromRequest<S> for ValidJson<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Validate,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state).await?;
+        value.validate().map_err(ApiError::Validation)?;
+        Ok(ValidJson(value))
+    }
+}
@@ -0,0 +1,775 @@
+// 往外发的 webhook：handler 入队，后台任务投递，失败按指数退避重试，重试够了进死信表
+// 1. enqueue 接受任意 PgExecutor，可以和业务数据写在同一个事务里，提交了才会发出去；
+//    每个订阅了这个事件的端点一行，端点(地址、密钥、订阅的事件)在配置里；
+//    enqueue 不知道事务什么时候提交，提交以后调 notify 叫醒 run() 马上发，不调就等下一轮
+// 2. run() 是后台循环，多实例同时跑也没问题：认领用 FOR UPDATE SKIP LOCKED，认领时把下次时间往后推，
+//    进程挂了的话过了超时时间别的实例会接着发；只认领自己配置里有的端点，
+//    滚动发布时新加的端点留给配了它的实例发，配置里删掉的端点的投递一直留在表里
+// 3. 请求体就是事件的 JSON，请求头带 Webhook-Id / Webhook-Event / Webhook-Timestamp / Webhook-Signature，
+//    签名是 v1=hex(HMAC-SHA256(secret, "{id}.{timestamp}.{body}"))，接收方用 verify 校验；重试时 Webhook-Id 不变，可以拿来去重
+// 4. 2xx 算成功，其他状态码、连不上、超时都算失败，第 n 次失败后等 backoff_base * 2^(n-1)，最多 backoff_max
+// 5. 失败 max_attempts 次后挪到 webhook_dead_letters，admin 可以在 /admin/webhooks/dead-letters 查看和重放
+// 6. 只用了 HttpConnector，端点只能是 http://，和 gateway 一样
+
+use crate::auth::{AuthKeys, AuthLayer};
+use crate::error::{ApiError, ErrorBody};
+use crate::state::AppState;
+use anyhow::{Context as _, bail};
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, HeaderName, Method, StatusCode, Uri};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use dep_serialization::ApiResponse;
+use hmac::{Hmac, Mac};
+use hyper_util::client::legacy::Client;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::rt::TokioExecutor;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use sqlx::{FromRow, PgExecutor, PgPool};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+use tokio::task::JoinSet;
+use utoipa::ToSchema;
+
+pub const WEBHOOK_ID: HeaderName = HeaderName::from_static("webhook-id");
+pub const WEBHOOK_EVENT: HeaderName = HeaderName::from_static("webhook-event");
+pub const WEBHOOK_TIMESTAMP: HeaderName = HeaderName::from_static("webhook-timestamp");
+pub const WEBHOOK_SIGNATURE: HeaderName = HeaderName::from_static("webhook-signature");
+
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 8;
+pub const DEFAULT_BACKOFF_BASE: Duration = Duration::from_secs(5);
+pub const DEFAULT_BACKOFF_MAX: Duration = Duration::from_secs(60 * 60);
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 签名，id 和 timestamp 也签进去，防止拿旧请求体换个 id 重放
+pub fn sign(secret: &[u8], id: &str, timestamp: u64, body: &[u8]) -> String {
+    format!(
+        "v1={}",
+        hex::encode(mac(secret, id, timestamp, body).finalize().into_bytes())
+    )
+}
+
+fn mac(secret: &[u8], id: &str, timestamp: u64, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(id.as_bytes());
+    mac.update(b".");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+/// 接收方用：签名对得上，而且时间戳和现在相差不超过 tolerance
+pub fn verify(secret: &[u8], headers: &HeaderMap, body: &[u8], tolerance: Duration) -> bool {
+    let header = |name: &HeaderName| headers.get(name).and_then(|v| v.to_str().ok());
+    let (Some(id), Some(timestamp), Some(signature)) = (
+        header(&WEBHOOK_ID),
+        header(&WEBHOOK_TIMESTAMP).and_then(|t| t.parse::<u64>().ok()),
+        header(&WEBHOOK_SIGNATURE)
+            .and_then(|s| s.strip_prefix("v1="))
+            .and_then(|s| hex::decode(s).ok()),
+    ) else {
+        return false;
+    };
+    if now_secs().abs_diff(timestamp) > tolerance.as_secs() {
+        return false;
+    }
+    // verify_slice 是常数时间比较
+    mac(secret, id, timestamp, body)
+        .verify_slice(&signature)
+        .is_ok()
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// 端点地址只能是带主机的 http://，配置校验也用它
+pub fn parse_url(url: &str) -> anyhow::Result<Uri> {
+    let parsed: Uri = url
+        .parse()
+        .with_context(|| format!("webhook url {:?}", url))?;
+    if parsed.scheme_str() != Some("http") || parsed.host().is_none_or(str::is_empty) {
+        bail!(
+            "only http:// webhook urls with a host are supported, got {:?}",
+            url
+        );
+    }
+    Ok(parsed)
+}
+
+/// 一个接收地址，events 为空表示订阅所有事件
+#[derive(Debug, Clone)]
+pub struct Endpoint {
+    pub name: String,
+    url: Uri,
+    secret: String,
+    events: Vec<String>,
+}
+
+impl Endpoint {
+    pub fn new(
+        name: impl Into<String>,
+        url: &str,
+        secret: impl Into<String>,
+    ) -> anyhow::Result<Self> {
+        Ok(Endpoint {
+            name: name.into(),
+            url: parse_url(url)?,
+            secret: secret.into(),
+            events: Vec::new(),
+        })
+    }
+
+    pub fn events<I: IntoIterator<Item = S>, S: Into<String>>(mut self, events: I) -> Self {
+        self.events = events.into_iter().map(Into::into).collect();
+        self
+    }
+
+    fn wants(&self, event: &str) -> bool {
+        self.events.is_empty() || self.events.iter().any(|e| e == event)
+    }
+}
+
+#[derive(Clone)]
+struct Options {
+    endpoints: Vec<Endpoint>,
+    max_attempts: u32,
+    backoff_base: Duration,
+    backoff_max: Duration,
+    timeout: Duration,
+    poll_interval: Duration,
+    batch: i64,
+}
+
+impl Options {
+    fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+        self.backoff_base
+            .checked_mul(factor)
+            .unwrap_or(self.backoff_max)
+            .min(self.backoff_max)
+    }
+}
+
+/// 用法：`Webhooks::new(pool).endpoint(Endpoint::new("crm", "http://crm/hooks", secret)?)`，
+/// 再 `tokio::spawn(webhooks.clone().run())`
+#[derive(Clone)]
+pub struct Webhooks {
+    pool: PgPool,
+    options: Arc<Options>,
+    client: Client<HttpConnector, Body>,
+    // 事务提交以后叫醒 run()，不用等到下一轮
+    wake: Arc<Notify>,
+}
+
+#[derive(FromRow)]
+struct Claimed {
+    id: i64,
+    event: String,
+    endpoint: String,
+    payload: String,
+    attempts: i32,
+}
+
+/// 死信表里的一条，时间都是 Unix 毫秒
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct DeadLetter {
+    pub id: i64,
+    pub event: String,
+    pub endpoint: String,
+    #[schema(value_type = Object)]
+    pub payload: Value,
+    pub attempts: i32,
+    pub last_error: String,
+    pub created_at: i64,
+    pub failed_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Replayed {
+    /// 重放后还是原来的 id，Webhook-Id 不变
+    pub id: i64,
+}
+
+impl Webhooks {
+    pub fn new(pool: PgPool) -> Self {
+        Webhooks {
+            pool,
+            options: Arc::new(Options {
+                endpoints: Vec::new(),
+                max_attempts: DEFAULT_MAX_ATTEMPTS,
+                backoff_base: DEFAULT_BACKOFF_BASE,
+                backoff_max: DEFAULT_BACKOFF_MAX,
+                timeout: DEFAULT_TIMEOUT,
+                poll_interval: Duration::from_secs(1),
+                batch: 32,
+            }),
+            client: Client::builder(TokioExecutor::new()).build_http(),
+            wake: Arc::new(Notify::new()),
+        }
+    }
+
+    fn update(mut self, f: impl FnOnce(&mut Options)) -> Self {
+        let mut options = Arc::unwrap_or_clone(self.options);
+        f(&mut options);
+        self.options = Arc::new(options);
+        self
+    }
+
+    pub fn endpoint(self, endpoint: Endpoint) -> Self {
+        self.update(|o| o.endpoints.push(endpoint))
+    }
+
+    pub fn max_attempts(self, max_attempts: u32) -> Self {
+        self.update(|o| o.max_attempts = max_attempts.max(1))
+    }
+
+    pub fn backoff(self, base: Duration, max: Duration) -> Self {
+        self.update(|o| {
+            o.backoff_base = base;
+            o.backoff_max = max;
+        })
+    }
+
+    /// 单次投递的超时，也是认领以后别的实例等多久才能接手
+    pub fn timeout(self, timeout: Duration) -> Self {
+        self.update(|o| o.timeout = timeout)
+    }
+
+    pub fn poll_interval(self, interval: Duration) -> Self {
+        self.update(|o| o.poll_interval = interval)
+    }
+
+    /// 给订阅了 event 的每个端点排一条，返回投递 id；没有端点订阅就什么都不做
+    pub async fn enqueue<'e, T: Serialize>(
+        &self,
+        db: impl PgExecutor<'e>,
+        event: &str,
+        payload: &T,
+    ) -> anyhow::Result<Vec<i64>> {
+        let endpoints: Vec<&str> = self
+            .options
+            .endpoints
+            .iter()
+            .filter(|e| e.wants(event))
+            .map(|e| e.name.as_str())
+            .collect();
+        if endpoints.is_empty() {
+            return Ok(Vec::new());
+        }
+        let ids: Vec<i64> = sqlx::query_scalar(
+            r#"
+            INSERT INTO webhook_deliveries (event, endpoint, payload)
+            SELECT $1, endpoint, $3::jsonb FROM UNNEST($2::text[]) AS t(endpoint)
+            RETURNING id
+            "#,
+        )
+        .bind(event)
+        .bind(&endpoints)
+        .bind(serde_json::to_string(payload)?)
+        .fetch_all(db)
+        .await?;
+        Ok(ids)
+    }
+
+    /// enqueue 的事务提交以后调，马上投递；还没提交就叫醒的话 run() 什么也认领不到，又要等一轮
+    pub fn notify(&self) {
+        self.wake.notify_one();
+    }
+
+    /// 后台一直跑，出错只打日志
+    pub async fn run(self) {
+        loop {
+            match self.deliver_due().await {
+                Ok(0) => {}
+                Ok(_) => continue,
+                Err(e) => log::warn!("deliver webhooks failed: {:#}", e),
+            }
+            tokio::select! {
+                _ = self.wake.notified() => {}
+                _ = tokio::time::sleep(self.options.poll_interval) => {}
+            }
+        }
+    }
+
+    /// 认领一批到点的投递并发出去，返回认领了多少条
+    pub async fn deliver_due(&self) -> anyhow::Result<usize> {
+        // 留一点余量，免得刚好超时的时候被别的实例重复认领
+        let lease = self.options.timeout + Duration::from_secs(5);
+        let endpoints: Vec<&str> = self
+            .options
+            .endpoints
+            .iter()
+            .map(|e| e.name.as_str())
+            .collect();
+        if endpoints.is_empty() {
+            return Ok(0);
+        }
+        let claimed: Vec<Claimed> = sqlx::query_as(
+            r#"
+            UPDATE webhook_deliveries
+            SET next_attempt_at = now() + make_interval(secs => $2)
+            WHERE id IN (
+                SELECT id FROM webhook_deliveries
+                WHERE next_attempt_at <= now() AND endpoint = ANY($3)
+                ORDER BY next_attempt_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, event, endpoint, payload::text AS payload, attempts
+            "#,
+        )
+        .bind(self.options.batch)
+        .bind(lease.as_secs_f64())
+        .bind(&endpoints)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let count = claimed.len();
+        let mut tasks = JoinSet::new();
+        for delivery in claimed {
+            let this = self.clone();
+            tasks.spawn(async move { this.deliver(delivery).await });
+        }
+        while let Some(result) = tasks.join_next().await {
+            if let Err(e) = result.map_err(anyhow::Error::from).and_then(|r| r) {
+                log::warn!("record webhook result failed: {:#}", e);
+            }
+        }
+        Ok(count)
+    }
+
+    async fn deliver(&self, delivery: Claimed) -> anyhow::Result<()> {
+        let attempts = delivery.attempts as u32 + 1;
+        let endpoint = self
+            .options
+            .endpoints
+            .iter()
+            .find(|e| e.name == delivery.endpoint);
+        // 认领时已经按配置过滤过了
+        let Some(endpoint) = endpoint else {
+            bail!("endpoint {} is not configured", delivery.endpoint);
+        };
+        match self.send(endpoint, &delivery).await {
+            Ok(()) => {
+                sqlx::query("DELETE FROM webhook_deliveries WHERE id = $1")
+                    .bind(delivery.id)
+                    .execute(&self.pool)
+                    .await?;
+                Ok(())
+            }
+            Err(e) if attempts >= self.options.max_attempts => {
+                log::warn!(
+                    "webhook {} to {} dead-lettered: {:#}",
+                    delivery.id,
+                    endpoint.name,
+                    e
+                );
+                self.dead_letter(delivery.id, attempts, &format!("{:#}", e))
+                    .await
+            }
+            Err(e) => {
+                let backoff = self.options.backoff(attempts);
+                sqlx::query(
+                    r#"
+                    UPDATE webhook_deliveries
+                    SET attempts = $2, last_error = $3,
+                        next_attempt_at = now() + make_interval(secs => $4)
+                    WHERE id = $1
+                    "#,
+                )
+                .bind(delivery.id)
+                .bind(attempts as i32)
+                .bind(format!("{:#}", e))
+                .bind(backoff.as_secs_f64())
+                .execute(&self.pool)
+                .await?;
+                Ok(())
+            }
+        }
+    }
+
+    async fn send(&self, endpoint: &Endpoint, delivery: &Claimed) -> anyhow::Result<()> {
+        let id = delivery.id.to_string();
+        let timestamp = now_secs();
+        let body = delivery.payload.as_bytes();
+        let signature = sign(endpoint.secret.as_bytes(), &id, timestamp, body);
+        let req = axum::http::Request::builder()
+            .method(Method::POST)
+            .uri(endpoint.url.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .header(WEBHOOK_ID, id)
+            .header(WEBHOOK_EVENT, delivery.event.as_str())
+            .header(WEBHOOK_TIMESTAMP, timestamp)
+            .header(WEBHOOK_SIGNATURE, signature)
+            .body(Body::from(delivery.payload.clone()))?;
+        let response = tokio::time::timeout(self.options.timeout, self.client.request(req))
+            .await
+            .map_err(|_| anyhow::anyhow!("timed out after {:?}", self.options.timeout))??;
+        let status = response.status();
+        if !status.is_success() {
+            bail!("{} responded {}", endpoint.url, status);
+        }
+        Ok(())
+    }
+
+    async fn dead_letter(&self, id: i64, attempts: u32, error: &str) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            WITH moved AS (
+                DELETE FROM webhook_deliveries WHERE id = $1
+                RETURNING id, event, endpoint, payload, created_at
+            )
+            INSERT INTO webhook_dead_letters (id, event, endpoint, payload, attempts, last_error, created_at)
+            SELECT id, event, endpoint, payload, $2, $3, created_at FROM moved
+            "#,
+        )
+        .bind(id)
+        .bind(attempts as i32)
+        .bind(error)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// 最近进死信表的 limit 条
+    pub async fn dead_letters(&self, limit: i64) -> Result<Vec<DeadLetter>, sqlx::Error> {
+        sqlx::query_as(
+            r#"
+            SELECT id, event, endpoint, payload, attempts, last_error,
+                   (extract(epoch FROM created_at) * 1000)::bigint AS created_at,
+                   (extract(epoch FROM failed_at) * 1000)::bigint AS failed_at
+            FROM webhook_dead_letters
+            ORDER BY failed_at DESC, id DESC
+            LIMIT $1
+            "#,
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// 挪回投递表，次数清零马上发；没有这条返回 false
+    pub async fn replay(&self, id: i64) -> Result<bool, sqlx::Error> {
+        let replayed: Option<i64> = sqlx::query_scalar(
+            r#"
+            WITH moved AS (
+                DELETE FROM webhook_dead_letters WHERE id = $1
+                RETURNING id, event, endpoint, payload, created_at
+            )
+            INSERT INTO webhook_deliveries (id, event, endpoint, payload, created_at)
+            SELECT id, event, endpoint, payload, created_at FROM moved
+            RETURNING id
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+        if replayed.is_some() {
+            self.wake.notify_one();
+        }
+        Ok(replayed.is_some())
+    }
+}
+
+pub fn routes(keys: &AuthKeys) -> Router<AppState> {
+    let admin = AuthLayer::new(keys.clone()).require_roles(["admin"]);
+    Router::new()
+        .route("/admin/webhooks/dead-letters", get(list_dead_letters))
+        .route(
+            "/admin/webhooks/dead-letters/{id}/replay",
+            post(replay_dead_letter),
+        )
+        .route_layer(admin)
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/webhooks/dead-letters",
+    tag = "webhooks",
+    security(("bearer" = ["admin"])),
+    responses(
+        (status = 200, description = "最近 100 条死信", body = ApiResponse<Vec<DeadLetter>>),
+        (status = 401, description = "没登录", body = ErrorBody),
+        (status = 403, description = "不是 admin", body = ErrorBody),
+    )
+)]
+async fn list_dead_letters(
+    State(state): State<AppState>,
+) -> Result<Json<ApiResponse<Vec<DeadLetter>>>, ApiError> {
+    let dead = state.webhooks.dead_letters(100).await?;
+    Ok(Json(ApiResponse::success(dead)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/webhooks/dead-letters/{id}/replay",
+    tag = "webhooks",
+    params(("id" = i64, Path, description = "投递 id，也就是 Webhook-Id")),
+    security(("bearer" = ["admin"])),
+    responses(
+        (status = 202, description = "已经放回投递队列", body = ApiResponse<Replayed>),
+        (status = 401, description = "没登录", body = ErrorBody),
+        (status = 403, description = "不是 admin", body = ErrorBody),
+        (status = 404, description = "死信不存在", body = ErrorBody),
+    )
+)]
+async fn replay_dead_letter(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<(StatusCode, Json<ApiResponse<Replayed>>), ApiError> {
+    if !state.webhooks.replay(id).await? {
+        return Err(ApiError::NotFound(format!("死信 {} 不存在", id)));
+    }
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(ApiResponse::success(Replayed { id })),
+    ))
+}
+
+#[tokio::test]
+async fn webhook_signature_roundtrip() {
+    let body = br#"{"id":1}"#;
+    let timestamp = now_secs();
+    let mut headers = HeaderMap::new();
+    headers.insert(WEBHOOK_ID, "42".parse().unwrap());
+    headers.insert(WEBHOOK_TIMESTAMP, timestamp.into());
+    let signature = sign(b"secret", "42", timestamp, body);
+    headers.insert(WEBHOOK_SIGNATURE, signature.parse().unwrap());
+    let tolerance = Duration::from_secs(300);
+    assert!(verify(b"secret", &headers, body, tolerance));
+    assert!(!verify(b"other", &headers, body, tolerance));
+    assert!(!verify(b"secret", &headers, br#"{"id":2}"#, tolerance));
+
+    // id 也在签名里
+    headers.insert(WEBHOOK_ID, "43".parse().unwrap());
+    assert!(!verify(b"secret", &headers, body, tolerance));
+
+    // 太旧的时间戳不认
+    let old = timestamp - 600;
+    headers.insert(WEBHOOK_ID, "42".parse().unwrap());
+    headers.insert(WEBHOOK_TIMESTAMP, old.into());
+    let signature = sign(b"secret", "42", old, body);
+    headers.insert(WEBHOOK_SIGNATURE, signature.parse().unwrap());
+    assert!(!verify(b"secret", &headers, body, tolerance));
+
+    let options = Webhooks::new(PgPool::connect_lazy("postgres://localhost/x").unwrap())
+        .backoff(Duration::from_secs(5), Duration::from_secs(30))
+        .options;
+    let backoff: Vec<u64> = (1..=5).map(|n| options.backoff(n).as_secs()).collect();
+    assert_eq!(backoff, vec![5, 10, 20, 30, 30]);
+}
+
+#[tokio::test]
+async fn webhooks_retry_dead_letter_and_replay() {
+    use axum::body::Bytes;
+    use axum::http::Request;
+    use http_body_util::BodyExt;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use tower::ServiceExt;
+
+    // 假的接收方：/ok 每个 Webhook-Id 前两次返回 500，/down 一直 500
+    type Received = Arc<Mutex<HashMap<String, Vec<(String, bool)>>>>;
+    let received: Received = Arc::default();
+    let receiver = |secret: &'static [u8], fail_first: usize, received: Received| {
+        move |headers: HeaderMap, body: Bytes| async move {
+            let id = headers[&WEBHOOK_ID].to_str().unwrap().to_string();
+            let event = headers[&WEBHOOK_EVENT].to_str().unwrap().to_string();
+            let valid = verify(secret, &headers, &body, Duration::from_secs(60));
+            let mut received = received.lock().unwrap();
+            let attempts = received.entry(id).or_default();
+            attempts.push((event, valid));
+            if attempts.len() <= fail_first {
+                StatusCode::INTERNAL_SERVER_ERROR
+            } else {
+                StatusCode::NO_CONTENT
+            }
+        }
+    };
+    let server = Router::new()
+        .route("/ok", post(receiver(b"ok-secret", 2, received.clone())))
+        .route(
+            "/down",
+            post(receiver(b"down-secret", usize::MAX, received.clone())),
+        );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, server).await });
+
+    let pool = crate::state::get_pool().await.unwrap();
+    crate::state::init_schema(&pool).await.unwrap();
+    let ok = Endpoint::new("test-ok", &format!("http://{}/ok", addr), "ok-secret")
+        .unwrap()
+        .events(["user.created"]);
+    let down = Endpoint::new("test-down", &format!("http://{}/down", addr), "down-secret").unwrap();
+    let webhooks = Webhooks::new(pool.clone())
+        .endpoint(ok)
+        .endpoint(down)
+        .max_attempts(3)
+        .backoff(Duration::from_millis(20), Duration::from_millis(50))
+        .timeout(Duration::from_secs(2));
+
+    // 在事务里入队，回滚了就不会发
+    let mut tx = pool.begin().await.unwrap();
+    let ids = webhooks
+        .enqueue(&mut *tx, "user.created", &json!({"id": 1}))
+        .await
+        .unwrap();
+    assert_eq!(ids.len(), 2);
+    tx.rollback().await.unwrap();
+    let pending: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM webhook_deliveries WHERE id = ANY($1)")
+            .bind(&ids)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(pending, 0);
+
+    let created = webhooks
+        .enqueue(&pool, "user.created", &json!({"id": 1}))
+        .await
+        .unwrap();
+    let deleted = webhooks
+        .enqueue(&pool, "user.deleted", &json!({"id": 1}))
+        .await
+        .unwrap();
+    assert_eq!((created.len(), deleted.len()), (2, 1));
+    let (ok_id, down_ids) = (created[0], [created[1], deleted[0]]);
+    // 别的实例配置里的端点，这个实例不认领
+    let elsewhere = Webhooks::new(pool.clone())
+        .endpoint(Endpoint::new("test-elsewhere", "http://127.0.0.1:9/", "x").unwrap());
+    let elsewhere_id = elsewhere
+        .enqueue(&pool, "user.created", &json!({"id": 1}))
+        .await
+        .unwrap()[0];
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+    loop {
+        webhooks.deliver_due().await.unwrap();
+        let left: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM webhook_deliveries WHERE id = ANY($1)")
+                .bind([ok_id, down_ids[0], down_ids[1]])
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        if left == 0 {
+            break;
+        }
+        assert!(
+            tokio::time::Instant::now() < deadline,
+            "deliveries still pending"
+        );
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let untouched: i32 =
+        sqlx::query_scalar("SELECT attempts FROM webhook_deliveries WHERE id = $1")
+            .bind(elsewhere_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(untouched, 0);
+    sqlx::query("DELETE FROM webhook_deliveries WHERE id = $1")
+        .bind(elsewhere_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    // 重试时 id 不变，每次签名都对
+    {
+        let received = received.lock().unwrap();
+        let ok_attempts = &received[&ok_id.to_string()];
+        assert_eq!(ok_attempts.len(), 3);
+        assert!(
+            ok_attempts
+                .iter()
+                .all(|(event, valid)| event == "user.created" && *valid)
+        );
+        for id in down_ids {
+            assert_eq!(received[&id.to_string()].len(), 3);
+        }
+    }
+    let dead = webhooks.dead_letters(100).await.unwrap();
+    for id in down_ids {
+        let letter = dead.iter().find(|d| d.id == id).unwrap();
+        assert_eq!(
+            (letter.endpoint.as_str(), letter.attempts),
+            ("test-down", 3)
+        );
+        assert!(letter.last_error.contains("500"), "{}", letter.last_error);
+    }
+    assert!(dead.iter().all(|d| d.id != ok_id));
+
+    // admin 接口查看和重放
+    let keys = AuthKeys::hs256(b"webhooks-secret");
+    let admin = crate::auth::AuthUser::new(0, "admin", vec!["admin".to_string()]);
+    let token = keys.issue(&admin, crate::auth::TokenType::Access).unwrap();
+    let app = crate::app(AppState::new(pool.clone(), keys).with_webhooks(webhooks.clone()));
+    let send = |method: &str, uri: String, token: Option<&str>| {
+        let mut req = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            req = req.header("authorization", format!("Bearer {}", token));
+        }
+        let req = req.body(Body::empty()).unwrap();
+        let app = app.clone();
+        async move {
+            let resp = app.oneshot(req).await.unwrap();
+            let status = resp.status();
+            let body = resp.into_body().collect().await.unwrap().to_bytes();
+            (
+                status,
+                serde_json::from_slice::<Value>(&body).unwrap_or(Value::Null),
+            )
+        }
+    };
+    let list = "/admin/webhooks/dead-letters".to_string();
+    assert_eq!(
+        send("GET", list.clone(), None).await.0,
+        StatusCode::UNAUTHORIZED
+    );
+    let (status, body) = send("GET", list, Some(&token)).await;
+    assert_eq!(status, StatusCode::OK);
+    let listed = body["data"].as_array().unwrap();
+    assert!(
+        listed
+            .iter()
+            .any(|d| d["id"] == down_ids[0] && d["payload"]["id"] == 1)
+    );
+
+    let replay = format!("/admin/webhooks/dead-letters/{}/replay", down_ids[0]);
+    let (status, body) = send("POST", replay.clone(), Some(&token)).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(body["data"]["id"], down_ids[0]);
+    assert_eq!(
+        send("POST", replay, Some(&token)).await.0,
+        StatusCode::NOT_FOUND
+    );
+    let attempts: i32 = sqlx::query_scalar("SELECT attempts FROM webhook_deliveries WHERE id = $1")
+        .bind(down_ids[0])
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(attempts, 0);
+
+    sqlx::query("DELETE FROM webhook_deliveries WHERE id = $1")
+        .bind(down_ids[0])
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query("DELETE FROM webhook_dead_letters WHERE id = $1")
+        .bind(down_ids[1])
+        .execute(&pool)
+        .await
+        .unwrap();
+}
@@ -0,0 +1,2 @@
+// 前端构建产物的替身，测试只看文件有没有按规则返回
+document.getElementById("app").textContent = "hello from dep_web, " + location.pathname;